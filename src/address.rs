@@ -0,0 +1,291 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// An IP prefix in CIDR notation, such as `10.0.5.0/24`.
+#[derive(Clone, Copy, Debug)]
+pub struct Prefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Prefix {
+    fn max_len(addr: &IpAddr) -> u8 {
+        if addr.is_ipv4() { 32 } else { 128 }
+    }
+
+    fn mask(&self) -> u128 {
+        let host_bits = (Self::max_len(&self.addr) - self.len) as u32;
+        let full = if self.addr.is_ipv4() { u32::MAX as u128 } else { u128::MAX };
+        full & u128::MAX.checked_shl(host_bits).unwrap_or(0)
+    }
+
+    /// Return true if `addr` is in the prefix, which never contains addresses of the other family.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        addr.is_ipv4() == self.addr.is_ipv4() && Self::bits(addr) & self.mask() == Self::bits(&self.addr) & self.mask()
+    }
+
+    /// Return the first and last addresses of the prefix, as integers.
+    pub(crate) fn range(&self) -> (u128, u128) {
+        let first = Self::bits(&self.addr) & self.mask();
+        let full = if self.addr.is_ipv4() { u32::MAX as u128 } else { u128::MAX };
+        (first, first | (full & !self.mask()))
+    }
+
+    pub(crate) fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    /// Return `addr` as an integer.
+    pub(crate) fn bits(addr: &IpAddr) -> u128 {
+        match addr {
+            IpAddr::V4(a) => u32::from(*a) as u128,
+            IpAddr::V6(a) => u128::from(*a),
+        }
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr = addr.trim().parse::<IpAddr>().map_err(|e| format!("Invalid prefix {}: {}", s, e))?;
+        let len = match len {
+            Some(len) => len.trim().parse::<u8>().map_err(|e| format!("Invalid prefix length in {}: {}", s, e))?,
+            None => Self::max_len(&addr),
+        };
+        if len > Self::max_len(&addr) {
+            return Err(format!("Prefix length too large in {}", s));
+        }
+        Ok(Prefix { addr, len })
+    }
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// Return true if `ip` is a loopback, private or link-local address, where very low latencies are expected.
+pub fn is_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+/// A rule replacing the network part of addresses in `from` by `to`, keeping the host part.
+#[derive(Clone, Copy, Debug)]
+pub struct RewriteRule {
+    from: Prefix,
+    to: Prefix,
+}
+
+impl RewriteRule {
+    /// Return the rewritten address, or `None` if the rule doesn't apply to `addr`.
+    pub fn apply(&self, addr: &SocketAddr) -> Option<SocketAddr> {
+        if !self.from.contains(&addr.ip()) {
+            return None;
+        }
+        let mask = self.to.mask();
+        let bits = (Prefix::bits(&self.to.addr) & mask) | (Prefix::bits(&addr.ip()) & !mask);
+        let ip = match self.to.addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+        };
+        Some(SocketAddr::new(ip, addr.port()))
+    }
+}
+
+impl FromStr for RewriteRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((from, to)) = s.split_once("->") else { return Err(format!("Rewrite rule {} is not of the form 'prefix -> prefix'", s)) };
+        let from = from.parse::<Prefix>()?;
+        let to = to.parse::<Prefix>()?;
+        if from.addr.is_ipv4() != to.addr.is_ipv4() || from.len != to.len {
+            return Err(format!("Prefixes of rewrite rule {} must have the same family and length", s));
+        }
+        Ok(RewriteRule { from, to })
+    }
+}
+
+/// Return `addr` rewritten by the first matching rule, or unchanged if no rule matches.
+pub fn rewrite(rules: &[RewriteRule], addr: SocketAddr) -> SocketAddr {
+    rules.iter().find_map(|r| r.apply(&addr)).unwrap_or(addr)
+}
+
+/// An additional TPU address of a validator identity, such as its IPv6 address when gossip advertises IPv4.
+#[derive(Clone, Debug)]
+pub struct AltTpu {
+    pub pubkey: String,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for AltTpu {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((pubkey, addr)) = s.split_once('=') else { return Err(format!("Alternate TPU {} is not of the form 'pubkey=ip:port'", s)) };
+        let addr = addr.trim().parse::<SocketAddr>().map_err(|e| format!("Invalid address in {}: {}", s, e))?;
+        Ok(AltTpu { pubkey: pubkey.trim().to_string(), addr })
+    }
+}
+
+/// Primary and backup TPU addresses of a validator identity, given as destination `pubkey=ip:port,ip:port...`,
+/// all measured to validate failover sites.
+#[derive(Clone, Debug)]
+pub struct FailoverTarget {
+    pub pubkey: String,
+    pub addrs: Vec<SocketAddr>,
+}
+
+impl FromStr for FailoverTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((pubkey, addrs)) = s.split_once('=') else { return Err(format!("Failover target {} is not of the form 'pubkey=ip:port,ip:port'", s)) };
+        let addrs = addrs.split(',').map(|a| a.trim().parse::<SocketAddr>().map_err(|e| format!("Invalid address in {}: {}", s, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(FailoverTarget { pubkey: pubkey.trim().to_string(), addrs })
+    }
+}
+
+/// A TPU given by hostname, e.g. `tpu.example.com:8009`, measured at all the IPv4 and IPv6 addresses it resolves to.
+#[derive(Clone, Debug)]
+pub struct HostPort {
+    pub host: String,
+    pub port: u16,
+}
+
+impl HostPort {
+    /// Return the addresses of the host, without duplicates.
+    pub fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs: Vec<SocketAddr> = (self.host.as_str(), self.port).to_socket_addrs()?.collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        Ok(addrs)
+    }
+}
+
+impl FromStr for HostPort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((host, port)) = s.trim().rsplit_once(':') else { return Err(format!("{} is not of the form 'host:port'", s)) };
+        let port = port.parse::<u16>().map_err(|e| format!("Invalid port in {}: {}", s, e))?;
+        // Letters tell a hostname from a mistyped IPv4 address
+        let valid = host.split('.').all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+            && host.chars().any(|c| c.is_ascii_alphabetic());
+        if !valid {
+            return Err(format!("Invalid hostname in {}", s));
+        }
+        Ok(HostPort { host: host.to_string(), port })
+    }
+}
+
+/// Service of the validators whose address is measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PortType {
+    /// TPU QUIC, with QUIC handshakes
+    #[default]
+    Tpu,
+    /// TPU forwards QUIC, with QUIC handshakes
+    TpuForwards,
+    /// Gossip, with gossip pings
+    Gossip,
+    /// RPC, with TCP connections
+    Rpc,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(s: &str) -> Prefix {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn rewrite_of(rule: &str, addr: &str) -> Option<SocketAddr> {
+        rule.parse::<RewriteRule>().unwrap().apply(&addr.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_prefixes() {
+        assert_eq!(prefix("10.0.5.0/24").to_string(), "10.0.5.0/24");
+        assert_eq!(prefix(" 10.0.5.0 / 24 ").to_string(), "10.0.5.0/24");
+        assert_eq!(prefix("10.0.5.1").to_string(), "10.0.5.1/32");
+        assert_eq!(prefix("2001:db8::/32").to_string(), "2001:db8::/32");
+        assert_eq!(prefix("2001:db8::1").to_string(), "2001:db8::1/128");
+        assert_eq!(prefix("0.0.0.0/0").range(), (0, u32::MAX as u128));
+        assert_eq!(prefix("10.0.5.9/24").range(), (0x0a00_0500, 0x0a00_05ff));
+        assert_eq!(prefix("::/0").range(), (0, u128::MAX));
+    }
+
+    #[test]
+    fn rejects_invalid_prefixes() {
+        for s in ["10.0.5.0/33", "2001:db8::/129", "10.0.5/24", "10.0.5.0/", "10.0.5.0/-1", "10.0.5.0/24/8", "example.com/24", ""] {
+            assert!(s.parse::<Prefix>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn contains_the_addresses_of_the_prefix() {
+        assert!(prefix("10.0.5.0/24").contains(&ip("10.0.5.255")));
+        assert!(!prefix("10.0.5.0/24").contains(&ip("10.0.6.0")));
+        assert!(prefix("0.0.0.0/0").contains(&ip("203.0.113.9")));
+        assert!(prefix("203.0.113.9/32").contains(&ip("203.0.113.9")));
+        assert!(!prefix("203.0.113.9/32").contains(&ip("203.0.113.8")));
+        assert!(prefix("2001:db8::/32").contains(&ip("2001:db8:ffff::1")));
+        assert!(!prefix("2001:db8::/32").contains(&ip("2001:db9::")));
+        assert!(prefix("::/0").contains(&ip("2001:db8::1")));
+        assert!(prefix("2001:db8::1/128").contains(&ip("2001:db8::1")));
+        assert!(!prefix("2001:db8::1/128").contains(&ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn never_contains_the_other_family() {
+        assert!(!prefix("0.0.0.0/0").contains(&ip("::ffff:10.0.0.1")));
+        assert!(!prefix("::/0").contains(&ip("10.0.0.1")));
+        assert!(!prefix("::/96").contains(&ip("0.0.0.1")));
+    }
+
+    #[test]
+    fn rewrites_the_network_bits() {
+        assert_eq!(rewrite_of("10.0.5.0/24 -> 192.0.2.0/24", "10.0.5.7:8009"), Some("192.0.2.7:8009".parse().unwrap()));
+        assert_eq!(rewrite_of("10.0.5.7/32 -> 192.0.2.1/32", "10.0.5.7:8009"), Some("192.0.2.1:8009".parse().unwrap()));
+        assert_eq!(rewrite_of("0.0.0.0/0 -> 0.0.0.0/0", "10.0.5.7:8009"), Some("10.0.5.7:8009".parse().unwrap()));
+        assert_eq!(rewrite_of("2001:db8:1::/48 -> 2001:db8:2::/48", "[2001:db8:1:5::7]:8009"), Some("[2001:db8:2:5::7]:8009".parse().unwrap()));
+        assert_eq!(rewrite_of("2001:db8::7/128 -> fd00::1/128", "[2001:db8::7]:8009"), Some("[fd00::1]:8009".parse().unwrap()));
+    }
+
+    #[test]
+    fn leaves_other_addresses_to_the_next_rules() {
+        assert_eq!(rewrite_of("10.0.5.0/24 -> 192.0.2.0/24", "10.0.6.7:8009"), None);
+        assert_eq!(rewrite_of("10.0.5.0/24 -> 192.0.2.0/24", "[::ffff:10.0.5.7]:8009"), None);
+        assert_eq!(rewrite_of("::/0 -> ::/0", "10.0.5.7:8009"), None);
+        let rules = ["10.0.5.0/24 -> 192.0.2.0/24", "10.0.0.0/8 -> 172.16.0.0/8"].map(|r| r.parse::<RewriteRule>().unwrap());
+        assert_eq!(rewrite(&rules, "10.0.5.7:1".parse().unwrap()), "192.0.2.7:1".parse().unwrap());
+        assert_eq!(rewrite(&rules, "10.9.5.7:1".parse().unwrap()), "172.9.5.7:1".parse().unwrap());
+        assert_eq!(rewrite(&rules, "11.0.0.1:1".parse().unwrap()), "11.0.0.1:1".parse().unwrap());
+    }
+
+    #[test]
+    fn rejects_rules_between_different_prefixes() {
+        assert!("10.0.0.0/8 -> 2001:db8::/8".parse::<RewriteRule>().is_err());
+        assert!("10.0.0.0/8 -> 172.16.0.0/12".parse::<RewriteRule>().is_err());
+        assert!("10.0.0.0/8".parse::<RewriteRule>().is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! Alerts on the reachability and distance of critical validators, on the latency objectives of network
+//! segments, and on the distance and failure rate of whole measures, raised as soon as a measure shows a
+//! regression.
+
+use crate::health::{is_failure, DistanceMetric, Thresholds};
+use crate::output::{DistanceReport, GroupReport, TpuReport, LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tracing::warn;
+
+/// Number of offending validators listed in the alerts on the thresholds of a measure
+const TOP_OFFENDERS: usize = 5;
+/// Longest message accepted by Discord webhooks
+const DISCORD_MAX_CONTENT: usize = 2000;
+
+/// A watched validator identity, with an optional maximum distance in µs.
+#[derive(Clone, Debug)]
+pub struct CriticalTarget {
+    pub pubkey: String,
+    pub threshold_us: Option<u32>,
+}
+
+impl FromStr for CriticalTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((pubkey, threshold)) => {
+                let threshold = threshold.trim().parse::<u32>().map_err(|e| format!("Invalid threshold in {}: {}", s, e))?;
+                Ok(CriticalTarget { pubkey: pubkey.trim().to_string(), threshold_us: Some(threshold) })
+            }
+            None => Ok(CriticalTarget { pubkey: s.trim().to_string(), threshold_us: None }),
+        }
+    }
+}
+
+/// A latency objective of a segment: its stake-weighted distance, or its simple distance without stake, in µs.
+#[derive(Clone, Debug)]
+pub struct SegmentObjective {
+    pub segment: String,
+    pub threshold_us: u32,
+}
+
+impl FromStr for SegmentObjective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((segment, threshold)) = s.rsplit_once('=') else { return Err(format!("Objective {} is not of the form 'segment=µs'", s)) };
+        let threshold = threshold.trim().parse::<u32>().map_err(|e| format!("Invalid threshold in {}: {}", s, e))?;
+        Ok(SegmentObjective { segment: segment.trim().to_string(), threshold_us: threshold })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    Unreachable,
+    AboveThreshold,
+    Recovered,
+}
+
+#[derive(Serialize)]
+pub struct Alert {
+    pub schema_version: u32,
+    pub timestamp: String,
+    /// Critical validator, for alerts on validators
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    /// Segment, for alerts on segment objectives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+    pub address: Option<SocketAddr>,
+    pub alert: AlertKind,
+    pub distance_us: Option<u32>,
+    pub threshold_us: Option<u32>,
+    pub error: Option<String>,
+    /// Thresholds of the measure exceeded, for alerts on the thresholds of a measure
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exceeded: Vec<String>,
+    /// Validators contributing most to the exceeded thresholds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub offenders: Vec<Offender>,
+    /// Human-readable summary, also understood by chat webhooks
+    pub text: String,
+}
+
+/// Validator listed in an alert on the thresholds of a measure: among the farthest, weighted by stake, when the
+/// distance is above its threshold, or among the failing ones with the most stake when the failure rate is.
+#[derive(Serialize)]
+pub struct Offender {
+    pub pubkey: Option<String>,
+    pub address: SocketAddr,
+    pub stake: u64,
+    pub distance_us: Option<u32>,
+    pub error: Option<String>,
+}
+
+impl Display for Offender {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ({}, {} SOL): ", self.pubkey.as_deref().unwrap_or("unknown"), self.address, self.stake / LAMPORTS_PER_SOL)?;
+        match (&self.error, self.distance_us) {
+            (Some(error), _) => write!(f, "{}", error),
+            (None, Some(distance)) => write!(f, "{} µs", distance),
+            (None, None) => write!(f, "not measured"),
+        }
+    }
+}
+
+impl Alert {
+    /// Return the summary of the alert followed by its offenders, one per line.
+    fn message(&self) -> String {
+        let mut message = self.text.clone();
+        for offender in &self.offenders {
+            message += &format!("\n• {}", offender);
+        }
+        message
+    }
+}
+
+/// Payload of the requests POSTed to the alert webhooks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WebhookFormat {
+    /// The alert as JSON, like --alert-url
+    #[default]
+    Json,
+    /// Message of a Slack incoming webhook
+    Slack,
+    /// Message of a Discord webhook
+    Discord,
+}
+
+/// URL receiving the alerts, with the payload it expects.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    pub url: String,
+    pub format: WebhookFormat,
+}
+
+impl Webhook {
+    /// Return the body of the request notifying `alert`.
+    fn payload(&self, alert: &Alert) -> serde_json::Value {
+        match self.format {
+            WebhookFormat::Json => serde_json::to_value(alert).expect("Cannot serialize alert"),
+            WebhookFormat::Slack => json!({ "text": alert.message() }),
+            WebhookFormat::Discord => json!({ "content": alert.message().chars().take(DISCORD_MAX_CONTENT).collect::<String>() }),
+        }
+    }
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Tracker of the state of critical targets, segment objectives and measure thresholds across measures, only
+/// alerting on changes.
+pub struct Watchdog {
+    targets: Vec<CriticalTarget>,
+    objectives: Vec<SegmentObjective>,
+    thresholds: Option<Thresholds>,
+    failing: HashSet<String>,
+    failing_segments: HashSet<String>,
+    /// Whether the last measure exceeded `thresholds`
+    exceeded: bool,
+}
+
+/// Return the alert to raise for a state `kind` of `key`, failing or not, given the keys already failing.
+fn transition(failing: &mut HashSet<String>, key: &str, kind: Option<AlertKind>) -> Option<AlertKind> {
+    match kind {
+        Some(kind) if failing.insert(key.to_string()) => Some(kind),
+        Some(_) => None,
+        None if failing.remove(key) => Some(AlertKind::Recovered),
+        None => None,
+    }
+}
+
+impl Watchdog {
+    pub fn new(targets: Vec<CriticalTarget>, objectives: Vec<SegmentObjective>, thresholds: Option<Thresholds>) -> Self {
+        Watchdog { targets, objectives, thresholds, failing: HashSet::new(), failing_segments: HashSet::new(), exceeded: false }
+    }
+
+    /// Return the alerts for the critical targets which became unreachable, too far, or recovered in `report`.
+    pub fn check(&mut self, report: &DistanceReport) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for target in &self.targets {
+            let tpu = report.tpus.iter().find(|t| !t.alternate && t.pubkeys.contains(&target.pubkey));
+            let kind = match tpu {
+                Some(TpuReport { distance_us: Some(distance), error: None, .. }) => match target.threshold_us {
+                    Some(threshold) if *distance > threshold => Some(AlertKind::AboveThreshold),
+                    _ => None,
+                },
+                _ => Some(AlertKind::Unreachable),
+            };
+            let Some(kind) = transition(&mut self.failing, &target.pubkey, kind) else { continue };
+            let distance_us = tpu.and_then(|t| t.distance_us);
+            let error = match tpu {
+                Some(tpu) => tpu.error.clone(),
+                None => Some("Not among measured TPUs".to_string()),
+            };
+            let text = match kind {
+                AlertKind::Unreachable => format!("Critical validator {} is unreachable: {}", target.pubkey, error.as_deref().unwrap_or("")),
+                AlertKind::AboveThreshold => format!("Critical validator {} is at {} µs, above its threshold of {} µs",
+                                                     target.pubkey, distance_us.unwrap_or_default(), target.threshold_us.unwrap_or_default()),
+                AlertKind::Recovered => format!("Critical validator {} recovered, at {} µs", target.pubkey, distance_us.unwrap_or_default()),
+            };
+            alerts.push(Alert {
+                schema_version: SCHEMA_VERSION,
+                timestamp: report.timestamp.clone(),
+                pubkey: Some(target.pubkey.clone()),
+                segment: None,
+                address: tpu.map(|t| t.address),
+                alert: kind,
+                distance_us,
+                threshold_us: target.threshold_us,
+                error,
+                exceeded: Vec::new(),
+                offenders: Vec::new(),
+                text,
+            });
+        }
+        if let Some(segments) = &report.segments {
+            alerts.extend(self.check_segments(&report.timestamp, segments));
+        }
+        alerts.extend(self.check_thresholds(report));
+        alerts
+    }
+
+    /// Return the alert for the measure if it went above or back below its thresholds.
+    fn check_thresholds(&mut self, report: &DistanceReport) -> Option<Alert> {
+        let thresholds = self.thresholds.as_ref()?;
+        let exceeded = thresholds.exceeded(report);
+        let kind = match (exceeded.is_empty(), self.exceeded) {
+            (false, false) => AlertKind::AboveThreshold,
+            (true, true) => AlertKind::Recovered,
+            _ => return None,
+        };
+        self.exceeded = !exceeded.is_empty();
+        let distance = match thresholds.metric {
+            DistanceMetric::Simple => &report.simple,
+            DistanceMetric::StakeWeighted => &report.stake_weighted,
+        };
+        let distance_us = distance.as_ref().map(|d| u32::try_from(d.distance_us).unwrap_or(u32::MAX));
+        let (text, offenders) = match kind {
+            AlertKind::Recovered => (format!("Measure back within its thresholds, at {} µs", distance_us.unwrap_or_default()), Vec::new()),
+            _ => {
+                let above_distance = thresholds.distance_us.is_some_and(|t| distance.as_ref().is_none_or(|d| d.distance_us > t));
+                (format!("Measure above its thresholds: {}", exceeded.join(", ")), offenders(report, above_distance))
+            }
+        };
+        Some(Alert {
+            schema_version: SCHEMA_VERSION,
+            timestamp: report.timestamp.clone(),
+            pubkey: None,
+            segment: None,
+            address: None,
+            alert: kind,
+            distance_us,
+            threshold_us: thresholds.distance_us.map(|t| u32::try_from(t).unwrap_or(u32::MAX)),
+            error: None,
+            exceeded,
+            offenders,
+            text,
+        })
+    }
+
+    /// Return the alerts for the segments whose distance went above or back below their objective.
+    fn check_segments(&mut self, timestamp: &str, segments: &[GroupReport]) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for objective in &self.objectives {
+            let distance_us = segments.iter().find(|g| g.name == objective.segment)
+                .and_then(|g| g.stake_weighted_distance_us.or(g.distance_us))
+                .map(|d| u32::try_from(d).unwrap_or(u32::MAX));
+            let kind = match distance_us {
+                Some(distance) if distance > objective.threshold_us => Some(AlertKind::AboveThreshold),
+                Some(_) => None,
+                None => Some(AlertKind::Unreachable),
+            };
+            let Some(kind) = transition(&mut self.failing_segments, &objective.segment, kind) else { continue };
+            let text = match kind {
+                AlertKind::Unreachable => format!("No validator of segment {} is reachable", objective.segment),
+                AlertKind::AboveThreshold => format!("Segment {} is at {} µs, above its objective of {} µs",
+                                                     objective.segment, distance_us.unwrap_or_default(), objective.threshold_us),
+                AlertKind::Recovered => format!("Segment {} recovered, at {} µs", objective.segment, distance_us.unwrap_or_default()),
+            };
+            alerts.push(Alert {
+                schema_version: SCHEMA_VERSION,
+                timestamp: timestamp.to_string(),
+                pubkey: None,
+                segment: Some(objective.segment.clone()),
+                address: None,
+                alert: kind,
+                distance_us,
+                threshold_us: Some(objective.threshold_us),
+                error: None,
+                exceeded: Vec::new(),
+                offenders: Vec::new(),
+                text,
+            });
+        }
+        alerts
+    }
+}
+
+/// Return the validators contributing most to a measure above its thresholds: the farthest by stake times
+/// distance if `above_distance`, otherwise the failing ones with the most stake.
+fn offenders(report: &DistanceReport, above_distance: bool) -> Vec<Offender> {
+    let mut tpus: Vec<&TpuReport> = report.tpus.iter().filter(|t| !t.alternate).collect();
+    if above_distance {
+        tpus.retain(|t| t.error.is_none() && t.distance_us.is_some());
+        tpus.sort_by_key(|t| std::cmp::Reverse(t.stake as u128 * t.distance_us.unwrap_or_default() as u128));
+    } else {
+        tpus.retain(|t| is_failure(t));
+        tpus.sort_by_key(|t| std::cmp::Reverse(t.stake));
+    }
+    tpus.into_iter().take(TOP_OFFENDERS).map(|t| Offender {
+        pubkey: t.pubkeys.first().cloned(),
+        address: t.address,
+        stake: t.stake,
+        distance_us: t.distance_us,
+        error: t.error.clone(),
+    }).collect()
+}
+
+/// Print `alert` on stderr, POST it as JSON to each of `urls`, and in the format of each of `webhooks` to them.
+pub async fn notify(urls: &[String], webhooks: &[Webhook], alert: &Alert) {
+    eprintln!("ALERT {} {}", alert.timestamp, alert);
+    let client = reqwest::Client::new();
+    let body = serde_json::to_value(alert).expect("Cannot serialize alert");
+    let requests = urls.iter().map(|url| (url, body.clone())).chain(webhooks.iter().map(|w| (&w.url, w.payload(alert))));
+    for (url, body) in requests {
+        let sent = client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body.to_string()).send().await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = sent {
+            warn!("Failed to send alert to {}: {}", url, e);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Classification of the TPUs in latency bands, e.g. co-located, metro or intercontinental, with the share of
+//! the stake in each band, to communicate results without raw microseconds.
+
+use crate::output::{TpuReport, LAMPORTS_PER_SOL};
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Default bands, from the closest to the farthest.
+pub const DEFAULT_BANDS: &str = "co-located<1ms,metro<5ms,regional<20ms,continental<50ms,intercontinental";
+
+/// A band of distances, given as `label<duration` or, for the farthest band, as `label` alone.
+#[derive(Clone, Debug)]
+pub struct LatencyBand {
+    pub label: String,
+    /// Distances in the band are below this bound, in µs, `None` for an unbounded band
+    pub below_us: Option<u32>,
+}
+
+impl FromStr for LatencyBand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, below_us) = match s.split_once('<') {
+            Some((label, bound)) => {
+                let bound = humantime::parse_duration(bound.trim()).map_err(|e| format!("Invalid bound of band {}: {}", s, e))?;
+                let below_us = u32::try_from(bound.as_micros()).map_err(|_| format!("Bound of band {} is too large", s))?;
+                (label, Some(below_us))
+            }
+            None => (s, None),
+        };
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(format!("Band {} has no label", s));
+        }
+        Ok(LatencyBand { label: label.to_string(), below_us })
+    }
+}
+
+/// TPUs of a band and their stake.
+#[derive(Serialize)]
+pub struct BandReport {
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below_us: Option<u32>,
+    pub tpus: u64,
+    pub stake: u64,
+    /// Share of the total stake, in %
+    pub stake_pct: f64,
+}
+
+/// Return the bands sorted from the closest to the farthest, the unbounded one last.
+fn sorted(bands: &[LatencyBand]) -> Vec<&LatencyBand> {
+    let mut sorted: Vec<&LatencyBand> = bands.iter().collect();
+    sorted.sort_by_key(|band| band.below_us.unwrap_or(u32::MAX));
+    sorted
+}
+
+/// Return the label of the closest band containing `distance_us`, if any.
+fn band_of<'a>(bands: &[&'a LatencyBand], distance_us: u32) -> Option<&'a LatencyBand> {
+    bands.iter().copied().find(|band| band.below_us.is_none_or(|below| distance_us < below))
+}
+
+impl BandReport {
+    /// Set the band of each successfully measured TPU, and return the number of TPUs and the stake in each of the
+    /// `bands`, with its share of `total_stake`. Alternate addresses are labelled but not counted.
+    pub fn classify(tpus: &mut [TpuReport], total_stake: u64, bands: &[LatencyBand]) -> Vec<BandReport> {
+        let bands = sorted(bands);
+        let mut reports: Vec<BandReport> = bands.iter().map(|band| BandReport {
+            label: band.label.clone(),
+            below_us: band.below_us,
+            tpus: 0,
+            stake: 0,
+            stake_pct: 0.0,
+        }).collect();
+        for tpu in tpus.iter_mut().filter(|t| t.error.is_none()) {
+            let Some(distance) = tpu.distance_us else { continue };
+            let Some(band) = band_of(&bands, distance) else { continue };
+            tpu.band = Some(band.label.clone());
+            if tpu.alternate {
+                continue;
+            }
+            let report = reports.iter_mut().find(|r| r.label == band.label).expect("Band of classified TPU");
+            report.tpus += 1;
+            report.stake += tpu.stake;
+        }
+        if total_stake > 0 {
+            for report in &mut reports {
+                report.stake_pct = 100.0 * report.stake as f64 / total_stake as f64;
+            }
+        }
+        reports
+    }
+
+    pub(crate) fn print_text(&self, total_stake: u64) {
+        let bound = self.below_us.map(|below| format!(" (<{})", humantime::format_duration(Duration::from_micros(below as u64)))).unwrap_or_default();
+        if total_stake > 0 {
+            println!("  {}{}: {} TPUs, {} SOL, {:.2}% of stake", self.label, bound, self.tpus, self.stake / LAMPORTS_PER_SOL, self.stake_pct);
+        } else {
+            println!("  {}{}: {} TPUs", self.label, bound, self.tpus);
+        }
+    }
+}
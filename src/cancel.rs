@@ -0,0 +1,52 @@
+//! Cooperative cancellation of measures, e.g. on Ctrl-C, at a deadline, or when an adaptive mode has seen
+//! enough.
+//!
+//! The task measuring each TPU checks the cancellation before each connection attempt and while waiting, so
+//! that a cancelled measure stops within a connection timeout, closing its persistent connection, instead of
+//! running its whole schedule.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+/// Handle cancelling the measures it was given to, shared by its clones.
+#[derive(Clone, Debug)]
+pub struct Cancellation(Arc<watch::Sender<bool>>);
+
+impl Default for Cancellation {
+    fn default() -> Self {
+        Cancellation(Arc::new(watch::Sender::new(false)))
+    }
+}
+
+impl Cancellation {
+    pub fn new() -> Cancellation {
+        Cancellation::default()
+    }
+
+    /// Cancel the measures in progress and the following ones.
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until cancelled, at once if already cancelled.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.0.subscribe();
+        // The sender lives as long as self, so the wait cannot fail
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// Cancel after `delay`, e.g. to bound the duration of a measure.
+    pub fn cancel_after(&self, delay: Duration) {
+        let cancellation = self.clone();
+        tokio::spawn(async move {
+            sleep(delay).await;
+            cancellation.cancel();
+        });
+    }
+}
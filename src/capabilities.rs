@@ -0,0 +1,95 @@
+//! Platform-dependent features, probed at runtime so that the tool degrades gracefully where they are missing,
+//! and optional features selected at build time.
+
+use crate::fallback::icmp_socket;
+use crate::hook::SHELL;
+use crate::pcap;
+use crate::quic::BIND_DEVICE;
+use crate::traceroute::traceroute_available;
+use crate::udp::OsUdpDrops;
+use quinn::udp::UdpSocketState;
+use serde::Serialize;
+use socket2::Domain;
+use std::net::{SocketAddr, UdpSocket};
+
+#[derive(Serialize)]
+pub struct Capability {
+    pub name: &'static str,
+    pub available: bool,
+    /// What is used, or what happens when the feature is missing
+    pub detail: String,
+}
+
+fn capability(name: &'static str, available: bool, detail: impl Into<String>) -> Capability {
+    Capability { name, available, detail: detail.into() }
+}
+
+/// Return the platform-dependent features of the current host.
+pub fn probe() -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+
+    let ipv6 = UdpSocket::bind(SocketAddr::from(([0u16; 8], 0)));
+    capabilities.push(match &ipv6 {
+        Ok(_) => capability("dual_stack_socket", true, "IPv6 socket, IPv4 addresses are reached as mapped addresses"),
+        Err(e) => capability("dual_stack_socket", false, format!("IPv4 socket, IPv6 TPUs can't be measured: {}", e)),
+    });
+
+    let socket = ipv6.or_else(|_| UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))));
+    match socket.and_then(|socket| UdpSocketState::new((&socket).into())) {
+        Ok(state) => {
+            let gso = state.max_gso_segments();
+            let gro = state.gro_segments();
+            capabilities.push(capability("udp_gso", gso > 1, format!("Up to {} datagram(s) per send", gso)));
+            capabilities.push(capability("udp_gro", gro > 1, format!("Up to {} datagram(s) per receive", gro)));
+        }
+        Err(e) => {
+            capabilities.push(capability("udp_gso", false, format!("Cannot probe the UDP socket: {}", e)));
+            capabilities.push(capability("udp_gro", false, format!("Cannot probe the UDP socket: {}", e)));
+        }
+    }
+
+    capabilities.push(match OsUdpDrops::snapshot() {
+        Some(_) => capability("host_udp_drops", true, "Host-wide UDP buffer overflows read from /proc/net/snmp"),
+        None => capability("host_udp_drops", false, "Host UDP buffer overflows are not reported"),
+    });
+
+    capabilities.push(match BIND_DEVICE {
+        true => capability("bind_interface", true, "--interface binds the QUIC socket with SO_BINDTODEVICE"),
+        false => capability("bind_interface", false, "--interface is rejected, --bind with the address of the interface is an alternative"),
+    });
+
+    capabilities.push(capability("on_result_shell", true, format!("Commands run with {} {}", SHELL[0], SHELL[1])));
+
+    capabilities.push(match pcap::tcpdump_available() {
+        true => capability("pcap_on_anomaly", true, "--pcap-on-anomaly captures with tcpdump"),
+        false => capability("pcap_on_anomaly", false, "tcpdump is not installed, --pcap-on-anomaly only reports anomalies"),
+    });
+
+    capabilities.push(match traceroute_available() {
+        true => capability("traceroute", true, "--traceroute runs traceroute"),
+        false => capability("traceroute", false, "traceroute is not installed, --traceroute reports failed traces"),
+    });
+
+    capabilities.push(match icmp_socket(Domain::IPV4) {
+        Ok((_, false)) => capability("icmp_socket", true, "--fallback-probe icmp sends echoes with an unprivileged ping socket"),
+        Ok((_, true)) => capability("icmp_socket", true, "--fallback-probe icmp sends echoes with a raw socket"),
+        Err(e) => capability("icmp_socket", false, format!("--fallback-probe icmp gets no network distance, udp is an alternative: {}", e)),
+    });
+
+    capabilities
+}
+
+/// Return the optional cargo features, and whether this binary was built with them.
+pub fn features() -> Vec<Capability> {
+    vec![
+        capability("geoip", cfg!(feature = "geoip"), "--geoip reads MaxMind databases"),
+        capability("sqlite", cfg!(feature = "sqlite"), "--store and the history subcommand use SQLite databases"),
+        capability("tui", cfg!(feature = "tui"), "--tui draws a live dashboard in the terminal"),
+    ]
+}
+
+pub fn print_text(capabilities: &[Capability]) {
+    for c in capabilities {
+        println!("{:20} {:3} {}", c.name, if c.available { "yes" } else { "no" }, c.detail);
+    }
+}
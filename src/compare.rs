@@ -0,0 +1,114 @@
+//! Side-by-side comparison of two network paths, i.e. of measures of the same TPUs from two source addresses.
+
+use crate::output::{DistanceReport, LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Distance of a TPU over both paths. The delta is the second distance minus the first one.
+#[derive(Serialize)]
+pub struct TpuDelta {
+    pub address: SocketAddr,
+    pub pubkeys: Vec<String>,
+    pub stake: u64,
+    pub first_us: u32,
+    pub second_us: u32,
+    pub delta_us: i64,
+}
+
+/// Averages of the TPUs measured over both paths, so that both are computed on the same set of TPUs.
+#[derive(Serialize)]
+pub struct PathAverages {
+    pub first_us: u64,
+    pub second_us: u64,
+    pub delta_us: i64,
+}
+
+#[derive(Serialize)]
+pub struct PathComparison {
+    pub schema_version: u32,
+    pub first: String,
+    pub second: String,
+    /// TPUs measured over both paths, by decreasing stake
+    pub tpus: Vec<TpuDelta>,
+    /// TPUs measured over one path only
+    pub first_only: u64,
+    pub second_only: u64,
+    pub simple: Option<PathAverages>,
+    pub stake_weighted: Option<PathAverages>,
+}
+
+fn averages(first_sum: u128, second_sum: u128, weight: u128) -> Option<PathAverages> {
+    if weight == 0 {
+        return None;
+    }
+    let first_us = (first_sum / weight) as u64;
+    let second_us = (second_sum / weight) as u64;
+    Some(PathAverages { first_us, second_us, delta_us: second_us as i64 - first_us as i64 })
+}
+
+impl PathComparison {
+    /// Compare the successfully measured TPUs of `first` and `second`, leaving out alternate addresses.
+    pub fn new(first_label: String, first: &DistanceReport, second_label: String, second: &DistanceReport) -> PathComparison {
+        let measured = |report: &DistanceReport| -> HashMap<SocketAddr, u32> {
+            report.tpus.iter()
+                .filter(|t| t.error.is_none() && !t.alternate)
+                .filter_map(|t| Some((t.address, t.distance_us?)))
+                .collect()
+        };
+        let first_measured = measured(first);
+        let second_measured = measured(second);
+        let mut tpus: Vec<TpuDelta> = first.tpus.iter()
+            .filter_map(|t| {
+                let first_us = *first_measured.get(&t.address)?;
+                let second_us = *second_measured.get(&t.address)?;
+                Some(TpuDelta { address: t.address, pubkeys: t.pubkeys.clone(), stake: t.stake, first_us, second_us,
+                    delta_us: second_us as i64 - first_us as i64 })
+            })
+            .collect();
+        tpus.sort_by(|a, b| b.stake.cmp(&a.stake));
+
+        let (mut first_sum, mut second_sum, mut first_sum_w, mut second_sum_w, mut stake) = (0u128, 0u128, 0u128, 0u128, 0u128);
+        for t in &tpus {
+            first_sum += t.first_us as u128;
+            second_sum += t.second_us as u128;
+            first_sum_w += t.first_us as u128 * t.stake as u128;
+            second_sum_w += t.second_us as u128 * t.stake as u128;
+            stake += t.stake as u128;
+        }
+        PathComparison {
+            schema_version: SCHEMA_VERSION,
+            first: first_label,
+            second: second_label,
+            first_only: (first_measured.len() - tpus.len()) as u64,
+            second_only: (second_measured.len() - tpus.len()) as u64,
+            simple: averages(first_sum, second_sum, tpus.len() as u128),
+            stake_weighted: averages(first_sum_w, second_sum_w, stake),
+            tpus,
+        }
+    }
+
+    pub fn print_text(&self, details: bool) {
+        if details {
+            println!("{:<46} {:>12} {:>10} {:>10} {:>10}", "TPU", "Stake (SOL)", self.first, self.second, "Delta");
+            for t in &self.tpus {
+                println!("{:<46} {:>12} {:>10} {:>10} {:>+10}", t.address, t.stake / LAMPORTS_PER_SOL, t.first_us, t.second_us, t.delta_us);
+            }
+        }
+        println!("{} TPUs measured over both paths, {} over {} only, {} over {} only",
+                 self.tpus.len(), self.first_only, self.first, self.second_only, self.second);
+        for (name, averages) in [("Simple", &self.simple), ("Stake-weighted", &self.stake_weighted)] {
+            let Some(a) = averages else { continue };
+            let verdict = match a.delta_us {
+                d if d < 0 => format!("{} saves {} µs", self.second, -d),
+                d if d > 0 => format!("{} saves {} µs", self.first, d),
+                _ => "no difference".to_string(),
+            };
+            println!("{} distance: {} µs via {}, {} µs via {}, {}", name, a.first_us, self.first, a.second_us, self.second, verdict);
+        }
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize comparison"));
+    }
+}
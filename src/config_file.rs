@@ -0,0 +1,111 @@
+//! Configuration file of the tool, in TOML, `config.toml` of the configuration directory by default
+//! (`~/.config/solana-distance/config.toml`), for runs from systemd timers or cron without long command lines.
+//!
+//! Each top-level key is a long option of the command line, with dashes or underscores: `true` for a flag, a
+//! number or a string for a value, an array for an option which can be repeated, and a number of repetitions for
+//! `verbose`. Options given on the command line override those of the file. Two tables are not options:
+//! `clusters`, the RPC URL of named clusters selected with `--cluster`, and `targets`, named lists of validator
+//! pubkeys or TPU ip:port measured with `targets use`.
+//!
+//! ```toml
+//! count = 3
+//! output = "json"
+//! geoip = ["GeoLite2-City.mmdb", "GeoLite2-ASN.mmdb"]
+//! cluster = "testnet"
+//!
+//! [clusters]
+//! testnet = "https://api.testnet.solana.com"
+//!
+//! [targets]
+//! mine = ["puffinQSvKFriPbyE5atyx1ptfnyytovbzxybr1jsyy", "64.130.57.131:8009"]
+//! ```
+
+use crate::target_sets::config_dir;
+use clap::{ArgAction, Command};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Options selecting the configuration file, which it cannot set.
+const NOT_CONFIGURABLE: [&str; 2] = ["config", "no-config"];
+
+/// Return the path of the default configuration file.
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+fn invalid_data(error: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.into())
+}
+
+/// Options, clusters and target lists of a configuration file.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFile {
+    options: Table,
+    /// RPC URL of each named cluster
+    pub clusters: HashMap<String, String>,
+    /// Named lists of validator pubkeys or TPU ip:port
+    pub targets: HashMap<String, Vec<String>>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> io::Result<ConfigFile> {
+        let mut options: Table = fs::read_to_string(path)?.parse().map_err(|e: toml::de::Error| invalid_data(e.to_string()))?;
+        let clusters = match options.remove("clusters") {
+            Some(Value::Table(clusters)) => clusters.into_iter().map(|(name, url)| match url {
+                Value::String(url) => Ok((name, url)),
+                _ => Err(invalid_data(format!("The RPC URL of cluster {} is not a string", name))),
+            }).collect::<io::Result<_>>()?,
+            Some(_) => return Err(invalid_data("clusters is not a table")),
+            None => HashMap::new(),
+        };
+        let targets = match options.remove("targets") {
+            Some(Value::Table(targets)) => targets.into_iter().map(|(name, list)| {
+                let list = match list {
+                    Value::Array(list) => list.into_iter().map(|t| match t {
+                        Value::String(target) => Some(target),
+                        _ => None,
+                    }).collect::<Option<Vec<_>>>(),
+                    _ => None,
+                };
+                list.map(|list| (name.clone(), list)).ok_or_else(|| invalid_data(format!("Target list {} is not an array of strings", name)))
+            }).collect::<io::Result<_>>()?,
+            Some(_) => return Err(invalid_data("targets is not a table")),
+            None => HashMap::new(),
+        };
+        Ok(ConfigFile { options, clusters, targets })
+    }
+
+    /// Return the options of the file as command-line arguments of `command`, leaving out those whose id is
+    /// `overridden`, e.g. given on the command line.
+    pub fn arguments(&self, command: &Command, overridden: impl Fn(&str) -> bool) -> io::Result<Vec<String>> {
+        let mut arguments = Vec::new();
+        for (key, value) in &self.options {
+            let long = key.replace('_', "-");
+            let arg = command.get_arguments()
+                .find(|a| a.get_long() == Some(long.as_str()) && !NOT_CONFIGURABLE.contains(&long.as_str()))
+                .ok_or_else(|| invalid_data(format!("Unknown option {}", key)))?;
+            if overridden(arg.get_id().as_str()) {
+                continue;
+            }
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                match (value, arg.get_action()) {
+                    (Value::Boolean(true), ArgAction::SetTrue) => arguments.push(format!("--{}", long)),
+                    (Value::Boolean(false), ArgAction::SetTrue) => (),
+                    (Value::Integer(n), ArgAction::Count) if *n >= 0 => arguments.extend((0..*n).map(|_| format!("--{}", long))),
+                    (_, ArgAction::SetTrue | ArgAction::Count) => return Err(invalid_data(format!("Invalid value of {}: {}", key, value))),
+                    (Value::String(s), _) => arguments.push(format!("--{}={}", long, s)),
+                    (Value::Integer(_) | Value::Float(_) | Value::Boolean(_), _) => arguments.push(format!("--{}={}", long, value)),
+                    _ => return Err(invalid_data(format!("Invalid value of {}: {}", key, value))),
+                }
+            }
+        }
+        Ok(arguments)
+    }
+}
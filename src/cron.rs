@@ -0,0 +1,214 @@
+//! Cron-like schedules of the rounds of a measurement plan, e.g. `0 3 * * *` for 3:00 UTC every night.
+//!
+//! A schedule has the five fields minute, hour, day of month, month and day of week (0 or 7 being Sunday), each
+//! `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or a comma-separated list of them. As with cron, a
+//! day matches if either the day of month or the day of week matches when both are restricted. Times are in UTC.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Days searched for the next matching minute, enough for schedules on February 29th
+const SEARCH_DAYS: u64 = 8 * 366;
+
+/// Set of the allowed values of a field, as a bit mask.
+#[derive(Clone, Copy, Debug)]
+struct Field {
+    mask: u64,
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(s: &str, min: u32, max: u32) -> Result<Field, String> {
+        let mut mask = 0u64;
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).ok_or_else(|| format!("Invalid step in {}", part))?),
+                None => (part, 1),
+            };
+            let number = |n: &str| n.parse::<u32>().ok().filter(|n| (min..=max).contains(n)).ok_or_else(|| format!("{} is not in {}-{}", n, min, max));
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (number(start)?, number(end)?),
+                    // A single value with a step starts the range, as in `5/15`
+                    None if part.contains('/') => (number(range)?, max),
+                    None => (number(range)?, number(range)?),
+                },
+            };
+            if start > end {
+                return Err(format!("Invalid range {}", range));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+        Ok(Field { mask, restricted: !s.starts_with('*') })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.mask & (1 << value) != 0
+    }
+}
+
+/// A parsed cron-like schedule.
+#[derive(Clone, Debug)]
+pub struct CronSchedule {
+    source: String,
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Schedule {} doesn't have the 5 fields minute, hour, day of month, month and day of week", s));
+        };
+        let mut weekdays = Field::parse(weekdays, 0, 7)?;
+        // Sunday is both 0 and 7
+        if weekdays.contains(7) {
+            weekdays.mask |= 1;
+        }
+        let schedule = CronSchedule {
+            source: s.to_string(),
+            minutes: Field::parse(minutes, 0, 59)?,
+            hours: Field::parse(hours, 0, 23)?,
+            days: Field::parse(days, 1, 31)?,
+            months: Field::parse(months, 1, 12)?,
+            weekdays,
+        };
+        if schedule.next_after(SystemTime::now()).is_none() {
+            return Err(format!("Schedule {} never matches", s));
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Return the year, month and day of a number of days since 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+impl CronSchedule {
+    fn day_matches(&self, days: u64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday
+        let weekday = ((days + 4) % 7) as u32;
+        let day_matches = match (self.days.restricted, self.weekdays.restricted) {
+            (true, true) => self.days.contains(day) || self.weekdays.contains(weekday),
+            _ => self.days.contains(day) && self.weekdays.contains(weekday),
+        };
+        self.months.contains(month) && day_matches
+    }
+
+    /// Return the first minute matching the schedule strictly after `time`, if any in the next years.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let start = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / 60 + 1;
+        let first_day = start / 1440;
+        for days in first_day..first_day + SEARCH_DAYS {
+            if !self.day_matches(days) {
+                continue;
+            }
+            let from = if days == first_day { start % 1440 } else { 0 };
+            if let Some(minute) = (from..1440).find(|m| self.hours.contains((m / 60) as u32) && self.minutes.contains((m % 60) as u32)) {
+                return Some(UNIX_EPOCH + Duration::from_secs((days * 1440 + minute) * 60));
+            }
+        }
+        None
+    }
+
+    /// Return the time to wait from now until the next matching minute.
+    pub fn until_next(&self) -> Duration {
+        let now = SystemTime::now();
+        let next = self.next_after(now).expect("Schedule checked when parsed");
+        next.duration_since(now).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the first minute matching `schedule` strictly after the RFC 3339 time `after`.
+    fn next(schedule: &str, after: &str) -> String {
+        let schedule: CronSchedule = schedule.parse().unwrap();
+        let next = schedule.next_after(humantime::parse_rfc3339(after).unwrap()).unwrap();
+        humantime::format_rfc3339(next).to_string()
+    }
+
+    #[test]
+    fn starts_at_the_next_minute() {
+        assert_eq!(next("* * * * *", "2024-09-02T10:00:30Z"), "2024-09-02T10:01:00Z");
+        assert_eq!(next("0 3 * * *", "2024-09-02T03:00:00Z"), "2024-09-03T03:00:00Z");
+        assert_eq!(next("0 3 * * *", "2024-09-02T02:59:59Z"), "2024-09-02T03:00:00Z");
+    }
+
+    #[test]
+    fn matches_ranges_steps_and_lists() {
+        assert_eq!(next("10-12 * * * *", "2024-09-02T10:11:00Z"), "2024-09-02T10:12:00Z");
+        assert_eq!(next("10-12 * * * *", "2024-09-02T10:12:00Z"), "2024-09-02T11:10:00Z");
+        assert_eq!(next("*/15 * * * *", "2024-09-02T10:16:00Z"), "2024-09-02T10:30:00Z");
+        assert_eq!(next("5/20 * * * *", "2024-09-02T10:46:00Z"), "2024-09-02T11:05:00Z");
+        assert_eq!(next("0-30/10 8 * * *", "2024-09-02T08:30:00Z"), "2024-09-03T08:00:00Z");
+        assert_eq!(next("0,30 9,17 * * *", "2024-09-02T09:30:00Z"), "2024-09-02T17:00:00Z");
+        assert_eq!(next("0 0-6/3,12 * * *", "2024-09-02T06:00:00Z"), "2024-09-02T12:00:00Z");
+    }
+
+    #[test]
+    fn matches_days_of_month_or_of_week() {
+        // 2024-09-02 is a Monday
+        assert_eq!(next("0 9 * * 1-5", "2024-09-06T09:00:00Z"), "2024-09-09T09:00:00Z");
+        assert_eq!(next("0 0 * * 0", "2024-09-02T00:00:00Z"), "2024-09-08T00:00:00Z");
+        assert_eq!(next("0 0 * * 7", "2024-09-02T00:00:00Z"), "2024-09-08T00:00:00Z");
+        assert_eq!(next("0 0 10 * *", "2024-09-02T00:00:00Z"), "2024-09-10T00:00:00Z");
+        // Either the 10th or a Monday when both are restricted
+        assert_eq!(next("0 0 10 * 1", "2024-09-03T00:00:00Z"), "2024-09-09T00:00:00Z");
+        assert_eq!(next("0 0 10 * 1", "2024-09-09T00:00:00Z"), "2024-09-10T00:00:00Z");
+        // As with cron, a step on `*` doesn't count as a restriction: both have to match, the 21st being a Monday
+        assert_eq!(next("0 0 */10 * 1", "2024-09-02T00:00:00Z"), "2024-10-21T00:00:00Z");
+    }
+
+    #[test]
+    fn rolls_over_months_and_years() {
+        assert_eq!(next("0 0 1 * *", "2024-01-31T12:00:00Z"), "2024-02-01T00:00:00Z");
+        assert_eq!(next("0 0 * * *", "2024-12-31T23:59:00Z"), "2025-01-01T00:00:00Z");
+        assert_eq!(next("0 12 31 * *", "2024-04-01T00:00:00Z"), "2024-05-31T12:00:00Z");
+        assert_eq!(next("0 0 * 3 *", "2024-02-29T00:00:00Z"), "2024-03-01T00:00:00Z");
+        assert_eq!(next("0 0 29 2 *", "2024-03-01T00:00:00Z"), "2028-02-29T00:00:00Z");
+        assert_eq!(next("30 6 1 1,7 *", "2024-07-01T06:30:00Z"), "2025-01-01T06:30:00Z");
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for schedule in ["* * * *", "* * * * * *", "60 * * * *", "* 24 * * *", "* * 0 * *", "* * * 13 *", "* * * * 8",
+                         "*/0 * * * *", "5-1 * * * *", "a * * * *", "0 0 30 2 *", "0 0 31 4,6,9,11 *"] {
+            assert!(schedule.parse::<CronSchedule>().is_err(), "{} parsed", schedule);
+        }
+    }
+
+    #[test]
+    fn displays_its_source() {
+        assert_eq!("*/5 * * * 1-5".parse::<CronSchedule>().unwrap().to_string(), "*/5 * * * 1-5");
+    }
+}
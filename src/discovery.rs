@@ -0,0 +1,626 @@
+use crate::address::{rewrite, FailoverTarget, HostPort, PortType, Prefix};
+use crate::gossip::crawl;
+use crate::leader::LeaderSchedule;
+use crate::udp::PacketTrace;
+use crate::NodeError::{NoContactInfo, NoTPU, NotAStakedNode, NotAllowed};
+use crate::{Config, Error, Errors};
+use solana_rpc_client::api::client_error::Result as ClientResult;
+use solana_rpc_client::rpc_client::RpcClient;
+use semver::Version;
+use solana_rpc_client_types::response::{RpcContactInfo, RpcVoteAccountInfo, RpcVoteAccountStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+#[derive(Default)]
+pub(crate) struct Tpu {
+    pub(crate) stake: u64,
+    pub(crate) ids: Vec<String>,
+    pub(crate) version: Option<String>,
+    /// Additional address of an identity measured elsewhere, left out of the aggregates
+    pub(crate) alternate: bool,
+    /// For the addresses of a failover target, whether gossip currently advertises it
+    pub(crate) advertised: Option<bool>,
+    /// Hostname of the destination resolving to the address
+    pub(crate) hostname: Option<String>,
+}
+
+impl Tpu {
+    fn add_id(&mut self, ci: &RpcContactInfo) {
+        self.ids.push(ci.pubkey.clone());
+        if self.version.is_none() {
+            self.version = ci.version.clone();
+        }
+    }
+}
+
+/// Set of TPUs to measure, with the total stake and the errors met while discovering them.
+pub(crate) struct Targets {
+    pub(crate) tpus: HashMap<SocketAddr, Tpu>,
+    pub(crate) total_stake: u64,
+    pub(crate) errors: Errors,
+    /// Leader slots of the current epoch, if requested
+    pub(crate) leader_schedule: Option<LeaderSchedule>,
+}
+
+impl Targets {
+    /// Leave out the TPUs outside `allow_prefix`, if it isn't empty, counting the primary ones as not allowed.
+    pub(crate) fn retain_allowed(&mut self, allow_prefix: &[Prefix]) {
+        if allow_prefix.is_empty() {
+            return;
+        }
+        let errors = &mut self.errors;
+        self.tpus.retain(|sock_addr, tpu| {
+            let allowed = allow_prefix.iter().any(|p| p.contains(&sock_addr.ip()));
+            if !allowed && !tpu.alternate {
+                errors.add(NotAllowed, tpu.stake);
+            }
+            allowed
+        });
+    }
+}
+
+/// Contact infos and vote accounts of the cluster, kept from one discovery to the next with `cache_cluster`, and
+/// from one run to the next in the cache file.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct ClusterCache {
+    /// RPC and gossip entrypoint the cluster was fetched from
+    rpc_url: String,
+    gossip_entrypoint: Option<String>,
+    /// When the oldest of the contact infos and vote accounts was fetched, in seconds since the Unix epoch
+    fetched_at: Option<u64>,
+    nodes: Option<Vec<RpcContactInfo>>,
+    vote_accounts: Option<RpcVoteAccountStatus>,
+    /// Whether part of the cluster was fetched, rather than read from the cache file
+    #[serde(skip)]
+    fetched: bool,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl ClusterCache {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.nodes.is_none() && self.vote_accounts.is_none()
+    }
+
+    /// Return the cluster of the cache file of `config`, unless it is to be refreshed, if it was fetched from the
+    /// same RPC and gossip entrypoint less than `cache_ttl` ago.
+    pub(crate) fn load(config: &Config) -> Option<ClusterCache> {
+        let path = config.cache_file.as_ref().filter(|_| !config.refresh_cache)?;
+        let cache: ClusterCache = match fs::read(path).map(|content| serde_json::from_slice(&content)) {
+            Ok(Ok(cache)) => cache,
+            Ok(Err(e)) => {
+                warn!("Ignoring invalid cluster cache {}: {}", path.display(), e);
+                return None;
+            }
+            Err(_) => return None,
+        };
+        let age = unix_time().saturating_sub(cache.fetched_at?);
+        if cache.rpc_url != config.rpc_url || cache.gossip_entrypoint != config.gossip_entrypoint || age >= config.cache_ttl.as_secs() {
+            debug!("Cluster cache {} is stale or of another cluster", path.display());
+            return None;
+        }
+        debug!("Using the cluster cached in {} {} s ago", path.display(), age);
+        Some(cache)
+    }
+
+    /// Write the cluster to the cache file of `config`, if part of it was fetched since the last write. A failure
+    /// is only logged.
+    pub(crate) fn save(&mut self, config: &Config) {
+        let Some(path) = config.cache_file.as_ref().filter(|_| self.fetched) else { return };
+        self.fetched = false;
+        let written = path.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, serde_json::to_vec(self).expect("Cannot serialize cluster")));
+        if let Err(e) = written {
+            warn!("Failed to write cluster cache {}: {}", path.display(), e);
+        }
+    }
+
+    /// Record that part of the cluster was fetched with `config`, keeping the time of the oldest part.
+    fn record_fetch(&mut self, config: &Config) {
+        self.fetched = true;
+        self.fetched_at.get_or_insert_with(unix_time);
+        self.rpc_url = config.rpc_url.clone();
+        self.gossip_entrypoint = config.gossip_entrypoint.clone();
+    }
+}
+
+/// Return the address of the configured service of a node, or its gossip address shifted by `port_offset` if specified.
+fn tpu_address(ci: &RpcContactInfo, config: &Config) -> Option<SocketAddr> {
+    match config.port_offset {
+        Some(offset) => {
+            let gossip = ci.gossip?;
+            let port = u16::try_from(gossip.port() as i32 + offset).ok()?;
+            Some(SocketAddr::new(gossip.ip(), port))
+        }
+        None => match config.port_type {
+            PortType::Tpu => ci.tpu_quic,
+            PortType::TpuForwards => ci.tpu_forwards_quic,
+            PortType::Gossip => ci.gossip,
+            PortType::Rpc => ci.rpc,
+        },
+    }
+}
+
+/// Attempts of an RPC request before giving up, the pause between attempts doubling after each failure.
+const RPC_ATTEMPTS: u32 = 5;
+const RPC_BACKOFF: Duration = Duration::from_secs(1);
+/// Time after which a failed RPC request isn't retried anymore, e.g. on an unreachable endpoint
+const RPC_RETRY_TIME: Duration = Duration::from_secs(30);
+
+/// Gossip entrypoints of the public clusters, by genesis hash
+const CLUSTER_ENTRYPOINTS: [(&str, &str); 3] = [
+    ("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d", "entrypoint.mainnet-beta.solana.com:8001"),
+    ("4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY", "entrypoint.testnet.solana.com:8001"),
+    ("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG", "entrypoint.devnet.solana.com:8001"),
+];
+
+/// Run an RPC request on each of `rpc_clients` in turn until it succeeds, failing over to the next endpoint on an
+/// error or a timeout, and retrying with a growing pause once all of them failed, e.g. on rate-limited or truncated
+/// responses, for at most `RPC_RETRY_TIME`.
+///
+/// The RPC client already retries, briefly, on HTTP 429: this covers public endpoints throttling large
+/// responses for longer, or cutting them.
+///
+/// The pauses block the thread: from async code, run it with `blocking`.
+pub(crate) fn with_retries<T>(what: &'static str, rpc_clients: &[RpcClient], request: impl Fn(&RpcClient) -> ClientResult<T>) -> Result<T, Error> {
+    let mut pause = RPC_BACKOFF;
+    let start = Instant::now();
+    debug!("Requesting {} from RPC", what);
+    for attempt in 1..=RPC_ATTEMPTS {
+        for (i, rpc_client) in rpc_clients.iter().enumerate() {
+            match request(rpc_client) {
+                Ok(result) => return Ok(result),
+                Err(source) if i + 1 == rpc_clients.len() && (attempt == RPC_ATTEMPTS || start.elapsed() + pause > RPC_RETRY_TIME) => {
+                    return Err(Error::Rpc { what, source });
+                }
+                Err(e) => match rpc_clients.get(i + 1) {
+                    Some(next) => warn!("Failed to get {} from {}: {}, failing over to {}", what, rpc_client.url(), e, next.url()),
+                    None => {
+                        warn!("Failed to get {}: {}, retrying in {} s", what, e, pause.as_secs());
+                        sleep(pause);
+                        pause *= 2;
+                    }
+                },
+            }
+        }
+    }
+    unreachable!("No RPC endpoint to request {} from", what)
+}
+
+/// Run the blocking RPC and gossip requests of `requests` on the blocking threads of the runtime, off the workers
+/// driving the handshakes.
+pub(crate) async fn blocking<T: Send + 'static>(requests: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(requests).await.expect("RPC requests panicked")
+}
+
+/// Return the contact infos of the public cluster of `rpc_clients` crawled from gossip, which serves them in small
+/// responses of one partition of the gossip table each, paced by the crawler, or `None` if the cluster is not a
+/// public one.
+fn crawl_public_cluster(rpc_clients: &[RpcClient]) -> Option<Result<Vec<RpcContactInfo>, Error>> {
+    let genesis_hash = rpc_clients.iter().find_map(|rpc_client| rpc_client.get_genesis_hash().ok())?.to_string();
+    let (_, entrypoint) = CLUSTER_ENTRYPOINTS.iter().find(|(hash, _)| *hash == genesis_hash)?;
+    warn!("Falling back to crawling gossip through {}", entrypoint);
+    Some(crawl(entrypoint).map_err(|source| Error::Gossip { entrypoint: entrypoint.to_string(), source }))
+}
+
+/// Return the contact infos of the cluster, from gossip if an entrypoint is configured, otherwise from RPC, falling
+/// back to gossip on public clusters if RPC keeps failing.
+fn cluster_nodes(config: &Config, rpc_clients: &[RpcClient], cache: &mut ClusterCache) -> Result<Vec<RpcContactInfo>, Error> {
+    if let Some(nodes) = &cache.nodes {
+        return Ok(nodes.clone());
+    }
+    let nodes = match &config.gossip_entrypoint {
+        Some(entrypoint) => crawl(entrypoint).map_err(|source| Error::Gossip { entrypoint: entrypoint.clone(), source })?,
+        None => match with_retries("cluster nodes", rpc_clients, RpcClient::get_cluster_nodes) {
+            Ok(nodes) => nodes,
+            // Retrying doesn't help endpoints which always truncate or throttle the whole table
+            Err(e) => crawl_public_cluster(rpc_clients).unwrap_or(Err(e))?,
+        },
+    };
+    debug!("Got the contact infos of {} nodes", nodes.len());
+    cache.record_fetch(config);
+    cache.nodes = Some(nodes.clone());
+    Ok(nodes)
+}
+
+/// Return true if the version of `ci` matches the version requirement of `config`, if any, and `ci` matches all
+/// its selectors.
+fn version_selected(config: &Config, ci: &RpcContactInfo) -> bool {
+    config.version_req.as_ref().is_none_or(|req| {
+        ci.version.as_deref().and_then(|v| Version::parse(v).ok()).is_some_and(|v| req.matches(&v))
+    }) && config.selectors.iter().all(|selector| selector.matches(&ci.pubkey, ci.version.as_deref()))
+}
+
+/// Return true if `stake`, in lamports, is in the stake range of `config`.
+fn stake_selected(config: &Config, stake: u64) -> bool {
+    config.min_stake.is_none_or(|min| stake >= min) && config.max_stake.is_none_or(|max| stake <= max)
+}
+
+/// Return the vote accounts, with the delinquent ones if requested.
+fn vote_accounts(config: &Config, rpc_clients: &[RpcClient], cache: &mut ClusterCache) -> Result<Vec<RpcVoteAccountInfo>, Error> {
+    let vote_accounts = match &cache.vote_accounts {
+        Some(vote_accounts) => vote_accounts.clone(),
+        None => {
+            let vote_accounts = with_retries("vote accounts", rpc_clients, RpcClient::get_vote_accounts)?;
+            cache.record_fetch(config);
+            cache.vote_accounts = Some(vote_accounts.clone());
+            vote_accounts
+        }
+    };
+    let mut accounts = vote_accounts.current;
+    if config.include_delinquent {
+        accounts.extend(vote_accounts.delinquent);
+    }
+    Ok(accounts)
+}
+
+/// Keep the `top` highest-staked vote accounts, and only the highest-staked ones covering `top_pct` % of the stake.
+fn top_by_stake(mut vote_accounts: Vec<RpcVoteAccountInfo>, top: Option<usize>, top_pct: Option<f64>) -> Vec<RpcVoteAccountInfo> {
+    vote_accounts.sort_by(|a, b| b.activated_stake.cmp(&a.activated_stake));
+    let mut count = top.unwrap_or(vote_accounts.len());
+    if let Some(pct) = top_pct {
+        let target = vote_accounts.iter().map(|va| va.activated_stake as f64).sum::<f64>() * pct / 100.0;
+        let mut covered = 0.0;
+        let covering = vote_accounts.iter().take_while(|va| {
+            let below = covered < target;
+            covered += va.activated_stake as f64;
+            below
+        }).count();
+        count = count.min(covering);
+    }
+    vote_accounts.truncate(count);
+    vote_accounts
+}
+
+/// Merge the TPUs sharing an IP address into the one of the lowest port, summing their stake, for each host to be
+/// probed once.
+fn group_by_ip(tpus: HashMap<SocketAddr, Tpu>) -> HashMap<SocketAddr, Tpu> {
+    let mut addrs: Vec<SocketAddr> = tpus.keys().copied().collect();
+    addrs.sort_unstable();
+    let mut first_port: HashMap<IpAddr, SocketAddr> = HashMap::new();
+    for addr in addrs {
+        first_port.entry(addr.ip()).or_insert(addr);
+    }
+    let mut grouped: HashMap<SocketAddr, Tpu> = HashMap::new();
+    for (sock_addr, tpu) in tpus {
+        let host = grouped.entry(first_port[&sock_addr.ip()]).or_default();
+        host.stake += tpu.stake;
+        host.ids.extend(tpu.ids);
+        host.version = host.version.take().or(tpu.version);
+        host.hostname = host.hostname.take().or(tpu.hostname);
+    }
+    grouped
+}
+
+/// Resolve the requested destinations, or the whole cluster if none, into the set of TPUs to measure. The
+/// cluster is taken from `cache` if it was kept from a previous discovery or read from the cache file, and the
+/// parts fetched are added to it.
+pub(crate) fn discover(config: &Config, rpc_clients: &[RpcClient], cache: &mut ClusterCache) -> Result<Targets, Error> {
+    let destination = &config.destinations;
+    let nodes_cnt = destination.len();
+    let in_shard = |key: &str| config.shard.is_none_or(|shard| shard.contains(key));
+    let mut nodes_pk = Vec::new();
+    let mut nodes_sa = Vec::new();
+    let mut failovers = Vec::new();
+    let mut hostnames: HashMap<SocketAddr, String> = HashMap::new();
+
+    // The destinations of other shards still count, e.g. to measure the cluster if there are none at all
+    for str in destination.iter().filter(|d| in_shard(d)) {
+        if str.contains('=') {
+            let failover = str.parse::<FailoverTarget>().map_err(Error::Destination)?;
+            nodes_pk.push(failover.pubkey.clone());
+            failovers.push(failover);
+            continue;
+        }
+        match (str.parse::<SocketAddr>(), str.parse::<HostPort>()) {
+            (Ok(sock_addr), _) => {
+                nodes_sa.push(sock_addr);
+            }
+            (Err(_), Ok(host)) => {
+                let addrs = host.resolve().map_err(|e| Error::Destination(format!("Cannot resolve {}: {}", host.host, e)))?;
+                debug!("{} resolves to {:?}", host.host, addrs);
+                for sock_addr in addrs {
+                    hostnames.insert(sock_addr, host.host.clone());
+                    nodes_sa.push(sock_addr);
+                }
+            }
+            (Err(_), Err(_)) => {
+                nodes_pk.push(str.clone());
+            }
+        }
+    }
+
+    let mut tpus: HashMap<SocketAddr, Tpu> = HashMap::new();
+    let mut total_stake = 0;
+
+    let mut errors = Errors(HashMap::new());
+
+    let no_stake_weighting = if nodes_cnt == 1 {
+        true
+    } else {
+        !config.stake_weighting
+    };
+
+    // Selecting by stake needs the vote accounts
+    let top_stake = config.top_stake.is_some() || config.top_stake_pct.is_some() || config.min_stake.is_some() || config.max_stake.is_some();
+
+    match (nodes_cnt == 0, no_stake_weighting && !top_stake) {
+
+        (true, false) => {
+            let rpc_nodes = cluster_nodes(config, rpc_clients, cache)?;
+            let rpc_nodes_hash = HashMap::<String, RpcContactInfo>::from_iter(rpc_nodes.into_iter().map(|n| (n.pubkey.clone(), n)));
+            let rpc_vote_accounts: Vec<RpcVoteAccountInfo> = vote_accounts(config, rpc_clients, cache)?
+                .into_iter().filter(|va| va.activated_stake != 0).collect();
+            // The total stake of the shards adds up to the stake of the cluster
+            total_stake = rpc_vote_accounts.iter().filter(|va| in_shard(&va.node_pubkey)).map(|va| va.activated_stake).sum();
+            // The highest-staked validators are taken among the ones passing the filters
+            let selected: Vec<RpcVoteAccountInfo> = rpc_vote_accounts.into_iter()
+                .filter(|va| stake_selected(config, va.activated_stake))
+                .filter(|va| (config.version_req.is_none() && config.selectors.is_empty()) || rpc_nodes_hash.get(&va.node_pubkey).is_some_and(|ci| version_selected(config, ci)))
+                .collect();
+            for va in top_by_stake(selected, config.top_stake, config.top_stake_pct).into_iter().filter(|va| in_shard(&va.node_pubkey)) {
+                if let Some(ci) = rpc_nodes_hash.get(&va.node_pubkey) {
+                    if let Some(sock_addr) = tpu_address(ci, config) {
+                        let tpu = tpus.entry(sock_addr).or_default();
+                        tpu.add_id(ci);
+                        tpu.stake += va.activated_stake;
+                    } else {
+                        errors.add(NoTPU, va.activated_stake)
+                    }
+                } else {
+                    errors.add(NoContactInfo, va.activated_stake)
+                }
+            }
+        }
+
+        (true, true) => {
+            let rpc_nodes = cluster_nodes(config, rpc_clients, cache)?;
+            for ci in rpc_nodes.into_iter().filter(|ci| in_shard(&ci.pubkey) && version_selected(config, ci)) {
+                if let Some(sock_addr) = tpu_address(&ci, config) {
+                    let tpu = tpus.entry(sock_addr).or_default();
+                    tpu.add_id(&ci);
+                } else {
+                    errors.add(NoTPU, 0)
+                }
+            }
+        }
+
+        (false, false) => {
+            let rpc_nodes = cluster_nodes(config, rpc_clients, cache)?;
+            let rpc_vote_accounts = vote_accounts(config, rpc_clients, cache)?;
+            let rpc_pk_vote_accounts = HashMap::<String, &RpcVoteAccountInfo>::from_iter(rpc_vote_accounts.iter().map(|va| (va.node_pubkey.clone(), va)));
+            if !nodes_pk.is_empty() {
+                let rpc_pk_nodes = HashMap::<String, &RpcContactInfo>::from_iter(rpc_nodes.iter().map(|n| (n.pubkey.clone(), n)));
+                for pk in &nodes_pk {
+                    if let Some(va) = rpc_pk_vote_accounts.get(pk) {
+                        if let Some(ci) = rpc_pk_nodes.get(pk) {
+                            if !stake_selected(config, va.activated_stake) || !version_selected(config, ci) {
+                                continue;
+                            }
+                            if let Some(sock_addr) = tpu_address(ci, config) {
+                                let tpu = tpus.entry(sock_addr).or_default();
+                                tpu.add_id(ci);
+                                tpu.stake += va.activated_stake;
+                                total_stake += va.activated_stake;
+                            } else {
+                                errors.add(NoTPU, va.activated_stake)
+                            }
+                        } else {
+                            errors.add(NoContactInfo, va.activated_stake);
+                        }
+                    } else {
+                        errors.add(NotAStakedNode, 0);
+                    }
+                }
+            }
+            if !nodes_sa.is_empty() {
+                let mut rpc_addr_nodes = HashMap::<SocketAddr, Vec<&RpcContactInfo>>::new();
+                for node in &rpc_nodes {
+                    if let Some(sock_addr) = tpu_address(node, config) {
+                        rpc_addr_nodes.entry(sock_addr).or_default().push(node);
+                    }
+                }
+                for &sock_addr in &nodes_sa {
+                    let tpu = tpus.entry(sock_addr).or_default();
+                    let mut excluded = false;
+                    for ci in rpc_addr_nodes.get(&sock_addr).into_iter().flatten() {
+                        if let Some(va) = rpc_pk_vote_accounts.get(&ci.pubkey) {
+                            if !stake_selected(config, va.activated_stake) || !version_selected(config, ci) {
+                                excluded = true;
+                                continue;
+                            }
+                            tpu.add_id(ci);
+                            tpu.stake += va.activated_stake;
+                            total_stake += va.activated_stake;
+                        }
+                    }
+                    if tpu.stake == 0 {
+                        if !excluded {
+                            errors.add(NotAStakedNode, 0);
+                        }
+                        tpus.remove(&sock_addr);
+                    }
+                }
+            }
+        }
+
+        (false, true) => {
+            let rpc_nodes = cluster_nodes(config, rpc_clients, cache)?;
+            if !nodes_pk.is_empty() {
+                let rpc_pk_nodes = HashMap::<String, &RpcContactInfo>::from_iter(rpc_nodes.iter().map(|n| (n.pubkey.clone(), n)));
+                for pk in &nodes_pk {
+                    if let Some(ci) = rpc_pk_nodes.get(pk) {
+                        if !version_selected(config, ci) {
+                            continue;
+                        }
+                        if let Some(sock_addr) = tpu_address(ci, config) {
+                            let tpu = tpus.entry(sock_addr).or_default();
+                            tpu.add_id(ci);
+                        } else {
+                            errors.add(NoTPU, 0)
+                        }
+                    } else {
+                        errors.add(NoContactInfo, 0);
+                    }
+                }
+            }
+            if !nodes_sa.is_empty() {
+                let mut rpc_addr_nodes = HashMap::<SocketAddr, Vec<&RpcContactInfo>>::new();
+                for node in &rpc_nodes {
+                    if let Some(sock_addr) = tpu_address(node, config) {
+                        rpc_addr_nodes.entry(sock_addr).or_default().push(node);
+                    }
+                }
+                for &sock_addr in &nodes_sa {
+                    let tpu = tpus.entry(sock_addr).or_default();
+                    let mut excluded = false;
+                    for ci in rpc_addr_nodes.get(&sock_addr).into_iter().flatten() {
+                        if version_selected(config, ci) {
+                            tpu.add_id(ci);
+                        } else {
+                            excluded = true;
+                        }
+                    }
+                    // Addresses unknown to gossip are measured, but not the ones of filtered out identities only
+                    if excluded && tpu.ids.is_empty() {
+                        tpus.remove(&sock_addr);
+                    }
+                }
+            }
+        }
+    }
+
+    for (sock_addr, hostname) in hostnames {
+        if let Some(tpu) = tpus.get_mut(&sock_addr) {
+            tpu.hostname = Some(hostname);
+        }
+    }
+
+    if !config.rewrite.is_empty() {
+        let mut rewritten: HashMap<SocketAddr, Tpu> = HashMap::new();
+        for (sock_addr, tpu) in tpus {
+            let new_tpu = rewritten.entry(rewrite(&config.rewrite, sock_addr)).or_default();
+            new_tpu.stake += tpu.stake;
+            new_tpu.ids.extend(tpu.ids);
+            new_tpu.version = new_tpu.version.take().or(tpu.version);
+            new_tpu.hostname = new_tpu.hostname.take().or(tpu.hostname);
+        }
+        tpus = rewritten;
+    }
+
+    let mut ips: HashMap<IpAddr, usize> = HashMap::new();
+    for sock_addr in tpus.keys() {
+        *ips.entry(sock_addr.ip()).or_default() += 1;
+    }
+    let shared = ips.values().filter(|ports| **ports > 1).count();
+    if shared > 0 && config.group_by_ip {
+        debug!("Probing once each of {} IP addresses with TPUs on several ports", shared);
+        tpus = group_by_ip(tpus);
+    } else if shared > 0 {
+        warn!("{} IP addresses have TPUs on several ports, probed separately, use --group-by-ip to probe each host once", shared);
+    }
+
+    for failover in &failovers {
+        let advertised = tpus.iter_mut().find(|(_, t)| !t.alternate && t.ids.contains(&failover.pubkey));
+        let (stake, version) = match advertised {
+            Some((_, primary)) => {
+                primary.advertised = Some(true);
+                (primary.stake, primary.version.clone())
+            }
+            None => (0, None),
+        };
+        // Backups are measured even if gossip doesn't advertise the identity, e.g. during a switch
+        for &addr in &failover.addrs {
+            if tpus.contains_key(&addr) {
+                continue;
+            }
+            let tpu = Tpu { stake, ids: vec![failover.pubkey.clone()], version: version.clone(), alternate: true, advertised: Some(false), hostname: None };
+            tpus.insert(addr, tpu);
+        }
+    }
+
+    for alt in &config.alt_tpus {
+        if tpus.contains_key(&alt.addr) {
+            continue;
+        }
+        // Only measure alternate addresses of identities which are measured at their advertised address
+        if let Some(primary) = tpus.values().find(|t| !t.alternate && t.ids.contains(&alt.pubkey)) {
+            let tpu = Tpu { stake: primary.stake, ids: vec![alt.pubkey.clone()], version: primary.version.clone(), alternate: true, advertised: None, hostname: None };
+            tpus.insert(alt.addr, tpu);
+        }
+    }
+
+    let mut targets = Targets { tpus, total_stake, errors, leader_schedule: None };
+    targets.retain_allowed(&config.allow_prefix);
+    if config.leader_schedule {
+        targets.leader_schedule = Some(LeaderSchedule::fetch(rpc_clients)?);
+    }
+    Ok(targets)
+}
+
+/// Return the trace of the target requested in the configuration, if it is among the TPUs to measure.
+pub(crate) fn resolve_trace(config: &Config, targets: &Targets) -> Option<Arc<PacketTrace>> {
+    config.trace.as_ref().and_then(|target| {
+        let sock_addr = target.parse::<SocketAddr>().ok().filter(|sa| targets.tpus.contains_key(sa))
+            .or_else(|| targets.tpus.iter().find(|(_, tpu)| tpu.ids.contains(target)).map(|(sa, _)| *sa));
+        match sock_addr {
+            Some(sock_addr) => {
+                let trace = PacketTrace::new(sock_addr, config.on_trace.clone());
+                trace.line(&format!("Tracing {} at {} {:?}", target, sock_addr, targets.tpus[&sock_addr].ids));
+                Some(trace)
+            }
+            None => {
+                warn!("Trace target {} is not among measured TPUs", target);
+                None
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Targets {
+        let tpu = |stake, alternate| Tpu { stake, alternate, ..Tpu::default() };
+        Targets {
+            tpus: HashMap::from([
+                ("10.0.5.1:8009".parse().unwrap(), tpu(100, false)),
+                ("10.0.6.1:8009".parse().unwrap(), tpu(20, false)),
+                ("[2001:db8::1]:8009".parse().unwrap(), tpu(3, false)),
+                ("[2001:db9::1]:8009".parse().unwrap(), tpu(100, true)),
+            ]),
+            total_stake: 123,
+            errors: Errors(HashMap::new()),
+            leader_schedule: None,
+        }
+    }
+
+    #[test]
+    fn retains_the_allowed_tpus() {
+        let mut targets = targets();
+        targets.retain_allowed(&["10.0.5.0/24".parse().unwrap(), "2001:db8::/32".parse().unwrap()]);
+        let mut addrs = targets.tpus.keys().map(|a| a.to_string()).collect::<Vec<_>>();
+        addrs.sort();
+        assert_eq!(addrs, ["10.0.5.1:8009", "[2001:db8::1]:8009"]);
+        // The alternate address is measured elsewhere, and not counted
+        assert_eq!(targets.errors.0.get(&NotAllowed), Some(&(1, 20)));
+        assert_eq!(targets.total_stake, 123);
+    }
+
+    #[test]
+    fn retains_all_tpus_without_prefixes() {
+        let mut targets = targets();
+        targets.retain_allowed(&[]);
+        assert_eq!(targets.tpus.len(), 4);
+        assert!(targets.errors.0.is_empty());
+    }
+}
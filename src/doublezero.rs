@@ -0,0 +1,200 @@
+use crate::output::{DistanceReport, LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error as _;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// Base URL of the public Doublezero API
+pub const DEFAULT_DOUBLEZERO_URL: &str = "https://doublezero.xyz";
+/// Longest excerpt of an unexpected response body kept in errors
+const MAX_BODY_EXCERPT: usize = 200;
+/// Pause before the first retry, doubled at each retry
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// Endpoint, timeout and retries of the requests to the Doublezero API.
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    /// Base URL of the API, e.g. of a staging or self-hosted instance
+    pub url: String,
+    /// Timeout of each request, connection included
+    pub timeout: Duration,
+    /// Number of retries after a failed connection, a timeout or a server error
+    pub retries: u32,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions { url: DEFAULT_DOUBLEZERO_URL.to_string(), timeout: Duration::from_secs(10), retries: 3 }
+    }
+}
+
+/// Latest version of the response schema of the Doublezero API this version reads, responses without version
+/// being of version 1
+const API_VERSION: u32 = 1;
+
+fn default_api_version() -> u32 {
+    1
+}
+
+/// Envelope of the responses of the Doublezero API, the data being decoded once the response is known successful.
+#[derive(Deserialize)]
+struct ApiResponse {
+    #[serde(default = "default_api_version")]
+    version: u32,
+    #[serde(default)]
+    success: bool,
+    error: Option<String>,
+    message: Option<String>,
+    #[serde(default)]
+    data: Value,
+}
+
+#[derive(Deserialize)]
+struct ValidatorsData {
+    validators: Vec<ApiValidator>,
+}
+
+#[derive(Deserialize)]
+struct ApiValidator {
+    account: String,
+}
+
+fn decode_doublezero_info(dz_info: &str) -> Result<Vec<String>, Error> {
+    let response = serde_json::from_str::<ApiResponse>(dz_info).map_err(|e| Error::DoublezeroDecode(e.to_string()))?;
+    if response.version > API_VERSION {
+        return Err(Error::DoublezeroVersion { version: response.version, supported: API_VERSION });
+    }
+    if !response.success {
+        let message = response.error.or(response.message);
+        return Err(Error::DoublezeroApi(message.unwrap_or_else(|| "Unsuccessful response without error message".to_string())));
+    }
+    let data = ValidatorsData::deserialize(response.data).map_err(|e| Error::DoublezeroDecode(format!("data: {}", e)))?;
+    if data.validators.is_empty() {
+        return Err(Error::DoublezeroDecode("No validators".to_string()));
+    }
+    Ok(data.validators.into_iter().map(|v| v.account).collect())
+}
+
+async fn fetch_once(client: &reqwest::Client, url: &str) -> Result<Vec<String>, Error> {
+    let response = client.get(url).send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        let body = body.chars().take(MAX_BODY_EXCERPT).collect::<String>().trim().to_string();
+        return Err(Error::DoublezeroStatus { status: status.as_u16(), body });
+    }
+    decode_doublezero_info(&body)
+}
+
+/// Return true if a request failing with `error` may succeed when retried.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::DoublezeroRequest(_) => true,
+        Error::DoublezeroStatus { status, .. } => *status >= 500 || *status == 429,
+        _ => false,
+    }
+}
+
+/// Return the identity pubkeys of the validators connected to the Doublezero network `network`.
+///
+/// Failed connections, timeouts and server errors are retried with an exponential backoff, errors of the API
+/// and undecodable responses are returned at once.
+pub async fn fetch_validators(network: &str, options: &FetchOptions) -> Result<Vec<String>, Error> {
+    let url = format!("{}/api/dz-validators?network={}", options.url.trim_end_matches('/'), network);
+    let client = reqwest::Client::builder().timeout(options.timeout).build()?;
+    let mut pause = BACKOFF;
+    for _ in 0..options.retries {
+        match fetch_once(&client, &url).await {
+            Err(e) if is_transient(&e) => {
+                let cause = e.source().map(|s| format!(": {}", s)).unwrap_or_default();
+                warn!("Failed to fetch the validators of Doublezero network {}: {}{}, retrying in {} s", network, e, cause, pause.as_secs());
+                sleep(pause).await;
+                pause *= 2;
+            }
+            result => return result,
+        }
+    }
+    fetch_once(&client, &url).await
+}
+
+/// Measure of the validators of one Doublezero network.
+#[derive(Serialize)]
+pub struct NetworkReport {
+    pub network: String,
+    /// Number of validators listed by the Doublezero API
+    pub validators: usize,
+    pub report: DistanceReport,
+}
+
+/// A validator connected to several of the measured Doublezero networks.
+#[derive(Serialize)]
+pub struct SharedValidator {
+    pub pubkey: String,
+    pub networks: Vec<String>,
+    pub stake: u64,
+    /// Distance measured to its TPU, the same one in all the networks
+    pub distance_us: Option<u32>,
+}
+
+/// Measures of several Doublezero networks, each on its own validator set, with the validators they share.
+#[derive(Serialize)]
+pub struct NetworksReport {
+    pub schema_version: u32,
+    pub networks: Vec<NetworkReport>,
+    /// Validators in several networks, by decreasing stake
+    pub shared: Vec<SharedValidator>,
+    pub shared_stake: u64,
+}
+
+impl NetworksReport {
+    /// Gather the measures of the networks, each with the validators listed by the Doublezero API.
+    pub fn new(measures: Vec<(String, Vec<String>, DistanceReport)>) -> NetworksReport {
+        let mut networks_of: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (network, validators, _) in &measures {
+            for pubkey in validators {
+                networks_of.entry(pubkey).or_default().push(network.clone());
+            }
+        }
+        let mut shared: Vec<SharedValidator> = networks_of.into_iter()
+            .filter(|(_, networks)| networks.len() > 1)
+            .map(|(pubkey, networks)| {
+                let tpus = || measures.iter().flat_map(|(_, _, report)| &report.tpus).filter(|t| !t.alternate && t.pubkeys.iter().any(|pk| pk == pubkey));
+                SharedValidator {
+                    pubkey: pubkey.to_string(),
+                    networks,
+                    stake: tpus().map(|t| t.stake).max().unwrap_or(0),
+                    distance_us: tpus().filter(|t| t.error.is_none()).find_map(|t| t.distance_us),
+                }
+            })
+            .collect();
+        shared.sort_by(|a, b| b.stake.cmp(&a.stake));
+        let shared_stake = shared.iter().map(|v| v.stake).sum();
+        let networks = measures.into_iter()
+            .map(|(network, validators, report)| NetworkReport { network, validators: validators.len(), report })
+            .collect();
+        NetworksReport { schema_version: SCHEMA_VERSION, networks, shared, shared_stake }
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize report"));
+    }
+
+    /// Print the summary of each network, preceded by the result of each of its TPUs, or of the first `limit` ones,
+    /// if `details` is set, then the validators they share.
+    pub fn print_text(&self, details: bool, limit: Option<usize>) {
+        for network in &self.networks {
+            println!("Doublezero network {}: {} validators", network.network, network.validators);
+            network.report.print_text(details, limit);
+            println!();
+        }
+        println!("Validators in several networks: {} ({} SOL)", self.shared.len(), self.shared_stake / LAMPORTS_PER_SOL);
+        for validator in &self.shared {
+            let distance = validator.distance_us.map(|d| format!("{} µs", d)).unwrap_or_else(|| "not measured".to_string());
+            println!("  {} {} SOL, {}: {}", validator.pubkey, validator.stake / LAMPORTS_PER_SOL, distance, validator.networks.join(", "));
+        }
+    }
+}
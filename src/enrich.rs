@@ -0,0 +1,179 @@
+//! Validators given by name, e.g. `"Jito Labs"`, resolved into their identity pubkeys with the validator list of a
+//! public info API, validators.app or Stakewiz, the names being matched ignoring case, spaces and punctuation.
+
+use crate::address::HostPort;
+use crate::Error;
+use serde::Deserialize;
+use solana_pubkey::Pubkey;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Longest excerpt of an unexpected response body kept in errors
+const MAX_BODY_EXCERPT: usize = 200;
+/// List of the mainnet validators of validators.app, which requires a token
+const VALIDATORS_APP_URL: &str = "https://www.validators.app/api/v1/validators/mainnet.json?limit=10000";
+/// List of the mainnet validators of Stakewiz
+const STAKEWIZ_URL: &str = "https://api.stakewiz.com/validators";
+
+/// Public API listing the names of the validators.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InfoApi {
+    /// Stakewiz, without token
+    #[default]
+    Stakewiz,
+    /// validators.app, requiring the token of a free account
+    ValidatorsApp,
+}
+
+impl InfoApi {
+    fn name(self) -> &'static str {
+        match self {
+            InfoApi::Stakewiz => "Stakewiz",
+            InfoApi::ValidatorsApp => "validators.app",
+        }
+    }
+}
+
+/// API, token and timeout of the requests resolving validator names.
+#[derive(Clone, Debug)]
+pub struct InfoApiOptions {
+    pub api: InfoApi,
+    /// Token of the validators.app API
+    pub token: Option<String>,
+    /// Timeout of the request, connection included
+    pub timeout: Duration,
+}
+
+impl Default for InfoApiOptions {
+    fn default() -> Self {
+        InfoApiOptions { api: InfoApi::default(), token: None, timeout: Duration::from_secs(10) }
+    }
+}
+
+/// Validator of the list of an info API.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatorInfo {
+    pub name: String,
+    pub identity: String,
+}
+
+#[derive(Deserialize)]
+struct ValidatorsAppValidator {
+    name: Option<String>,
+    account: String,
+}
+
+#[derive(Deserialize)]
+struct StakewizValidator {
+    name: Option<String>,
+    identity: String,
+}
+
+/// Return true if `destination` is neither a validator pubkey, a TPU address or hostname nor a failover target, and
+/// is then the name of a validator.
+pub fn is_name(destination: &str) -> bool {
+    let destination = destination.trim();
+    !destination.is_empty() && !destination.contains('=') && destination.parse::<SocketAddr>().is_err()
+        && destination.parse::<HostPort>().is_err() && destination.parse::<Pubkey>().is_err()
+}
+
+/// Return the named validators listed by `options.api`.
+pub async fn fetch_validators(options: &InfoApiOptions) -> Result<Vec<ValidatorInfo>, Error> {
+    let api = options.api.name();
+    let request_error = |source| Error::InfoApiRequest { api, source };
+    let client = reqwest::Client::builder().timeout(options.timeout).build().map_err(request_error)?;
+    let request = match options.api {
+        InfoApi::Stakewiz => client.get(STAKEWIZ_URL),
+        InfoApi::ValidatorsApp => {
+            let token = options.token.as_deref().ok_or(Error::InfoApiToken(api))?;
+            client.get(VALIDATORS_APP_URL).header("Token", token)
+        }
+    };
+    let response = request.send().await.map_err(request_error)?;
+    let status = response.status();
+    let body = response.text().await.map_err(request_error)?;
+    if !status.is_success() {
+        let body = body.chars().take(MAX_BODY_EXCERPT).collect::<String>().trim().to_string();
+        return Err(Error::InfoApiStatus { api, status: status.as_u16(), body });
+    }
+    let decode_error = |e: serde_json::Error| Error::InfoApiDecode { api, reason: e.to_string() };
+    let validators: Vec<ValidatorInfo> = match options.api {
+        InfoApi::Stakewiz => serde_json::from_str::<Vec<StakewizValidator>>(&body).map_err(decode_error)?
+            .into_iter().filter_map(|v| Some(ValidatorInfo { name: v.name?, identity: v.identity })).collect(),
+        InfoApi::ValidatorsApp => serde_json::from_str::<Vec<ValidatorsAppValidator>>(&body).map_err(decode_error)?
+            .into_iter().filter_map(|v| Some(ValidatorInfo { name: v.name?, identity: v.account })).collect(),
+    };
+    Ok(validators.into_iter().filter(|v| !normalize(&v.name).is_empty()).collect())
+}
+
+/// Return the lowercase letters and digits of `name`.
+fn normalize(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// Return the number of insertions, deletions and substitutions of characters turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current.push((previous[j] + usize::from(ca != *cb)).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Return the validators whose name best matches `query`, ignoring case, spaces and punctuation: the ones named
+/// exactly so, else the ones whose name contains it, else the closest ones by edit distance, up to a quarter of
+/// the length of the query.
+pub fn find<'a>(query: &str, validators: &'a [ValidatorInfo]) -> Vec<&'a ValidatorInfo> {
+    let query = normalize(query);
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let names: Vec<(String, &ValidatorInfo)> = validators.iter().map(|v| (normalize(&v.name), v)).collect();
+    let exact: Vec<&ValidatorInfo> = names.iter().filter(|(name, _)| *name == query).map(|(_, v)| *v).collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+    let containing: Vec<&ValidatorInfo> = names.iter().filter(|(name, _)| name.contains(&query)).map(|(_, v)| *v).collect();
+    if !containing.is_empty() {
+        return containing;
+    }
+    let max_distance = query.chars().count() / 4;
+    let distances: Vec<(usize, &ValidatorInfo)> = names.iter().map(|(name, v)| (edit_distance(&query, name), *v)).collect();
+    let best = distances.iter().map(|(distance, _)| *distance).min().filter(|best| *best <= max_distance);
+    distances.into_iter().filter(|(distance, _)| Some(*distance) == best).map(|(_, v)| v).collect()
+}
+
+/// Replace the validator names among `destinations` by the identities of the validators matching them, fetching the
+/// list of `options.api` only if there are names. A name matching several validators is replaced by all of them.
+pub async fn resolve_names(destinations: Vec<String>, options: &InfoApiOptions) -> Result<Vec<String>, Error> {
+    if !destinations.iter().any(|d| is_name(d)) {
+        return Ok(destinations);
+    }
+    let validators = fetch_validators(options).await?;
+    let mut resolved = Vec::with_capacity(destinations.len());
+    for destination in destinations {
+        if !is_name(&destination) {
+            resolved.push(destination);
+            continue;
+        }
+        let found = find(&destination, &validators);
+        if found.is_empty() {
+            return Err(Error::UnknownValidator { name: destination, api: options.api.name() });
+        }
+        if found.len() > 1 {
+            let names: Vec<&str> = found.iter().map(|v| v.name.as_str()).collect();
+            warn!("{} matches {} validators of {}, measuring all of them: {}", destination, found.len(), options.api.name(), names.join(", "));
+        }
+        for v in found {
+            debug!("Resolved {} into {} ({})", destination, v.identity, v.name);
+            resolved.push(v.identity.clone());
+        }
+    }
+    Ok(resolved)
+}
@@ -0,0 +1,80 @@
+//! Events of the measures of a [`DistanceMeasurer`](crate::DistanceMeasurer), as an async [`Stream`], for
+//! applications embedding the library to follow a measure as it progresses, e.g. to draw their own interface or
+//! feed a pipeline, without polling.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), solana_distance::Error> {
+//! use solana_distance::events::{MeasurementEvent, Stream};
+//! use std::future::poll_fn;
+//! use std::pin::pin;
+//!
+//! let mut measurer = solana_distance::DistanceMeasurer::builder().build();
+//! let mut events = pin!(measurer.subscribe());
+//! tokio::spawn(async move { measurer.measure().await });
+//! while let Some(event) = poll_fn(|cx| events.as_mut().poll_next(cx)).await {
+//!     if let MeasurementEvent::TargetFinished(tpu) = event {
+//!         println!("{}: {:?} µs", tpu.address, tpu.distance_us);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::output::{Distance, TpuReport};
+pub use futures_core::Stream;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Step of a measure. Distances are in µs.
+#[derive(Clone)]
+pub enum MeasurementEvent {
+    /// The destinations were resolved into these TPUs, about to be measured
+    Resolved { targets: Vec<SocketAddr>, total_stake: u64 },
+    /// A connection attempt to a TPU completed, with its RTT if it succeeded. `attempt` counts from 1, and starts
+    /// again when a TPU is retried.
+    AttemptCompleted { address: SocketAddr, attempt: usize, rtt_us: Option<u32> },
+    /// The measure of a TPU completed, as passed to [`on_result`](crate::DistanceMeasurerBuilder::on_result)
+    TargetFinished(Box<TpuReport>),
+    /// The measure completed with these aggregates, the full report being returned by
+    /// [`measure`](crate::DistanceMeasurer::measure). A measure failing, e.g. when the cluster cannot be fetched,
+    /// ends without it.
+    RunFinished { cancelled: bool, connection_successful: u64, simple: Option<Distance>, stake_weighted: Option<Distance> },
+}
+
+/// Senders of the events to the subscribed streams.
+#[derive(Clone, Default)]
+pub(crate) struct EventSenders(Vec<UnboundedSender<MeasurementEvent>>);
+
+impl EventSenders {
+    pub(crate) fn subscribe(&mut self) -> MeasurementEvents {
+        let (sender, receiver) = unbounded_channel();
+        self.0.push(sender);
+        MeasurementEvents(receiver)
+    }
+
+    /// Send `event` to the streams still subscribed, building it only if there are some.
+    pub(crate) fn send(&self, event: impl FnOnce() -> MeasurementEvent) {
+        let live: Vec<_> = self.0.iter().filter(|s| !s.is_closed()).collect();
+        if let Some((last, others)) = live.split_last() {
+            let event = event();
+            for sender in others {
+                let _ = sender.send(event.clone());
+            }
+            let _ = last.send(event);
+        }
+    }
+}
+
+/// Stream of the events of the measures of a [`DistanceMeasurer`](crate::DistanceMeasurer), ending when it is
+/// dropped. Events are buffered until they are read.
+pub struct MeasurementEvents(UnboundedReceiver<MeasurementEvent>);
+
+impl Stream for MeasurementEvents {
+    type Item = MeasurementEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<MeasurementEvent>> {
+        self.0.poll_recv(cx)
+    }
+}
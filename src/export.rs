@@ -0,0 +1,90 @@
+//! Export of reports to HTTP endpoints, such as the webhooks of spreadsheet importers.
+//!
+//! Reports are flattened to one row per TPU with the same columns in both formats, so that each
+//! round of measure can be appended to a sheet as is.
+
+use crate::output::DistanceReport;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Header line followed by one line per TPU
+    Csv,
+    /// Array of flat objects, one per TPU
+    Json,
+}
+
+const COLUMNS: [&str; 11] = ["timestamp", "address", "pubkeys", "stake", "version", "client", "distance_us", "stddev_us", "min_rtt_us", "network_distance_us", "error"];
+const NUMERIC: [&str; 5] = ["stake", "distance_us", "stddev_us", "min_rtt_us", "network_distance_us"];
+
+fn rows(report: &DistanceReport) -> Vec<[String; COLUMNS.len()]> {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    report.tpus.iter().map(|tpu| [
+        report.timestamp.clone(),
+        tpu.address.to_string(),
+        tpu.pubkeys.join(" "),
+        tpu.stake.to_string(),
+        opt(tpu.version.clone()),
+        tpu.client.to_string(),
+        opt(tpu.distance_us.map(|d| d.to_string())),
+        opt(tpu.stddev_us.map(|d| d.to_string())),
+        opt(tpu.min_rtt_us.map(|d| d.to_string())),
+        opt(tpu.network_distance_us.map(|d| d.to_string())),
+        opt(tpu.error.clone()),
+    ]).collect()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Return the CSV header line.
+pub fn csv_header() -> String {
+    COLUMNS.join(",") + "\n"
+}
+
+/// Return the report as CSV lines, one per TPU, without header.
+pub fn csv_rows(report: &DistanceReport) -> String {
+    let mut csv = String::new();
+    for row in rows(report) {
+        csv += &row.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(",");
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Return the report as CSV, with a header line.
+pub fn to_csv(report: &DistanceReport) -> String {
+    csv_header() + &csv_rows(report)
+}
+
+/// Return the report as a JSON array of flat objects. Empty fields are `null`.
+pub fn to_json(report: &DistanceReport) -> String {
+    let rows: Vec<Value> = rows(report).into_iter().map(|row| {
+        let object: Map<String, Value> = COLUMNS.iter().zip(row).map(|(column, field)| {
+            let value = match field.parse::<u64>() {
+                _ if field.is_empty() => Value::Null,
+                Ok(n) if NUMERIC.contains(column) => Value::from(n),
+                _ => Value::String(field),
+            };
+            (column.to_string(), value)
+        }).collect();
+        Value::Object(object)
+    }).collect();
+    Value::Array(rows).to_string()
+}
+
+/// POST the report to `url` in `format`.
+pub async fn post(url: &str, format: ExportFormat, report: &DistanceReport) -> Result<(), reqwest::Error> {
+    let (content_type, body) = match format {
+        ExportFormat::Csv => ("text/csv", to_csv(report)),
+        ExportFormat::Json => ("application/json", to_json(report)),
+    };
+    reqwest::Client::new().post(url).header(CONTENT_TYPE, content_type).body(body).send().await?.error_for_status()?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Fallback probes of the TPUs without any successful connection, which are often validators rate-limiting
+//! unstaked QUIC connections rather than unreachable hosts: an ICMP echo, or a UDP datagram to a closed port
+//! answered by an ICMP port unreachable. Half of their shortest RTT is reported as the network distance of the
+//! host, apart from the distance to its TPU.
+
+use rand::Rng;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+/// Probe of the hosts whose TPU cannot be connected to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FallbackProbe {
+    /// No fallback probe
+    #[default]
+    None,
+    /// ICMP echo, with an unprivileged ping socket where allowed, or a raw socket with CAP_NET_RAW
+    Icmp,
+    /// UDP datagram to a port unlikely to be open, answered by an ICMP port unreachable
+    Udp,
+}
+
+/// Number of probes of a host, the shortest RTT being kept.
+const ATTEMPTS: u16 = 3;
+/// Destination port of the UDP probes, the first port of traceroute
+const UDP_PROBE_PORT: u16 = 33434;
+const PAYLOAD: &[u8] = b"solana-distance";
+
+/// Return the shortest RTT in µs of the fallback probes of `ip`, or `None` if none was answered.
+pub(crate) async fn network_rtt(probe: FallbackProbe, ip: IpAddr, timeout: Duration) -> Option<u32> {
+    let mut best = None;
+    for seq in 1..=ATTEMPTS {
+        let rtt = match probe {
+            FallbackProbe::None => return None,
+            FallbackProbe::Icmp => match tokio::task::spawn_blocking(move || icmp_echo(ip, seq, timeout)).await {
+                Ok(Ok(rtt)) => rtt,
+                Ok(Err(e)) => {
+                    debug!("ICMP echo of {} failed: {}", ip, e);
+                    return None;
+                }
+                Err(_) => None,
+            },
+            FallbackProbe::Udp => udp_probe(ip, timeout).await,
+        };
+        if let Some(rtt) = rtt {
+            best = Some(best.map_or(rtt, |best: u32| best.min(rtt)));
+        }
+    }
+    best
+}
+
+/// Return the Internet checksum of `data`.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2).map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32).sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Return an ICMP socket of `domain`, and whether it is a raw socket. Ping sockets are allowed by
+/// net.ipv4.ping_group_range, and rewrite the identifier of the echo requests.
+pub(crate) fn icmp_socket(domain: Domain) -> io::Result<(Socket, bool)> {
+    let protocol = if domain == Domain::IPV6 { Protocol::ICMPV6 } else { Protocol::ICMPV4 };
+    match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(socket) => Ok((socket, false)),
+        Err(_) => Ok((Socket::new(domain, Type::RAW, Some(protocol))?, true)),
+    }
+}
+
+/// Return the RTT in µs of an ICMP echo of `ip`, or `None` if no reply came within `timeout`.
+fn icmp_echo(ip: IpAddr, seq: u16, timeout: Duration) -> io::Result<Option<u32>> {
+    let (domain, request, reply) = match ip {
+        IpAddr::V4(_) => (Domain::IPV4, 8, 0),
+        IpAddr::V6(_) => (Domain::IPV6, 128, 129),
+    };
+    let (mut socket, raw) = icmp_socket(domain)?;
+    socket.connect(&SocketAddr::new(ip, 0).into())?;
+    let id: u16 = rand::rng().random();
+    let mut packet = [request, 0, 0, 0].to_vec();
+    packet.extend(id.to_be_bytes());
+    packet.extend(seq.to_be_bytes());
+    packet.extend(PAYLOAD);
+    // The kernel computes the checksum of ICMPv6, which covers a pseudo-header
+    if ip.is_ipv4() {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    let start = Instant::now();
+    socket.send(&packet)?;
+    let mut buf = [0; 1500];
+    loop {
+        let Some(remaining) = timeout.checked_sub(start.elapsed()).filter(|r| !r.is_zero()) else { return Ok(None) };
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // Raw IPv4 sockets receive the IP header
+        let offset = if raw && ip.is_ipv4() { (buf[0] & 0x0f) as usize * 4 } else { 0 };
+        let Some(message) = buf[..len].get(offset..offset + 8) else { continue };
+        let matches = message[0] == reply && message[6..8] == seq.to_be_bytes() && (!raw || message[4..6] == id.to_be_bytes());
+        if matches {
+            return Ok(Some(start.elapsed().as_micros().try_into().expect("rtt overflow")));
+        }
+    }
+}
+
+/// Return the RTT in µs of a UDP datagram to a closed port of `ip`, answered by an ICMP port unreachable reported
+/// on the connected socket, or by a reply if the port happens to be open.
+async fn udp_probe(ip: IpAddr, timeout: Duration) -> Option<u32> {
+    let bind: SocketAddr = if ip.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = UdpSocket::bind(bind).await.ok()?;
+    socket.connect((ip, UDP_PROBE_PORT)).await.ok()?;
+    let start = Instant::now();
+    socket.send(PAYLOAD).await.ok()?;
+    let mut buf = [0; 64];
+    match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+        _ => return None,
+    }
+    Some(start.elapsed().as_micros().try_into().expect("rtt overflow"))
+}
@@ -0,0 +1,106 @@
+//! Location and network of TPUs, from MaxMind DB files such as GeoLite2-City and GeoLite2-ASN.
+//!
+//! Reading the databases requires the `geoip` feature.
+
+use serde::Serialize;
+
+#[cfg(feature = "geoip")]
+pub use db::GeoDb;
+
+/// Location and network of an address, as far as the databases know.
+#[derive(Serialize, Clone, Default)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 code
+    pub country: Option<String>,
+    /// English name
+    pub city: Option<String>,
+    /// Approximate position of the city, in degrees
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub asn: Option<u32>,
+    pub as_organization: Option<String>,
+}
+
+impl GeoInfo {
+    /// Return the name of the AS, with its number.
+    pub fn as_name(&self) -> Option<String> {
+        Some(match &self.as_organization {
+            Some(organization) => format!("AS{} {}", self.asn?, organization),
+            None => format!("AS{}", self.asn?),
+        })
+    }
+}
+
+#[cfg(feature = "geoip")]
+mod db {
+    use super::GeoInfo;
+    use maxminddb::{MaxMindDbError, Reader};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+    use std::net::IpAddr;
+    use std::path::Path;
+
+    /// The fields used from City, Country and ASN databases, which can be decoded from any of them.
+    #[derive(Deserialize)]
+    struct Record<'a> {
+        #[serde(borrow)]
+        country: Option<Country<'a>>,
+        #[serde(borrow)]
+        city: Option<City<'a>>,
+        location: Option<Location>,
+        autonomous_system_number: Option<u32>,
+        autonomous_system_organization: Option<&'a str>,
+    }
+
+    #[derive(Deserialize)]
+    struct Country<'a> {
+        iso_code: Option<&'a str>,
+    }
+
+    #[derive(Deserialize)]
+    struct City<'a> {
+        #[serde(borrow)]
+        names: Option<BTreeMap<&'a str, &'a str>>,
+    }
+
+    #[derive(Deserialize)]
+    struct Location {
+        latitude: Option<f64>,
+        longitude: Option<f64>,
+    }
+
+    /// MaxMind databases, looked up in turn to fill each field with the first database having it.
+    #[derive(Default)]
+    pub struct GeoDb {
+        readers: Vec<Reader<Vec<u8>>>,
+    }
+
+    impl GeoDb {
+        /// Add the database at `path`, looked up after the previous ones.
+        pub fn add(&mut self, path: &Path) -> Result<(), MaxMindDbError> {
+            self.readers.push(Reader::open_readfile(path)?);
+            Ok(())
+        }
+
+        /// Return what the databases know about `ip`, or `None` if it is in none of them.
+        pub fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+            let mut info = GeoInfo::default();
+            let mut found = false;
+            for reader in &self.readers {
+                // An address missing or malformed in a database may still be in another one
+                let Ok(Some(record)) = reader.lookup(ip).and_then(|r| r.decode::<Record>()) else { continue };
+                found = true;
+                info.country = info.country.or(record.country.and_then(|c| c.iso_code).map(str::to_string));
+                info.city = info.city.or(record.city.and_then(|c| c.names?.get("en").map(|n| n.to_string())));
+                if info.latitude.is_none() {
+                    if let Some(Location { latitude: Some(latitude), longitude: Some(longitude) }) = record.location {
+                        (info.latitude, info.longitude) = (Some(latitude), Some(longitude));
+                    }
+                }
+                info.asn = info.asn.or(record.autonomous_system_number);
+                info.as_organization = info.as_organization.or(record.autonomous_system_organization.map(str::to_string));
+            }
+            found.then_some(info)
+        }
+    }
+}
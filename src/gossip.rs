@@ -0,0 +1,613 @@
+//! Read-only crawl of the gossip network, to collect contact infos without `getClusterNodes`.
+//!
+//! The crawler behaves like a spy node: it asks the entrypoint for its shred version through the
+//! ip echo server, answers its pings, and sends pull requests with empty bloom filters so that the
+//! whole gossip table is returned. Only the contact infos are decoded from pull responses; they are
+//! located by scanning the packets, and kept if their signature is valid, so other values don't
+//! need to be parsed.
+
+use sha2::{Digest, Sha256};
+use solana_keypair::{Keypair, Signer};
+use solana_pubkey::Pubkey;
+use solana_rpc_client_types::response::RpcContactInfo;
+use solana_signature::Signature;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const PACKET_DATA_SIZE: usize = 1232;
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum duration of a crawl
+const CRAWL_TIME: Duration = Duration::from_secs(10);
+/// Time waited for responses after the last request of a round
+const IDLE_TIME: Duration = Duration::from_secs(1);
+const REQUEST_SPACING: Duration = Duration::from_millis(2);
+/// Pull requests are split in 2^MASK_BITS partitions of the gossip table to limit the size of each response
+const MASK_BITS: u32 = 8;
+const BLOOM_KEYS: usize = 3;
+const BLOOM_BITS: u64 = 64;
+
+// Protocol message and CRDS value tags
+const PULL_REQUEST: u32 = 0;
+const PULL_RESPONSE: u32 = 1;
+const PING_MESSAGE: u32 = 4;
+const PONG_MESSAGE: u32 = 5;
+const CRDS_CONTACT_INFO: u32 = 11;
+
+// Socket tags of contact infos
+const SOCKET_GOSSIP: u8 = 0;
+const SOCKET_RPC: u8 = 2;
+const SOCKET_RPC_PUBSUB: u8 = 3;
+const SOCKET_SERVE_REPAIR: u8 = 4;
+const SOCKET_TPU: u8 = 5;
+const SOCKET_TPU_FORWARDS: u8 = 6;
+const SOCKET_TPU_FORWARDS_QUIC: u8 = 7;
+const SOCKET_TPU_QUIC: u8 = 8;
+const SOCKET_TPU_VOTE: u8 = 9;
+const SOCKET_TVU: u8 = 10;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_ip(buf: &mut Vec<u8>, ip: &IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            buf.extend(0u32.to_le_bytes());
+            buf.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.extend(1u32.to_le_bytes());
+            buf.extend(ip.octets());
+        }
+    }
+}
+
+/// Cursor over a bincode-encoded buffer.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let b = self.buf.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(b)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn varint(&mut self, max_bits: u32) -> Option<u64> {
+        let mut v = 0u64;
+        for shift in (0..max_bits).step_by(7) {
+            let b = self.u8()?;
+            v |= ((b & 0x7f) as u64).checked_shl(shift)?;
+            if b & 0x80 == 0 {
+                return (v.checked_shr(max_bits).unwrap_or(0) == 0).then_some(v);
+            }
+        }
+        None
+    }
+
+    /// Length of a `short_vec`, encoded on up to 3 bytes.
+    fn short_len(&mut self) -> Option<usize> {
+        let mut v = 0usize;
+        for shift in [0, 7, 14] {
+            let b = self.u8()?;
+            v |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    fn ip(&mut self) -> Option<IpAddr> {
+        match self.u32()? {
+            0 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(self.bytes(4)?).ok()?))),
+            1 => Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(self.bytes(16)?).ok()?))),
+            _ => None,
+        }
+    }
+}
+
+/// Return the public address of this host as seen by the entrypoint, and the shred version of the cluster.
+fn ip_echo(entrypoint: SocketAddr) -> io::Result<(IpAddr, Option<u16>)> {
+    let mut stream = TcpStream::connect_timeout(&entrypoint, IO_TIMEOUT)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    // 4 bytes header, no TCP nor UDP port to check, and a terminating newline
+    let mut request = vec![0u8; 4 + 2 * 4 * 2];
+    request.push(b'\n');
+    stream.write_all(&request)?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    if response.starts_with(b"HTTP") {
+        return Err(invalid("Entrypoint answered with HTTP, is it an RPC port instead of a gossip port?"));
+    }
+    let mut r = Reader { buf: &response, pos: 0 };
+    if r.bytes(4) != Some(&[0; 4]) {
+        return Err(invalid("Invalid ip echo response header"));
+    }
+    let address = r.ip().ok_or_else(|| invalid("Invalid ip echo response"))?;
+    let shred_version = match r.u8() {
+        Some(1) => r.u16(),
+        _ => None,
+    };
+    Ok((address, shred_version))
+}
+
+/// Return our signed contact info, advertising only a gossip address, as a CRDS value.
+fn contact_info(keypair: &Keypair, gossip: SocketAddr, shred_version: u16, outset: u64) -> Vec<u8> {
+    let mut data = CRDS_CONTACT_INFO.to_le_bytes().to_vec();
+    data.extend(keypair.pubkey().to_bytes());
+    put_varint(&mut data, now_ms());
+    data.extend(outset.to_le_bytes());
+    data.extend(shred_version.to_le_bytes());
+    // Version: major, minor and patch as varints, commit, feature set, and an unknown client id
+    for part in [env!("CARGO_PKG_VERSION_MAJOR"), env!("CARGO_PKG_VERSION_MINOR"), env!("CARGO_PKG_VERSION_PATCH")] {
+        put_varint(&mut data, part.parse().unwrap());
+    }
+    data.extend(0u32.to_le_bytes());
+    data.extend(0u32.to_le_bytes());
+    put_varint(&mut data, u16::MAX as u64);
+    // One address, one socket (key, address index, port offset), no extension
+    data.push(1);
+    put_ip(&mut data, &gossip.ip());
+    data.push(1);
+    data.extend([SOCKET_GOSSIP, 0]);
+    put_varint(&mut data, gossip.port() as u64);
+    data.push(0);
+
+    let mut value = keypair.sign_message(&data).as_ref().to_vec();
+    value.extend(data);
+    value
+}
+
+/// Return a pull request for the partition `index` of the gossip table, with an empty bloom filter.
+fn pull_request(index: u64, caller: &[u8]) -> Vec<u8> {
+    let mut req = PULL_REQUEST.to_le_bytes().to_vec();
+    req.extend((BLOOM_KEYS as u64).to_le_bytes());
+    for _ in 0..BLOOM_KEYS {
+        req.extend(rand::random::<u64>().to_le_bytes());
+    }
+    // Bit vector: some blocks, its length in bits, and the number of bits set
+    req.push(1);
+    req.extend((BLOOM_BITS / 64).to_le_bytes());
+    req.extend(vec![0u8; BLOOM_BITS as usize / 8]);
+    req.extend(BLOOM_BITS.to_le_bytes());
+    req.extend(0u64.to_le_bytes());
+    let mask = (index << (64 - MASK_BITS)) | (u64::MAX >> MASK_BITS);
+    req.extend(mask.to_le_bytes());
+    req.extend(MASK_BITS.to_le_bytes());
+    req.extend(caller);
+    req
+}
+
+/// Return the hash of a ping token, expected in the pong.
+fn ping_hash(token: &[u8]) -> [u8; 32] {
+    Sha256::new().chain_update(b"SOLANA_PING_PONG").chain_update(token).finalize().into()
+}
+
+/// Return a signed ping carrying `token`.
+fn ping_message(keypair: &Keypair, token: &[u8; 32]) -> Vec<u8> {
+    let mut ping = PING_MESSAGE.to_le_bytes().to_vec();
+    ping.extend(keypair.pubkey().to_bytes());
+    ping.extend(token);
+    ping.extend(keypair.sign_message(token).as_ref());
+    ping
+}
+
+fn pong(keypair: &Keypair, ping: &[u8]) -> Option<Vec<u8>> {
+    let token = ping.get(4 + 32..4 + 64)?;
+    let hash = ping_hash(token);
+    let mut pong = PONG_MESSAGE.to_le_bytes().to_vec();
+    pong.extend(keypair.pubkey().to_bytes());
+    pong.extend(hash);
+    pong.extend(keypair.sign_message(&hash[..]).as_ref());
+    Some(pong)
+}
+
+/// Decode the contact info starting after the CRDS tag, return it with its wallclock.
+fn decode_contact_info(r: &mut Reader) -> Option<(RpcContactInfo, u64)> {
+    let pubkey = Pubkey::new_from_array(r.bytes(32)?.try_into().ok()?);
+    let wallclock = r.varint(64)?;
+    let _outset = r.u64()?;
+    let shred_version = r.u16()?;
+    let major = r.varint(16)?;
+    // The two high bits of the minor version encode a pre-release
+    let minor = r.varint(16)? & 0x3fff;
+    let patch = r.varint(16)?;
+    let _commit = r.u32()?;
+    let feature_set = r.u32()?;
+    let _client = r.varint(16)?;
+    let addrs = (0..r.short_len()?).map(|_| r.ip()).collect::<Option<Vec<_>>>()?;
+    let mut sockets = HashMap::new();
+    let mut port = 0u16;
+    for _ in 0..r.short_len()? {
+        let key = r.u8()?;
+        let index = r.u8()?;
+        port = port.checked_add(r.varint(16)? as u16)?;
+        sockets.insert(key, SocketAddr::new(*addrs.get(index as usize)?, port));
+    }
+    // No extension is defined yet
+    if r.short_len()? != 0 {
+        return None;
+    }
+    let socket = |key| sockets.get(&key).copied();
+    Some((RpcContactInfo {
+        pubkey: pubkey.to_string(),
+        gossip: socket(SOCKET_GOSSIP),
+        tvu: socket(SOCKET_TVU),
+        tpu: socket(SOCKET_TPU),
+        tpu_quic: socket(SOCKET_TPU_QUIC),
+        tpu_forwards: socket(SOCKET_TPU_FORWARDS),
+        tpu_forwards_quic: socket(SOCKET_TPU_FORWARDS_QUIC),
+        tpu_vote: socket(SOCKET_TPU_VOTE),
+        serve_repair: socket(SOCKET_SERVE_REPAIR),
+        rpc: socket(SOCKET_RPC),
+        pubsub: socket(SOCKET_RPC_PUBSUB),
+        version: Some(format!("{}.{}.{}", major, minor, patch)),
+        feature_set: Some(feature_set),
+        shred_version: Some(shred_version),
+    }, wallclock))
+}
+
+/// Return the contact infos with a valid signature found in a pull response packet, which are newer than
+/// those of `known` nodes.
+fn scan_pull_response(packet: &[u8], known: &HashMap<String, (RpcContactInfo, u64)>) -> Vec<(RpcContactInfo, u64)> {
+    let mut found = Vec::new();
+    // Skip the message tag, the responder pubkey and the number of values
+    let mut start = 4 + 32 + 8;
+    while start + 64 + 4 + 32 < packet.len() {
+        let data = start + 64;
+        if packet[data..data + 4] == CRDS_CONTACT_INFO.to_le_bytes() {
+            let mut r = Reader { buf: packet, pos: data + 4 };
+            if let Some(ci) = decode_contact_info(&mut r) {
+                // Avoid verifying signatures, which is costly, of the values already received
+                if known.get(&ci.0.pubkey).is_some_and(|(_, w)| *w >= ci.1) {
+                    start = r.pos;
+                    continue;
+                }
+                let signature = Signature::from(<[u8; 64]>::try_from(&packet[start..data]).unwrap());
+                if signature.verify(&packet[data + 4..data + 4 + 32], &packet[data..r.pos]) {
+                    found.push(ci);
+                    start = r.pos;
+                    continue;
+                }
+            }
+        }
+        start += 1;
+    }
+    found
+}
+
+/// Return the RTTs in µs of `count` pings to the gossip service at `addr`, sent `interval` apart. Pings
+/// without a pong within `timeout` are lost.
+pub(crate) fn ping(addr: &str, count: usize, interval: Duration, timeout: Duration) -> io::Result<Vec<u32>> {
+    let addr = addr.to_socket_addrs()?.next().ok_or_else(|| invalid("Cannot resolve gossip address"))?;
+    let bind_ip: IpAddr = if addr.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+    let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0))?;
+    let keypair = Keypair::new();
+    let mut buf = [0u8; PACKET_DATA_SIZE];
+    let mut rtts = Vec::new();
+    let mut next_send = Instant::now();
+    for _ in 0..count {
+        std::thread::sleep(next_send.saturating_duration_since(Instant::now()));
+        next_send += interval;
+        let token: [u8; 32] = rand::random();
+        let hash = ping_hash(&token);
+        let sent = Instant::now();
+        socket.send_to(&ping_message(&keypair, &token), addr)?;
+        // Ignore other packets, e.g. the pongs of previous pings arriving late
+        while let Some(left) = timeout.checked_sub(sent.elapsed()).filter(|left| !left.is_zero()) {
+            socket.set_read_timeout(Some(left))?;
+            match socket.recv_from(&mut buf) {
+                Ok((n, from)) if from == addr && n >= 4 + 64 && buf[..4] == PONG_MESSAGE.to_le_bytes() && buf[4 + 32..4 + 64] == hash => {
+                    rtts.push(sent.elapsed().as_micros().try_into().expect("rtt overflow"));
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(rtts)
+}
+
+/// Crawl the gossip network through `entrypoint` (host:port of a gossip service), return all contact infos.
+pub(crate) fn crawl(entrypoint: &str) -> io::Result<Vec<RpcContactInfo>> {
+    let entrypoint = entrypoint.to_socket_addrs()?.next().ok_or_else(|| invalid("Cannot resolve entrypoint"))?;
+    let (public_ip, shred_version) = ip_echo(entrypoint)?;
+    let shred_version = shred_version.ok_or_else(|| invalid("Entrypoint did not report the shred version of the cluster"))?;
+
+    let bind_ip: IpAddr = if entrypoint.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+    let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0))?;
+    socket.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let gossip = SocketAddr::new(public_ip, socket.local_addr()?.port());
+    let keypair = Keypair::new();
+    let outset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+
+    let mut nodes: HashMap<String, (RpcContactInfo, u64)> = HashMap::new();
+    let mut buf = [0u8; PACKET_DATA_SIZE];
+    let start = Instant::now();
+    // Requests are sent in rounds over all partitions, spaced to avoid losses at the entrypoint, until a round
+    // brings no new node. Requests of the first round are only answered by a ping, restarting the round.
+    let mut caller = contact_info(&keypair, gossip, shred_version, outset);
+    let mut next_index = 0;
+    let mut next_send = Instant::now();
+    let mut round_end = None;
+    let mut round_new = false;
+    let mut answered = false;
+    while start.elapsed() < CRAWL_TIME {
+        if next_index < 1 << MASK_BITS && Instant::now() >= next_send {
+            socket.send_to(&pull_request(next_index, &caller), entrypoint)?;
+            next_index += 1;
+            next_send += REQUEST_SPACING;
+            if next_index == 1 << MASK_BITS {
+                round_end = Some(Instant::now() + IDLE_TIME);
+            }
+        }
+        if round_end.is_some_and(|t| Instant::now() >= t) {
+            if round_new || !answered {
+                // Refresh the contact info since values newer than the caller's wallclock are not returned
+                caller = contact_info(&keypair, gossip, shred_version, outset);
+                next_index = 0;
+                next_send = Instant::now();
+                round_end = None;
+                round_new = false;
+            } else {
+                break;
+            }
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((n, from)) if from == entrypoint && n >= 4 => {
+                let packet = &buf[..n];
+                match u32::from_le_bytes(packet[..4].try_into().unwrap()) {
+                    PING_MESSAGE => {
+                        if let Some(pong) = pong(&keypair, packet) {
+                            socket.send_to(&pong, entrypoint)?;
+                            if !answered {
+                                next_index = 0;
+                                next_send = Instant::now();
+                                round_end = None;
+                            }
+                        }
+                    }
+                    PULL_RESPONSE => {
+                        answered = true;
+                        for (ci, wallclock) in scan_pull_response(packet, &nodes) {
+                            if ci.shred_version != Some(shred_version) {
+                                continue;
+                            }
+                            match nodes.get(&ci.pubkey) {
+                                Some((_, w)) if *w >= wallclock => {}
+                                known => {
+                                    round_new |= known.is_none();
+                                    nodes.insert(ci.pubkey.clone(), (ci, wallclock));
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(nodes.into_values().map(|(ci, _)| ci).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a contact info as a CRDS value, from its tag, with a pre-release version 2.3.4.
+    fn contact_info_data(pubkey: &Pubkey, wallclock: u64, addrs: &[IpAddr], sockets: &[(u8, u8, u16)]) -> Vec<u8> {
+        let mut data = CRDS_CONTACT_INFO.to_le_bytes().to_vec();
+        data.extend(pubkey.to_bytes());
+        put_varint(&mut data, wallclock);
+        data.extend(7u64.to_le_bytes());
+        data.extend(42u16.to_le_bytes());
+        for part in [2, 0x4000 | 3, 4] {
+            put_varint(&mut data, part);
+        }
+        data.extend(0u32.to_le_bytes());
+        data.extend(0xdead_beefu32.to_le_bytes());
+        put_varint(&mut data, 3);
+        data.push(addrs.len() as u8);
+        for ip in addrs {
+            put_ip(&mut data, ip);
+        }
+        data.push(sockets.len() as u8);
+        for &(key, index, offset) in sockets {
+            data.extend([key, index]);
+            put_varint(&mut data, offset as u64);
+        }
+        data.push(0);
+        data
+    }
+
+    fn signed(keypair: &Keypair, data: &[u8]) -> Vec<u8> {
+        let mut value = keypair.sign_message(data).as_ref().to_vec();
+        value.extend(data);
+        value
+    }
+
+    /// Return a pull response from a random responder carrying `values`.
+    fn pull_response(values: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = PULL_RESPONSE.to_le_bytes().to_vec();
+        packet.extend(Pubkey::new_unique().to_bytes());
+        packet.extend((values.len() as u64).to_le_bytes());
+        for value in values {
+            packet.extend(value);
+        }
+        packet
+    }
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+    #[test]
+    fn reader_reads_little_endian_and_stops_at_the_end() {
+        let buf = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let mut r = Reader { buf: &buf, pos: 0 };
+        assert_eq!(r.u8(), Some(1));
+        assert_eq!(r.u16(), Some(0x0302));
+        assert_eq!(r.u32(), Some(0x0706_0504));
+        assert_eq!(r.u64(), None);
+        assert_eq!(r.pos, 7);
+        assert_eq!(r.bytes(2), Some(&[8, 9][..]));
+        assert_eq!(r.u8(), None);
+        assert_eq!(r.bytes(usize::MAX), None);
+    }
+
+    #[test]
+    fn reader_decodes_varints() {
+        for v in [0, 1, 127, 128, 300, u16::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, v);
+            assert_eq!(Reader { buf: &buf, pos: 0 }.varint(64), Some(v));
+        }
+        let mut buf = Vec::new();
+        put_varint(&mut buf, u16::MAX as u64 + 1);
+        assert_eq!(Reader { buf: &buf, pos: 0 }.varint(16), None);
+        // Continuation bit on the last byte
+        assert_eq!(Reader { buf: &[0x80, 0x80], pos: 0 }.varint(64), None);
+        assert_eq!(Reader { buf: &[0x80, 0x01], pos: 0 }.short_len(), Some(128));
+        assert_eq!(Reader { buf: &[0x80, 0x80, 0x80], pos: 0 }.short_len(), None);
+    }
+
+    #[test]
+    fn reader_decodes_ips() {
+        for ip in [V4, V6] {
+            let mut buf = Vec::new();
+            put_ip(&mut buf, &ip);
+            assert_eq!(Reader { buf: &buf, pos: 0 }.ip(), Some(ip));
+            assert_eq!(Reader { buf: &buf[..buf.len() - 1], pos: 0 }.ip(), None);
+        }
+        let mut buf = 2u32.to_le_bytes().to_vec();
+        buf.extend([0; 16]);
+        assert_eq!(Reader { buf: &buf, pos: 0 }.ip(), None);
+    }
+
+    #[test]
+    fn decodes_contact_info() {
+        let pubkey = Pubkey::new_unique();
+        let sockets = [(SOCKET_GOSSIP, 0, 8001), (SOCKET_TPU, 1, 2), (SOCKET_TPU_QUIC, 1, 6)];
+        let data = contact_info_data(&pubkey, 1_700_000_000_000, &[V4, V6], &sockets);
+        let mut r = Reader { buf: &data, pos: 4 };
+        let (ci, wallclock) = decode_contact_info(&mut r).unwrap();
+        assert_eq!(r.pos, data.len());
+        assert_eq!(wallclock, 1_700_000_000_000);
+        assert_eq!(ci.pubkey, pubkey.to_string());
+        assert_eq!(ci.gossip, Some(SocketAddr::new(V4, 8001)));
+        assert_eq!(ci.tpu, Some(SocketAddr::new(V6, 8003)));
+        assert_eq!(ci.tpu_quic, Some(SocketAddr::new(V6, 8009)));
+        assert_eq!(ci.rpc, None);
+        assert_eq!(ci.version.as_deref(), Some("2.3.4"));
+        assert_eq!(ci.feature_set, Some(0xdead_beef));
+        assert_eq!(ci.shred_version, Some(42));
+    }
+
+    #[test]
+    fn rejects_truncated_contact_info() {
+        let data = contact_info_data(&Pubkey::new_unique(), 1, &[V4], &[(SOCKET_GOSSIP, 0, 8001)]);
+        for len in 4..data.len() {
+            assert!(decode_contact_info(&mut Reader { buf: &data[..len], pos: 4 }).is_none(), "decoded {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_contact_info() {
+        // Socket on a missing address
+        let data = contact_info_data(&Pubkey::new_unique(), 1, &[V4], &[(SOCKET_GOSSIP, 1, 8001)]);
+        assert!(decode_contact_info(&mut Reader { buf: &data, pos: 4 }).is_none());
+        // Port overflow
+        let data = contact_info_data(&Pubkey::new_unique(), 1, &[V4], &[(SOCKET_GOSSIP, 0, 65535), (SOCKET_TPU, 0, 1)]);
+        assert!(decode_contact_info(&mut Reader { buf: &data, pos: 4 }).is_none());
+        // Unknown extension
+        let mut data = contact_info_data(&Pubkey::new_unique(), 1, &[V4], &[]);
+        *data.last_mut().unwrap() = 1;
+        data.push(0);
+        assert!(decode_contact_info(&mut Reader { buf: &data, pos: 4 }).is_none());
+    }
+
+    #[test]
+    fn scans_signed_contact_infos_among_other_values() {
+        let (first, second, forged) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let ci = |keypair: &Keypair, wallclock| contact_info_data(&keypair.pubkey(), wallclock, &[V4], &[(SOCKET_TPU_QUIC, 0, 8009)]);
+        let mut unknown = vec![0u8; 64];
+        unknown.extend(99u32.to_le_bytes());
+        unknown.extend([0u8; 40]);
+        // Signed by another key than the one of the contact info
+        let invalid = signed(&first, &ci(&forged, 5));
+        let packet = pull_response(&[unknown, signed(&first, &ci(&first, 5)), invalid, signed(&second, &ci(&second, 6))]);
+
+        let found = scan_pull_response(&packet, &HashMap::new());
+        let pubkeys: Vec<_> = found.iter().map(|(ci, wallclock)| (ci.pubkey.clone(), *wallclock)).collect();
+        assert_eq!(pubkeys, [(first.pubkey().to_string(), 5), (second.pubkey().to_string(), 6)]);
+
+        // Values not newer than the known ones are skipped
+        let known = found.into_iter().map(|(ci, wallclock)| (ci.pubkey.clone(), (ci, wallclock))).collect();
+        assert!(scan_pull_response(&packet, &known).is_empty());
+    }
+
+    #[test]
+    fn scans_truncated_pull_response() {
+        let keypair = Keypair::new();
+        let value = signed(&keypair, &contact_info_data(&keypair.pubkey(), 1, &[V4], &[(SOCKET_GOSSIP, 0, 8001)]));
+        let packet = pull_response(&[value]);
+        assert_eq!(scan_pull_response(&packet, &HashMap::new()).len(), 1);
+        for len in 0..packet.len() {
+            assert!(scan_pull_response(&packet[..len], &HashMap::new()).is_empty());
+        }
+    }
+
+    #[test]
+    fn answers_pings() {
+        let (ours, theirs) = (Keypair::new(), Keypair::new());
+        let token = [7u8; 32];
+        let ping = ping_message(&theirs, &token);
+        assert_eq!(ping[..4], PING_MESSAGE.to_le_bytes());
+        let answer = pong(&ours, &ping).unwrap();
+        assert_eq!(answer[..4], PONG_MESSAGE.to_le_bytes());
+        assert_eq!(answer[4..4 + 32], ours.pubkey().to_bytes());
+        assert_eq!(answer[4 + 32..4 + 64], ping_hash(&token));
+        let signature = Signature::from(<[u8; 64]>::try_from(&answer[4 + 64..]).unwrap());
+        assert!(signature.verify(&ours.pubkey().to_bytes(), &ping_hash(&token)));
+        assert!(pong(&ours, &ping[..4 + 63]).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+//! Thresholds of a health check, turning the result of a measure into the exit status of the process, for cron
+//! jobs, Nagios checks or systemd units to consume without parsing the output.
+
+use crate::output::{DistanceReport, TpuReport};
+use crate::NodeError::{Cancelled, ConnectionError, ConnectionFailed};
+
+/// Exit status of a measure exceeding a threshold, CRITICAL for Nagios. Failing runs exit with 1.
+pub const EXIT_THRESHOLD: i32 = 2;
+
+/// Average distance compared to the distance threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum DistanceMetric {
+    Simple,
+    StakeWeighted,
+}
+
+/// Limits of a healthy measure, unchecked when `None`.
+#[derive(Clone, Debug)]
+pub struct Thresholds {
+    pub distance_us: Option<u64>,
+    pub metric: DistanceMetric,
+    /// Highest share of the measured TPUs without any successful connection, in %
+    pub failure_pct: Option<f64>,
+}
+
+impl Thresholds {
+    /// Return a description of each threshold exceeded by `report`, empty if it is healthy. A measure without
+    /// the distance to check, e.g. without any successful connection, exceeds the distance threshold.
+    pub fn exceeded(&self, report: &DistanceReport) -> Vec<String> {
+        let mut exceeded = Vec::new();
+        if let Some(threshold) = self.distance_us {
+            let (label, distance) = match self.metric {
+                DistanceMetric::Simple => ("Simple distance", &report.simple),
+                DistanceMetric::StakeWeighted => ("Stake-weighted distance", &report.stake_weighted),
+            };
+            match distance {
+                Some(distance) if distance.distance_us > threshold => {
+                    exceeded.push(format!("{} {} µs is above {} µs", label, distance.distance_us, threshold));
+                }
+                Some(_) => (),
+                None => exceeded.push(format!("{} not measured, above {} µs", label, threshold)),
+            }
+        }
+        if let Some(threshold) = self.failure_pct {
+            let rate = failure_rate(report);
+            if rate > threshold {
+                exceeded.push(format!("Connection failure rate {:.1}% is above {}%", rate, threshold));
+            }
+        }
+        exceeded
+    }
+}
+
+/// Return the share of the measured TPUs, alternate addresses and cancelled TPUs excluded, without any
+/// successful connection, in %.
+pub fn failure_rate(report: &DistanceReport) -> f64 {
+    let cancelled = Cancelled.to_string();
+    let measured: Vec<_> = report.tpus.iter().filter(|t| !t.alternate && t.error.as_ref() != Some(&cancelled)).collect();
+    if measured.is_empty() {
+        return 0.0;
+    }
+    let failures = measured.iter().filter(|t| is_failure(t)).count();
+    failures as f64 * 100.0 / measured.len() as f64
+}
+
+/// Return true if no connection to `tpu` succeeded.
+pub fn is_failure(tpu: &TpuReport) -> bool {
+    let failed = [ConnectionFailed.to_string(), ConnectionError.to_string()];
+    tpu.error.as_ref().is_some_and(|e| failed.contains(e))
+}
@@ -0,0 +1,107 @@
+//! Commands run on the result of each TPU as soon as it is measured, for custom reactions during a measure.
+
+use crate::output::TpuReport;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tracing::warn;
+
+/// Shell running the commands, and its option taking a command line
+#[cfg(windows)]
+pub(crate) const SHELL: [&str; 2] = ["cmd", "/C"];
+#[cfg(not(windows))]
+pub(crate) const SHELL: [&str; 2] = ["sh", "-c"];
+
+/// Commands running at once, the results of the following TPUs waiting in a queue for one of them to exit
+pub const MAX_RUNNING: usize = 16;
+
+/// Result of a TPU waiting for its command to run
+struct Job {
+    address: SocketAddr,
+    env: Vec<(&'static str, String)>,
+    json: String,
+}
+
+/// Runner of a command on the result of each TPU, with at most a given number of commands running at once.
+#[derive(Debug)]
+pub struct Hooks {
+    sender: Mutex<Option<Sender<Job>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Hooks {
+    /// Start `running` threads running `command` with the shell, one result after the other.
+    pub fn new(command: String, running: usize) -> Hooks {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let (command, receiver) = (Arc::new(command), Arc::new(Mutex::new(receiver)));
+        let workers = (0..running).map(|_| {
+            let (command, receiver) = (command.clone(), receiver.clone());
+            thread::spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    Ok(job) => run_job(&command, job),
+                    Err(_) => return,
+                }
+            })
+        }).collect();
+        Hooks { sender: Mutex::new(Some(sender)), workers: Mutex::new(workers) }
+    }
+
+    /// Queue the command for `tpu`, passing it as JSON on stdin and as `SOLANA_DISTANCE_*` environment variables.
+    ///
+    /// The command runs in the background: the measure doesn't wait for it, and a failure is only reported on stderr.
+    pub fn run(&self, tpu: &TpuReport) {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        let env = vec![
+            ("SOLANA_DISTANCE_ADDRESS", tpu.address.to_string()),
+            ("SOLANA_DISTANCE_PUBKEYS", tpu.pubkeys.join(" ")),
+            ("SOLANA_DISTANCE_STAKE", tpu.stake.to_string()),
+            ("SOLANA_DISTANCE_VERSION", opt(tpu.version.clone())),
+            ("SOLANA_DISTANCE_CLIENT", tpu.client.to_string()),
+            ("SOLANA_DISTANCE_DISTANCE_US", opt(tpu.distance_us.map(|d| d.to_string()))),
+            ("SOLANA_DISTANCE_STDDEV_US", opt(tpu.stddev_us.map(|d| d.to_string()))),
+            ("SOLANA_DISTANCE_MIN_RTT_US", opt(tpu.min_rtt_us.map(|d| d.to_string()))),
+            ("SOLANA_DISTANCE_ERROR", opt(tpu.error.clone())),
+            ("SOLANA_DISTANCE_ALTERNATE", tpu.alternate.to_string()),
+            ("SOLANA_DISTANCE_ADVERTISED", opt(tpu.advertised.map(|a| a.to_string()))),
+        ];
+        let json = serde_json::to_string(tpu).expect("Cannot serialize result") + "\n";
+        match self.sender.lock().unwrap().as_ref() {
+            Some(sender) => sender.send(Job { address: tpu.address, env, json }).expect("Hook threads exited"),
+            None => warn!("Not running command for {}, the commands are being waited for", tpu.address),
+        }
+    }
+
+    /// Stop accepting results, and wait for the queued and running commands to exit.
+    pub fn wait(&self) {
+        drop(self.sender.lock().unwrap().take());
+        for worker in self.workers.lock().unwrap().drain(..) {
+            if worker.join().is_err() {
+                warn!("A command thread panicked");
+            }
+        }
+    }
+}
+
+/// Run `command` with the shell on the result of `job`, and wait for it to exit.
+fn run_job(command: &str, job: Job) {
+    let mut child = match Command::new(SHELL[0]).arg(SHELL[1]).arg(command).envs(job.env).stdin(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run {}: {}", command, e);
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command may exit without reading its input
+        let _ = stdin.write_all(job.json.as_bytes());
+    }
+    match child.wait() {
+        Ok(status) if !status.success() => warn!("Command failed for {}: {}", job.address, status),
+        Ok(_) => (),
+        Err(e) => warn!("Failed to wait for command run for {}: {}", job.address, e),
+    }
+}
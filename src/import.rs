@@ -0,0 +1,156 @@
+//! Latencies measured by other tools, e.g. smokeping or the portal of a provider, keyed by IP and merged into the
+//! report next to the distances of this tool, to cross-validate them in one document.
+//!
+//! A CSV file starts with a header line naming its columns: `ip`, and `distance_us`, `rtt_us` or `rtt_ms`, round
+//! trips being halved into distances, other columns being ignored. A JSON file is an array of objects with the same
+//! fields.
+
+use crate::output::TpuReport;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+/// Columns of a latency, with the factor turning them into a distance in µs, in order of preference
+const LATENCY_FIELDS: [(&str, f64); 3] = [("distance_us", 1.0), ("rtt_us", 0.5), ("rtt_ms", 500.0)];
+
+fn invalid_data(error: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.into())
+}
+
+/// Distances to IP addresses measured by another tool, in µs.
+pub struct ExternalLatencies {
+    /// Name of the file, without extension
+    source: String,
+    distances: HashMap<IpAddr, f64>,
+}
+
+impl ExternalLatencies {
+    /// Read a JSON file if its name ends in .json, a CSV file otherwise.
+    pub fn load(path: &Path) -> io::Result<ExternalLatencies> {
+        let content = fs::read_to_string(path)?;
+        let rows = match path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            true => json_rows(&content)?,
+            false => csv_rows(&content)?,
+        };
+        let mut distances = HashMap::new();
+        for (ip, field, value) in rows {
+            let ip = ip.parse::<IpAddr>().map_err(|e| invalid_data(format!("Invalid IP {}: {}", ip, e)))?;
+            let factor = LATENCY_FIELDS.iter().find(|(name, _)| *name == field).expect("Known latency field").1;
+            if !value.is_finite() || value < 0.0 {
+                return Err(invalid_data(format!("Invalid latency of {}: {}", ip, value)));
+            }
+            distances.insert(ip.to_canonical(), value * factor);
+        }
+        let source = path.file_stem().map_or_else(|| path.display().to_string(), |s| s.to_string_lossy().to_string());
+        Ok(ExternalLatencies { source, distances })
+    }
+
+    /// Return the imported distance of each TPU of `tpus` at an imported IP, alternate addresses included, with the
+    /// difference to the measured distance.
+    pub fn compare(&self, tpus: &[TpuReport]) -> ImportReport {
+        let mut imported: Vec<ImportedTpu> = tpus.iter().filter_map(|tpu| {
+            let imported_us = *self.distances.get(&tpu.address.ip().to_canonical())? as u32;
+            let distance_us = tpu.distance_us.filter(|_| tpu.error.is_none());
+            Some(ImportedTpu {
+                address: tpu.address,
+                pubkeys: tpu.pubkeys.clone(),
+                distance_us,
+                imported_us,
+                difference_us: distance_us.map(|d| imported_us as i64 - d as i64),
+            })
+        }).collect();
+        imported.sort_by_key(|t| t.address);
+        let mut differences: Vec<i64> = imported.iter().filter_map(|t| t.difference_us).collect();
+        differences.sort_unstable();
+        ImportReport {
+            source: self.source.clone(),
+            entries: self.distances.len(),
+            matched: imported.len(),
+            compared: differences.len(),
+            mean_difference_us: (!differences.is_empty()).then(|| differences.iter().sum::<i64>() / differences.len() as i64),
+            median_difference_us: differences.get(differences.len() / 2).copied(),
+            tpus: imported,
+        }
+    }
+}
+
+/// Return the IP, latency field and latency of each row of a CSV file.
+fn csv_rows(content: &str) -> io::Result<Vec<(String, &'static str, f64)>> {
+    let mut lines = content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    let header: Vec<String> = lines.next().ok_or_else(|| invalid_data("Missing CSV header"))?
+        .split(',').map(|c| c.trim().trim_matches('"').to_ascii_lowercase()).collect();
+    let column = |name: &str| header.iter().position(|c| c == name);
+    let ip_column = column("ip").ok_or_else(|| invalid_data("Missing ip column"))?;
+    let (latency_column, field) = LATENCY_FIELDS.iter().find_map(|(name, _)| Some((column(name)?, *name)))
+        .ok_or_else(|| invalid_data("Missing distance_us, rtt_us or rtt_ms column"))?;
+    lines.map(|line| {
+        let cells: Vec<&str> = line.split(',').map(|c| c.trim().trim_matches('"')).collect();
+        let (Some(ip), Some(latency)) = (cells.get(ip_column), cells.get(latency_column)) else {
+            return Err(invalid_data(format!("Invalid CSV line: {}", line)));
+        };
+        let latency = latency.parse::<f64>().map_err(|e| invalid_data(format!("Invalid latency in {}: {}", line, e)))?;
+        Ok((ip.to_string(), field, latency))
+    }).collect()
+}
+
+/// Return the IP, latency field and latency of each object of a JSON array.
+fn json_rows(content: &str) -> io::Result<Vec<(String, &'static str, f64)>> {
+    let rows: Vec<Value> = serde_json::from_str(content).map_err(|e| invalid_data(e.to_string()))?;
+    rows.iter().map(|row| {
+        let ip = row.get("ip").and_then(Value::as_str).ok_or_else(|| invalid_data(format!("Missing ip in {}", row)))?;
+        let (field, latency) = LATENCY_FIELDS.iter().find_map(|(name, _)| Some((*name, row.get(*name)?.as_f64()?)))
+            .ok_or_else(|| invalid_data(format!("Missing distance_us, rtt_us or rtt_ms in {}", row)))?;
+        Ok((ip.to_string(), field, latency))
+    }).collect()
+}
+
+/// Imported distance of a measured TPU, in µs.
+#[derive(Serialize)]
+pub struct ImportedTpu {
+    pub address: SocketAddr,
+    pub pubkeys: Vec<String>,
+    /// Distance measured by this tool, `None` if the TPU failed
+    pub distance_us: Option<u32>,
+    pub imported_us: u32,
+    /// Imported minus measured distance
+    pub difference_us: Option<i64>,
+}
+
+/// Comparison of the distances of a file with the measured ones, in µs.
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub source: String,
+    /// Addresses in the file
+    pub entries: usize,
+    /// Measured TPUs at an address of the file
+    pub matched: usize,
+    /// Matched TPUs with a measured distance
+    pub compared: usize,
+    /// Difference of the imported and measured distances, over the compared TPUs
+    pub mean_difference_us: Option<i64>,
+    pub median_difference_us: Option<i64>,
+    pub tpus: Vec<ImportedTpu>,
+}
+
+impl ImportReport {
+    pub(crate) fn print_text(&self, details: bool) {
+        print!("Imported from {}: {} addresses, {} measured TPUs matched, {} compared", self.source, self.entries, self.matched, self.compared);
+        match (self.mean_difference_us, self.median_difference_us) {
+            (Some(mean), Some(median)) => println!(", difference {:+} µs on average, {:+} µs median", mean, median),
+            _ => println!(),
+        }
+        if !details {
+            return;
+        }
+        for tpu in &self.tpus {
+            match (tpu.distance_us, tpu.difference_us) {
+                (Some(distance), Some(difference)) => println!("  {:21} imported {} µs, measured {} µs ({:+} µs)", tpu.address, tpu.imported_us, distance, difference),
+                _ => println!("  {:21} imported {} µs, not measured", tpu.address, tpu.imported_us),
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! InfluxDB line protocol of the reports, for Telegraf and InfluxDB pipelines: one point per TPU, tagged with its
+//! identity, address, AS and Doublezero membership, and one point of aggregates per measure.
+
+use crate::health::failure_rate;
+use crate::output::{DistanceReport, TpuReport};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use std::fmt::Display;
+use std::time::UNIX_EPOCH;
+
+/// Measurement of the points of the TPUs
+const TPU_MEASUREMENT: &str = "solana_distance_tpu";
+/// Measurement of the points of the aggregates
+const MEASURE_MEASUREMENT: &str = "solana_distance";
+
+/// Escape the commas, equal signs and spaces of a tag value.
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// Return a string field value, quoted.
+fn string_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Line of a point, without the tags and fields left out. A point without field has no line.
+fn line(measurement: &str, tags: &[(&str, Option<String>)], fields: &[(&str, Option<String>)], timestamp_ns: u128) -> String {
+    let tags: String = tags.iter().filter_map(|(key, value)| Some(format!(",{}={}", key, escape_tag(value.as_deref().filter(|v| !v.is_empty())?)))).collect();
+    let fields: Vec<String> = fields.iter().filter_map(|(key, value)| Some(format!("{}={}", key, value.as_ref()?))).collect();
+    match fields.is_empty() {
+        true => String::new(),
+        false => format!("{}{} {} {}\n", measurement, tags, fields.join(","), timestamp_ns),
+    }
+}
+
+/// Return an integer field value.
+fn integer(value: impl Display) -> Option<String> {
+    Some(format!("{}i", value))
+}
+
+fn tpu_line(tpu: &TpuReport, host: Option<&String>, timestamp_ns: u128) -> String {
+    let tags = [
+        ("pubkey", tpu.pubkeys.first().cloned()),
+        ("ip", Some(tpu.address.ip().to_string())),
+        ("port", Some(tpu.address.port().to_string())),
+        ("asn", tpu.geo.as_ref().and_then(|g| g.asn).map(|asn| asn.to_string())),
+        ("dz", tpu.doublezero.map(|dz| dz.to_string())),
+        ("client", Some(tpu.client.to_string())),
+        ("alternate", tpu.alternate.then(|| "true".to_string())),
+        ("host", host.cloned()),
+    ];
+    let fields = [
+        ("stake", integer(tpu.stake)),
+        ("distance_us", tpu.distance_us.and_then(integer)),
+        ("stddev_us", tpu.stddev_us.and_then(integer)),
+        ("min_rtt_us", tpu.min_rtt_us.and_then(integer)),
+        ("network_distance_us", tpu.network_distance_us.and_then(integer)),
+        ("version", tpu.version.as_deref().map(string_field)),
+        ("error", tpu.error.as_deref().map(string_field)),
+    ];
+    line(TPU_MEASUREMENT, &tags, &fields, timestamp_ns)
+}
+
+fn measure_line(report: &DistanceReport, timestamp_ns: u128) -> String {
+    let tags = [("host", report.metadata.hostname.clone())];
+    let fields = [
+        ("simple_distance_us", report.simple.as_ref().and_then(|d| integer(d.distance_us))),
+        ("simple_stddev_us", report.simple.as_ref().and_then(|d| d.stddev_us).and_then(integer)),
+        ("stake_weighted_distance_us", report.stake_weighted.as_ref().and_then(|d| integer(d.distance_us))),
+        ("stake_weighted_stddev_us", report.stake_weighted.as_ref().and_then(|d| d.stddev_us).and_then(integer)),
+        ("stake_p50_us", report.stake_percentiles.as_ref().and_then(|p| integer(p.p50_us))),
+        ("stake_p90_us", report.stake_percentiles.as_ref().and_then(|p| integer(p.p90_us))),
+        ("stake_p99_us", report.stake_percentiles.as_ref().and_then(|p| integer(p.p99_us))),
+        ("connection_successful", integer(report.connection_successful)),
+        ("failure_rate_pct", Some(failure_rate(report).to_string())),
+        ("measured_stake", integer(report.measured_stake)),
+        ("total_stake", integer(report.total_stake)),
+        ("epoch", report.metadata.epoch.and_then(integer)),
+        ("slot", report.metadata.slot.and_then(integer)),
+    ];
+    line(MEASURE_MEASUREMENT, &tags, &fields, timestamp_ns)
+}
+
+/// Return the report as line protocol, one point per TPU followed by the point of the aggregates, timestamped with
+/// the start of the measure in ns.
+pub fn to_lines(report: &DistanceReport) -> String {
+    let timestamp_ns = humantime::parse_rfc3339(&report.timestamp).ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |t| t.as_nanos());
+    let host = report.metadata.hostname.as_ref();
+    let mut lines: String = report.tpus.iter().map(|tpu| tpu_line(tpu, host, timestamp_ns)).collect();
+    lines += &measure_line(report, timestamp_ns);
+    lines
+}
+
+/// Write the points of the report to the write endpoint `url` of InfluxDB, e.g.
+/// `http://localhost:8086/api/v2/write?org=ORG&bucket=BUCKET`, with the API `token` if any.
+pub async fn write(url: &str, token: Option<&str>, report: &DistanceReport) -> Result<(), reqwest::Error> {
+    let mut request = reqwest::Client::new().post(url).header(CONTENT_TYPE, "text/plain; charset=utf-8");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Token {}", token));
+    }
+    request.body(to_lines(report)).send().await?.error_for_status()?;
+    Ok(())
+}
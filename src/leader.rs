@@ -0,0 +1,88 @@
+use crate::cancel::Cancellation;
+use crate::discovery::{blocking, with_retries};
+use crate::Error;
+use solana_commitment_config::CommitmentConfig;
+use solana_rpc_client::rpc_client::RpcClient;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+
+/// Nominal duration of a slot
+const SLOT: Duration = Duration::from_millis(400);
+
+/// Wait until the current slot is more than `margin` slots away from an epoch boundary, where stakes and leader
+/// schedules change, or until cancelled.
+pub(crate) async fn avoid_epoch_boundary(rpc_clients: &Arc<[RpcClient]>, margin: u64, cancellation: &Cancellation) -> Result<(), Error> {
+    loop {
+        let clients = rpc_clients.clone();
+        let epoch = blocking(move || with_retries("epoch info", &clients, |c| c.get_epoch_info_with_commitment(CommitmentConfig::processed()))).await?;
+        let remaining = epoch.slots_in_epoch - epoch.slot_index;
+        // Slots to wait for until `margin` slots into the next or current epoch
+        let wait = match (remaining <= margin, epoch.slot_index < margin) {
+            (true, _) => remaining + margin,
+            (false, true) => margin - epoch.slot_index,
+            (false, false) => return Ok(()),
+        };
+        warn!("Slot {} is within {} slots of the boundary of epoch {}, waiting {} s", epoch.absolute_slot, margin, epoch.epoch,
+              (SLOT * wait as u32).as_secs());
+        tokio::select! {
+            _ = sleep(SLOT * wait as u32) => {}
+            _ = cancellation.cancelled() => return Ok(()),
+        }
+    }
+}
+
+/// Leader slots of the current epoch, by identity, with the time at which the current slot was observed.
+pub(crate) struct LeaderSchedule {
+    observed: Instant,
+    slot: u64,
+    leaders: HashMap<String, Vec<u64>>,
+}
+
+impl LeaderSchedule {
+    /// Fetch the leader schedule of the current epoch.
+    pub(crate) fn fetch(rpc_clients: &[RpcClient]) -> Result<LeaderSchedule, Error> {
+        let leaders = with_retries("leader schedule", rpc_clients, |c| c.get_leader_schedule(None))?.unwrap_or_default();
+        let epoch = with_retries("epoch info", rpc_clients, |c| c.get_epoch_info_with_commitment(CommitmentConfig::processed()))?;
+        let observed = Instant::now();
+        let first_slot = epoch.absolute_slot - epoch.slot_index;
+        let leaders = leaders.into_iter()
+            .map(|(id, slots)| (id, slots.into_iter().map(|index| first_slot + index as u64).collect()))
+            .collect();
+        Ok(LeaderSchedule { observed, slot: epoch.absolute_slot, leaders })
+    }
+
+    /// Return the leader slots of the identities `ids`, sharing a TPU.
+    pub(crate) fn slots_of(&self, ids: &[String]) -> LeaderSlots {
+        let slots = ids.iter().filter_map(|id| self.leaders.get(id)).flatten().copied().collect();
+        LeaderSlots { observed: self.observed, slot: self.slot, slots: Arc::new(slots) }
+    }
+}
+
+/// Leader slots of a TPU, to schedule connection attempts outside of them.
+#[derive(Clone)]
+pub(crate) struct LeaderSlots {
+    observed: Instant,
+    slot: u64,
+    slots: Arc<HashSet<u64>>,
+}
+
+impl LeaderSlots {
+    /// Return the first time from `t` which is neither in a leader slot nor in the slot preceding one, so that
+    /// the handshake is over when the leader slots start.
+    ///
+    /// Slots are assumed to last 400 ms from the observed slot, which drifts by a few % but is good enough for
+    /// the duration of a measure.
+    pub(crate) fn next_free(&self, t: Instant) -> Instant {
+        let since = t.saturating_duration_since(self.observed);
+        let mut slot = self.slot + (since.as_millis() / SLOT.as_millis()) as u64;
+        let mut free = t;
+        while self.slots.contains(&slot) || self.slots.contains(&(slot + 1)) {
+            slot += 1;
+            free = self.observed + SLOT * (slot - self.slot) as u32;
+        }
+        free
+    }
+}
@@ -0,0 +1,889 @@
+//! Measure the distance in µs to the Solana cluster, to Doublezero, or to individual validators.
+//!
+//! The distance is half of the round-trip time measured during the QUIC handshake with the TPU of
+//! each validator. A [`DistanceMeasurer`] is configured with its builder and can be reused to measure
+//! several times with the same QUIC endpoint:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), solana_distance::Error> {
+//! let mut measurer = solana_distance::DistanceMeasurer::builder()
+//!     .count(3)
+//!     .destinations(vec!["puffinQSvKFriPbyE5atyx1ptfnyytovbzxybr1jsyy".to_string()])
+//!     .build();
+//! let report = measurer.measure().await?;
+//! println!("{:?}", report.simple.map(|d| d.distance_us));
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod address;
+pub mod alert;
+pub mod band;
+pub mod cancel;
+pub mod capabilities;
+pub mod compare;
+pub mod config_file;
+pub mod cron;
+mod discovery;
+pub mod doublezero;
+pub mod enrich;
+pub mod events;
+pub mod export;
+pub mod fallback;
+pub mod geo;
+mod gossip;
+pub mod health;
+pub mod hook;
+pub mod import;
+pub mod influx;
+mod leader;
+mod measure;
+pub mod metadata;
+pub mod output;
+pub mod owd;
+pub mod pcap;
+pub mod peering;
+pub mod placement;
+pub mod plan;
+pub mod plot;
+pub mod progress;
+pub mod prometheus;
+pub mod provider;
+pub mod remote;
+pub mod report;
+pub mod snapshot;
+pub mod solana_config;
+mod quic;
+mod ratelimit;
+pub mod routing;
+pub mod segment;
+pub mod select;
+pub mod shard;
+pub mod storage;
+pub mod stress;
+mod target_file;
+pub mod target_sets;
+pub mod tui;
+pub mod traceroute;
+pub mod tx;
+mod udp;
+
+use crate::address::{AltTpu, PortType, Prefix, RewriteRule};
+use crate::cancel::Cancellation;
+use crate::discovery::{blocking, discover, resolve_trace, ClusterCache, Targets};
+use crate::events::{EventSenders, MeasurementEvent, MeasurementEvents};
+use crate::fallback::FallbackProbe;
+use crate::leader::{avoid_epoch_boundary, LeaderSchedule};
+use crate::measure::{estimate, measure_targets, stale_targets, CONNECTION_TIMEOUT, LEADER_WINDOW};
+use crate::output::{Estimate, StaleRefresh, TpuReport};
+use crate::progress::Progress;
+use crate::ratelimit::RateLimiter;
+use crate::quic::{client_config, new_quic_endpoint, STEADY_KEEP_ALIVE};
+use crate::select::Selector;
+use crate::shard::Shard;
+use crate::udp::{PacketTrace, UdpCounters};
+use crate::NodeError::{Cancelled, ConnectionError, ConnectionFailed, IdentityMismatch, ImplausiblyLow, NoContactInfo, NoTPU, NotAStakedNode, NotAllowed, OnlyOneSuccessfulConnection};
+use quinn::{ClientConfig, Endpoint};
+use semver::VersionReq;
+use solana_keypair::Keypair;
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_rpc_client::api::client_error::Error as ClientError;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::iter;
+use std::mem;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+pub use crate::output::DistanceReport;
+
+pub const DEFAULT_RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+
+/// Public RPC of the clusters selected by name.
+pub const CLUSTER_PRESETS: [(&str, &str); 3] = [
+    ("mainnet", DEFAULT_RPC_URL),
+    ("testnet", "https://api.testnet.solana.com"),
+    ("devnet", "https://api.devnet.solana.com"),
+];
+
+/// Failure preventing a measure, or the resolution of its destinations.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to get {what} from RPC")]
+    Rpc { what: &'static str, source: ClientError },
+    #[error("Failed to crawl gossip through {entrypoint}")]
+    Gossip { entrypoint: String, source: io::Error },
+    #[error("Cannot send request to Doublezero API")]
+    DoublezeroRequest(#[from] reqwest::Error),
+    #[error("Doublezero API returned HTTP {status}: {body}")]
+    DoublezeroStatus { status: u16, body: String },
+    #[error("Doublezero API error: {0}")]
+    DoublezeroApi(String),
+    #[error("Doublezero API response has schema version {version}, this version reads up to {supported}")]
+    DoublezeroVersion { version: u32, supported: u32 },
+    #[error("Failed to decode Doublezero API response: {0}")]
+    DoublezeroDecode(String),
+    #[error("Invalid destination: {0}")]
+    Destination(String),
+    #[error("Cannot send request to {api} API")]
+    InfoApiRequest { api: &'static str, source: reqwest::Error },
+    #[error("{api} API returned HTTP {status}: {body}")]
+    InfoApiStatus { api: &'static str, status: u16, body: String },
+    #[error("Failed to decode {api} API response: {reason}")]
+    InfoApiDecode { api: &'static str, reason: String },
+    #[error("The {0} API requires a token")]
+    InfoApiToken(&'static str),
+    #[error("No validator named {name} in {api}")]
+    UnknownValidator { name: String, api: &'static str },
+    #[error("Failed to read targets {}", path.display())]
+    TargetsRead { path: PathBuf, source: io::Error },
+    #[error("Failed to save targets to {}", path.display())]
+    TargetsWrite { path: PathBuf, source: io::Error },
+    #[error("Cannot create QUIC endpoint")]
+    Endpoint(#[source] io::Error),
+    #[error("Invalid stress test: {0}")]
+    Stress(String),
+    #[error("Invalid rate of {0} connection attempts per second, expected a positive number")]
+    Rate(f64),
+}
+
+/// Reason for a node not to be measured, counted in the report.
+#[derive(Eq, Hash, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum NodeError {
+    ConnectionFailed,
+    OnlyOneSuccessfulConnection,
+    ConnectionError,
+    NoContactInfo,
+    NoTPU,
+    NotAStakedNode,
+    NotAllowed,
+    ImplausiblyLow,
+    IdentityMismatch,
+    Cancelled,
+}
+impl NodeError {
+    pub(crate) const ALL: [NodeError; 10] = [ConnectionFailed, OnlyOneSuccessfulConnection, ConnectionError, NoContactInfo, NoTPU, NotAStakedNode, NotAllowed, ImplausiblyLow, IdentityMismatch, Cancelled];
+}
+pub(crate) struct Errors(pub(crate) HashMap<NodeError, (u64, u64)>);
+impl Errors {
+    pub(crate) fn add(&mut self, error: NodeError, stake: u64) {
+        let e = self.0.entry(error).or_insert((0, 0));
+        e.0 += 1;
+        e.1 += stake;
+    }
+}
+impl Display for NodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ConnectionError => write!(f, "Connection error"),
+            ConnectionFailed => write!(f, "No successful connection"),
+            OnlyOneSuccessfulConnection => write!(f, "Only one successful connection"),
+            NoContactInfo => write!(f, "No contact info"),
+            NoTPU => write!(f, "No TPU"),
+            NotAStakedNode => write!(f, "Not a staked node"),
+            NotAllowed => write!(f, "Outside allowed prefixes"),
+            ImplausiblyLow => write!(f, "Implausibly low distance"),
+            IdentityMismatch => write!(f, "Server identity mismatch"),
+            Cancelled => write!(f, "Cancelled before any connection attempt"),
+        }
+    }
+}
+
+/// When to replace the client identity of the probes by a fresh one, with
+/// [`rotate_identity`](DistanceMeasurerBuilder::rotate_identity).
+#[derive(Clone, Copy, Debug)]
+pub enum IdentityRotation {
+    /// After this number of connections, over all TPUs
+    Connections(usize),
+    /// For each TPU
+    Target,
+}
+
+impl FromStr for IdentityRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "target" => Ok(IdentityRotation::Target),
+            n => match n.parse::<usize>() {
+                Ok(0) | Err(_) => Err(format!("Invalid identity rotation {}, expected a number of connections or 'target'", s)),
+                Ok(n) => Ok(IdentityRotation::Connections(n)),
+            },
+        }
+    }
+}
+
+/// Callback on the result of each TPU.
+pub(crate) type OnResult = Arc<dyn Fn(&TpuReport) + Send + Sync>;
+/// Callback on the progress of a measure.
+pub(crate) type OnProgress = Arc<dyn Fn(&Progress) + Send + Sync>;
+/// Callback on each line of the timeline of a traced TPU.
+pub(crate) type OnTrace = Arc<dyn Fn(&str) + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct Config {
+    pub(crate) rpc_url: String,
+    pub(crate) fallback_rpc_urls: Vec<String>,
+    pub(crate) destinations: Vec<String>,
+    pub(crate) count: usize,
+    pub(crate) budget_time: Option<Duration>,
+    pub(crate) interval: Duration,
+    pub(crate) connection_timeout: Duration,
+    pub(crate) stake_weighting: bool,
+    pub(crate) trace: Option<String>,
+    pub(crate) on_trace: Option<OnTrace>,
+    pub(crate) port_offset: Option<i32>,
+    pub(crate) port_type: PortType,
+    pub(crate) rewrite: Vec<RewriteRule>,
+    pub(crate) group_by_ip: bool,
+    pub(crate) allow_prefix: Vec<Prefix>,
+    pub(crate) plausibility_floor: u32,
+    pub(crate) fallback_probe: FallbackProbe,
+    pub(crate) alt_tpus: Vec<AltTpu>,
+    pub(crate) gossip_entrypoint: Option<String>,
+    pub(crate) max_concurrent: Option<usize>,
+    pub(crate) rate_per_ip: Option<f64>,
+    pub(crate) rate_per_subnet: Option<f64>,
+    pub(crate) on_result: Option<OnResult>,
+    pub(crate) on_progress: Option<OnProgress>,
+    pub(crate) events: EventSenders,
+    pub(crate) cache_cluster: bool,
+    pub(crate) cache_file: Option<PathBuf>,
+    pub(crate) cache_ttl: Duration,
+    pub(crate) refresh_cache: bool,
+    pub(crate) targets_file: Option<PathBuf>,
+    pub(crate) save_targets: Option<PathBuf>,
+    pub(crate) stale_refresh: Option<f64>,
+    pub(crate) top_stake: Option<usize>,
+    pub(crate) top_stake_pct: Option<f64>,
+    pub(crate) min_stake: Option<u64>,
+    pub(crate) max_stake: Option<u64>,
+    pub(crate) version_req: Option<VersionReq>,
+    pub(crate) selectors: Vec<Selector>,
+    pub(crate) include_delinquent: bool,
+    pub(crate) drop_worst: usize,
+    pub(crate) retries: usize,
+    pub(crate) leader_schedule: bool,
+    pub(crate) avoid_epoch_boundary: Option<u64>,
+    pub(crate) identity: Option<Arc<Keypair>>,
+    pub(crate) bind: Option<SocketAddr>,
+    pub(crate) interface: Option<String>,
+    pub(crate) steady_state: bool,
+    pub(crate) stream_open: bool,
+    pub(crate) resumption: bool,
+    pub(crate) mtu_probe: bool,
+    pub(crate) verify_identity: bool,
+    pub(crate) rotate_identity: Option<IdentityRotation>,
+    pub(crate) tx_payer: Option<Arc<Keypair>>,
+    pub(crate) shard: Option<Shard>,
+    pub(crate) cancellation: Cancellation,
+}
+
+/// Builder of a [`DistanceMeasurer`].
+#[derive(Clone)]
+pub struct DistanceMeasurerBuilder(Config);
+
+impl Default for DistanceMeasurerBuilder {
+    fn default() -> Self {
+        DistanceMeasurerBuilder(Config {
+            rpc_url: DEFAULT_RPC_URL.to_string(),
+            fallback_rpc_urls: vec![],
+            destinations: vec![],
+            count: 5,
+            budget_time: None,
+            interval: LEADER_WINDOW,
+            connection_timeout: CONNECTION_TIMEOUT,
+            stake_weighting: true,
+            trace: None,
+            on_trace: None,
+            port_offset: None,
+            port_type: PortType::Tpu,
+            rewrite: vec![],
+            group_by_ip: false,
+            allow_prefix: vec![],
+            plausibility_floor: 50,
+            fallback_probe: FallbackProbe::None,
+            alt_tpus: vec![],
+            gossip_entrypoint: None,
+            max_concurrent: None,
+            rate_per_ip: None,
+            rate_per_subnet: None,
+            on_result: None,
+            on_progress: None,
+            events: EventSenders::default(),
+            cache_cluster: false,
+            cache_file: None,
+            cache_ttl: Duration::ZERO,
+            refresh_cache: false,
+            targets_file: None,
+            save_targets: None,
+            stale_refresh: None,
+            top_stake: None,
+            top_stake_pct: None,
+            min_stake: None,
+            max_stake: None,
+            version_req: None,
+            selectors: vec![],
+            include_delinquent: false,
+            drop_worst: 0,
+            retries: 0,
+            leader_schedule: false,
+            avoid_epoch_boundary: None,
+            identity: None,
+            bind: None,
+            interface: None,
+            steady_state: false,
+            stream_open: false,
+            resumption: false,
+            mtu_probe: false,
+            verify_identity: false,
+            rotate_identity: None,
+            tx_payer: None,
+            shard: None,
+            cancellation: Cancellation::default(),
+        })
+    }
+}
+
+impl DistanceMeasurerBuilder {
+    /// URL of the RPC where cluster info is fetched from.
+    pub fn rpc_url(mut self, rpc_url: impl Into<String>) -> Self {
+        self.0.rpc_url = rpc_url.into();
+        self
+    }
+
+    /// Other RPCs of the same cluster, requests failing over to the next one when the previous one fails.
+    pub fn fallback_rpc_urls(mut self, fallback_rpc_urls: Vec<String>) -> Self {
+        self.0.fallback_rpc_urls = fallback_rpc_urls;
+        self
+    }
+
+    /// Validator pubkeys, TPU ip:port or failover targets `pubkey=ip:port,ip:port...` to measure. The whole cluster
+    /// is measured if empty.
+    pub fn destinations(mut self, destinations: Vec<String>) -> Self {
+        self.0.destinations = destinations;
+        self
+    }
+
+    /// Number of connection attempts per TPU.
+    pub fn count(mut self, count: usize) -> Self {
+        self.0.count = count;
+        self
+    }
+
+    /// Choose the number and spacing of connection attempts to fit the measure in this time, instead of `count`.
+    pub fn budget_time(mut self, budget_time: Option<Duration>) -> Self {
+        self.0.budget_time = budget_time;
+        self
+    }
+
+    /// Spacing of connection attempts to a TPU, 4 slots by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.0.interval = interval;
+        self
+    }
+
+    /// Time after which a connection attempt is considered failed.
+    pub fn connection_timeout(mut self, connection_timeout: Duration) -> Self {
+        self.0.connection_timeout = connection_timeout;
+        self
+    }
+
+    /// Weight the average distance by stake. Always disabled when measuring a single destination.
+    pub fn stake_weighting(mut self, stake_weighting: bool) -> Self {
+        self.0.stake_weighting = stake_weighting;
+        self
+    }
+
+    /// Write a timeline of the connection attempts to this validator pubkey or TPU ip:port, on standard error
+    /// unless an [`on_trace`](Self::on_trace) callback is set.
+    pub fn trace(mut self, trace: Option<String>) -> Self {
+        self.0.trace = trace;
+        self
+    }
+
+    /// Call `on_trace` with each line of the timeline of [`trace`](Self::trace), instead of writing it on
+    /// standard error.
+    pub fn on_trace(mut self, on_trace: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.0.on_trace = Some(Arc::new(on_trace));
+        self
+    }
+
+    /// Use the gossip address shifted by this port offset as TPU address.
+    pub fn port_offset(mut self, port_offset: Option<i32>) -> Self {
+        self.0.port_offset = port_offset;
+        self
+    }
+
+    /// Measure the address of this service of the validators, the TPU QUIC by default. Gossip is measured with
+    /// gossip pings, and RPC with TCP connections.
+    pub fn port_type(mut self, port_type: PortType) -> Self {
+        self.0.port_type = port_type;
+        self
+    }
+
+    /// Rules rewriting the resolved TPU addresses.
+    pub fn rewrite(mut self, rewrite: Vec<RewriteRule>) -> Self {
+        self.0.rewrite = rewrite;
+        self
+    }
+
+    /// Measure the TPUs sharing an IP address, e.g. of identities behind one host on different ports, as a single
+    /// TPU at the lowest port, with their identities and summed stake, instead of probing the host once per port.
+    pub fn group_by_ip(mut self, group_by_ip: bool) -> Self {
+        self.0.group_by_ip = group_by_ip;
+        self
+    }
+
+    /// Only measure TPUs in these prefixes, if not empty.
+    pub fn allow_prefix(mut self, allow_prefix: Vec<Prefix>) -> Self {
+        self.0.allow_prefix = allow_prefix;
+        self
+    }
+
+    /// Distance in µs below which a non-local TPU is considered implausible and excluded from averages.
+    pub fn plausibility_floor(mut self, plausibility_floor: u32) -> Self {
+        self.0.plausibility_floor = plausibility_floor;
+        self
+    }
+
+    /// Probe the hosts of the TPUs without any successful connection with `fallback_probe`, and report half of its
+    /// RTT as their network distance, in [`TpuReport::network_distance_us`], apart from the distance to the TPU.
+    pub fn fallback_probe(mut self, fallback_probe: FallbackProbe) -> Self {
+        self.0.fallback_probe = fallback_probe;
+        self
+    }
+
+    /// Additional addresses of measured identities, compared to their advertised address in the report.
+    pub fn alt_tpus(mut self, alt_tpus: Vec<AltTpu>) -> Self {
+        self.0.alt_tpus = alt_tpus;
+        self
+    }
+
+    /// Collect contact infos by crawling gossip through this entrypoint (host:port), RPC being only used for stakes.
+    pub fn gossip_entrypoint(mut self, gossip_entrypoint: Option<String>) -> Self {
+        self.0.gossip_entrypoint = gossip_entrypoint;
+        self
+    }
+
+    /// Maximum number of TPUs measured simultaneously, all of them if `None`.
+    pub fn max_concurrent(mut self, max_concurrent: Option<usize>) -> Self {
+        self.0.max_concurrent = max_concurrent;
+        self
+    }
+
+    /// Start at most `rate_per_ip` connection attempts per second to each IP address, spread out in time, e.g. to
+    /// co-located validators, to avoid their connection throttling. Unlimited if `None`, the measures fail with
+    /// [`Error::Rate`] if not positive.
+    pub fn rate_per_ip(mut self, rate_per_ip: Option<f64>) -> Self {
+        self.0.rate_per_ip = rate_per_ip;
+        self
+    }
+
+    /// Start at most `rate_per_subnet` connection attempts per second to each /24 IPv4 or /48 IPv6 subnet, spread
+    /// out in time. Unlimited if `None`, the measures fail with [`Error::Rate`] if not positive.
+    pub fn rate_per_subnet(mut self, rate_per_subnet: Option<f64>) -> Self {
+        self.0.rate_per_subnet = rate_per_subnet;
+        self
+    }
+
+    /// Leave out the `drop_worst` highest RTTs of each TPU from its distance estimate, as long as two remain.
+    pub fn drop_worst(mut self, drop_worst: usize) -> Self {
+        self.0.drop_worst = drop_worst;
+        self
+    }
+
+    /// Measure the TPUs without any successful connection again after the others, at most `retries` times, e.g. when
+    /// they were leader or rate-limiting during the first pass. Retries are not part of the time budget.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.0.retries = retries;
+        self
+    }
+
+    /// Fetch the leader schedule and delay the connection attempts falling in, or just before, the leader slots of
+    /// each validator, which deprioritize new connections while producing blocks.
+    pub fn leader_schedule(mut self, leader_schedule: bool) -> Self {
+        self.0.leader_schedule = leader_schedule;
+        self
+    }
+
+    /// Delay each measure while the current slot is within `margin` slots of an epoch boundary, before or after
+    /// it, where stakes and leader schedules change, until `margin` slots into the epoch.
+    pub fn avoid_epoch_boundary(mut self, margin: Option<u64>) -> Self {
+        self.0.avoid_epoch_boundary = margin;
+        self
+    }
+
+    /// Sign the client certificate with this identity, e.g. a staked validator's, instead of a new keypair for each
+    /// endpoint, to measure the stake-weighted QoS path of this identity.
+    pub fn identity(mut self, identity: Keypair) -> Self {
+        self.0.identity = Some(Arc::new(identity));
+        self
+    }
+
+    /// Send the QUIC probes from this local address, e.g. to choose the uplink of a multi-homed host.
+    pub fn bind(mut self, bind: Option<SocketAddr>) -> Self {
+        self.0.bind = bind;
+        self
+    }
+
+    /// Send the QUIC probes through this network interface, with `SO_BINDTODEVICE`. Only supported on Linux.
+    pub fn interface(mut self, interface: Option<String>) -> Self {
+        self.0.interface = interface;
+        self
+    }
+
+    /// Also keep a connection open to each TPU during the attempts, and compare the RTT of new connections with
+    /// the steady-state RTT of the persistent one.
+    pub fn steady_state(mut self, steady_state: bool) -> Self {
+        self.0.steady_state = steady_state;
+        self
+    }
+
+    /// After each successful handshake, also open a unidirectional stream and time how long the TPU takes to
+    /// grant it and read it to its end, in [`TpuReport::stream_us`], closer to the cost of sending a transaction
+    /// than the RTT. Only applies to QUIC ports.
+    pub fn stream_open(mut self, stream_open: bool) -> Self {
+        self.0.stream_open = stream_open;
+        self
+    }
+
+    /// After the attempts, connect twice more to each reachable TPU, the second connection resuming the TLS session
+    /// of the first one with 0-RTT if the TPU allows it, and report both handshake durations in
+    /// [`TpuReport::resumption`]. Only applies to QUIC ports.
+    pub fn resumption(mut self, resumption: bool) -> Self {
+        self.0.resumption = resumption;
+        self
+    }
+
+    /// After the attempts, keep a connection to each reachable TPU open a few seconds with path MTU discovery, and
+    /// report the largest datagram size of the path in [`TpuReport::mtu_probe`], flagging the paths dropping
+    /// datagrams above 1280 bytes, e.g. through tunnels. Only applies to QUIC ports.
+    pub fn mtu_probe(mut self, mtu_probe: bool) -> Self {
+        self.0.mtu_probe = mtu_probe;
+        self
+    }
+
+    /// Check that the Ed25519 key of the self-signed certificate of each TPU is the identity of its node, and
+    /// report the TPUs answering with another identity as [`TpuReport::server_identity`] and the
+    /// "Server identity mismatch" error, left out of the aggregates: a stale gossip entry or an address taken
+    /// over by another validator. The handshake signature is checked against this key, a TPU replaying the
+    /// certificate of another node failing to connect. Only applies to QUIC ports of known identities.
+    pub fn verify_identity(mut self, verify_identity: bool) -> Self {
+        self.0.verify_identity = verify_identity;
+        self
+    }
+
+    /// Connect with fresh unstaked identities, renewed as specified, instead of the single identity of the
+    /// endpoint, to tell a per-identity rate limit of the TPUs from their latency. Persistent connections of
+    /// [`steady_state`](Self::steady_state) keep the identity of the endpoint.
+    pub fn rotate_identity(mut self, rotate_identity: Option<IdentityRotation>) -> Self {
+        self.0.rotate_identity = rotate_identity;
+        self
+    }
+
+    /// After each measure, submit a transfer from `payer`, a funded keypair, to itself, and report the time until
+    /// it is confirmed with the distance to the leader it landed with.
+    pub fn tx_latency(mut self, payer: Keypair) -> Self {
+        self.0.tx_payer = Some(Arc::new(payer));
+        self
+    }
+
+    /// Only measure the slice `shard` of the targets, selected by identity, so that several probes measure
+    /// disjoint slices of the cluster or of the destinations, to be merged with
+    /// [`MergedReport::merge`](crate::shard::MergedReport::merge). With [`top_stake`](Self::top_stake), the
+    /// highest-staked validators of the whole cluster are sharded.
+    pub fn shard(mut self, shard: Option<Shard>) -> Self {
+        self.0.shard = shard;
+        self
+    }
+
+    /// Stop the measures when `cancellation` is cancelled: no new connection attempt starts, attempts in progress
+    /// are abandoned and persistent connections closed, and the report covers the attempts already done, TPUs
+    /// without any being counted as cancelled. Retries are skipped once cancelled.
+    pub fn cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.0.cancellation = cancellation;
+        self
+    }
+
+    /// Only measure the `top_stake` highest-staked validators when measuring the whole cluster, with stake weighting.
+    pub fn top_stake(mut self, top_stake: Option<usize>) -> Self {
+        self.0.top_stake = top_stake;
+        self
+    }
+
+    /// Only measure the highest-staked validators covering this percentage of the stake when measuring the whole
+    /// cluster, with stake weighting.
+    pub fn top_stake_pct(mut self, top_stake_pct: Option<f64>) -> Self {
+        self.0.top_stake_pct = top_stake_pct;
+        self
+    }
+
+    /// Only measure the validators with at least `min_stake` lamports of stake, with stake weighting.
+    pub fn min_stake(mut self, min_stake: Option<u64>) -> Self {
+        self.0.min_stake = min_stake;
+        self
+    }
+
+    /// Only measure the validators with at most `max_stake` lamports of stake, with stake weighting.
+    pub fn max_stake(mut self, max_stake: Option<u64>) -> Self {
+        self.0.max_stake = max_stake;
+        self
+    }
+
+    /// Only measure the validators whose version advertised in gossip matches `version_req`, e.g. `~2.1`.
+    /// Validators without version, or with a version which isn't semver, are left out.
+    pub fn version_req(mut self, version_req: Option<VersionReq>) -> Self {
+        self.0.version_req = version_req;
+        self
+    }
+
+    /// Only measure the validators matching all the `selectors`, e.g. `version=2.2.*` or `pubkey=Cert*`, as
+    /// advertised in gossip. Validators without contact info are left out.
+    pub fn select(mut self, selectors: Vec<Selector>) -> Self {
+        self.0.selectors = selectors;
+        self
+    }
+
+    /// Also measure the delinquent validators, with stake weighting, which are otherwise left out as not
+    /// voting.
+    pub fn include_delinquent(mut self, include_delinquent: bool) -> Self {
+        self.0.include_delinquent = include_delinquent;
+        self
+    }
+
+    /// Call `on_result` with the result of each TPU, as soon as its measure completes.
+    pub fn on_result(mut self, on_result: impl Fn(&TpuReport) + Send + Sync + 'static) -> Self {
+        self.0.on_result = Some(Arc::new(on_result));
+        self
+    }
+
+    /// Call `on_progress` with the number of TPUs completed so far, once the attempts start and as the measure of
+    /// each TPU completes, e.g. to draw a progress bar.
+    pub fn on_progress(mut self, on_progress: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.0.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    /// Fetch the contact infos and vote accounts of the cluster on the first measure only, and resolve the
+    /// destinations of the following ones with them, e.g. for interactive sessions measuring one validator at a
+    /// time. [`DistanceMeasurer::refresh_cluster`] fetches them again.
+    pub fn cache_cluster(mut self, cache_cluster: bool) -> Self {
+        self.0.cache_cluster = cache_cluster;
+        self
+    }
+
+    /// Keep the contact infos and vote accounts of the cluster in the file `cache_file` between runs, and resolve
+    /// the destinations with them while they are younger than `ttl`, e.g. for repeated or offline runs. The cache
+    /// is ignored if it was fetched from another RPC or gossip entrypoint.
+    pub fn cache_file(mut self, cache_file: Option<PathBuf>, ttl: Duration) -> Self {
+        self.0.cache_file = cache_file;
+        self.0.cache_ttl = ttl;
+        self
+    }
+
+    /// Fetch the cluster even if the cache file is fresh, and replace it.
+    pub fn refresh_cache(mut self, refresh_cache: bool) -> Self {
+        self.0.refresh_cache = refresh_cache;
+        self
+    }
+
+    /// Measure the TPUs saved in `targets_file` with [`save_targets`](Self::save_targets) instead of resolving the
+    /// destinations, without fetching the cluster, e.g. to measure the identical set from several vantage points.
+    /// The leader schedule is still fetched if requested, and the stale addresses are not refreshed.
+    pub fn targets_file(mut self, targets_file: Option<PathBuf>) -> Self {
+        self.0.targets_file = targets_file;
+        self
+    }
+
+    /// Write the TPUs resolved for each measure, with their identities and stakes, to the JSON file
+    /// `save_targets`.
+    pub fn save_targets(mut self, save_targets: Option<PathBuf>) -> Self {
+        self.0.save_targets = save_targets;
+        self
+    }
+
+    /// When the TPUs without any successful connection are at least `stale_refresh` % of the measured TPUs, fetch
+    /// the cluster again and measure their identities once more, as the addresses of the first snapshot may be
+    /// stale. The second pass is reported apart, in [`DistanceReport::stale_refresh`].
+    pub fn stale_refresh(mut self, stale_refresh: Option<f64>) -> Self {
+        self.0.stale_refresh = stale_refresh;
+        self
+    }
+
+    pub fn build(self) -> DistanceMeasurer {
+        DistanceMeasurer {
+            rpc_clients: iter::once(&self.0.rpc_url).chain(&self.0.fallback_rpc_urls).map(|url| RpcClient::new(url.clone())).collect(),
+            config: self.0,
+            endpoint: None,
+            identity: None,
+            steady_config: None,
+            trace: None,
+            cluster: ClusterCache::default(),
+        }
+    }
+}
+
+/// Measure the distance to the TPUs of the cluster or of the configured destinations.
+pub struct DistanceMeasurer {
+    config: Config,
+    /// Clients of the RPC and of its fallbacks, in order
+    rpc_clients: Arc<[RpcClient]>,
+    endpoint: Option<(Endpoint, Arc<UdpCounters>)>,
+    /// Identity of the endpoint, once created
+    identity: Option<Arc<Keypair>>,
+    /// Configuration of persistent connections, if requested
+    steady_config: Option<ClientConfig>,
+    trace: Option<Arc<PacketTrace>>,
+    /// Cluster kept from the previous measure, with `cache_cluster`, or read from the cache file
+    cluster: ClusterCache,
+}
+
+impl DistanceMeasurer {
+    pub fn builder() -> DistanceMeasurerBuilder {
+        DistanceMeasurerBuilder::default()
+    }
+
+    /// Replace the destinations of the following measures, keeping the endpoint and the cached cluster.
+    pub fn set_destinations(&mut self, destinations: Vec<String>) {
+        self.config.destinations = destinations;
+    }
+
+    /// Return a stream of the events of the following measures, ending when the measurer is dropped. Events are
+    /// buffered until read, the stream should be read as long as it is kept.
+    pub fn subscribe(&mut self) -> MeasurementEvents {
+        self.config.events.subscribe()
+    }
+
+    /// Fetch the cluster again at the next measure, rather than reusing the one kept with
+    /// [`cache_cluster`](DistanceMeasurerBuilder::cache_cluster), and from then on ignore the
+    /// [`cache_file`](DistanceMeasurerBuilder::cache_file), only writing it.
+    pub fn refresh_cluster(&mut self) {
+        self.cluster = ClusterCache::default();
+        self.config.refresh_cache = true;
+    }
+
+    /// Return the TPUs to measure, read from the targets file if any, otherwise discovered with `cluster`, read
+    /// from the cache file if empty, and save them if requested.
+    fn resolve(config: &Config, rpc_clients: &[RpcClient], cluster: &mut ClusterCache) -> Result<Targets, Error> {
+        let targets = match &config.targets_file {
+            Some(path) => {
+                let mut targets = target_file::load(path).map_err(|source| Error::TargetsRead { path: path.clone(), source })?;
+                // The file may have been edited since it was saved, the egress filter applies as to discovered TPUs
+                targets.retain_allowed(&config.allow_prefix);
+                if config.leader_schedule {
+                    targets.leader_schedule = Some(LeaderSchedule::fetch(rpc_clients)?);
+                }
+                targets
+            }
+            None => {
+                if cluster.is_empty() {
+                    *cluster = ClusterCache::load(config).unwrap_or_default();
+                }
+                let targets = discover(config, rpc_clients, cluster)?;
+                cluster.save(config);
+                targets
+            }
+        };
+        if let Some(path) = &config.save_targets {
+            target_file::save(path, &targets).map_err(|source| Error::TargetsWrite { path: path.clone(), source })?;
+        }
+        Ok(targets)
+    }
+
+    /// Discover the TPUs to measure through RPC, and return the expected duration and traffic of their
+    /// measure, without connecting to them.
+    pub fn estimate(&self) -> Result<Estimate, Error> {
+        let targets = Self::resolve(&self.config, &self.rpc_clients, &mut ClusterCache::default())?;
+        Ok(estimate(&self.config, &targets))
+    }
+
+    /// Discover the TPUs to measure through RPC, and measure their distance.
+    ///
+    /// The QUIC endpoint is created on the first call and kept for the following ones. Failures of
+    /// individual TPUs are counted in the report, only the failure of a whole stage is an error. The failure of
+    /// the landing of a transaction, with [`tx_latency`](DistanceMeasurerBuilder::tx_latency), is in its report.
+    pub async fn measure(&mut self) -> Result<DistanceReport, Error> {
+        // Shared by the measure of the refreshed stale TPUs, still spacing the attempts to the same hosts
+        let rate_limiter = RateLimiter::new(self.config.rate_per_ip, self.config.rate_per_subnet)?.map(Arc::new);
+        if let Some(margin) = self.config.avoid_epoch_boundary {
+            avoid_epoch_boundary(&self.rpc_clients, margin, &self.config.cancellation).await?;
+        }
+        let (config, rpc_clients, mut cluster) = (self.config.clone(), self.rpc_clients.clone(), mem::take(&mut self.cluster));
+        let (targets, cluster) = blocking(move || (Self::resolve(&config, &rpc_clients, &mut cluster), cluster)).await;
+        if self.config.cache_cluster {
+            self.cluster = cluster;
+        }
+        let targets = targets?;
+        self.config.events.send(|| MeasurementEvent::Resolved { targets: targets.tpus.keys().copied().collect(), total_stake: targets.total_stake });
+        if self.endpoint.is_none() {
+            self.trace = resolve_trace(&self.config, &targets);
+            let keypair = self.config.identity.clone().unwrap_or_else(|| Arc::new(Keypair::new()));
+            self.endpoint = Some(new_quic_endpoint(&keypair, self.config.bind, self.config.interface.as_deref(), self.trace.clone(), self.config.verify_identity).await.map_err(Error::Endpoint)?);
+            self.steady_config = self.config.steady_state.then(|| client_config(&keypair, STEADY_KEEP_ALIVE, self.config.verify_identity));
+            self.identity = Some(keypair);
+        }
+        let (endpoint, udp_counters) = self.endpoint.as_ref().unwrap();
+        let identity = self.identity.as_ref().unwrap();
+        // Only recorded in the report, a single request to the first RPC answering it
+        let rpc_clients = self.rpc_clients.clone();
+        let epoch = blocking(move || rpc_clients.iter().find_map(|rpc_client| match rpc_client.get_epoch_info() {
+            Ok(epoch) => Some((rpc_client.url(), epoch)),
+            Err(e) => {
+                debug!("Failed to get epoch info from {}: {}", rpc_client.url(), e);
+                None
+            }
+        })).await;
+        let mut report = measure_targets(&self.config, endpoint, self.steady_config.as_ref(), identity, udp_counters, targets, rate_limiter.clone(), self.trace.clone()).await;
+        if let Some((rpc_url, epoch)) = epoch {
+            report.metadata.rpc_url = Some(rpc_url);
+            report.metadata.epoch = Some(epoch.epoch);
+            report.metadata.slot = Some(epoch.absolute_slot);
+            report.metadata.slot_index = Some(epoch.slot_index);
+            report.metadata.slots_in_epoch = Some(epoch.slots_in_epoch);
+        }
+        let stale_refresh = self.config.stale_refresh.filter(|_| self.config.targets_file.is_none());
+        if let Some((config, failed)) = stale_refresh.and_then(|pct| stale_targets(&self.config, &report, pct)) {
+            // The cached snapshot is not reused, but replaced by the new one
+            let (refresh_config, rpc_clients) = (config.clone(), self.rpc_clients.clone());
+            let (targets, cluster) = blocking(move || {
+                let mut cluster = ClusterCache::default();
+                let targets = discover(&refresh_config, &rpc_clients, &mut cluster);
+                if targets.is_ok() {
+                    cluster.save(&refresh_config);
+                }
+                (targets, cluster)
+            }).await;
+            let targets = targets?;
+            if self.config.cache_cluster {
+                self.cluster = cluster;
+            }
+            let moved = targets.tpus.keys().filter(|addr| !failed.contains(addr)).count() as u64;
+            let second = measure_targets(&config, endpoint, self.steady_config.as_ref(), identity, udp_counters, targets, rate_limiter, None).await;
+            report.stale_refresh = Some(StaleRefresh {
+                failed: failed.len() as u64,
+                moved,
+                connection_successful: second.tpus.iter().filter(|t| t.error.is_none() && t.distance_us.is_some()).count() as u64,
+                tpus: second.tpus,
+            });
+        }
+        if let Some(payer) = &self.config.tx_payer {
+            let (rpc_clients, payer, tpus) = (self.rpc_clients.clone(), payer.clone(), report.tpus.clone());
+            report.tx_landing = Some(blocking(move || tx::land(&rpc_clients, &payer, &tpus)).await);
+        }
+        self.config.events.send(|| MeasurementEvent::RunFinished {
+            cancelled: report.cancelled,
+            connection_successful: report.connection_successful,
+            simple: report.simple,
+            stake_weighted: report.stake_weighted,
+        });
+        Ok(report)
+    }
+}
@@ -1,458 +1,1458 @@
-mod quic;
-
-use crate::quic::{new_quic_endpoint, socket_addr_to_quic_server_name};
-use crate::Error::{ConnectionError, ConnectionFailed, NoContactInfo, NoTPU, NotAStakedNode, OnlyOneSuccessfulConnection};
-use clap::Parser;
-use quinn::{Endpoint, VarInt};
-use rand::Rng;
-use solana_keypair::Keypair;
-use solana_rpc_client::rpc_client::RpcClient;
-use solana_rpc_client_types::response::{RpcContactInfo, RpcVoteAccountInfo};
-use std::collections::HashMap;
-use std::fmt;
-use std::fmt::{Display, Formatter};
-use std::net::SocketAddr;
+use anyhow::{anyhow, bail, Context};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use solana_distance::address::{AltTpu, PortType, Prefix, RewriteRule};
+use solana_distance::alert::{notify, CriticalTarget, SegmentObjective, Watchdog, Webhook, WebhookFormat};
+use solana_distance::band::{BandReport, LatencyBand, DEFAULT_BANDS};
+use solana_distance::cancel::Cancellation;
+use solana_distance::compare::PathComparison;
+use solana_distance::config_file::{self, ConfigFile};
+use solana_distance::doublezero::{FetchOptions, NetworksReport, DEFAULT_DOUBLEZERO_URL};
+use solana_distance::enrich::{self, InfoApi, InfoApiOptions};
+use solana_distance::export::ExportFormat;
+use solana_distance::fallback::FallbackProbe;
+use solana_distance::hook::{self, Hooks};
+#[cfg(feature = "geoip")]
+use solana_distance::geo::GeoDb;
+use solana_distance::import::ExternalLatencies;
+use solana_distance::metadata::{self, DEFAULT_IP_ECHO_URL};
+use solana_distance::health::{DistanceMetric, Thresholds, EXIT_THRESHOLD};
+use solana_distance::pcap::AnomalyCapture;
+use solana_distance::owd::{Location, OneWayReport};
+use solana_distance::output::{DistanceReport, GroupReport, HistogramBucket, StakeCoverage, TpuOrder, LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use solana_distance::peering::NetworkDb;
+use solana_distance::placement::{AgentReport, Placement};
+use solana_distance::segment::SegmentMap;
+use solana_distance::select::Selector;
+use solana_distance::shard::{MergedReport, Shard};
+use solana_distance::plan::{Group, Plan, Probe};
+use solana_distance::progress::ProgressLine;
+use solana_distance::provider::{ProviderMap, ProviderReport};
+use solana_distance::remote::{RemoteOptions, RemoteReport};
+use solana_distance::snapshot::TopologyDiff;
+use solana_distance::solana_config::{self, SolanaConfig};
+use solana_distance::storage::Period;
+use solana_distance::stress::StressOptions;
+use solana_distance::traceroute::TracerouteMethod;
+#[cfg(feature = "sqlite")]
+use solana_distance::storage::{self, Store};
+use solana_distance::tui::Tui;
+use solana_keypair::read_keypair_file;
+use solana_distance::{capabilities, doublezero, export, influx, peering, plan, plot, prometheus, report, routing, target_sets, traceroute, DistanceMeasurer, DistanceMeasurerBuilder, Error, IdentityRotation, CLUSTER_PRESETS, DEFAULT_RPC_URL};
+use semver::VersionReq;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Add;
 use std::path::PathBuf;
-use std::time::Duration;
-use reqwest::blocking::Response;
-use serde_json::Value;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::fs::File;
 use tokio::io;
 use tokio::io::AsyncBufReadExt;
-use tokio::task::JoinHandle;
-use tokio::time::{sleep, sleep_until, timeout};
+use tokio::task::JoinSet;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, warn};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+use tokio::time::sleep;
+
+const PROMETHEUS_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// One row per TPU, followed by the aggregates as comment lines
+    Csv,
+    /// Self-contained HTML page with a map of the validators located by --geoip, for a single measure
+    Html,
+    /// InfluxDB line protocol, one point per TPU and one of aggregates per measure
+    Influx,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Measure the distance once, as without subcommand
+    Measure {
+        #[arg(help = "Optional list of validator pubkey, name or TPU ip:port or host:port")]
+        destination: Vec<String>,
+    },
+    /// Measure the distance to the validators connected to Doublezero networks, each separately
+    Doublezero {
+        #[arg(default_value = "mainnet", help = "Doublezero networks")]
+        network: Vec<String>,
+    },
+    /// Keep measuring indefinitely, printing one line per round
+    Watch {
+        #[arg(value_parser = humantime::parse_duration, help = "Start a new round at this interval (e.g. 30s, 5m)")]
+        interval: Duration,
+        #[arg(help = "Optional list of validator pubkey, name or TPU ip:port or host:port")]
+        destination: Vec<String>,
+    },
+    /// Serve Prometheus metrics of the last measure on http://ADDR/metrics, measuring every minute or --watch interval
+    Serve {
+        #[arg(help = "Listening address, e.g. 127.0.0.1:9100")]
+        listen: SocketAddr,
+        #[arg(help = "Optional list of validator pubkey, name or TPU ip:port or host:port")]
+        destination: Vec<String>,
+    },
+    /// Measure the validator pubkeys or TPU ip:port typed on standard input, one line at a time, with a persistent
+    /// endpoint and the cluster fetched once ('refresh' fetches it again, 'quit' exits)
+    Repl,
+    /// Open a burst of concurrent connections to the TPU of your own validator, and report how many it accepts and
+    /// how long their handshakes take under load
+    Stress {
+        #[arg(help = "TPU ip:port of a validator you operate")]
+        target: SocketAddr,
+        #[arg(long, default_value_t = 100, help = "Number of connections of the burst, at most 1000")]
+        connections: usize,
+        #[arg(long, value_name = "N", default_value_t = 50, help = "New connections per second, at most 500")]
+        rate: u32,
+        #[arg(long, help = "Connect with a new identity for each connection, rather than with --identity or a single new one")]
+        fresh_identities: bool,
+        #[arg(long, help = "Confirm that the target is a validator you operate, or have permission to load-test")]
+        own_target: bool,
+    },
+    /// Measure from several hosts at once over SSH, running this tool on each of them with `--output json`, and
+    /// print their stake-weighted distances side by side, e.g. to pick a datacenter
+    Remote {
+        #[arg(required = true, value_name = "[USER@]HOST", help = "SSH destinations with the tool installed")]
+        hosts: Vec<String>,
+        #[arg(long, default_value = "solana-distance", help = "Path of the tool on the hosts")]
+        binary: String,
+        #[arg(last = true, value_name = "ARGS", help = "Destinations and options of the measure on the hosts, after --")]
+        args: Vec<String>,
+    },
+    /// Manage named target sets, stored under the configuration directory
+    Targets {
+        #[command(subcommand)]
+        action: TargetsAction,
+    },
+    /// Compare snapshots of the cluster, i.e. reports saved with `--output json`
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Merge the reports of all the shards of a measure made with `--shard`, saved with `--output json`
+    Merge {
+        #[arg(required = true, value_name = "REPORT", help = "Report of a shard")]
+        reports: Vec<PathBuf>,
+    },
+    /// Estimate the location minimizing the leader-weighted distance, from reports of agents at known positions
+    /// saved with `--output json --geoip <City database>`
+    Placement {
+        #[arg(required = true, value_name = "LAT,LON=REPORT", help = "Position of an agent and its report")]
+        agents: Vec<AgentReport>,
+        #[arg(long, default_value_t = 20000, help = "Number of steps of simulated annealing")]
+        iterations: usize,
+    },
+    /// Report the trend of the distance in the database given with `--store`
+    History {
+        #[arg(long, default_value_t = 30, help = "Number of days to report, up to now")]
+        days: u32,
+        #[arg(long, value_enum, default_value_t = Period::Day, help = "Aggregate the measures per period of this length")]
+        period: Period,
+        #[arg(long, help = "Report the distance to this validator rather than the aggregate distances")]
+        pubkey: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotAction {
+    /// Report validators which appeared, disappeared, changed TPU address or stake between two snapshots
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        #[arg(long, value_name = "%", default_value_t = 10.0, help = "Report stake changes above this percentage of the old stake")]
+        stake_change_pct: f64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TargetsAction {
+    /// Save a list of validator pubkey or TPU ip:port under a name
+    Save {
+        name: String,
+        destination: Vec<String>,
+        #[arg(short, long, help = "Path to a file containing a list of validator pubkey or ip:port")]
+        file: Option<PathBuf>,
+    },
+    /// Measure the distance to a saved target set, with the options given before `targets`
+    Use {
+        name: String,
+    },
+    /// List saved target sets
+    List,
+    /// Delete a saved target set
+    Delete {
+        name: String,
+    },
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about = "Measure the distance in µm to the Solana cluster, to Doublezero, or to individual validators")]
 struct Args {
-    #[arg(help = "Optional list of validator pubkey or TPU ip:port, or a Doublezero network name if option -2 is specified",)]
+    #[arg(help = "Optional list of validator pubkey, validator name, TPU ip:port or host:port or failover target pubkey=ip:port,ip:port..., or a Doublezero network name if option -2 is specified",)]
     destination: Vec<String>,
-    #[arg(short, long, help = "Print details for each validator we are connecting to")]
+    #[arg(global = true, short, long, help = "Print details for each validator we are connecting to, and UDP statistics")]
     details: bool,
-    #[arg(short, long, help = "Path to a file containing a list of validator pubkey or ip:port")]
+    #[arg(global = true, long, value_enum, help = "Sort the TPUs of the details and of the JSON and CSV outputs: by distance, farthest first and failed ones last, by stake, highest first, or by address")]
+    sort: Option<TpuOrder>,
+    #[arg(global = true, long, value_name = "N", requires = "details", help = "Only print the details of the first N TPUs, e.g. the farthest ones with --sort distance")]
+    limit: Option<usize>,
+    #[arg(global = true, short, long, help = "Path to a file containing a list of validator pubkey or ip:port")]
     file: Option<PathBuf>,
-    #[arg(short='s', long, help = "If specified, disable the stake-weighting of the average distance")]
+    #[arg(global = true, long, value_name = "FILE", help = "Save the resolved TPUs, with their identities and stakes, to this JSON file, to measure the identical set later with --targets")]
+    save_targets: Option<PathBuf>,
+    #[arg(global = true, long = "targets", value_name = "FILE", conflicts_with_all = ["file", "doublezero", "top_stake", "top_stake_pct", "gossip", "stale_refresh", "plan"], help = "Measure exactly the TPUs saved with --save-targets, without fetching the cluster, e.g. from several vantage points")]
+    targets_file: Option<PathBuf>,
+    #[arg(global = true, short='s', long, help = "If specified, disable the stake-weighting of the average distance")]
     no_stake_weighting: bool,
-    #[arg(short, long, default_value_t = 5, help = "Number of connection attempts, one attempt is performed every 1,8 secs")]
+    #[arg(global = true, short, long, default_value_t = 5, help = "Number of connection attempts, one attempt is performed every --interval")]
     count: usize,
-    #[arg(short, long, help = "URL of the RPC where cluster info is fetched from", default_value="https://api.mainnet-beta.solana.com")]
-    rpc: String,
-    #[arg(short='2', long, help = "Measure the distance to the a Doublezero network passed as an optional argument [default: mainnet]")]
+    #[arg(global = true, long = "interval", value_name = "MS", default_value_t = 1600, value_parser = clap::value_parser!(u64).range(1..), help = "Spacing of connection attempts to a TPU, 4 slots by default, e.g. for clusters with other slot timings")]
+    probe_interval: u64,
+    #[arg(global = true, long, value_name = "MS", default_value_t = 1600, value_parser = clap::value_parser!(u64).range(1..), help = "Time after which a connection attempt is considered failed")]
+    timeout: u64,
+    #[arg(global = true, long, value_name = "N", default_value_t = 0, help = "Discard the N highest RTTs of each TPU before estimating its distance, as long as two remain, to filter sporadic spikes")]
+    drop_worst: usize,
+    #[arg(global = true, long, value_name = "N", default_value_t = 0, help = "Measure the TPUs without any successful connection again after the others, up to N times, e.g. when they were leader or rate-limiting")]
+    retries: usize,
+    #[arg(global = true, long, value_name = "%", value_parser = parse_percentage, help = "Fetch the cluster again and measure the TPUs without any successful connection once more when they are at least this percentage of the measured TPUs, their addresses being possibly stale, reporting both passes")]
+    stale_refresh: Option<f64>,
+    #[arg(global = true, long, value_name = "SECS", default_value_t = 300, help = "Reuse the contact infos and stakes of the cluster fetched by a previous run within this time, kept in ~/.cache/solana-distance/cluster.json, 0 to always fetch them. With --watch, serve or a plan, only the first round reuses them")]
+    cache_ttl: u64,
+    #[arg(global = true, long, help = "Fetch the contact infos and stakes of the cluster even if they are cached, and replace the cache")]
+    refresh: bool,
+    #[arg(global = true, long, help = "Also keep a connection open to each TPU during the attempts, and report their handshake duration and the TPUs whose connection setup RTT differs from their steady-state RTT")]
+    steady_state: bool,
+    #[arg(global = true, long, help = "After each handshake, also open a stream to the TPU and report the time until the TPU granted and read it, closer to the cost of sending a transaction than the RTT")]
+    stream_open: bool,
+    #[arg(global = true, long, help = "After the attempts, connect twice more to each reachable TPU, resuming the TLS session with 0-RTT the second time, and report what resumption saves")]
+    resumption: bool,
+    #[arg(global = true, long, help = "After the attempts, discover the path MTU to each reachable TPU for a few seconds, and flag the paths dropping datagrams above 1280 bytes")]
+    mtu_probe: bool,
+    #[arg(global = true, long, help = "Check that the key of the certificate of each TPU is the identity of its node, and report the TPUs answering with another identity as errors")]
+    verify_identity: bool,
+    #[arg(global = true, long, help = "Fetch the leader schedule and keep connection attempts out of the leader slots of each validator, where new connections are deprioritized")]
+    leader_schedule: bool,
+    #[arg(global = true, long, value_name = "SLOTS", num_args = 0..=1, require_equals = true, default_missing_value = "150",
+          help = "Delay each measure while within this many slots of an epoch boundary, where stakes and leader schedules change [default: 150]")]
+    avoid_epoch_boundary: Option<u64>,
+    #[arg(global = true, short, long, value_name = "SECS", conflicts_with = "count", help = "Choose the number and spacing of connection attempts to fit the run in this wall-clock time")]
+    budget_time: Option<f64>,
+    #[arg(global = true, long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "Measure at most N TPUs simultaneously, in successive batches, to spare local ports and UDP buffers")]
+    max_concurrent: Option<u64>,
+    #[arg(global = true, long, value_name = "N", value_parser = parse_rate, help = "Start at most N connection attempts per second to each IP address, spreading out the attempts to co-located validators")]
+    rate_per_ip: Option<f64>,
+    #[arg(global = true, long, value_name = "N", value_parser = parse_rate, help = "Start at most N connection attempts per second to each /24 IPv4 or /48 IPv6 subnet")]
+    rate_per_subnet: Option<f64>,
+    #[arg(global = true, long, value_name = "I/N", help = "Only measure the I-th of N deterministic slices of the targets, selected by identity, to split a measure across probes and merge their reports with the merge subcommand")]
+    shard: Option<Shard>,
+    #[arg(global = true, long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["file", "doublezero", "no_stake_weighting"], help = "Only measure the N highest-staked validators of the cluster")]
+    top_stake: Option<u64>,
+    #[arg(global = true, long, value_name = "%", value_parser = parse_percentage, conflicts_with_all = ["file", "doublezero", "no_stake_weighting"], help = "Only measure the highest-staked validators covering this percentage of the stake of the cluster")]
+    top_stake_pct: Option<f64>,
+    #[arg(global = true, long, value_name = "SOL", conflicts_with = "no_stake_weighting", help = "Only measure the validators with at least this stake")]
+    min_stake: Option<u64>,
+    #[arg(global = true, long, value_name = "SOL", conflicts_with = "no_stake_weighting", help = "Only measure the validators with at most this stake")]
+    max_stake: Option<u64>,
+    #[arg(global = true, long, value_name = "REQ", help = "Only measure the validators whose version matches this semver requirement, e.g. '~2.1' or '>=2.1, <2.3'")]
+    version_req: Option<VersionReq>,
+    #[arg(global = true, long, value_name = "FIELD=PATTERN", help = "Only measure the validators whose pubkey, version or client matches this pattern, with * and ? wildcards, e.g. 'version=2.2.*' or 'pubkey=Cert*', can be repeated to match all")]
+    select: Vec<Selector>,
+    #[arg(global = true, long, conflicts_with = "no_stake_weighting", help = "Also measure the delinquent validators")]
+    include_delinquent: bool,
+    #[arg(global = true, short, long, value_name = "TARGET", help = "Print a timeline of the connection attempts to this validator pubkey or TPU ip:port, on stderr")]
+    trace: Option<String>,
+    #[arg(global = true, long, value_enum, default_value_t = PortType::Tpu, conflicts_with = "port_offset", help = "Service of the validators to measure: QUIC handshakes to the TPU or TPU forwards port, gossip pings, or TCP connections to RPC")]
+    port_type: PortType,
+    #[arg(global = true, long, value_name = "N", allow_negative_numbers = true, help = "Use the gossip IP and port shifted by this offset as TPU address, instead of the advertised TPU QUIC address")]
+    port_offset: Option<i32>,
+    #[arg(global = true, long, value_name = "RULE", help = "Rewrite resolved TPU addresses with a rule such as '1.2.3.0/24 -> 10.0.5.0/24', can be repeated")]
+    rewrite: Vec<RewriteRule>,
+    #[arg(global = true, long, help = "Measure the TPUs sharing an IP address on different ports once, as a single TPU with their identities and stake")]
+    group_by_ip: bool,
+    #[arg(global = true, long, value_name = "CIDR", help = "Only measure TPUs and plan targets whose address is in this prefix, can be repeated")]
+    allow_prefix: Vec<Prefix>,
+    #[arg(global = true, long, value_name = "PUBKEY=IP:PORT", help = "Also measure this other address, e.g. IPv6, of a measured identity and compare IPv4 and IPv6 distances, can be repeated")]
+    alt_tpu: Vec<AltTpu>,
+    #[arg(global = true, long, value_name = "ENTRYPOINT", help = "Collect contact infos by crawling gossip through this entrypoint, e.g. entrypoint.mainnet-beta.solana.com:8001, RPC being only used for stakes")]
+    gossip: Option<String>,
+    #[arg(global = true, long, value_name = "IP[:PORT]", value_parser = parse_bind, help = "Send the QUIC probes from this local address, e.g. to choose the uplink of a multi-homed host")]
+    bind: Option<SocketAddr>,
+    #[arg(global = true, long, value_name = "NAME", help = "Send the QUIC probes through this network interface, e.g. a Doublezero tunnel (Linux only)")]
+    interface: Option<String>,
+    #[arg(global = true, long, value_name = "IP1,IP2", value_parser = parse_compare, conflicts_with_all = ["bind", "interface", "watch", "prometheus_listen", "textfile", "estimate", "plan"], help = "Measure every TPU from both local addresses at the same time, e.g. the Internet and Doublezero uplinks, and print the difference")]
+    compare: Option<(IpAddr, IpAddr)>,
+    #[arg(global = true, long, value_name = "KEYPAIR", help = "Path to an identity keypair JSON file signing the client certificate, e.g. a staked validator's to measure its stake-weighted QoS path, instead of a new keypair")]
+    identity: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "N|target", conflicts_with = "identity", help = "Connect with a fresh client identity every N connections, or for each TPU, to detect per-identity rate limits")]
+    rotate_identity: Option<IdentityRotation>,
+    #[arg(global = true, long, value_name = "KEYPAIR", help = "Path to a funded keypair JSON file submitting a self-transfer after each measure, to report its landing time with the distance to the leader of its slot")]
+    tx_latency: Option<PathBuf>,
+    #[arg(global = true, short, long, help = "URL of the RPC where cluster info is fetched from, can be repeated for requests to fail over to the next one [default: json_rpc_url of the Solana CLI configuration, or https://api.mainnet-beta.solana.com]")]
+    rpc: Vec<String>,
+    #[arg(global = true, long, value_name = "NAME", help = "Fetch cluster info from the public RPC of this cluster, mainnet, testnet or devnet, or from the RPC of a cluster of the configuration file, unless --rpc is given")]
+    cluster: Option<String>,
+    #[arg(global = true, long, value_name = "FILE", help = "TOML configuration file of default options, cluster RPCs and target lists, overridden by the command line [default: ~/.config/solana-distance/config.toml]")]
+    config: Option<PathBuf>,
+    #[arg(global = true, long, conflicts_with = "config", help = "Ignore the configuration file")]
+    no_config: bool,
+    #[arg(global = true, long, value_name = "FILE", help = "Configuration of the Solana CLI whose json_rpc_url and keypair_path are the default RPC and identity [default: ~/.config/solana/cli/config.yml]")]
+    solana_config: Option<PathBuf>,
+    #[arg(global = true, long, conflicts_with = "solana_config", help = "Ignore the configuration of the Solana CLI, measuring with a new keypair through the default RPC")]
+    no_solana_config: bool,
+    #[arg(global = true, short='2', long, help = "Measure the distance to the validators of the Doublezero networks passed as optional arguments, each separately [default: mainnet]")]
     doublezero: bool,
+    #[arg(global = true, long, value_name = "URL", default_value = DEFAULT_DOUBLEZERO_URL, help = "Base URL of the Doublezero API, e.g. of a staging or self-hosted instance")]
+    doublezero_url: String,
+    #[arg(global = true, long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "10s", help = "Timeout of each request to the Doublezero API")]
+    doublezero_timeout: Duration,
+    #[arg(global = true, long, value_name = "N", default_value_t = 3, help = "Retries of a request to the Doublezero API failing to connect, timing out or with a server error")]
+    doublezero_retries: u32,
+    #[arg(global = true, long, value_enum, default_value_t = InfoApi::Stakewiz, help = "API resolving the destinations given by validator name, e.g. \"Jito Labs\", into identities")]
+    info_api: InfoApi,
+    #[arg(global = true, long, value_name = "TOKEN", help = "Token of the validators.app API, for --info-api validators-app")]
+    info_api_token: Option<String>,
+    #[arg(global = true, long, help = "Show the names of the validators, fetched from --info-api, next to their identity in the details and in the JSON output")]
+    names: bool,
+    #[arg(global = true, short, long, value_enum, default_value_t = OutputFormat::Text, help = "Output format")]
+    output: OutputFormat,
+    #[arg(global = true, long, help = "Summarize distance and stake by validator client implementation")]
+    by_client: bool,
+    #[arg(global = true, long, help = "Summarize the stake and median distance by hosting provider, e.g. OVH or TeraSwitch, from the ASN of --geoip or the --providers file")]
+    by_provider: bool,
+    #[arg(global = true, long, value_name = "FILE", requires = "by_provider", help = "Providers of prefixes or ASNs for --by-provider, in lines 'prefix-or-ASN provider', before the bundled table of known hosting providers")]
+    providers: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "PCT,...", num_args = 0..=1, require_equals = true, value_delimiter = ',', value_parser = parse_percentage,
+          default_missing_value = "33,50,66.7,90,95", help = "Report the distance within which the closest validators reach these percentages of the stake [default: 33,50,66.7,90,95]")]
+    stake_coverage: Option<Vec<f64>>,
+    #[arg(global = true, long, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "10", value_parser = clap::value_parser!(u32).range(1..),
+          help = "Print histograms of the number and stake of validators by distance, in buckets of this many ms [default: 10]")]
+    histogram: Option<u32>,
+    #[arg(global = true, long, value_name = "URL", num_args = 0..=1, require_equals = true, default_missing_value = DEFAULT_IP_ECHO_URL,
+          help = "Record the public address of this host in the reports, as answered by this HTTP echo service [default: https://api.ipify.org]")]
+    public_ip: Option<String>,
+    #[arg(global = true, long, value_name = "BAND,...", num_args = 0..=1, require_equals = true, value_delimiter = ',',
+          default_missing_value = DEFAULT_BANDS, help = "Label each validator with the band of its distance and report the stake share per band [default: co-located<1ms,metro<5ms,regional<20ms,continental<50ms,intercontinental]")]
+    bands: Option<Vec<LatencyBand>>,
+    #[arg(global = true, long, value_name = "FILE", conflicts_with = "plan", help = "Rank the networks of this IP-to-network database (ip2asn TSV, or lines 'prefix label') where peering would most reduce the stake-weighted distance")]
+    peering_wishlist: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "FILE", help = "Annotate each TPU with its country, city and autonomous system from this MaxMind database (e.g. GeoLite2-City, GeoLite2-ASN), and summarize the distance per country and per AS, can be repeated (geoip feature)")]
+    geoip: Vec<PathBuf>,
+    #[arg(global = true, long, value_name = "FILE", help = "Compare the distances with the latencies measured by another tool, e.g. smokeping, from this CSV or JSON file of IPs with distance_us, rtt_us or rtt_ms, can be repeated")]
+    import: Vec<PathBuf>,
+    #[arg(global = true, long, help = "Report the one-way delay of each TPU and the averages as a range rather than half of the round trip, paths being possibly asymmetric")]
+    one_way: bool,
+    #[arg(global = true, long, value_name = "LAT,LON", requires_all = ["one_way", "geoip"], help = "Position of this host, narrowing the range of --one-way with the time light takes to reach each TPU located by --geoip")]
+    location: Option<Location>,
+    #[arg(global = true, long, value_name = "N", help = "Report the BGP prefix and AS paths of the N farthest TPUs, from a looking glass")]
+    as_paths: Option<usize>,
+    #[arg(global = true, long, value_name = "URL", default_value = routing::RIPESTAT_LOOKING_GLASS, requires = "as_paths", help = "Looking glass queried by --as-paths, answering like the RIPEstat looking-glass API, {ip} being replaced by the address of the TPU")]
+    looking_glass_url: String,
+    #[arg(global = true, long, value_name = "N", help = "Trace the routes to the N farthest TPUs in parallel with traceroute, reporting their hops")]
+    traceroute: Option<usize>,
+    #[arg(global = true, long, value_name = "TARGET", help = "Also trace the route to this validator pubkey or TPU ip:port, can be repeated")]
+    traceroute_to: Vec<String>,
+    #[arg(global = true, long, value_enum, default_value_t = TracerouteMethod::Udp, help = "Probes of the traceroutes: UDP datagrams, or ICMP echoes requiring root or CAP_NET_RAW")]
+    traceroute_method: TracerouteMethod,
+    #[arg(global = true, short, long, value_name = "INTERVAL", value_parser = humantime::parse_duration, help = "Keep measuring indefinitely, starting a new round at this interval (e.g. 30s, 5m)")]
+    watch: Option<Duration>,
+    #[arg(global = true, long, conflicts_with_all = ["output", "details", "compare", "estimate", "plan", "prometheus_listen", "textfile"], help = "Keep measuring and show a live dashboard of the validators sorted by distance, the stake-weighted distance over the rounds and error counters, starting a new round at each --watch interval or right after the previous one (tui feature)")]
+    tui: bool,
+    #[arg(global = true, long, help = "Don't show the progress of the measure, shown on a terminal with the text output")]
+    no_progress: bool,
+    #[arg(global = true, short, long, action = clap::ArgAction::Count, conflicts_with = "tui", help = "Log on stderr the RPC requests, the failed QUIC handshakes and the result of each TPU, and with -vv each successful handshake")]
+    verbose: u8,
+    #[arg(global = true, short, long, conflicts_with_all = ["verbose", "details", "tui"], help = "Only print the average distances, without breakdowns, progress or warnings other than errors")]
+    quiet: bool,
+    #[arg(global = true, long, value_name = "µs", conflicts_with_all = ["watch", "prometheus_listen", "tui", "compare", "estimate", "plan"], help = "Exit with status 2 when the average distance of the measure is above this threshold, or wasn't measured")]
+    fail_above: Option<u64>,
+    #[arg(global = true, long, value_enum, requires = "fail_above", help = "Average distance checked by --fail-above [default: stake-weighted, simple without stake weighting]")]
+    fail_metric: Option<DistanceMetric>,
+    #[arg(global = true, long, value_name = "%", value_parser = parse_percentage, conflicts_with_all = ["watch", "prometheus_listen", "tui", "compare", "estimate", "plan"], help = "Exit with status 2 when the share of the measured TPUs without any successful connection is above this percentage")]
+    fail_loss_above: Option<f64>,
+    #[arg(global = true, long, value_name = "µs", default_value_t = 50, help = "Distance below which a non-local TPU is considered implausible (e.g. local proxy or wrong address) and excluded from averages")]
+    plausibility_floor: u32,
+    #[arg(global = true, long, value_enum, default_value_t = FallbackProbe::None, help = "Probe the hosts of the TPUs without any successful connection with an ICMP echo or a UDP datagram, reporting half of its RTT as their network distance, apart from the TPU distance")]
+    fallback_probe: FallbackProbe,
+    #[arg(global = true, long, value_name = "URL", help = "POST the per-TPU results of each measure to this URL, e.g. a spreadsheet import webhook")]
+    export_url: Option<String>,
+    #[arg(global = true, long, value_enum, default_value_t = ExportFormat::Csv, requires = "export_url", help = "Format of the exported results")]
+    export_format: ExportFormat,
+    #[arg(global = true, long, value_name = "URL", help = "Write the points of each measure in InfluxDB line protocol to this write endpoint, e.g. http://localhost:8086/api/v2/write?org=ORG&bucket=BUCKET")]
+    influx_url: Option<String>,
+    #[arg(global = true, long, value_name = "TOKEN", requires = "influx_url", help = "API token of the InfluxDB of --influx-url")]
+    influx_token: Option<String>,
+    #[arg(global = true, long, value_name = "ADDR", help = "Serve Prometheus metrics of the last measure on http://ADDR/metrics, measuring every --watch interval [default: 1m]")]
+    prometheus_listen: Option<SocketAddr>,
+    #[arg(global = true, long, value_name = "FILE", help = "Write the Prometheus metrics of each measure to this file, for the node_exporter textfile collector (name it *.prom)")]
+    textfile: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "FILE", help = "Write the stake and distance of each measured validator to this file after each measure, as an SVG scatter plot if it ends in .svg, as CSV otherwise")]
+    scatter: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "FILE", help = "Append the per-TPU and aggregate results of each measure to this SQLite database, for the history subcommand (sqlite feature)")]
+    store: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "PUBKEY[=µs]", help = "Alert as soon as this validator becomes unreachable or farther than the optional threshold, can be repeated")]
+    critical: Vec<CriticalTarget>,
+    #[arg(global = true, long, value_name = "FILE", help = "Summarize the distance per network segment, e.g. Doublezero device or city, read from this file of lines 'pubkey segment'")]
+    segments: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "SEGMENT=µs", requires = "segments", help = "Alert as soon as the distance of this segment exceeds this objective, stake-weighted unless stake weighting is disabled, can be repeated")]
+    slo: Vec<SegmentObjective>,
+    #[arg(global = true, long, value_name = "URL", help = "POST alerts on critical validators and segments as JSON to this URL, can be repeated")]
+    alert_url: Vec<String>,
+    #[arg(global = true, long, value_name = "µs", help = "Alert as soon as the average distance of a measure exceeds this threshold, or wasn't measured, listing the farthest validators by stake")]
+    alert_above: Option<u64>,
+    #[arg(global = true, long, value_name = "%", value_parser = parse_percentage, help = "Alert as soon as the share of the measured TPUs without any successful connection exceeds this percentage, listing the failing validators with the most stake")]
+    alert_loss_above: Option<f64>,
+    #[arg(global = true, long, value_name = "URL", help = "POST alerts as --alert-webhook-format messages to this URL, e.g. a Slack or Discord webhook, can be repeated")]
+    alert_webhook: Vec<String>,
+    #[arg(global = true, long, value_enum, default_value_t = WebhookFormat::Json, help = "Payload of the alerts POSTed to --alert-webhook")]
+    alert_webhook_format: WebhookFormat,
+    #[arg(global = true, long, value_name = "DIR", help = "Capture with tcpdump, into this directory, the traffic of the next round to each TPU failing or much farther than in the previous rounds, in the watch mode")]
+    pcap_on_anomaly: Option<PathBuf>,
+    #[arg(global = true, long, value_name = "COMMAND", help = "Run this shell command as each TPU is measured, with its result as JSON on stdin and in SOLANA_DISTANCE_* environment variables")]
+    on_result: Option<String>,
+    #[arg(global = true, long, help = "Print the platform-dependent features available on this host and exit")]
+    capabilities: bool,
+    #[arg(global = true, long, help = "Print the optional features this binary was built with and exit")]
+    features: bool,
+    #[arg(global = true, long, conflicts_with = "plan", help = "Print the expected duration, number of connections and traffic of a measure of the resolved targets, without measuring them")]
+    estimate: bool,
+    #[arg(global = true, long, value_name = "FILE", conflicts_with_all = ["file", "doublezero", "watch", "prometheus_listen", "textfile", "critical", "slo", "alert_above", "alert_loss_above"], help = "Run the measurement plan in this JSON file, measuring each group of targets with its own probe type and schedule")]
+    plan: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Cancellation of the measures, on Ctrl-C
+    #[arg(skip)]
+    cancellation: Cancellation,
+    /// Runner of the commands of `--on-result`, waited for before exiting
+    #[arg(skip)]
+    hooks: Option<Arc<Hooks>>,
+    #[arg(skip)]
+    config_file: ConfigFile,
+}
+
+impl Args {
+    /// Turn the measure subcommands into the equivalent options, which they are shorthands for.
+    fn apply_command(&mut self) -> anyhow::Result<()> {
+        let name = match &self.command {
+            Some(Command::Measure { .. }) => "measure",
+            Some(Command::Doublezero { .. }) => "doublezero",
+            Some(Command::Watch { .. }) => "watch",
+            Some(Command::Serve { .. }) => "serve",
+            _ => return self.check_destinations(),
+        };
+        if self.plan.is_some() {
+            bail!("--plan cannot be used with the {} subcommand", name);
+        }
+        match self.command.take() {
+            Some(Command::Measure { destination }) => self.destination.extend(destination),
+            Some(Command::Doublezero { network }) => {
+                if !self.destination.is_empty() || self.file.is_some() || self.top_stake.is_some() || self.top_stake_pct.is_some() {
+                    bail!("The doublezero subcommand measures all the validators of the network, without other destinations");
+                }
+                self.doublezero = true;
+                self.destination = network;
+            }
+            Some(Command::Watch { interval, destination }) => {
+                self.watch = Some(interval);
+                self.destination.extend(destination);
+            }
+            Some(Command::Serve { listen, destination }) => {
+                self.prometheus_listen = Some(listen);
+                self.destination.extend(destination);
+            }
+            _ => unreachable!(),
+        }
+        self.check_destinations()
+    }
+
+    /// Parse the command line, completed with the options of the configuration file it doesn't give. A missing
+    /// configuration file is only an error when given with `--config`.
+    fn parse_with_config() -> anyhow::Result<Args> {
+        let argv: Vec<OsString> = env::args_os().collect();
+        let matches = Args::command().get_matches_from(&argv);
+        let path = match matches.get_one::<PathBuf>("config") {
+            _ if matches.get_flag("no_config") => None,
+            Some(path) => Some((path.clone(), true)),
+            None => config_file::default_path().map(|path| (path, false)),
+        };
+        let config = match path.map(|(path, explicit)| (ConfigFile::load(&path), path, explicit)) {
+            None => return Ok(Args::from_arg_matches(&matches)?),
+            Some((Err(e), _, false)) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Args::from_arg_matches(&matches)?),
+            Some((config, path, _)) => config.with_context(|| format!("Failed to read configuration file {}", path.display()))?,
+        };
+        let options = config.arguments(&Args::command(), |id| matches.value_source(id) == Some(ValueSource::CommandLine))
+            .context("Invalid configuration file")?;
+        // Global options are accepted before the subcommand
+        let argv = argv[..1].iter().cloned().chain(options.into_iter().map(OsString::from)).chain(argv[1..].iter().cloned());
+        let mut args = Args::parse_from(argv);
+        args.config_file = config;
+        Ok(args)
+    }
+
+    /// Set the RPC of `--cluster`, unless `--rpc` is given. The clusters of the configuration file come before the
+    /// presets.
+    fn apply_cluster(&mut self) -> anyhow::Result<()> {
+        let Some(cluster) = &self.cluster else { return Ok(()) };
+        let preset = CLUSTER_PRESETS.iter().find(|(name, _)| name == cluster).map(|(_, url)| url.to_string());
+        let Some(url) = self.config_file.clusters.get(cluster).cloned().or(preset) else {
+            bail!("Unknown cluster {}, neither mainnet, testnet, devnet nor a cluster of the configuration file", cluster)
+        };
+        if self.rpc.is_empty() {
+            self.rpc.push(url);
+        }
+        Ok(())
+    }
+
+    /// Default the RPC and the identity to those of the Solana CLI configuration. A missing configuration is only an
+    /// error when given with `--solana-config`.
+    fn apply_solana_config(&mut self) -> anyhow::Result<()> {
+        if self.no_solana_config {
+            return Ok(());
+        }
+        let config = match &self.solana_config {
+            Some(path) => SolanaConfig::load(path).with_context(|| format!("Failed to read Solana CLI configuration {}", path.display()))?,
+            None => match solana_config::default_path().map(|path| SolanaConfig::load(&path)) {
+                Some(Ok(config)) => config,
+                _ => return Ok(()),
+            },
+        };
+        if self.rpc.is_empty() {
+            self.rpc.extend(config.json_rpc_url);
+        }
+        // A rotating identity already replaces the keypair
+        if self.identity.is_none() && self.rotate_identity.is_none() {
+            self.identity = config.keypair_path;
+        }
+        Ok(())
+    }
+
+    fn thresholds(&self) -> Thresholds {
+        let default_metric = if self.no_stake_weighting { DistanceMetric::Simple } else { DistanceMetric::StakeWeighted };
+        Thresholds { distance_us: self.fail_above, metric: self.fail_metric.unwrap_or(default_metric), failure_pct: self.fail_loss_above }
+    }
+
+    /// Return the thresholds of the measure raising alerts, `None` without any.
+    fn alert_thresholds(&self) -> Option<Thresholds> {
+        let metric = if self.no_stake_weighting { DistanceMetric::Simple } else { DistanceMetric::StakeWeighted };
+        (self.alert_above.is_some() || self.alert_loss_above.is_some())
+            .then_some(Thresholds { distance_us: self.alert_above, metric, failure_pct: self.alert_loss_above })
+    }
+
+    fn webhooks(&self) -> Vec<Webhook> {
+        self.alert_webhook.iter().map(|url| Webhook { url: url.clone(), format: self.alert_webhook_format }).collect()
+    }
+
+    fn doublezero_options(&self) -> FetchOptions {
+        FetchOptions { url: self.doublezero_url.clone(), timeout: self.doublezero_timeout, retries: self.doublezero_retries }
+    }
+
+    fn info_api_options(&self) -> InfoApiOptions {
+        InfoApiOptions { api: self.info_api, token: self.info_api_token.clone(), ..InfoApiOptions::default() }
+    }
+
+    /// Reject the options selecting their own targets along with destinations. Global options can't conflict with
+    /// the destinations with clap, which are not an argument of every subcommand.
+    fn check_destinations(&self) -> anyhow::Result<()> {
+        if self.destination.is_empty() || self.doublezero {
+            return Ok(());
+        }
+        for (option, set) in [("--top-stake", self.top_stake.is_some()), ("--top-stake-pct", self.top_stake_pct.is_some()), ("--plan", self.plan.is_some()), ("--targets", self.targets_file.is_some())] {
+            if set {
+                bail!("{} cannot be used with destinations", option);
+            }
+        }
+        Ok(())
+    }
 }
 
-struct TPU {
-    stake: u64,
-    join: Option<JoinHandle<(u32, u64)>>,
-    ids: Vec<String>,
-}
-
-#[derive(Eq, Hash, PartialEq)]
-enum Error {
-    ConnectionFailed,
-    OnlyOneSuccessfulConnection,
-    ConnectionError,
-    NoContactInfo,
-    NoTPU,
-    NotAStakedNode,
-}
-struct Errors(HashMap<Error, (u64, u64)>);
-impl Errors {
-    fn new(&mut self, error: Error, stake: u64) {
-        let e = self.0.entry(error).or_insert((0, 0));
-        e.0 += 1;
-        e.1 += stake;
-    }
-    fn new_and_print_if(&mut self, error: Error, stake: u64, print: bool) {
-        if print { println!("{}", error) }
-        self.new(error, stake);
-    }
-}
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            ConnectionError => write!(f, "Connection error"),
-            ConnectionFailed => write!(f, "No successful connection"),
-            OnlyOneSuccessfulConnection => write!(f, "Only one successful connection"),
-            NoContactInfo => write!(f, "No contact info"),
-            NoTPU => write!(f, "No TPU"),
-            NotAStakedNode => write!(f, "Not a staked node"),
-        }
-    }
-}
-
-const LEADER_WINDOW: Duration = Duration::from_millis(4 * 400); // 4 slots
-const CONNECTION_TIMEOUT: Duration = LEADER_WINDOW;
-
-/// Return latency estimate and its variance.
-///
-/// Send `count` connection requests, spaced 4 slots apart, to give a good chance that at least one request
-/// doesn't arrive when the validator is busy being leader.
-/// Add a random temporization if requested.
-///
-/// We collect latencies and assume they follow a 2-parameter exponential distribution:
-/// p(x) = 1/b exp(-(x-a)/b)
-/// Parameters are estimated using unbiased MLE:
-/// https://www.researchgate.net/publication/233060006_Estimation_in_two-parameter_exponential_distributions
-/// a = (n*min(x) - mean(x))/(n-1)
-/// b = n*(mean(x) - min(x))/(n-1)
-/// var(a) = b^2 / (n(n-1))
-async fn latency(endpoint: Endpoint, tpu_quic: SocketAddr, count: usize, temporization: bool) -> (u32, u64) {
-    let server_name = socket_addr_to_quic_server_name(tpu_quic);
-    if temporization {
-        let delay= rand::rng().random_range(Duration::ZERO..LEADER_WINDOW);
-        sleep(delay).await;
-    }
-    let mut t = tokio::time::Instant::now();
-    let mut lat_min = ping(&endpoint, &server_name, tpu_quic).await;
-    let mut lat_sum;
-    let mut lat_cnt;
-    if lat_min == u32::MAX {
-        lat_cnt = 0;
-        lat_sum = 0;
-    } else {
-        lat_cnt = 1;
-        lat_sum = lat_min as u64;
-    }
-    for _ in 1..count {
-        t = t.add(LEADER_WINDOW);
-        sleep_until(t).await;
-        let lat = ping(&endpoint, &server_name, tpu_quic).await;
-        if lat != u32::MAX {
-            lat_min = lat_min.min(lat);
-            lat_sum += lat as u64;
-            lat_cnt += 1;
-        }
-    }
-    if lat_cnt < 2 {
-        (lat_min, u64::MAX)
-    } else {
-        let lat_mean = lat_sum / lat_cnt;
-        let a = (lat_cnt * lat_min as u64 - lat_mean) / (lat_cnt - 1);
-        let b = (lat_cnt * (lat_mean - lat_min as u64)) / (lat_cnt - 1);
-        (a.try_into().expect("rtt overflow"), (b*b)/(lat_cnt*(lat_cnt-1)))
-    }
-}
-
-async fn ping(endpoint: &Endpoint, server_name: &String, tpu_quic: SocketAddr) -> u32 {
-    let connecting = endpoint.connect(tpu_quic, server_name).expect("Connection configuration error");
-    if let Ok(Ok(connection)) = timeout(CONNECTION_TIMEOUT, connecting).await {
-        // With a timeout of 2 s, rtt in µs should never overflow u32.
-        let rtt: u32 = connection.rtt().as_micros().try_into().expect("rtt overflow");
-        connection.close(VarInt::default(), &[]);
-        rtt/2
-    } else {
-        u32::MAX
-    }
-}
-
-fn decode_doublezero_info(dz_info: Response) -> Result<Vec<String>, &'static str> {
-    let Ok(j) = dz_info.json::<Value>() else { return Err("Invalid JSON") };
-    let Some(j) = j.as_object() else { return Err("Not an object") };
-    if j.get("success") != Some(&Value::Bool(true)) { return Err("Failed") };
-    let Some(j) = j.get("data") else { return Err("No data") };
-    let Some(j) = j.as_object() else { return Err("data is not an object") };
-    let Some(j) = j.get("validators") else { return Err("No validators") };
-    let Some(j) = j.as_array() else { return Err("validators is not an array") };
-    let mut res = Vec::new();
-    for v in j {
-        let Some(j) = v.as_object() else { return Err("validators is not an array of objects") };
-        let Some(j) = j.get("account") else { return Err("validator has no account") };
-        res.push(j.as_str().unwrap().to_string());
-    }
-    if res.is_empty() { return Err("No validators") };
-    Ok(res)
+fn parse_bind(s: &str) -> Result<SocketAddr, String> {
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 0)))
+        .map_err(|_| format!("Invalid local address {}", s))
 }
 
-#[tokio::main]
-async fn main() {
+fn parse_compare(s: &str) -> Result<(IpAddr, IpAddr), String> {
+    let (first, second) = s.split_once(',').ok_or_else(|| format!("Expected two comma-separated local addresses, got {}", s))?;
+    let parse = |ip: &str| ip.trim().parse::<IpAddr>().map_err(|_| format!("Invalid local address {}", ip));
+    Ok((parse(first)?, parse(second)?))
+}
 
-    let args = Args::parse();
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(pct) if pct > 0.0 && pct <= 100.0 => Ok(pct),
+        _ => Err("expected a percentage in ]0, 100]".to_string()),
+    }
+}
 
-    let rpc_client = RpcClient::new(args.rpc);
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err("expected a positive number of attempts per second".to_string()),
+    }
+}
+
+async fn read_destinations(path: &PathBuf, destination: &mut Vec<String>) -> anyhow::Result<()> {
+    let file = File::open(path).await.with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut lines = io::BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await.with_context(|| format!("Failed to read {}", path.display()))? {
+        destination.push(line);
+    }
+    Ok(())
+}
 
-    let mut destination = args.destination;
+/// Run the `targets` subcommands other than `use`, return false for `use`.
+async fn manage_targets(action: &TargetsAction) -> anyhow::Result<bool> {
+    match action {
+        TargetsAction::Save { name, destination, file } => {
+            let mut destination = destination.clone();
+            if let Some(path) = file {
+                read_destinations(path, &mut destination).await?;
+            }
+            let path = target_sets::save(name, &destination).with_context(|| format!("Failed to save target set {}", name))?;
+            println!("Saved {} targets to {}", destination.len(), path.display());
+        }
+        TargetsAction::Use { .. } => return Ok(false),
+        TargetsAction::List => {
+            for name in target_sets::list().context("Failed to list target sets")? {
+                println!("{}", name);
+            }
+        }
+        TargetsAction::Delete { name } => {
+            target_sets::delete(name).with_context(|| format!("Failed to delete target set {}", name))?;
+        }
+    }
+    Ok(true)
+}
+
+/// Return the list of requested validator pubkeys and TPU addresses.
+async fn destinations(args: &Args) -> anyhow::Result<Vec<String>> {
+    let mut destination = args.destination.clone();
+
+    if let Some(path) = &args.file {
+        read_destinations(path, &mut destination).await?;
+    }
 
-    if let Some(path) = args.file {
-        let file = File::open(path).await.expect("Failed to open specified file");
-        let mut lines = io::BufReader::new(file).lines();
-        while let Some(line) = lines.next_line().await.expect("Failed to read specified file") {
-            destination.push(line);
+    if let Some(Command::Targets { action: TargetsAction::Use { name } }) = &args.command {
+        // The target lists of the configuration file come before the saved sets
+        match args.config_file.targets.get(name) {
+            Some(targets) => destination.extend(targets.iter().cloned()),
+            None => destination.extend(target_sets::load(name).with_context(|| format!("Failed to load target set {}", name))?),
         }
     }
 
     if args.doublezero {
         let network = destination.pop().unwrap_or("mainnet".to_string());
         if !destination.is_empty() {
-            panic!("Only one Doublezero network name can be specified");
+            bail!("Only one Doublezero network name can be specified");
         }
-        let url = format!("https://doublezero.xyz/api/dz-validators?network={}", network);
-        let dz_info = reqwest::blocking::get(&url).expect("Cannot send request to Doublezero API");
-        destination = decode_doublezero_info(dz_info).unwrap_or_else(|e| panic!("Failed to decode Doublezero API response: {}", e));
+        destination = doublezero::fetch_validators(&network, &args.doublezero_options()).await?;
     }
 
-    let nodes_cnt = destination.len();
-    let mut nodes_pk = Vec::new();
-    let mut nodes_sa = Vec::new();
+    Ok(enrich::resolve_names(destination, &args.info_api_options()).await?)
+}
+
+/// Databases annotating or recording the reports, opened once for all the measures.
+#[derive(Default)]
+struct Databases {
+    networks: Option<NetworkDb>,
+    segments: Option<SegmentMap>,
+    providers: Option<ProviderMap>,
+    imports: Vec<ExternalLatencies>,
+    capture: Option<Mutex<AnomalyCapture>>,
+    /// Names of the validators by identity, with --names
+    names: Option<HashMap<String, String>>,
+    /// Public address of the host, with --public-ip
+    public_ip: Option<IpAddr>,
+    /// Identities connected to Doublezero, tagging the points of the InfluxDB outputs
+    doublezero: Option<HashSet<String>>,
+    #[cfg(feature = "geoip")]
+    geo: Option<GeoDb>,
+    #[cfg(feature = "sqlite")]
+    store: Option<Store>,
+}
 
-    for str in destination.into_iter() {
-        match str.parse::<SocketAddr>() {
-            Ok(sock_addr) => {
-                nodes_sa.push(sock_addr);
+impl Databases {
+    async fn load(args: &Args) -> anyhow::Result<Databases> {
+        let networks = match &args.peering_wishlist {
+            Some(path) => Some(NetworkDb::load(path).with_context(|| format!("Failed to read network database {}", path.display()))?),
+            None => None,
+        };
+        let segments = match &args.segments {
+            Some(path) => Some(SegmentMap::load(path).with_context(|| format!("Failed to read segments {}", path.display()))?),
+            None => None,
+        };
+        let providers = match &args.providers {
+            Some(path) => Some(ProviderMap::load(path).with_context(|| format!("Failed to read providers {}", path.display()))?),
+            None => None,
+        };
+        if args.by_provider && args.geoip.is_empty() && providers.is_none() {
+            bail!("--by-provider requires the ASN database of --geoip or a --providers file");
+        }
+        let imports = args.import.iter()
+            .map(|path| ExternalLatencies::load(path).with_context(|| format!("Failed to read imported latencies {}", path.display())))
+            .collect::<anyhow::Result<_>>()?;
+        let capture = match &args.pcap_on_anomaly {
+            Some(dir) => {
+                std::fs::create_dir_all(dir).with_context(|| format!("Cannot create capture directory {}", dir.display()))?;
+                Some(Mutex::new(AnomalyCapture::new(dir)))
             }
-            Err(_) => {
-                nodes_pk.push(str);
+            None => None,
+        };
+        // The names only make the details readable, the measure goes on without them
+        let names = match args.names {
+            true => match enrich::fetch_validators(&args.info_api_options()).await {
+                Ok(validators) => Some(validators.into_iter().map(|v| (v.identity, v.name)).collect()),
+                Err(e) => {
+                    warn!("Failed to fetch the names of the validators: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+        let public_ip = match &args.public_ip {
+            Some(url) => metadata::public_ip(url).await.inspect_err(|e| warn!("Failed to get the public address from {}: {}", url, e)).ok(),
+            None => None,
+        };
+        // Like the names, the membership only tags the points, the measure goes on without it
+        let doublezero = match args.output == OutputFormat::Influx || args.influx_url.is_some() {
+            true => {
+                let network = args.destination.last().filter(|_| args.doublezero).map_or("mainnet", String::as_str);
+                doublezero::fetch_validators(network, &args.doublezero_options()).await
+                    .inspect_err(|e| warn!("Failed to fetch the validators of Doublezero network {}: {}", network, e)).ok()
+                    .map(HashSet::from_iter)
+            }
+            false => None,
+        };
+        #[cfg(feature = "geoip")]
+        let geo = match args.geoip.is_empty() {
+            true => None,
+            false => {
+                let mut geo = GeoDb::default();
+                for path in &args.geoip {
+                    geo.add(path).with_context(|| format!("Failed to read geolocation database {}", path.display()))?;
+                }
+                Some(geo)
             }
+        };
+        #[cfg(not(feature = "geoip"))]
+        if !args.geoip.is_empty() {
+            bail!("--geoip requires the geoip feature, this binary was built without it");
+        }
+        #[cfg(feature = "sqlite")]
+        let store = match &args.store {
+            Some(path) => Some(Store::open(path).with_context(|| format!("Failed to open database {}", path.display()))?),
+            None => None,
+        };
+        #[cfg(not(feature = "sqlite"))]
+        if args.store.is_some() {
+            bail!("--store requires the sqlite feature, this binary was built without it");
         }
+        Ok(Databases {
+            networks,
+            segments,
+            providers,
+            imports,
+            capture,
+            names,
+            public_ip,
+            doublezero,
+            #[cfg(feature = "geoip")]
+            geo,
+            #[cfg(feature = "sqlite")]
+            store,
+        })
     }
 
-    let mut tpus: HashMap<SocketAddr, TPU> = HashMap::new();
-    let mut total_stake = 0;
+    fn annotate(&self, report: &mut DistanceReport) {
+        report.metadata.public_ip = self.public_ip;
+        if let Some(names) = &self.names {
+            for tpu in &mut report.tpus {
+                tpu.names = tpu.pubkeys.iter().filter_map(|pubkey| names.get(pubkey).cloned()).collect();
+            }
+        }
+        if let Some(doublezero) = &self.doublezero {
+            for tpu in &mut report.tpus {
+                tpu.doublezero = Some(tpu.pubkeys.iter().any(|pubkey| doublezero.contains(pubkey)));
+            }
+        }
+        #[cfg(feature = "geoip")]
+        if let Some(geo) = &self.geo {
+            for tpu in &mut report.tpus {
+                tpu.geo = geo.lookup(tpu.address.ip());
+            }
+            let unknown = || "Unknown".to_string();
+            report.countries = Some(GroupReport::group_by(&report.tpus, |t| t.geo.as_ref().and_then(|g| g.country.clone()).unwrap_or_else(unknown)));
+            report.asns = Some(GroupReport::group_by(&report.tpus, |t| t.geo.as_ref().and_then(|g| g.as_name()).unwrap_or_else(unknown)));
+        }
+        if let Some(segments) = &self.segments {
+            report.segments = Some(GroupReport::group_by(&report.tpus, |t| segments.segment(t)));
+        }
+        if let Some(networks) = &self.networks {
+            report.peering = Some(peering::wishlist(&report.tpus, networks, report.measured_stake));
+        }
+        if !self.imports.is_empty() {
+            report.imported = Some(self.imports.iter().map(|imports| imports.compare(&report.tpus)).collect());
+        }
+    }
+}
 
-    let mut errors = Errors(HashMap::new());
+async fn measure(args: &Args, measurer: &mut DistanceMeasurer, watchdog: &mut Option<Watchdog>, databases: &Databases) -> Result<DistanceReport, Error> {
+    let mut report = measurer.measure().await?;
+    if args.by_client {
+        report.clients = Some(GroupReport::group_by(&report.tpus, |t| t.client.to_string()));
+    }
+    if let Some(thresholds) = &args.stake_coverage {
+        report.stake_coverage = Some(StakeCoverage::curve(&report.tpus, report.total_stake, thresholds));
+    }
+    if let Some(width) = args.histogram {
+        report.histogram = Some(HistogramBucket::histogram(&report.tpus, width.saturating_mul(1000)));
+    }
+    if let Some(bands) = &args.bands {
+        report.bands = Some(BandReport::classify(&mut report.tpus, report.total_stake, bands));
+    }
+    databases.annotate(&mut report);
+    if args.by_provider {
+        report.providers = Some(ProviderReport::group(&report.tpus, databases.providers.as_ref()));
+    }
+    if let Some(capture) = &databases.capture {
+        capture.lock().unwrap().check(&report.tpus);
+    }
+    if args.one_way {
+        report.one_way = Some(OneWayReport::estimate(&mut report.tpus, args.location));
+    }
+    if let Some(order) = args.sort {
+        report.sort_tpus(order);
+    }
+    if let Some(watchdog) = watchdog {
+        for alert in watchdog.check(&report) {
+            notify(&args.alert_url, &args.webhooks(), &alert).await;
+        }
+    }
+    if let Some(worst) = args.as_paths {
+        report.routing = Some(routing::worst_routes(&args.looking_glass_url, &report.tpus, worst).await);
+    }
+    if args.traceroute.is_some() || !args.traceroute_to.is_empty() {
+        report.traceroutes = Some(traceroute::trace_routes(&report.tpus, args.traceroute.unwrap_or(0), &args.traceroute_to, args.traceroute_method).await);
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(store) = &databases.store {
+        // Like exports, a failure doesn't abort the measure or the watch mode
+        if let Err(e) = store.append(&report) {
+            warn!("Failed to store results: {}", e);
+        }
+    }
+    if let Some(path) = &args.scatter {
+        let plot = match path.extension().is_some_and(|e| e.eq_ignore_ascii_case("svg")) {
+            true => plot::to_svg(&report),
+            false => plot::to_csv(&report),
+        };
+        if let Err(e) = std::fs::write(path, plot) {
+            warn!("Failed to write scatter plot {}: {}", path.display(), e);
+        }
+    }
+    if let Some(url) = &args.export_url {
+        // Don't abort the measure, and the watch mode, on a failing export
+        if let Err(e) = export::post(url, args.export_format, &report).await {
+            warn!("Failed to export results to {}: {}", url, e);
+        }
+    }
+    if let Some(url) = &args.influx_url {
+        if let Err(e) = influx::write(url, args.influx_token.as_deref(), &report).await {
+            warn!("Failed to write results to InfluxDB {}: {}", url, e);
+        }
+    }
+    Ok(report)
+}
 
-    let no_stake_weighting = if nodes_cnt == 1 {
-        true
-    } else {
-        args.no_stake_weighting
-    };
+/// Measure the validators of each of several Doublezero networks separately, and report the validators they share.
+async fn measure_networks(args: &Args, budget_time: Option<Duration>) -> anyhow::Result<()> {
+    for (option, set) in [("--watch", args.watch.is_some()), ("--prometheus-listen", args.prometheus_listen.is_some()), ("--textfile", args.textfile.is_some()),
+                          ("--compare", args.compare.is_some()), ("--estimate", args.estimate), ("--tui", args.tui), ("--critical", !args.critical.is_empty()), ("--slo", !args.slo.is_empty()),
+                          ("--alert-above", args.alert_above.is_some()), ("--alert-loss-above", args.alert_loss_above.is_some()),
+                          ("--fail-above", args.fail_above.is_some()), ("--fail-loss-above", args.fail_loss_above.is_some())] {
+        if set {
+            bail!("{} cannot be used with several Doublezero networks", option);
+        }
+    }
+    if args.output == OutputFormat::Csv {
+        // Rows of the networks would be mixed up
+        bail!("CSV output is not available with several Doublezero networks");
+    }
+    if args.output == OutputFormat::Html {
+        bail!("HTML output is not available with several Doublezero networks");
+    }
+    if args.output == OutputFormat::Influx {
+        bail!("InfluxDB output is not available with several Doublezero networks, use --influx-url");
+    }
+    if args.file.is_some() {
+        bail!("Several Doublezero networks cannot be measured with a destination file");
+    }
+    let databases = Databases::load(args).await?;
+    let mut measures = Vec::new();
+    for network in &args.destination {
+        let validators = doublezero::fetch_validators(network, &args.doublezero_options()).await.with_context(|| format!("Failed to fetch the validators of Doublezero network {}", network))?;
+        let mut measurer = builder(args)?
+            .destinations(validators.clone())
+            .count(args.count)
+            .budget_time(budget_time)
+            .build();
+        let report = measure(args, &mut measurer, &mut None, &databases).await?;
+        measures.push((network.clone(), validators, report));
+    }
+    let report = NetworksReport::new(measures);
+    match args.output {
+        OutputFormat::Json => report.print_json(),
+        OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => report.print_text(args.details, args.limit),
+    }
+    Ok(())
+}
 
-    match (nodes_cnt == 0, no_stake_weighting) {
-
-        (true, false) => {
-            let rpc_nodes = rpc_client.get_cluster_nodes().expect("Failed to get cluster nodes");
-            let rpc_nodes_hash = HashMap::<String, RpcContactInfo>::from_iter(rpc_nodes.into_iter().map(|n| (n.pubkey.clone(), n)));
-            let rpc_vote_accounts = rpc_client.get_vote_accounts().expect("Failed to get vote accounts").current;
-            for va in rpc_vote_accounts {
-                if va.activated_stake != 0 {
-                    total_stake += va.activated_stake;
-                    if let Some(ci) = rpc_nodes_hash.get(&va.node_pubkey) {
-                        if let Some(sock_addr) = ci.tpu_quic {
-                            let tpu = tpus.entry(sock_addr).or_insert(TPU {
-                                stake: 0,
-                                join: None,
-                                ids: vec![],
-                            });
-                            tpu.ids.push(va.node_pubkey.to_string());
-                            tpu.stake += va.activated_stake;
-                        } else {
-                            errors.new(NoTPU, va.activated_stake)
-                        }
-                    } else {
-                        errors.new(NoContactInfo, va.activated_stake)
-                    }
-                }
-            }
+/// Return a builder with the measure options of the command line, other than the destinations and the
+/// number of attempts.
+fn builder(args: &Args) -> anyhow::Result<DistanceMeasurerBuilder> {
+    if let (Some(min), Some(max)) = (args.min_stake, args.max_stake) {
+        if min > max {
+            bail!("--min-stake {} is above --max-stake {}", min, max);
         }
+    }
+    let mut builder = DistanceMeasurer::builder()
+        .rpc_url(args.rpc.first().cloned().unwrap_or_else(|| DEFAULT_RPC_URL.to_string()))
+        .fallback_rpc_urls(args.rpc.iter().skip(1).cloned().collect())
+        .stake_weighting(!args.no_stake_weighting)
+        .interval(Duration::from_millis(args.probe_interval))
+        .connection_timeout(Duration::from_millis(args.timeout))
+        .trace(args.trace.clone())
+        .port_offset(args.port_offset)
+        .port_type(args.port_type)
+        .rewrite(args.rewrite.clone())
+        .group_by_ip(args.group_by_ip)
+        .allow_prefix(args.allow_prefix.clone())
+        .plausibility_floor(args.plausibility_floor)
+        .fallback_probe(args.fallback_probe)
+        .drop_worst(args.drop_worst)
+        .retries(args.retries)
+        .stale_refresh(args.stale_refresh)
+        .cache_file(target_sets::cache_dir().filter(|_| args.cache_ttl > 0).map(|dir| dir.join("cluster.json")), Duration::from_secs(args.cache_ttl))
+        .refresh_cache(args.refresh)
+        .targets_file(args.targets_file.clone())
+        .save_targets(args.save_targets.clone())
+        .leader_schedule(args.leader_schedule)
+        .avoid_epoch_boundary(args.avoid_epoch_boundary)
+        .steady_state(args.steady_state)
+        .stream_open(args.stream_open)
+        .resumption(args.resumption)
+        .mtu_probe(args.mtu_probe)
+        .verify_identity(args.verify_identity)
+        .rotate_identity(args.rotate_identity)
+        .alt_tpus(args.alt_tpu.clone())
+        .gossip_entrypoint(args.gossip.clone())
+        .max_concurrent(args.max_concurrent.map(|n| n as usize))
+        .rate_per_ip(args.rate_per_ip)
+        .rate_per_subnet(args.rate_per_subnet)
+        .top_stake(args.top_stake.map(|n| n as usize))
+        .top_stake_pct(args.top_stake_pct)
+        .shard(args.shard)
+        .min_stake(args.min_stake.map(|sol| sol.saturating_mul(LAMPORTS_PER_SOL)))
+        .max_stake(args.max_stake.map(|sol| sol.saturating_mul(LAMPORTS_PER_SOL)))
+        .version_req(args.version_req.clone())
+        .select(args.select.clone())
+        .include_delinquent(args.include_delinquent)
+        .bind(args.bind)
+        .interface(args.interface.clone())
+        .cancellation(args.cancellation.clone());
+    if let Some(hooks) = args.hooks.clone() {
+        builder = builder.on_result(move |tpu| hooks.run(tpu));
+    }
+    if let Some(path) = &args.identity {
+        let identity = read_keypair_file(path).map_err(|e| anyhow!("Failed to read identity {}: {}", path.display(), e))?;
+        builder = builder.identity(identity);
+    }
+    if let Some(path) = &args.tx_latency {
+        let payer = read_keypair_file(path).map_err(|e| anyhow!("Failed to read keypair {}: {}", path.display(), e))?;
+        builder = builder.tx_latency(payer);
+    }
+    Ok(builder)
+}
 
-        (true, true) => {
-            let rpc_nodes = rpc_client.get_cluster_nodes().expect("Failed to get cluster nodes");
-            for ci in rpc_nodes {
-                if let Some(sock_addr) = ci.tpu_quic {
-                    let tpu = tpus.entry(sock_addr).or_insert(TPU {
-                        stake: 0,
-                        join: None,
-                        ids: vec![],
-                    });
-                    tpu.ids.push(ci.pubkey.to_string());
-                } else {
-                    errors.new(NoTPU, 0)
-                }
-            }
+/// Wait for `duration`, returning false if the measures are cancelled first.
+async fn pause(args: &Args, duration: Duration) -> bool {
+    tokio::select! {
+        _ = sleep(duration) => true,
+        _ = args.cancellation.cancelled() => false,
+    }
+}
+
+/// Cancel the measures on the first Ctrl-C, for the attempts already done to be reported, and exit at once on
+/// the second one.
+fn cancel_on_ctrl_c(cancellation: Cancellation) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
         }
+        eprintln!("Cancelling the measure, press Ctrl-C again to exit at once");
+        cancellation.cancel();
+        let _ = tokio::signal::ctrl_c().await;
+        process::exit(130);
+    });
+}
 
-        (false, false) => {
-            let rpc_nodes = rpc_client.get_cluster_nodes().expect("Failed to get cluster nodes");
-            let rpc_vote_accounts = rpc_client.get_vote_accounts().expect("Failed to get vote accounts").current;
-            let rpc_pk_vote_accounts = HashMap::<String, &RpcVoteAccountInfo>::from_iter(rpc_vote_accounts.iter().map(|va| (va.node_pubkey.clone(), va)));
-            if !nodes_pk.is_empty() {
-                let rpc_pk_nodes = HashMap::<String, &RpcContactInfo>::from_iter(rpc_nodes.iter().map(|n| (n.pubkey.clone(), n)));
-                for pk in nodes_pk {
-                    if let Some(va) = rpc_pk_vote_accounts.get(&pk) {
-                        if let Some(ci) = rpc_pk_nodes.get(&pk) {
-                            if let Some(sock_addr) = ci.tpu_quic {
-                                let tpu = tpus.entry(sock_addr).or_insert(TPU {
-                                    stake: 0,
-                                    join: None,
-                                    ids: vec![],
-                                });
-                                tpu.ids.push(pk);
-                                tpu.stake += va.activated_stake;
-                                total_stake += va.activated_stake;
-                            } else {
-                                errors.new(NoTPU, va.activated_stake)
-                            }
-                        } else {
-                            errors.new(NoContactInfo, va.activated_stake);
-                        }
-                    } else {
-                        errors.new(NotAStakedNode, 0);
-                    }
-                }
+/// Measure a group of a plan at its own interval or schedule, until cancelled. QUIC groups are measured with
+/// `measurer`.
+async fn run_group(args: Arc<Args>, group: Group, mut measurer: Option<DistanceMeasurer>) {
+    let mut next_round = tokio::time::Instant::now();
+    loop {
+        if let Some(schedule) = &group.schedule {
+            if !pause(&args, schedule.until_next()).await {
+                return;
             }
-            if !nodes_sa.is_empty() {
-                let mut rpc_addr_nodes = HashMap::<SocketAddr, Vec<&RpcContactInfo>>::new();
-                for node in &rpc_nodes {
-                    if let Some(sock_addr) = node.tpu_quic {
-                        rpc_addr_nodes.entry(sock_addr).or_insert(vec![]).push(node);
-                    }
+        }
+        match &mut measurer {
+            Some(measurer) => {
+                match measure(&args, measurer, &mut None, &Databases::default()).await {
+                    Ok(report) => match args.output {
+                        OutputFormat::Text => println!("{} {} {}", report.timestamp, group.name, report.round_summary()),
+                        OutputFormat::Json => println!("{}", json!({"schema_version": SCHEMA_VERSION, "group": group.name, "probe": group.probe, "report": report})),
+                        OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => unreachable!("CSV, HTML and InfluxDB outputs are rejected with a plan"),
+                    },
+                    Err(e) => error!("Measure of {} failed: {:#}", group.name, anyhow::Error::from(e)),
                 }
-                for sock_addr in nodes_sa {
-                    let tpu = tpus.entry(sock_addr).or_insert(TPU {
-                        stake: 0,
-                        join: None,
-                        ids: vec![],
-                    });
-                    for ci in rpc_addr_nodes.get(&sock_addr).unwrap() {
-                        if let Some(va) = rpc_pk_vote_accounts.get(&ci.pubkey) {
-                            tpu.ids.push(ci.pubkey.clone());
-                            tpu.stake += va.activated_stake;
-                            total_stake += va.activated_stake;
+                // Like --watch rounds, the following runs fetch the cluster again
+                measurer.refresh_cluster();
+            }
+            None => {
+                let timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+                let results = plan::probe(&group, &args.allow_prefix).await;
+                match args.output {
+                    OutputFormat::Text => {
+                        for result in &results {
+                            result.print_text(&format!("{} {}", timestamp, group.name));
                         }
                     }
-                    if tpu.stake == 0 {
-                        errors.new(NotAStakedNode, 0);
-                        tpus.remove(&sock_addr);
-                    }
+                    OutputFormat::Json => println!("{}", json!({"schema_version": SCHEMA_VERSION, "group": group.name, "probe": group.probe, "timestamp": timestamp, "results": results})),
+                    OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => unreachable!("CSV, HTML and InfluxDB outputs are rejected with a plan"),
                 }
             }
         }
-
-        (false, true) => {
-            let rpc_nodes = rpc_client.get_cluster_nodes().expect("Failed to get cluster nodes");
-            if !nodes_pk.is_empty() {
-                let rpc_pk_nodes = HashMap::<String, &RpcContactInfo>::from_iter(rpc_nodes.iter().map(|n| (n.pubkey.clone(), n)));
-                for pk in nodes_pk {
-                    if let Some(ci) = rpc_pk_nodes.get(&pk) {
-                        if let Some(sock_addr) = ci.tpu_quic {
-                            let tpu = tpus.entry(sock_addr).or_insert(TPU {
-                                stake: 0,
-                                join: None,
-                                ids: vec![],
-                            });
-                            tpu.ids.push(pk);
-                        } else {
-                            errors.new(NoTPU, 0)
-                        }
-                    } else {
-                        errors.new(NoContactInfo, 0);
-                    }
-                }
+        if args.cancellation.is_cancelled() {
+            return;
+        }
+        if let Some(every) = group.every {
+            next_round = next_round.add(every).max(tokio::time::Instant::now());
+            if !pause(&args, next_round - tokio::time::Instant::now()).await {
+                return;
             }
-            if !nodes_sa.is_empty() {
-                let mut rpc_addr_nodes = HashMap::<SocketAddr, Vec<&RpcContactInfo>>::new();
-                for node in &rpc_nodes {
-                    if let Some(sock_addr) = node.tpu_quic {
-                        rpc_addr_nodes.entry(sock_addr).or_insert(vec![]).push(node);
-                    }
-                }
-                for sock_addr in nodes_sa {
-                    let tpu = tpus.entry(sock_addr).or_insert(TPU {
-                        stake: 0,
-                        join: None,
-                        ids: vec![],
-                    });
-                    for ci in rpc_addr_nodes.get(&sock_addr).unwrap() {
-                        tpu.ids.push(ci.pubkey.clone());
-                    }
-                }
+        }
+    }
+}
+
+/// Run all the groups of `plan` concurrently, until cancelled.
+async fn run_plan(args: Arc<Args>, plan: Plan) -> anyhow::Result<()> {
+    let mut groups = JoinSet::new();
+    for group in plan.groups {
+        let measurer = match group.probe {
+            Probe::Quic => Some(builder(&args)?.rpc_url(plan.rpc.clone()).fallback_rpc_urls(Vec::new()).destinations(group.destinations.clone()).count(group.count).build()),
+            Probe::Gossip | Probe::Rpc => None,
+        };
+        groups.spawn(run_group(args.clone(), group, measurer));
+    }
+    groups.join_all().await;
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn history(args: &Args, days: u32, period: Period, pubkey: Option<&str>) -> anyhow::Result<()> {
+    let Some(path) = &args.store else { bail!("The history subcommand requires --store") };
+    let store = Store::open(path).with_context(|| format!("Failed to open database {}", path.display()))?;
+    let points = store.trend(days, period, pubkey).context("Failed to read the history")?;
+    match args.output {
+        OutputFormat::Json => storage::print_json(&points),
+        OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => storage::print_text(&points, pubkey),
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn history(_args: &Args, _days: u32, _period: Period, _pubkey: Option<&str>) -> anyhow::Result<()> {
+    bail!("The history subcommand requires the sqlite feature, this binary was built without it")
+}
+
+/// Measure the destinations typed on each line of standard input until its end, 'quit' or Ctrl-C, resolving them with
+/// the cluster fetched on the first line.
+async fn repl(args: &Args, budget_time: Option<Duration>) -> anyhow::Result<()> {
+    for (option, set) in [("destinations", !args.destination.is_empty() || args.file.is_some()), ("--doublezero", args.doublezero), ("--watch", args.watch.is_some()),
+                          ("--prometheus-listen", args.prometheus_listen.is_some()), ("--tui", args.tui), ("--compare", args.compare.is_some()), ("--estimate", args.estimate), ("--plan", args.plan.is_some()),
+                          ("--fail-above", args.fail_above.is_some()), ("--fail-loss-above", args.fail_loss_above.is_some())] {
+        if set {
+            bail!("{} cannot be used with the repl subcommand", option);
+        }
+    }
+    if matches!(args.output, OutputFormat::Csv | OutputFormat::Html) {
+        bail!("{:?} output is not available with the repl subcommand", args.output);
+    }
+    let mut measurer = builder(args)?
+        .count(args.count)
+        .budget_time(budget_time)
+        .cache_cluster(true)
+        .build();
+    let databases = Databases::load(args).await?;
+    let prompt = std::io::stdin().is_terminal();
+    let mut lines = io::BufReader::new(io::stdin()).lines();
+    loop {
+        if prompt {
+            eprint!("> ");
+        }
+        let line = tokio::select! {
+            line = lines.next_line() => line.context("Failed to read standard input")?,
+            _ = args.cancellation.cancelled() => None,
+        };
+        let Some(line) = line else { return Ok(()) };
+        let destinations: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        match destinations.first().map(String::as_str) {
+            None => continue,
+            Some("quit" | "exit") => return Ok(()),
+            Some("refresh") => {
+                measurer.refresh_cluster();
+                continue;
             }
+            Some(_) => (),
+        }
+        measurer.set_destinations(destinations);
+        match measure(args, &mut measurer, &mut None, &databases).await {
+            Ok(report) => match args.output {
+                OutputFormat::Text => report.print_text(args.details, args.limit),
+                OutputFormat::Json => report.print_json_line(),
+                OutputFormat::Influx => report.print_influx(),
+                OutputFormat::Csv | OutputFormat::Html => unreachable!("CSV and HTML outputs are rejected with the repl subcommand"),
+            },
+            // Keep the session, e.g. after a typo in a destination
+            Err(e) => error!("Measure failed: {:#}", anyhow::Error::from(e)),
+        }
+        if args.cancellation.is_cancelled() {
+            return Ok(());
         }
     }
+}
 
+async fn stress(args: &Args, target: SocketAddr, connections: usize, rate: u32, fresh_identities: bool, own_target: bool) -> anyhow::Result<()> {
+    if !own_target {
+        bail!("The stress subcommand loads the QUIC ingress of the target, only run it against a validator you operate, with --own-target");
+    }
+    let identity = match &args.identity {
+        Some(path) => Some(read_keypair_file(path).map_err(|e| anyhow!("Failed to read identity {}: {}", path.display(), e))?),
+        None => None,
+    };
+    let options = StressOptions {
+        connections,
+        rate,
+        connection_timeout: Duration::from_millis(args.timeout),
+        identity,
+        fresh_identities,
+        bind: args.bind,
+        interface: args.interface.clone(),
+    };
+    let report = solana_distance::stress::run(target, &options, &args.cancellation).await?;
+    match args.output {
+        OutputFormat::Json => report.print_json(),
+        OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => report.print_text(),
+    }
+    Ok(())
+}
 
-    let endpoint = new_quic_endpoint(&Keypair::new(), 0).await;
+/// Start the dashboard of `--tui`, if requested.
+fn start_tui(args: &Args) -> anyhow::Result<Option<Tui>> {
+    if !args.tui {
+        return Ok(None);
+    }
+    if args.prometheus_listen.is_some() {
+        bail!("--tui cannot be used with the serve subcommand");
+    }
+    dashboard(args).map(Some)
+}
 
-    let temporization = tpus.len() > 1;
-    for (sock_addr, tpu) in &mut tpus {
-        tpu.join = Some(tokio::spawn(latency(endpoint.clone(), *sock_addr, args.count, temporization)));
+#[cfg(feature = "tui")]
+fn dashboard(args: &Args) -> anyhow::Result<Tui> {
+    Tui::start(args.cancellation.clone()).context("Cannot start the dashboard")
+}
+
+#[cfg(not(feature = "tui"))]
+fn dashboard(_args: &Args) -> anyhow::Result<Tui> {
+    bail!("--tui requires the tui feature, this binary was built without it")
+}
+
+/// Log the diagnostics of this crate on stderr, warnings by default, more with each -v, only errors with
+/// --quiet, and nothing over the dashboard of --tui.
+fn init_logging(args: &Args) {
+    let level = match (args.quiet, args.tui, args.verbose) {
+        (_, true, _) => LevelFilter::OFF,
+        (true, _, _) => LevelFilter::ERROR,
+        (_, _, 0) => LevelFilter::WARN,
+        (_, _, 1) => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    // The dependencies, e.g. quinn and rustls, are too verbose at debug level
+    let filter = Targets::new().with_target("solana_distance", level);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr).with_target(false))
+        .with(filter)
+        .init();
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+
+    let mut args = Args::parse_with_config()?;
+    init_logging(&args);
+    args.apply_command()?;
+    args.apply_cluster()?;
+    args.apply_solana_config()?;
+    args.hooks = args.on_result.clone().map(|command| Arc::new(Hooks::new(command, hook::MAX_RUNNING)));
+    let hooks = args.hooks.clone();
+    let result = run(args).await;
+    // The commands of the last results may still be running
+    if let Some(hooks) = hooks {
+        hooks.wait();
     }
+    result
+}
 
-    let mut lat_sum_w = 0;
-    let mut lat_sum = 0;
-    let mut lat_cnt = 0;
-    let mut lat_stk = 0;
+async fn run(args: Args) -> anyhow::Result<()> {
 
-    let mut var_sum_w = 0;
-    let mut var_sum = 0;
+    if let Some(Command::Targets { action }) = &args.command {
+        if manage_targets(action).await? {
+            return Ok(());
+        }
+    }
 
-    for (sock_addr, tpu) in tpus {
-        match tpu.join {
-            Some(join) => {
-                if args.details {
-                    if total_stake > 0 {
-                        print!("{:21} {:>9} SOL {:?} ", sock_addr, tpu.stake / 1_000_000_000, tpu.ids);
-                    } else {
-                        print!("{:21} {:?} ", sock_addr, tpu.ids);
-                    }
+    if args.capabilities || args.features {
+        let capabilities = if args.capabilities { capabilities::probe() } else { capabilities::features() };
+        match args.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&capabilities)?),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => capabilities::print_text(&capabilities),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Snapshot { action: SnapshotAction::Diff { old, new, stake_change_pct } }) = &args.command {
+        let diff = TopologyDiff::compare(old, new, *stake_change_pct).context("Failed to read snapshots")?;
+        match args.output {
+            OutputFormat::Json => diff.print_json(),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => diff.print_text(),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Merge { reports }) = &args.command {
+        let merged = MergedReport::merge(reports).context("Failed to merge shards")?;
+        match args.output {
+            OutputFormat::Json => merged.print_json(),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => merged.print_text(),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Placement { agents, iterations }) = &args.command {
+        let placement = Placement::estimate(agents, *iterations).context("Failed to estimate the best location")?;
+        match args.output {
+            OutputFormat::Json => placement.print_json(),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => placement.print_text(),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::History { days, period, pubkey }) = &args.command {
+        return history(&args, *days, *period, pubkey.as_deref());
+    }
+
+    if let Some(Command::Remote { hosts, binary, args: remote_args }) = &args.command {
+        // A Ctrl-C interrupts the SSH sessions too, which share the terminal
+        let options = RemoteOptions { binary: binary.clone(), args: remote_args.clone() };
+        let report = RemoteReport::run(hosts, &options);
+        match args.output {
+            OutputFormat::Json => report.print_json(),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => report.print_text(),
+        }
+        if report.vantages.iter().all(|v| v.error.is_some()) {
+            bail!("No host could measure");
+        }
+        return Ok(());
+    }
+
+    cancel_on_ctrl_c(args.cancellation.clone());
+    if let Some(&Command::Stress { target, connections, rate, fresh_identities, own_target }) = args.command.as_ref() {
+        return stress(&args, target, connections, rate, fresh_identities, own_target).await;
+    }
+    if let Some(Command::Repl) = &args.command {
+        let budget_time = args.budget_time.map(Duration::try_from_secs_f64).transpose().context("Invalid time budget")?;
+        return repl(&args, budget_time).await;
+    }
+    if let Some(path) = &args.plan {
+        if args.output == OutputFormat::Csv {
+            // Rows of the groups, with different probes, wouldn't share the same columns
+            bail!("CSV output is not available with a plan");
+        }
+        if args.output == OutputFormat::Html {
+            bail!("HTML output is not available with a plan");
+        }
+        if args.output == OutputFormat::Influx {
+            bail!("InfluxDB output is not available with a plan");
+        }
+        let plan = Plan::load(path).with_context(|| format!("Invalid plan {}", path.display()))?;
+        return run_plan(Arc::new(args), plan).await;
+    }
+
+    let budget_time = args.budget_time.map(Duration::try_from_secs_f64).transpose().context("Invalid time budget")?;
+    if args.doublezero && args.destination.len() > 1 {
+        return measure_networks(&args, budget_time).await;
+    }
+    if let Some((first, second)) = args.compare {
+        let destinations = destinations(&args).await?;
+        let path = |ip: IpAddr| -> anyhow::Result<DistanceMeasurer> {
+            Ok(builder(&args)?
+                .destinations(destinations.clone())
+                .count(args.count)
+                .budget_time(budget_time)
+                .bind(Some(SocketAddr::new(ip, 0)))
+                .build())
+        };
+        let (mut first_measurer, mut second_measurer) = (path(first)?, path(second)?);
+        let (first_report, second_report) = tokio::try_join!(first_measurer.measure(), second_measurer.measure())?;
+        let comparison = PathComparison::new(first.to_string(), &first_report, second.to_string(), &second_report);
+        match args.output {
+            OutputFormat::Json => comparison.print_json(),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => comparison.print_text(args.details),
+        }
+        return Ok(());
+    }
+
+    let mut builder = builder(&args)?
+        .destinations(destinations(&args).await?)
+        .count(args.count)
+        .budget_time(budget_time);
+    // Only the text output is meant to be read on a terminal as the measure goes
+    if let Some(line) = (args.output == OutputFormat::Text && !args.tui && !args.no_progress && !args.quiet).then(ProgressLine::new).flatten() {
+        builder = builder.on_progress(move |progress| line.update(progress));
+    }
+    let mut measurer = builder.build();
+
+    if args.estimate {
+        let estimate = measurer.estimate()?;
+        match args.output {
+            OutputFormat::Json => estimate.print_json(),
+            OutputFormat::Text | OutputFormat::Csv | OutputFormat::Html | OutputFormat::Influx => estimate.print_text(),
+        }
+        return Ok(());
+    }
+
+    if args.output == OutputFormat::Html {
+        if args.watch.is_some() || args.prometheus_listen.is_some() || args.tui {
+            bail!("HTML output is only available for a single measure");
+        }
+        if args.geoip.is_empty() {
+            bail!("HTML output requires a City database of --geoip to plot the validators");
+        }
+    }
+    let databases = Databases::load(&args).await?;
+    let alert_thresholds = args.alert_thresholds();
+    let mut watchdog = (!args.critical.is_empty() || !args.slo.is_empty() || alert_thresholds.is_some())
+        .then(|| Watchdog::new(args.critical.clone(), args.slo.clone(), alert_thresholds));
+    let metrics = match args.prometheus_listen {
+        Some(addr) => Some(prometheus::spawn_server(addr).await.with_context(|| format!("Cannot listen on {}", addr))?),
+        None => args.textfile.as_ref().map(|_| Default::default()),
+    };
+
+    let tui = start_tui(&args)?;
+    let Some(period) = args.watch.or(args.prometheus_listen.map(|_| PROMETHEUS_PERIOD)).or(tui.as_ref().map(|_| Duration::ZERO)) else {
+        let report = measure(&args, &mut measurer, &mut watchdog, &databases).await?;
+        match args.output {
+            OutputFormat::Text => {
+                if args.details && budget_time.is_some() {
+                    println!("Time budget: {} attempts every {} ms", report.attempts, report.interval_ms);
                 }
-                match join.await {
-                    Ok((u32::MAX, _)) => {
-                        errors.new_and_print_if(ConnectionFailed, tpu.stake, args.details);
-                    }
-                    Ok((lat, u64::MAX)) => {
-                        // Ignore this measure if args.count > 1 since we won't be able to
-                        // compute global variance
-                        if args.count == 1 {
-                            if total_stake > 0 {
-                                lat_sum_w += lat as u128 * tpu.stake as u128;
-                                lat_stk += tpu.stake;
-                            }
-                            lat_sum += lat as u64;
-                            lat_cnt += 1;
-                            if args.details {
-                                println!("{} µs", lat);
-                            }
-                        } else {
-                            errors.new_and_print_if(OnlyOneSuccessfulConnection, tpu.stake, args.details);
-                        }
-                    }
-                    Ok((lat, var)) => {
-                        if total_stake > 0 {
-                            lat_sum_w += lat as u128 * tpu.stake as u128;
-                            lat_stk += tpu.stake;
-                            var_sum_w += var as u128 * tpu.stake as u128;
-                        }
-                        lat_sum += lat as u64;
-                        lat_cnt += 1;
-                        var_sum += var as u128;
+                match args.quiet {
+                    true => report.print_aggregates(),
+                    false => report.print_text(args.details, args.limit),
+                }
+            }
+            OutputFormat::Json => report.print_json(),
+            OutputFormat::Csv => report.print_csv(true),
+            OutputFormat::Html => print!("{}", report::to_html(&report)),
+            OutputFormat::Influx => report.print_influx(),
+        }
+        let exceeded = args.thresholds().exceeded(&report);
+        if let (Some(metrics), Some(path)) = (&metrics, &args.textfile) {
+            let mut metrics = metrics.lock().unwrap();
+            metrics.update(report);
+            prometheus::write_textfile(path, &metrics).with_context(|| format!("Cannot write {}", path.display()))?;
+        }
+        if !exceeded.is_empty() {
+            for threshold in exceeded {
+                error!("{}", threshold);
+            }
+            if let Some(hooks) = &args.hooks {
+                hooks.wait();
+            }
+            process::exit(EXIT_THRESHOLD);
+        }
+        return Ok(());
+    };
+
+    // Keep the endpoint, and re-run the discovery at each round to follow changes in the cluster: only the first
+    // round may use the cache file
+    let mut next_round = tokio::time::Instant::now();
+    let mut csv_header = true;
+    loop {
+        match measure(&args, &mut measurer, &mut watchdog, &databases).await {
+            Ok(report) => {
+                match args.output {
+                    _ if tui.is_some() => (),
+                    OutputFormat::Text => {
                         if args.details {
-                            println!("{} µs ± {} µs", lat, var.isqrt());
+                            report.print_details(args.limit);
                         }
+                        report.print_round()
                     }
-                    Err(_) => {
-                        errors.new_and_print_if(ConnectionError, tpu.stake, args.details);
+                    OutputFormat::Json => report.print_json_line(),
+                    OutputFormat::Csv => {
+                        report.print_csv(csv_header);
+                        csv_header = false;
                     }
+                    OutputFormat::Html => unreachable!("HTML output is rejected with --watch"),
+                    OutputFormat::Influx => report.print_influx(),
+                }
+                if let Some(metrics) = &metrics {
+                    let mut metrics = metrics.lock().unwrap();
+                    metrics.update(report);
+                    if let Some(path) = &args.textfile {
+                        // Keep measuring, the file may be writable again at the next round
+                        if let Err(e) = prometheus::write_textfile(path, &metrics) {
+                            warn!("Cannot write {}: {}", path.display(), e);
+                        }
+                    }
+                } else if let Some(tui) = &tui {
+                    tui.update(report);
                 }
             }
-            None => {
-            }
+            // Skip the round, the next one may succeed
+            Err(e) => match &tui {
+                Some(tui) => tui.fail(format!("{:#}", anyhow::Error::from(e))),
+                None => error!("Measure failed: {:#}", anyhow::Error::from(e)),
+            },
         }
-    }
-
-    if lat_cnt > 0 {
-        if args.count > 1 {
-            println!("Simple distance: {} ± {} µs", lat_sum / lat_cnt as u64, (var_sum / lat_cnt).isqrt());
-        } else {
-            println!("Simple distance: {} µs", lat_sum / lat_cnt as u64);
+        if args.cancellation.is_cancelled() {
+            return Ok(());
         }
-        if total_stake > 0 {
-            if args.count > 1 {
-                println!("Stake-weighted distance: {} ± {} µs", lat_sum_w / lat_stk as u128, (var_sum_w / lat_stk as u128).isqrt());
-            } else {
-                println!("Stake-weighted distance: {} µs", lat_sum_w / lat_stk as u128);
-            }
-            println!("Total stake: {} SOL", lat_stk / 1_000_000_000);
+        measurer.refresh_cluster();
+        next_round = next_round.add(period).max(tokio::time::Instant::now());
+        if !pause(&args, next_round - tokio::time::Instant::now()).await {
+            return Ok(());
         }
-        println!("Connection successful: {}", lat_cnt);
     }
-
-    for (error, (cnt, stk)) in &errors.0 {
-        if total_stake > 0 && *error != NotAStakedNode {
-            println!("{}: {} ({:.2}% of total stake)", error, cnt, 100.0 * *stk as f64 / (total_stake as f64));
-        } else {
-            println!("{}: {}", error, cnt);
-        }
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,948 @@
+use crate::address::{is_local, PortType};
+use crate::cancel::Cancellation;
+use crate::discovery::{Targets, Tpu};
+use crate::events::{EventSenders, MeasurementEvent};
+use crate::fallback;
+use crate::gossip;
+use crate::leader::LeaderSlots;
+use crate::metadata::RunMetadata;
+use crate::progress::Progress;
+use crate::output::{client_from_version, SCHEMA_VERSION, Distance, DistanceReport, DualStackReport, ErrorReport, Estimate, GroupReport, MtuProbe, QuicStats, Resumption, RttStats, StakePercentiles, SteadyStateReport, TpuReport, UdpReport};
+use crate::ratelimit::RateLimiter;
+use crate::quic::{client_config, mtu_discovery_client_config, server_identity, socket_addr_to_quic_server_name, IdentityRotator, MIN_MTU, QUIC_KEEP_ALIVE, STEADY_KEEP_ALIVE};
+use crate::udp::{OsUdpDrops, PacketTrace, UdpCounters};
+use crate::{Config, IdentityRotation};
+use crate::NodeError::{Cancelled, ConnectionError, ConnectionFailed, IdentityMismatch, ImplausiblyLow, NotAStakedNode, OnlyOneSuccessfulConnection};
+use quinn::{ClientConfig, Connection, Endpoint, VarInt};
+use rand::Rng;
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::ops::Add;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{sleep_until, timeout, Instant};
+use tracing::{debug, warn};
+
+pub(crate) const LEADER_WINDOW: Duration = Duration::from_millis(4 * 400); // 4 slots
+pub(crate) const CONNECTION_TIMEOUT: Duration = LEADER_WINDOW;
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+/// Packets and bytes sent by a QUIC handshake and its close, mostly Initial packets padded to 1200 bytes
+const QUIC_PACKETS: u64 = 3;
+const QUIC_BYTES: u64 = 2700;
+/// Packets and bytes sent by an empty stream opened after a handshake
+const STREAM_PACKETS: u64 = 1;
+const STREAM_BYTES: u64 = 60;
+/// Bytes sent by a keep-alive ping of a QUIC connection
+const KEEP_ALIVE_BYTES: u64 = 50;
+/// Packets and bytes sent by a gossip ping
+const GOSSIP_PACKETS: u64 = 1;
+const GOSSIP_BYTES: u64 = 132;
+/// Packets and bytes sent by a TCP connection and its close: SYN, ACK, FIN and ACK with their headers
+const TCP_PACKETS: u64 = 4;
+const TCP_BYTES: u64 = 220;
+/// Time given to path MTU discovery, a search taking a few round trips, more when probes are lost
+const MTU_PROBE_TIME: Duration = Duration::from_secs(3);
+
+/// Return the number of connection attempts and their spacing fitting in `budget`.
+///
+/// Attempts are kept `interval` apart as long as the budget allows at least two of them,
+/// otherwise the spacing is reduced since two samples are needed to estimate the variance.
+fn plan_for_budget(budget: Duration, interval: Duration, connection_timeout: Duration, temporization: bool) -> (usize, Duration) {
+    let fixed = if temporization { connection_timeout + interval.min(LEADER_WINDOW) } else { connection_timeout };
+    let available = budget.saturating_sub(fixed);
+    let count = (available.as_micros() / interval.as_micros().max(1)) as usize + 1;
+    if count >= 2 {
+        (count, interval)
+    } else if available >= MIN_INTERVAL {
+        (2, available)
+    } else {
+        (1, interval)
+    }
+}
+
+/// Connection attempts performed to measure a number of TPUs.
+#[derive(Clone, Copy)]
+struct Schedule {
+    max_concurrent: Option<usize>,
+    batches: u32,
+    temporization: bool,
+    count: usize,
+    interval: Duration,
+    /// Time budget of each TPU, its share of the time budget of the measure
+    budget: Option<Duration>,
+}
+
+impl Schedule {
+    fn new(config: &Config, tpus: usize) -> Schedule {
+        // With a concurrency limit, TPUs are measured in successive batches which share the time budget
+        let max_concurrent = config.max_concurrent.map(|max| max.max(1));
+        let concurrent = max_concurrent.map_or(tpus, |max| max.min(tpus));
+        let batches = tpus.div_ceil(concurrent.max(1)).max(1) as u32;
+        let temporization = concurrent > 1;
+        let (count, interval) = match config.budget_time {
+            Some(budget) => plan_for_budget(budget / batches, config.interval, config.connection_timeout, temporization),
+            None => (config.count, config.interval),
+        };
+        let budget = config.budget_time.map(|budget| budget / batches);
+        Schedule { max_concurrent, batches, temporization, count, interval, budget }
+    }
+
+    /// Return the longest time the attempts can take, when the temporization is maximal and the last
+    /// attempt of each batch times out.
+    fn max_duration(&self, connection_timeout: Duration) -> Duration {
+        let temporization = if self.temporization { self.interval.min(LEADER_WINDOW) } else { Duration::ZERO };
+        let batch = temporization + self.interval * (self.count as u32).saturating_sub(1) + connection_timeout;
+        batch * self.batches
+    }
+}
+
+/// Return the expected duration and traffic of the measure of `targets`, without measuring them.
+pub(crate) fn estimate(config: &Config, targets: &Targets) -> Estimate {
+    let schedule = Schedule::new(config, targets.tpus.len());
+    let quic = matches!(config.port_type, PortType::Tpu | PortType::TpuForwards);
+    let persistent = if quic && config.steady_state { targets.tpus.len() } else { 0 };
+    // At most, every TPU is reachable and connected to twice more
+    let resumed = if quic && config.resumption { 2 * targets.tpus.len() } else { 0 };
+    let mtu_probed = if quic && config.mtu_probe { targets.tpus.len() } else { 0 };
+    let connections = targets.tpus.len() * schedule.count + persistent + resumed + mtu_probed;
+    let (packets, bytes) = match config.port_type {
+        PortType::Tpu | PortType::TpuForwards if config.stream_open => (QUIC_PACKETS + STREAM_PACKETS, QUIC_BYTES + STREAM_BYTES),
+        PortType::Tpu | PortType::TpuForwards => (QUIC_PACKETS, QUIC_BYTES),
+        PortType::Gossip => (GOSSIP_PACKETS, GOSSIP_BYTES),
+        PortType::Rpc => (TCP_PACKETS, TCP_BYTES),
+    };
+    // Persistent connections send a keep-alive ping during the attempts
+    let pings = persistent as u64 * (schedule.interval * (schedule.count as u32).saturating_sub(1)).div_duration_f64(STEADY_KEEP_ALIVE) as u64;
+    Estimate {
+        schema_version: SCHEMA_VERSION,
+        tpus: targets.tpus.len(),
+        attempts: schedule.count,
+        interval_ms: schedule.interval.as_millis() as u64,
+        batches: schedule.batches,
+        max_duration_ms: schedule.max_duration(config.connection_timeout).as_millis() as u64,
+        connections,
+        tx_packets: connections as u64 * packets + pings,
+        tx_bytes: connections as u64 * bytes + pings * KEEP_ALIVE_BYTES,
+    }
+}
+
+/// Results of the connection attempts to a TPU.
+struct Attempts {
+    /// Number of attempts done, fewer than scheduled if cancelled or past the deadline
+    done: usize,
+    /// True if the measure was cancelled before the end of its schedule
+    cancelled: bool,
+    /// RTTs of the successful attempts
+    samples: Vec<u32>,
+    /// Smoothed RTT of the persistent connection, if requested and it stayed open
+    steady_rtt: Option<u32>,
+    /// Durations of the successful handshakes, along with the persistent connection
+    connect_times: Vec<u32>,
+    /// Times from opening a stream to the TPU reading it to its end, if requested
+    stream_times: Vec<u32>,
+    /// Statistics of the QUIC path over the successful handshakes
+    quic_stats: Option<QuicStats>,
+    /// Identity in the certificate of the server of the last successful handshake
+    server_identity: Option<Pubkey>,
+}
+
+/// Latency estimate of a TPU, in µs. `distance` is `u32::MAX` if no connection succeeded,
+/// and `variance` is `u64::MAX` if fewer than two connections succeeded.
+pub(crate) struct Measure {
+    /// Number of connection attempts done
+    attempts: usize,
+    cancelled: bool,
+    distance: u32,
+    variance: u64,
+    min: u32,
+    /// RTTs of the successful connection attempts
+    samples: Vec<u32>,
+    /// Smoothed RTT of the persistent connection, if requested and it stayed open
+    steady_rtt: Option<u32>,
+    /// Median duration of the successful handshakes, along with the persistent connection
+    connect_time: Option<u32>,
+    /// Median time from opening a stream to the TPU reading it to its end, if requested
+    stream_time: Option<u32>,
+    /// Statistics of the QUIC path over the successful handshakes
+    quic_stats: Option<QuicStats>,
+    /// Identity in the certificate of the server of the last successful handshake
+    server_identity: Option<Pubkey>,
+    /// RTT of the fallback probe of the host, if no connection succeeded
+    network_rtt: Option<u32>,
+    /// Handshakes with and without session resumption, if requested and a connection succeeded
+    resumption: Option<Resumption>,
+    /// Path MTU, if requested and a connection succeeded
+    mtu_probe: Option<MtuProbe>,
+}
+
+impl Measure {
+    /// Return the latency estimate and its variance from the RTTs of the successful connection attempts,
+    /// leaving out the `drop_worst` highest ones, as long as two remain.
+    ///
+    /// We assume latencies follow a 2-parameter exponential distribution:
+    /// p(x) = 1/b exp(-(x-a)/b)
+    /// Parameters are estimated using unbiased MLE:
+    /// https://www.researchgate.net/publication/233060006_Estimation_in_two-parameter_exponential_distributions
+    /// a = (n*min(x) - mean(x))/(n-1)
+    /// b = n*(mean(x) - min(x))/(n-1)
+    /// var(a) = b^2 / (n(n-1))
+    fn new(attempts: Attempts, drop_worst: usize) -> Measure {
+        let Attempts { done, cancelled, samples, steady_rtt, mut connect_times, mut stream_times, quic_stats, server_identity } = attempts;
+        let attempts = done;
+        connect_times.sort_unstable();
+        let connect_time = connect_times.get(connect_times.len() / 2).copied();
+        stream_times.sort_unstable();
+        let stream_time = stream_times.get(stream_times.len() / 2).copied();
+        let mut kept = samples.clone();
+        kept.sort_unstable();
+        kept.truncate(kept.len().saturating_sub(drop_worst).max(kept.len().min(2)));
+        let lat_cnt = kept.len() as u64;
+        let lat_min = kept.first().map_or(u32::MAX, |rtt| rtt / 2);
+        if lat_cnt < 2 {
+            Measure { attempts, cancelled, distance: lat_min, variance: u64::MAX, min: lat_min, samples, steady_rtt, connect_time, stream_time, quic_stats, server_identity, network_rtt: None, resumption: None, mtu_probe: None }
+        } else {
+            let lat_sum: u64 = kept.iter().map(|rtt| (rtt / 2) as u64).sum();
+            let lat_mean = lat_sum / lat_cnt;
+            // With outliers, the estimate of a can be negative, bound it to 0
+            let a = (lat_cnt * lat_min as u64).saturating_sub(lat_mean) / (lat_cnt - 1);
+            let b = (lat_cnt * (lat_mean - lat_min as u64)) / (lat_cnt - 1);
+            Measure { attempts, cancelled, distance: a.try_into().expect("rtt overflow"), variance: (b*b)/(lat_cnt*(lat_cnt-1)), min: lat_min, samples, steady_rtt, connect_time, stream_time, quic_stats, server_identity, network_rtt: None, resumption: None, mtu_probe: None }
+        }
+    }
+}
+
+/// Way of timing a round trip to a measured address.
+#[derive(Clone)]
+enum Prober {
+    /// QUIC handshake, with this endpoint, this configuration of a persistent connection if requested,
+    /// identities of the new connections if they are rotated, and a stream opened after the handshake if requested
+    Quic(Endpoint, Option<ClientConfig>, Option<Arc<IdentityRotator>>, bool),
+    /// Gossip ping
+    Gossip,
+    /// TCP connection
+    Tcp,
+}
+
+/// Wait until `instant`, returning false if the measure is cancelled first.
+async fn wait_until(instant: Instant, cancellation: &Cancellation) -> bool {
+    tokio::select! {
+        _ = sleep_until(instant) => true,
+        _ = cancellation.cancelled() => false,
+    }
+}
+
+/// Return the results of the connection attempts, with the smoothed RTT of a persistent connection kept open
+/// during the attempts, if requested.
+///
+/// Send `count` connection requests, spaced `interval` apart (4 slots by default), to give a good chance that
+/// at least one request doesn't arrive when the validator is busy being leader.
+/// Add a random temporization if requested, and delay the requests falling in the leader slots of the validator,
+/// if they are known.
+/// Stop as soon as `cancellation` is cancelled, and don't start attempts after the first one which couldn't time
+/// out within the time budget of the TPU, if any, e.g. when delayed by leader slots.
+/// Delay the attempts as needed by `rate_limiter`, if any, for those to co-located TPUs to be spread out.
+/// Send the result of each attempt to the subscribers of `events`.
+#[allow(clippy::too_many_arguments)]
+async fn latency(prober: Prober, addr: SocketAddr, schedule: Schedule, connection_timeout: Duration, leader_slots: Option<LeaderSlots>,
+                 rate_limiter: Option<Arc<RateLimiter>>, trace: Option<Arc<PacketTrace>>, cancellation: Cancellation, events: EventSenders) -> Attempts {
+    let Schedule { count, interval, temporization, budget, .. } = schedule;
+    let server_name = socket_addr_to_quic_server_name(addr);
+    let trace = trace.as_deref();
+    let mut attempts = Attempts { done: 0, cancelled: false, samples: Vec::with_capacity(count), steady_rtt: None, connect_times: Vec::new(), stream_times: Vec::new(), quic_stats: None, server_identity: None };
+    if temporization {
+        let delay= rand::rng().random_range(Duration::ZERO..interval.min(LEADER_WINDOW));
+        if let Some(trace) = trace {
+            trace.event(format_args!("waiting {} ms before first attempt", delay.as_millis()));
+        }
+        if !wait_until(Instant::now() + delay, &cancellation).await {
+            attempts.cancelled = true;
+            return attempts;
+        }
+    }
+    let steady = match &prober {
+        Prober::Quic(endpoint, Some(config), ..) => persistent_connection(endpoint, config, &server_name, addr, connection_timeout).await,
+        _ => None,
+    };
+    let mut t = Instant::now();
+    // Like the schedule, the time budget of the TPU sets aside the longest temporization
+    let max_temporization = if temporization { interval.min(LEADER_WINDOW) } else { Duration::ZERO };
+    let deadline = budget.map(|budget| t + budget.saturating_sub(max_temporization));
+    for i in 0..count {
+        if i > 0 {
+            t = t.add(interval);
+        }
+        let mut start = t;
+        if let Some(free) = leader_slots.as_ref().map(|l| l.next_free(t)).filter(|free| *free > t) {
+            if let Some(trace) = trace {
+                trace.event(format_args!("waiting {} ms for the end of leader slots", (free - t).as_millis()));
+            }
+            start = free;
+        }
+        if let Some(limited) = rate_limiter.as_ref().map(|r| r.reserve(addr.ip(), start)).filter(|limited| *limited > start) {
+            if let Some(trace) = trace {
+                trace.event(format_args!("waiting {} ms for the rate limit of the host", (limited - start).as_millis()));
+            }
+            start = limited;
+        }
+        if i > 0 && deadline.is_some_and(|deadline| start + connection_timeout > deadline) {
+            if let Some(trace) = trace {
+                trace.event(format_args!("time budget exhausted after {} attempts", i));
+            }
+            break;
+        }
+        if !wait_until(start, &cancellation).await {
+            attempts.cancelled = true;
+            break;
+        }
+        let attempt = async {
+            match &prober {
+                Prober::Quic(endpoint, steady_config, rotator, stream_open) => {
+                    let config = rotator.as_ref().map(|r| r.next());
+                    match ping(endpoint, config, &server_name, addr, connection_timeout, *stream_open, trace).await {
+                        Some(phases) => (phases.rtt, steady_config.is_some().then_some(phases.connect), phases.stream, Some(phases.stats), phases.server_identity),
+                        None => (u32::MAX, None, None, None, None),
+                    }
+                }
+                Prober::Gossip => (gossip_ping(addr, connection_timeout).await, None, None, None, None),
+                Prober::Tcp => (tcp_connect(addr, connection_timeout).await, None, None, None, None),
+            }
+        };
+        // An attempt in progress is abandoned, dropping its connection
+        let (rtt, connect_time, stream_time, stats, server_identity) = tokio::select! {
+            result = attempt => result,
+            _ = cancellation.cancelled() => {
+                attempts.cancelled = true;
+                break;
+            }
+        };
+        attempts.done += 1;
+        events.send(|| MeasurementEvent::AttemptCompleted { address: addr, attempt: attempts.done, rtt_us: (rtt != u32::MAX).then_some(rtt) });
+        attempts.connect_times.extend(connect_time);
+        attempts.stream_times.extend(stream_time);
+        if server_identity.is_some() {
+            attempts.server_identity = server_identity;
+        }
+        if let Some(stats) = stats {
+            attempts.quic_stats.get_or_insert_with(QuicStats::default).add(&stats);
+        }
+        if rtt != u32::MAX {
+            attempts.samples.push(rtt);
+        }
+    }
+    attempts.steady_rtt = steady.and_then(|connection| {
+        let rtt = connection.close_reason().is_none().then(|| connection.rtt().as_micros().try_into().expect("rtt overflow"));
+        connection.close(VarInt::default(), &[]);
+        rtt
+    });
+    attempts
+}
+
+/// Return a connection kept alive with frequent pings, whose smoothed RTT follows the steady-state data path.
+async fn persistent_connection(endpoint: &Endpoint, config: &ClientConfig, server_name: &str, addr: SocketAddr,
+                               connection_timeout: Duration) -> Option<Connection> {
+    let connecting = endpoint.connect_with(config.clone(), addr, server_name).ok()?;
+    timeout(connection_timeout, connecting).await.ok()?.ok()
+}
+
+/// Return the RTT in µs of a gossip ping, or `u32::MAX` if no pong was received.
+async fn gossip_ping(addr: SocketAddr, timeout: Duration) -> u32 {
+    let ping = tokio::task::spawn_blocking(move || gossip::ping(&addr.to_string(), 1, Duration::ZERO, timeout));
+    match ping.await {
+        Ok(Ok(rtts)) => rtts.first().copied().unwrap_or(u32::MAX),
+        _ => u32::MAX,
+    }
+}
+
+/// Return the handshake durations of a new connection to `addr` and of a second one resuming its session, or
+/// `None` if either failed. The connections have a configuration of their own, for the first one not to resume
+/// the session of another.
+async fn resume(endpoint: &Endpoint, identity: &Keypair, addr: SocketAddr, connection_timeout: Duration) -> Option<Resumption> {
+    let config = client_config(identity, QUIC_KEEP_ALIVE, false);
+    let server_name = socket_addr_to_quic_server_name(addr);
+    let start = Instant::now();
+    let connecting = endpoint.connect_with(config.clone(), addr, &server_name).ok()?;
+    let first = timeout(connection_timeout, connecting).await.ok()?.ok()?;
+    let full = start.elapsed();
+    // The session tickets are sent by the server once it receives the end of the handshake
+    tokio::time::sleep(2 * first.rtt()).await;
+    first.close(VarInt::default(), &[]);
+
+    let start = Instant::now();
+    let connecting = endpoint.connect_with(config, addr, &server_name).ok()?;
+    // Without a ticket allowing early data, the connection is resumed, if at all, by a 1-RTT handshake
+    let zero_rtt = match connecting.into_0rtt() {
+        Ok((connection, accepted)) => {
+            let accepted = timeout(connection_timeout, accepted).await.ok()?;
+            connection.close(VarInt::default(), &[]);
+            accepted
+        }
+        Err(connecting) => {
+            timeout(connection_timeout, connecting).await.ok()?.ok()?.close(VarInt::default(), &[]);
+            false
+        }
+    };
+    let resumed = start.elapsed();
+    tracing::trace!("Handshake with {} took {} µs, resumed {} µs, 0-RTT {}", addr, full.as_micros(), resumed.as_micros(), zero_rtt);
+    Some(Resumption { full_handshake_us: full.as_micros() as u64, resumed_handshake_us: resumed.as_micros() as u64, zero_rtt })
+}
+
+/// Return the path MTU to `addr` found by discovery on a connection kept open `MTU_PROBE_TIME`, or `None` if the
+/// connection failed or the measure was cancelled. A path is deemed to black-hole large datagrams if quinn detected
+/// it, or if all the probes above the minimum MTU were lost.
+async fn probe_mtu(endpoint: &Endpoint, identity: &Keypair, addr: SocketAddr, connection_timeout: Duration, cancellation: &Cancellation) -> Option<MtuProbe> {
+    let server_name = socket_addr_to_quic_server_name(addr);
+    let connecting = endpoint.connect_with(mtu_discovery_client_config(identity), addr, &server_name).ok()?;
+    let connection = timeout(connection_timeout, connecting).await.ok()?.ok()?;
+    let probed = wait_until(Instant::now() + MTU_PROBE_TIME, cancellation).await;
+    let path = connection.stats().path;
+    connection.close(VarInt::default(), &[]);
+    probed.then_some(MtuProbe {
+        mtu: path.current_mtu,
+        probes_sent: path.sent_plpmtud_probes,
+        probes_lost: path.lost_plpmtud_probes,
+        black_hole: path.black_holes_detected > 0 || (path.current_mtu <= MIN_MTU && path.lost_plpmtud_probes > 0),
+    })
+}
+
+/// Return the time in µs taken to establish a TCP connection, one RTT, or `u32::MAX` if it failed.
+async fn tcp_connect(addr: SocketAddr, connection_timeout: Duration) -> u32 {
+    let start = tokio::time::Instant::now();
+    match timeout(connection_timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => start.elapsed().as_micros().try_into().expect("rtt overflow"),
+        _ => u32::MAX,
+    }
+}
+
+/// Durations of the phases of a successful connection attempt, in µs.
+struct Phases {
+    /// RTT estimate of the connection
+    rtt: u32,
+    /// Time taken by the handshake, including the processing of the server
+    connect: u32,
+    /// Time from opening a unidirectional stream to the server reading it to its end, if requested and it did
+    stream: Option<u32>,
+    /// Statistics of the path at the end of the handshake
+    stats: QuicStats,
+    /// Identity of the validator in the certificate of the server, if it has an Ed25519 key
+    server_identity: Option<Pubkey>,
+}
+
+/// Return the time in µs from opening a unidirectional stream on `connection` to the server reading it to its end,
+/// or `None` if it failed or timed out. Opening waits for the server to grant a stream, which unstaked connections
+/// get few of. The stream is empty, leaving the TPU no transaction to process.
+async fn open_stream(connection: &Connection, connection_timeout: Duration) -> Option<u32> {
+    let start = Instant::now();
+    let stream = async {
+        let mut send = connection.open_uni().await.ok()?;
+        send.finish().ok()?;
+        // The server may also stop the stream, refusing it
+        matches!(send.stopped().await, Ok(None)).then_some(())
+    };
+    timeout(connection_timeout, stream).await.ok()??;
+    Some(start.elapsed().as_micros().try_into().expect("stream time overflow"))
+}
+
+/// Return the phases of a connection, with `config` rather than the default one of the endpoint if specified, and
+/// followed by a stream if `stream_open` is set, or `None` if it failed.
+async fn ping(endpoint: &Endpoint, config: Option<ClientConfig>, server_name: &str, tpu_quic: SocketAddr, connection_timeout: Duration,
+              stream_open: bool, trace: Option<&PacketTrace>) -> Option<Phases> {
+    let start = tokio::time::Instant::now();
+    let connecting = match config {
+        Some(config) => endpoint.connect_with(config, tpu_quic, server_name),
+        None => endpoint.connect(tpu_quic, server_name),
+    };
+    let mut connecting = connecting.expect("Connection configuration error");
+    let handshake = async move {
+        if let Some(trace) = trace {
+            trace.event(format_args!("connection attempt started"));
+            if connecting.handshake_data().await.is_ok() {
+                trace.event(format_args!("handshake data received from server"));
+            }
+        }
+        connecting.await
+    };
+    match timeout(connection_timeout, handshake).await {
+        Ok(Ok(connection)) => {
+            // With a timeout of 2 s, rtt in µs should never overflow u32.
+            let rtt: u32 = connection.rtt().as_micros().try_into().expect("rtt overflow");
+            let connect_time: u32 = start.elapsed().as_micros().try_into().expect("connect time overflow");
+            let path = connection.stats().path;
+            if let Some(trace) = trace {
+                trace.event(format_args!("1-RTT established, rtt {} µs, min rtt {} µs, {} packets sent, {} lost",
+                                         rtt, path.min_rtt.as_micros(), path.sent_packets, path.lost_packets));
+            }
+            let stats = QuicStats {
+                sent_packets: path.sent_packets,
+                lost_packets: path.lost_packets,
+                congestion_events: path.congestion_events,
+                min_cwnd: path.cwnd,
+                mtu: path.current_mtu,
+            };
+            // Qualified, `trace` being the packet trace of the target
+            tracing::trace!("Handshake with {} completed, rtt {} µs, connect time {} µs", tpu_quic, rtt, connect_time);
+            let stream = match stream_open {
+                true => open_stream(&connection, connection_timeout).await,
+                false => None,
+            };
+            if let (true, Some(trace)) = (stream_open, trace) {
+                match stream {
+                    Some(stream) => trace.event(format_args!("stream read by server after {} µs", stream)),
+                    None => trace.event(format_args!("stream not read by server")),
+                }
+            }
+            let server_identity = server_identity(&connection);
+            connection.close(VarInt::default(), &[]);
+            Some(Phases { rtt, connect: connect_time, stream, stats, server_identity })
+        }
+        Ok(Err(e)) => {
+            if let Some(trace) = trace {
+                trace.event(format_args!("connection failed: {}", e));
+            }
+            debug!("Handshake with {} failed: {}", tpu_quic, e);
+            None
+        }
+        Err(_) => {
+            if let Some(trace) = trace {
+                trace.event(format_args!("connection timed out"));
+            }
+            debug!("Handshake with {} timed out after {} ms", tpu_quic, connection_timeout.as_millis());
+            None
+        }
+    }
+}
+
+/// Return the configuration re-measuring the identities of the TPUs of `report` without any successful connection,
+/// if they are at least `threshold_pct` % of the measured TPUs, with the addresses they failed at.
+pub(crate) fn stale_targets(config: &Config, report: &DistanceReport, threshold_pct: f64) -> Option<(Config, HashSet<SocketAddr>)> {
+    let measured = report.tpus.iter().filter(|t| !t.alternate).count();
+    let failed_error = ConnectionFailed.to_string();
+    // Addresses given as destinations don't come from the snapshot
+    let failed: Vec<&TpuReport> = report.tpus.iter()
+        .filter(|t| !t.alternate && !t.pubkeys.is_empty() && t.error.as_ref() == Some(&failed_error))
+        .collect();
+    if report.cancelled || failed.is_empty() || (failed.len() as f64) < threshold_pct / 100.0 * measured as f64 {
+        return None;
+    }
+    let mut config = config.clone();
+    config.destinations = failed.iter().flat_map(|t| t.pubkeys.iter().cloned()).collect();
+    // The identities were already selected, and their alternate addresses measured
+    config.shard = None;
+    config.alt_tpus.clear();
+    Some((config, failed.iter().map(|t| t.address).collect()))
+}
+
+/// Measure the distance to all TPUs of `targets`.
+///
+/// New connections are made with `endpoint`, persistent ones with `steady_config` if specified, and the
+/// connections with a configuration of their own, e.g. timing session resumption, with `identity`.
+/// Delay the attempts as needed by `rate_limiter`, if any.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn measure_targets(config: &Config, endpoint: &Endpoint, steady_config: Option<&ClientConfig>, identity: &Arc<Keypair>,
+                                    udp_counters: &UdpCounters, targets: Targets, rate_limiter: Option<Arc<RateLimiter>>,
+                                    trace: Option<Arc<PacketTrace>>) -> DistanceReport {
+    let timestamp = SystemTime::now();
+    let Targets { mut tpus, total_stake, mut errors, leader_schedule } = targets;
+    let udp_start = udp_counters.totals();
+    let os_drops_start = OsUdpDrops::snapshot();
+
+    let schedule = Schedule::new(config, tpus.len());
+    let Schedule { max_concurrent, count, interval, .. } = schedule;
+    let semaphore = max_concurrent.map(|max| Arc::new(Semaphore::new(max)));
+    let rotator = match config.rotate_identity {
+        Some(IdentityRotation::Connections(every)) => Some(Arc::new(IdentityRotator::new(every, config.verify_identity))),
+        _ => None,
+    };
+    let prober = match config.port_type {
+        PortType::Tpu | PortType::TpuForwards => Prober::Quic(endpoint.clone(), steady_config.cloned(), rotator, config.stream_open),
+        PortType::Gossip => Prober::Gossip,
+        PortType::Rpc => Prober::Tcp,
+    };
+    let mut joins = JoinSet::new();
+    let mut spawned = HashMap::new();
+    let spawn = |joins: &mut JoinSet<Measure>, sock_addr: SocketAddr, tpu: &Tpu| {
+        let trace = trace.clone().filter(|t| t.addr() == sock_addr);
+        let leader_slots = leader_schedule.as_ref().map(|s| s.slots_of(&tpu.ids));
+        let prober = match (&prober, config.rotate_identity) {
+            (Prober::Quic(endpoint, steady_config, _, stream_open), Some(IdentityRotation::Target)) =>
+                Prober::Quic(endpoint.clone(), steady_config.clone(), Some(Arc::new(IdentityRotator::new(usize::MAX, config.verify_identity))), *stream_open),
+            _ => prober.clone(),
+        };
+        // Session resumption and MTU discovery only apply to QUIC
+        let quic = match &prober {
+            Prober::Quic(endpoint, ..) => Some((endpoint.clone(), identity.clone())),
+            _ => None,
+        };
+        let cancellation = config.cancellation.clone();
+        let measure = latency(prober, sock_addr, schedule, config.connection_timeout, leader_slots, rate_limiter.clone(), trace, cancellation.clone(),
+                              config.events.clone());
+        let semaphore = semaphore.clone();
+        let drop_worst = config.drop_worst;
+        let (fallback_probe, connection_timeout) = (config.fallback_probe, config.connection_timeout);
+        let (resumption, mtu_probe) = (config.resumption, config.mtu_probe);
+        joins.spawn(async move {
+            // The temporization and the time budget of each TPU start once it gets a slot, the measure ending at
+            // once if cancelled meanwhile
+            let _permit = match semaphore {
+                Some(semaphore) => tokio::select! {
+                    permit = semaphore.acquire_owned() => Some(permit.expect("Semaphore closed")),
+                    _ = cancellation.cancelled() => None,
+                },
+                None => None,
+            };
+            let mut measure = Measure::new(measure.await, drop_worst);
+            if measure.distance == u32::MAX && !measure.cancelled {
+                measure.network_rtt = fallback::network_rtt(fallback_probe, sock_addr.ip(), connection_timeout).await;
+            }
+            if let Some((endpoint, identity)) = quic.filter(|_| measure.distance != u32::MAX && !cancellation.is_cancelled()) {
+                if resumption {
+                    measure.resumption = resume(&endpoint, &identity, sock_addr, connection_timeout).await;
+                }
+                if mtu_probe {
+                    measure.mtu_probe = probe_mtu(&endpoint, &identity, sock_addr, connection_timeout, &cancellation).await;
+                }
+            }
+            measure
+        }).id()
+    };
+    for (sock_addr, tpu) in &tpus {
+        spawned.insert(spawn(&mut joins, *sock_addr, tpu), *sock_addr);
+    }
+    let started = Instant::now();
+    let mut progress = Progress {
+        completed: 0,
+        total: tpus.len(),
+        successful: 0,
+        failed: 0,
+        elapsed: Duration::ZERO,
+        max_duration: schedule.max_duration(config.connection_timeout),
+    };
+    if let Some(on_progress) = &config.on_progress {
+        on_progress(&progress);
+    }
+
+    let mut lat_sum_w = 0;
+    let mut lat_sum = 0;
+    let mut lat_cnt = 0;
+    let mut lat_stk = 0;
+
+    let mut var_sum_w = 0;
+    let mut var_sum = 0;
+
+    let mut tpu_reports = Vec::new();
+
+    // Handle the TPUs as their measure completes, for `on_result` to be called as soon as possible. TPUs without
+    // any successful connection are measured again, after the others, up to `retries` times.
+    let mut pass = 0;
+    let mut failed = Vec::new();
+    let mut cancelled = false;
+    loop {
+        while let Some(joined) = joins.join_next_with_id().await {
+            let (id, measure) = match joined {
+                Ok((id, m)) => (id, Ok(m)),
+                Err(e) => (e.id(), Err(e)),
+            };
+            let sock_addr = spawned[&id];
+            if let Err(e) = &measure {
+                warn!("Measure of {} failed: {}", sock_addr, e);
+            }
+            if let Ok(m) = &measure {
+                cancelled |= m.cancelled;
+            }
+            if pass < config.retries && !config.cancellation.is_cancelled() && matches!(&measure, Ok(m) if m.distance == u32::MAX && !m.cancelled) {
+                failed.push(sock_addr);
+                continue;
+            }
+            let tpu = tpus.remove(&sock_addr).expect("Unknown measured TPU");
+            let mut report = TpuReport {
+                address: sock_addr,
+                pubkeys: tpu.ids,
+                names: Vec::new(),
+                stake: tpu.stake,
+                client: client_from_version(tpu.version.as_deref()),
+                version: tpu.version,
+                min_rtt_us: None,
+                distance_us: None,
+                stddev_us: None,
+                rtt: None,
+                steady_rtt_us: None,
+                connect_us: None,
+                stream_us: None,
+                resumption: measure.as_ref().ok().and_then(|m| m.resumption.clone()),
+                mtu_probe: measure.as_ref().ok().and_then(|m| m.mtu_probe.clone()),
+                quic: measure.as_ref().ok().and_then(|m| m.quic_stats.clone()),
+                server_identity: None,
+                geo: None,
+                doublezero: None,
+                band: None,
+                one_way: None,
+                network_distance_us: measure.as_ref().ok().and_then(|m| m.network_rtt).map(|rtt| rtt / 2),
+                error: None,
+                alternate: tpu.alternate,
+                advertised: tpu.advertised,
+                hostname: tpu.hostname,
+            };
+            // The identity of the server, if verified and the TPU answered with another one than its node's
+            let mismatch = measure.as_ref().ok().and_then(|m| m.server_identity).map(|id| id.to_string())
+                .filter(|id| config.verify_identity && !report.pubkeys.is_empty() && !report.pubkeys.contains(id));
+            if tpu.alternate {
+                match measure {
+                    Ok(m) if m.attempts == 0 && m.cancelled => report.error = Some(Cancelled.to_string()),
+                    Ok(m) if m.distance == u32::MAX => report.error = Some(ConnectionFailed.to_string()),
+                    Ok(m) if mismatch.is_some() => {
+                        report.min_rtt_us = Some(2 * m.min as u64);
+                        report.rtt = RttStats::from_samples(&m.samples);
+                        report.distance_us = Some(m.distance);
+                        report.server_identity = mismatch;
+                        report.error = Some(IdentityMismatch.to_string());
+                    }
+                    Ok(m) => {
+                        report.min_rtt_us = Some(2 * m.min as u64);
+                        report.rtt = RttStats::from_samples(&m.samples);
+                        report.steady_rtt_us = m.steady_rtt.map(u64::from);
+                        report.connect_us = m.connect_time.map(u64::from);
+                        report.stream_us = m.stream_time.map(u64::from);
+                        report.distance_us = Some(m.distance);
+                        report.stddev_us = (m.variance != u64::MAX).then(|| m.variance.isqrt());
+                    }
+                    Err(_) => report.error = Some(ConnectionError.to_string()),
+                }
+            } else {
+                let error = match measure {
+                    Ok(m) if m.attempts == 0 && m.cancelled => Some(Cancelled),
+                    Ok(m) if m.distance == u32::MAX => Some(ConnectionFailed),
+                    Ok(m) if mismatch.is_some() => {
+                        report.min_rtt_us = Some(2 * m.min as u64);
+                        report.rtt = RttStats::from_samples(&m.samples);
+                        report.distance_us = Some(m.distance);
+                        report.server_identity = mismatch;
+                        Some(IdentityMismatch)
+                    }
+                    Ok(m) if m.distance < config.plausibility_floor && !is_local(&sock_addr.ip()) => {
+                        report.min_rtt_us = Some(2 * m.min as u64);
+                        report.rtt = RttStats::from_samples(&m.samples);
+                        report.steady_rtt_us = m.steady_rtt.map(u64::from);
+                        report.connect_us = m.connect_time.map(u64::from);
+                        report.stream_us = m.stream_time.map(u64::from);
+                        report.distance_us = Some(m.distance);
+                        Some(ImplausiblyLow)
+                    }
+                    Ok(m) if m.variance == u64::MAX => {
+                        report.min_rtt_us = Some(2 * m.min as u64);
+                        report.rtt = RttStats::from_samples(&m.samples);
+                        report.steady_rtt_us = m.steady_rtt.map(u64::from);
+                        report.connect_us = m.connect_time.map(u64::from);
+                        report.stream_us = m.stream_time.map(u64::from);
+                        // Ignore this measure if count > 1 since we won't be able to
+                        // compute global variance
+                        if count == 1 {
+                            if total_stake > 0 {
+                                lat_sum_w += m.distance as u128 * tpu.stake as u128;
+                                lat_stk += tpu.stake;
+                            }
+                            lat_sum += m.distance as u64;
+                            lat_cnt += 1;
+                            report.distance_us = Some(m.distance);
+                            None
+                        } else {
+                            Some(OnlyOneSuccessfulConnection)
+                        }
+                    }
+                    Ok(m) => {
+                        if total_stake > 0 {
+                            lat_sum_w += m.distance as u128 * tpu.stake as u128;
+                            lat_stk += tpu.stake;
+                            var_sum_w += m.variance as u128 * tpu.stake as u128;
+                        }
+                        lat_sum += m.distance as u64;
+                        lat_cnt += 1;
+                        var_sum += m.variance as u128;
+                        report.min_rtt_us = Some(2 * m.min as u64);
+                        report.rtt = RttStats::from_samples(&m.samples);
+                        report.steady_rtt_us = m.steady_rtt.map(u64::from);
+                        report.connect_us = m.connect_time.map(u64::from);
+                        report.stream_us = m.stream_time.map(u64::from);
+                        report.distance_us = Some(m.distance);
+                        report.stddev_us = Some(m.variance.isqrt());
+                        None
+                    }
+                    Err(_) => Some(ConnectionError),
+                };
+                if let Some(error) = error {
+                    report.error = Some(error.to_string());
+                    errors.add(error, tpu.stake);
+                }
+            }
+            debug!("Measured {}: distance {:?} µs, error {:?}", report.address, report.distance_us, report.error);
+            if let Some(on_result) = &config.on_result {
+                on_result(&report);
+            }
+            config.events.send(|| MeasurementEvent::TargetFinished(Box::new(report.clone())));
+            if let Some(on_progress) = &config.on_progress {
+                progress.completed += 1;
+                match (&report.error, report.distance_us) {
+                    (None, Some(_)) => progress.successful += 1,
+                    _ => progress.failed += 1,
+                }
+                progress.elapsed = started.elapsed();
+                on_progress(&progress);
+            }
+            tpu_reports.push(report);
+        }
+        if failed.is_empty() {
+            break;
+        }
+        pass += 1;
+        for sock_addr in failed.drain(..) {
+            spawned.insert(spawn(&mut joins, sock_addr, &tpus[&sock_addr]), sock_addr);
+        }
+    }
+
+    let rtt = RttStats::mean(tpu_reports.iter().filter(|t| t.error.is_none() && !t.alternate).filter_map(|t| t.rtt.as_ref()));
+    let dual_stack = DualStackReport::compare(&tpu_reports);
+    let steady_state = SteadyStateReport::compare(&tpu_reports);
+    let resolved: Vec<TpuReport> = tpu_reports.iter().filter(|t| t.hostname.is_some()).cloned().collect();
+    let hostnames = (!resolved.is_empty()).then(|| GroupReport::group_by(&resolved, |t| t.hostname.clone().unwrap_or_default()));
+    let os_drops = os_drops_start.zip(OsUdpDrops::snapshot()).map(|(start, end)| end.since(&start));
+    let udp = udp_counters.totals().since(&udp_start);
+
+    let (simple, stake_weighted) = if lat_cnt > 0 {
+        let simple = Distance {
+            distance_us: lat_sum / lat_cnt as u64,
+            stddev_us: (count > 1).then(|| (var_sum / lat_cnt).isqrt() as u64),
+        };
+        let stake_weighted = (total_stake > 0).then(|| Distance {
+            distance_us: (lat_sum_w / lat_stk as u128) as u64,
+            stddev_us: (count > 1).then(|| (var_sum_w / lat_stk as u128).isqrt() as u64),
+        });
+        (Some(simple), stake_weighted)
+    } else {
+        (None, None)
+    };
+    let stake_percentiles = stake_weighted.as_ref().and_then(|_| StakePercentiles::compute(&tpu_reports));
+
+    DistanceReport {
+        schema_version: SCHEMA_VERSION,
+        shard: config.shard,
+        timestamp: humantime::format_rfc3339_seconds(timestamp).to_string(),
+        metadata: RunMetadata::local(),
+        attempts: count,
+        interval_ms: interval.as_millis() as u64,
+        cancelled,
+        tpus: tpu_reports,
+        simple,
+        stake_weighted,
+        stake_percentiles,
+        measured_stake: lat_stk,
+        total_stake,
+        connection_successful: lat_cnt as u64,
+        rtt,
+        clients: None,
+        countries: None,
+        asns: None,
+        providers: None,
+        segments: None,
+        hostnames,
+        stake_coverage: None,
+        histogram: None,
+        bands: None,
+        peering: None,
+        routing: None,
+        traceroutes: None,
+        dual_stack,
+        steady_state,
+        tx_landing: None,
+        stale_refresh: None,
+        one_way: None,
+        imported: None,
+        errors: errors.0.iter().map(|(error, (cnt, stk))| ErrorReport {
+            error: error.to_string(),
+            count: *cnt,
+            stake: (total_stake > 0 && *error != NotAStakedNode).then_some(*stk),
+        }).collect(),
+        udp: UdpReport {
+            tx_datagrams: udp.tx_datagrams,
+            tx_bytes: udp.tx_bytes,
+            tx_errors: udp.tx_errors,
+            rx_datagrams: udp.rx_datagrams,
+            rx_bytes: udp.rx_bytes,
+            os_rcvbuf_errors: os_drops.map(|d| d.rcvbuf_errors),
+            os_sndbuf_errors: os_drops.map(|d| d.sndbuf_errors),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(1600);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn measure(samples: &[u32], drop_worst: usize) -> Measure {
+        let attempts = Attempts { done: samples.len(), cancelled: false, samples: samples.to_vec(), steady_rtt: None, connect_times: Vec::new(),
+                                  stream_times: Vec::new(), quic_stats: None, server_identity: None };
+        Measure::new(attempts, drop_worst)
+    }
+
+    #[test]
+    fn fits_attempts_interval_apart_in_the_budget() {
+        // The last attempt, at 8 s, times out by the end of the budget
+        assert_eq!(plan_for_budget(ms(10_000), ms(1600), TIMEOUT, false), (6, ms(1600)));
+        assert_eq!(plan_for_budget(ms(9_599), ms(1600), TIMEOUT, false), (5, ms(1600)));
+        // The temporization takes up to one interval, bounded by the leader window
+        assert_eq!(plan_for_budget(ms(10_000), ms(1600), TIMEOUT, true), (5, ms(1600)));
+        assert_eq!(plan_for_budget(ms(30_000), ms(10_000), TIMEOUT, true), (3, ms(10_000)));
+    }
+
+    #[test]
+    fn reduces_the_interval_for_two_attempts() {
+        assert_eq!(plan_for_budget(ms(2_000), ms(1600), TIMEOUT, false), (2, ms(400)));
+        assert_eq!(plan_for_budget(ms(1_700), ms(1600), TIMEOUT, false), (2, MIN_INTERVAL));
+        assert_eq!(plan_for_budget(ms(3_300), ms(1600), TIMEOUT, true), (2, ms(100)));
+    }
+
+    #[test]
+    fn makes_a_single_attempt_without_budget_for_two() {
+        assert_eq!(plan_for_budget(ms(1_699), ms(1600), TIMEOUT, false), (1, ms(1600)));
+        assert_eq!(plan_for_budget(ms(1_000), ms(1600), TIMEOUT, false), (1, ms(1600)));
+        assert_eq!(plan_for_budget(Duration::ZERO, ms(1600), TIMEOUT, true), (1, ms(1600)));
+    }
+
+    #[test]
+    fn drops_the_highest_rtts() {
+        // Latencies of 50, 100 and 150 µs kept
+        let m = measure(&[2000, 100, 300, 1000, 200], 2);
+        assert_eq!((m.distance, m.variance, m.min), (25, 937, 50));
+        // All samples are still reported
+        assert_eq!(m.samples.len(), 5);
+        assert_eq!(m.attempts, 5);
+        let m = measure(&[2000, 100, 300, 1000, 200], 0);
+        assert_eq!((m.distance, m.min), (0, 50));
+    }
+
+    #[test]
+    fn keeps_two_rtts() {
+        // Latencies of 100 and 105 µs kept, rather than only the lowest
+        assert_eq!(measure(&[1000, 210, 200], 5).distance, 98);
+        assert_eq!(measure(&[1000, 210, 200], 2).distance, 98);
+        assert_eq!(measure(&[1000, 210, 200], 1).distance, 98);
+        assert_eq!(measure(&[1000, 210, 200], 0).distance, 32);
+        assert_eq!(measure(&[210, 200], 1).variance, 8);
+    }
+
+    #[test]
+    fn has_no_variance_below_two_rtts() {
+        let m = measure(&[300], 1);
+        assert_eq!((m.distance, m.variance, m.min), (150, u64::MAX, 150));
+        let m = measure(&[], 1);
+        assert_eq!((m.distance, m.variance), (u32::MAX, u64::MAX));
+    }
+}
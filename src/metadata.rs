@@ -0,0 +1,63 @@
+//! Context of a measure recorded in its report, to compare the reports of different hosts or days: the host and
+//! its public address, the version of the tool, and the RPC endpoint with the epoch and slot at the start of the
+//! attempts.
+
+use serde::Serialize;
+use std::fs;
+use std::net::IpAddr;
+use std::process::Command;
+use std::time::Duration;
+use tracing::debug;
+
+/// Service answering HTTP requests with the public address of the client, as plain text
+pub const DEFAULT_IP_ECHO_URL: &str = "https://api.ipify.org";
+/// Timeout of the request to the IP echo service
+const IP_ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Context of a measure.
+#[derive(Serialize, Clone)]
+pub struct RunMetadata {
+    pub tool_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Public address of the host, with `--public-ip`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_ip: Option<IpAddr>,
+    /// RPC endpoint the epoch and slot were read from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// Position of the slot in its epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot_index: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slots_in_epoch: Option<u64>,
+}
+
+impl RunMetadata {
+    /// Return the metadata of the local host, without public address nor cluster state.
+    pub(crate) fn local() -> RunMetadata {
+        RunMetadata { tool_version: env!("CARGO_PKG_VERSION"), hostname: hostname(), public_ip: None, rpc_url: None, epoch: None, slot: None, slot_index: None, slots_in_epoch: None }
+    }
+}
+
+/// Return the name of the host, read from the kernel on Linux, otherwise from the hostname command.
+fn hostname() -> Option<String> {
+    let name = fs::read_to_string("/proc/sys/kernel/hostname").ok().or_else(|| {
+        let output = Command::new("hostname").output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    })?;
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/// Return the public address of the host, as seen by the HTTP echo service at `url`.
+pub async fn public_ip(url: &str) -> Result<IpAddr, String> {
+    let client = reqwest::Client::builder().timeout(IP_ECHO_TIMEOUT).build().map_err(|e| e.to_string())?;
+    let body = client.get(url).send().await.and_then(|r| r.error_for_status()).map_err(|e| e.to_string())?
+        .text().await.map_err(|e| e.to_string())?;
+    debug!("Public address from {}: {}", url, body.trim());
+    body.trim().parse().map_err(|_| format!("Unexpected response {}", body.chars().take(50).collect::<String>().trim()))
+}
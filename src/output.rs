@@ -0,0 +1,1132 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use serde::Serialize;
+use crate::export;
+use crate::geo::GeoInfo;
+use crate::import::ImportReport;
+use crate::influx;
+use crate::metadata::RunMetadata;
+use crate::peering;
+use crate::peering::PeeringCandidate;
+use crate::provider;
+use crate::provider::ProviderReport;
+use crate::quic::MIN_MTU;
+use crate::routing;
+use crate::routing::RouteContext;
+use crate::traceroute;
+use crate::traceroute::TraceReport;
+use crate::band::BandReport;
+use crate::owd::{OneWay, OneWayReport};
+use crate::shard::Shard;
+use crate::tx::TxLanding;
+
+pub const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+/// Version of the structure of the JSON outputs, `schema_version` of each document, increased when a field is
+/// removed or changes meaning. Documents written before versioning have no `schema_version`, read as 0.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Result of the measure of a single TPU. Stakes are in lamports, durations in µs.
+#[derive(Serialize, Clone)]
+pub struct TpuReport {
+    pub address: SocketAddr,
+    pub pubkeys: Vec<String>,
+    /// Names of the validators of the pubkeys, with `--names`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    pub stake: u64,
+    pub version: Option<String>,
+    pub client: &'static str,
+    pub min_rtt_us: Option<u64>,
+    pub distance_us: Option<u32>,
+    pub stddev_us: Option<u64>,
+    /// Distribution of the RTTs of the successful connection attempts
+    pub rtt: Option<RttStats>,
+    /// Smoothed RTT of a persistent connection, with `--steady-state`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub steady_rtt_us: Option<u64>,
+    /// Median duration of the handshakes, including the processing of the TPU, with `--steady-state`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_us: Option<u64>,
+    /// Median time from opening a unidirectional stream to the TPU reading it to its end, with `--stream-open`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_us: Option<u64>,
+    /// Handshakes with and without session resumption, with `--resumption`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumption: Option<Resumption>,
+    /// Path MTU, with `--mtu-probe`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtu_probe: Option<MtuProbe>,
+    /// Statistics of the QUIC path over the successful handshakes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quic: Option<QuicStats>,
+    /// Identity in the certificate of the TPU, when it isn't the identity of its node, with `--verify-identity`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_identity: Option<String>,
+    /// Location and network of the TPU, with `--geoip`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoInfo>,
+    /// Whether the identity is connected to Doublezero, with the InfluxDB outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doublezero: Option<bool>,
+    /// Latency band of the distance, with `--bands`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub band: Option<String>,
+    /// Range of the one-way delay, with `--one-way`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_way: Option<OneWay>,
+    /// Half of the RTT of the fallback probe of the host, when no connection to the TPU succeeded, with
+    /// `--fallback-probe`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_distance_us: Option<u32>,
+    pub error: Option<String>,
+    /// Additional address of an identity, left out of the aggregates
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub alternate: bool,
+    /// For the addresses of a failover target, whether gossip currently advertises it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub advertised: Option<bool>,
+    /// Hostname of the destination resolving to the address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+/// Distribution of RTTs, in µs. Percentiles use the nearest-rank method.
+#[derive(Serialize, Clone, Copy)]
+pub struct RttStats {
+    pub min_us: u64,
+    pub median_us: u64,
+    pub p95_us: u64,
+    pub max_us: u64,
+    /// Standard deviation of the RTTs
+    pub jitter_us: u64,
+}
+
+impl RttStats {
+    /// Return the distribution of `samples`, or `None` if there are none.
+    pub fn from_samples(samples: &[u32]) -> Option<RttStats> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let rank = |p: usize| sorted[(p * n).div_ceil(100).max(1) - 1] as u64;
+        let mean = sorted.iter().map(|s| *s as u64).sum::<u64>() / n as u64;
+        let variance = sorted.iter().map(|s| (*s as u64).abs_diff(mean).pow(2)).sum::<u64>() / n as u64;
+        Some(RttStats {
+            min_us: sorted[0] as u64,
+            median_us: rank(50),
+            p95_us: rank(95),
+            max_us: sorted[n - 1] as u64,
+            jitter_us: variance.isqrt(),
+        })
+    }
+
+    /// Return the average of each statistic over `stats`, or `None` if empty.
+    pub fn mean<'a>(stats: impl Iterator<Item = &'a RttStats>) -> Option<RttStats> {
+        let (cnt, sum) = stats.fold((0, [0; 5]), |(cnt, sum), s| {
+            (cnt + 1, [sum[0] + s.min_us, sum[1] + s.median_us, sum[2] + s.p95_us, sum[3] + s.max_us, sum[4] + s.jitter_us])
+        });
+        (cnt > 0).then(|| RttStats {
+            min_us: sum[0] / cnt,
+            median_us: sum[1] / cnt,
+            p95_us: sum[2] / cnt,
+            max_us: sum[3] / cnt,
+            jitter_us: sum[4] / cnt,
+        })
+    }
+}
+
+impl std::fmt::Display for RttStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "min/median/p95/max {}/{}/{}/{} µs, jitter {} µs", self.min_us, self.median_us, self.p95_us, self.max_us, self.jitter_us)
+    }
+}
+
+/// Statistics of the QUIC path at the end of the successful handshakes of a TPU, summed over them, telling losses
+/// and retransmissions from propagation delay. Quinn doesn't expose the number of probe timeouts, which the lost
+/// packets account for.
+#[derive(Serialize, Clone, Default)]
+pub struct QuicStats {
+    pub sent_packets: u64,
+    /// Packets declared lost and retransmitted
+    pub lost_packets: u64,
+    /// Losses or ECN marks reducing the congestion window
+    pub congestion_events: u64,
+    /// Smallest congestion window, in bytes
+    pub min_cwnd: u64,
+    /// Largest datagram size of the path, in bytes, the minimum of 1280 unless discovered
+    pub mtu: u16,
+}
+
+impl QuicStats {
+    /// Add the statistics of another connection.
+    pub(crate) fn add(&mut self, other: &QuicStats) {
+        self.min_cwnd = if self.sent_packets == 0 { other.min_cwnd } else { self.min_cwnd.min(other.min_cwnd) };
+        self.sent_packets += other.sent_packets;
+        self.lost_packets += other.lost_packets;
+        self.congestion_events += other.congestion_events;
+        self.mtu = self.mtu.max(other.mtu);
+    }
+}
+
+/// Path MTU found by discovery on a connection kept open, with `--mtu-probe`.
+#[derive(Serialize, Clone)]
+pub struct MtuProbe {
+    /// Largest datagram size of the path, in bytes
+    pub mtu: u16,
+    pub probes_sent: u64,
+    pub probes_lost: u64,
+    /// Whether the path drops the datagrams larger than the minimum MTU of QUIC, 1280 bytes
+    pub black_hole: bool,
+}
+
+/// Handshake durations of a new connection and of a second one resuming its TLS session, with `--resumption`.
+/// Durations are in µs.
+#[derive(Serialize, Clone)]
+pub struct Resumption {
+    pub full_handshake_us: u64,
+    pub resumed_handshake_us: u64,
+    /// Whether the TPU accepted early data, sent before the end of the resumed handshake
+    pub zero_rtt: bool,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Distance {
+    pub distance_us: u64,
+    pub stddev_us: Option<u64>,
+}
+
+/// Aggregate result of a group of TPUs.
+#[derive(Serialize)]
+pub struct GroupReport {
+    pub name: String,
+    pub connection_successful: u64,
+    pub measured_stake: u64,
+    pub distance_us: Option<u64>,
+    pub stake_weighted_distance_us: Option<u64>,
+}
+
+impl GroupReport {
+    /// Aggregate successfully measured TPUs by the group name returned by `key`, sorted by name.
+    pub fn group_by<K: Fn(&TpuReport) -> String>(tpus: &[TpuReport], key: K) -> Vec<GroupReport> {
+        let mut groups: BTreeMap<String, (u64, u64, u64, u128)> = BTreeMap::new();
+        for tpu in tpus {
+            if let (Some(distance), None, false) = (tpu.distance_us, &tpu.error, tpu.alternate) {
+                let g = groups.entry(key(tpu)).or_default();
+                g.0 += 1;
+                g.1 += tpu.stake;
+                g.2 += distance as u64;
+                g.3 += distance as u128 * tpu.stake as u128;
+            }
+        }
+        groups.into_iter().map(|(name, (cnt, stk, sum, sum_w))| GroupReport {
+            name,
+            connection_successful: cnt,
+            measured_stake: stk,
+            distance_us: (cnt > 0).then(|| sum / cnt),
+            stake_weighted_distance_us: (stk > 0).then(|| (sum_w / stk as u128) as u64),
+        }).collect()
+    }
+
+    fn print_text(&self) {
+        print!("  {}: ", self.name);
+        if let Some(distance) = self.distance_us {
+            print!("{} µs", distance);
+        }
+        if let Some(distance) = self.stake_weighted_distance_us {
+            print!(", stake-weighted {} µs, {} SOL", distance, self.measured_stake / LAMPORTS_PER_SOL);
+        }
+        println!(", {} connections", self.connection_successful);
+    }
+}
+
+/// Distance within which the closest TPUs reach a share of the stake.
+#[derive(Serialize)]
+pub struct StakeCoverage {
+    /// Share of the total stake, in %
+    pub stake_pct: f64,
+    /// Distance of the farthest of the closest TPUs reaching the share, `None` if the measured stake doesn't
+    pub distance_us: Option<u32>,
+    /// Number of the closest TPUs reaching the share
+    pub tpus: u64,
+}
+
+impl StakeCoverage {
+    /// Return the distance at which the cumulative stake of the successfully measured TPUs, closest first,
+    /// reaches each of the `thresholds` % of `total_stake`.
+    pub fn curve(tpus: &[TpuReport], total_stake: u64, thresholds: &[f64]) -> Vec<StakeCoverage> {
+        let mut measured: Vec<(u32, u64)> = tpus.iter()
+            .filter(|t| t.error.is_none() && !t.alternate)
+            .filter_map(|t| Some((t.distance_us?, t.stake)))
+            .collect();
+        measured.sort_unstable();
+        let cumulative: Vec<u128> = measured.iter().scan(0u128, |sum, (_, stake)| {
+            *sum += *stake as u128;
+            Some(*sum)
+        }).collect();
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_by(f64::total_cmp);
+        thresholds.into_iter().map(|stake_pct| {
+            let target = stake_pct / 100.0 * total_stake as f64;
+            let reached = (total_stake > 0).then(|| cumulative.iter().position(|c| *c as f64 >= target)).flatten();
+            StakeCoverage {
+                stake_pct,
+                distance_us: reached.map(|i| measured[i].0),
+                tpus: reached.map_or(0, |i| i as u64 + 1),
+            }
+        }).collect()
+    }
+
+    fn print_text(&self, total_stake: u64) {
+        match self.distance_us {
+            Some(distance) => println!("  {}% of stake: {} µs, {} TPUs", self.stake_pct, distance, self.tpus),
+            None if total_stake == 0 => println!("  {}% of stake: no stake", self.stake_pct),
+            None => println!("  {}% of stake: not reached by the measured TPUs", self.stake_pct),
+        }
+    }
+}
+
+/// Distances below which lie shares of the measured stake, in µs: unlike the stake-weighted mean, they aren't
+/// pulled down by a few close high-stake validators hiding a long tail.
+#[derive(Serialize, Clone, Copy)]
+pub struct StakePercentiles {
+    pub p50_us: u32,
+    pub p90_us: u32,
+    pub p99_us: u32,
+}
+
+impl StakePercentiles {
+    /// Return the stake-weighted percentiles of the distances of the successfully measured TPUs, `None` if they have
+    /// no stake.
+    pub fn compute(tpus: &[TpuReport]) -> Option<StakePercentiles> {
+        let measured: u64 = tpus.iter().filter(|t| t.error.is_none() && !t.alternate && t.distance_us.is_some()).map(|t| t.stake).sum();
+        let curve = StakeCoverage::curve(tpus, measured, &[50.0, 90.0, 99.0]);
+        Some(StakePercentiles { p50_us: curve[0].distance_us?, p90_us: curve[1].distance_us?, p99_us: curve[2].distance_us? })
+    }
+}
+
+/// Successfully measured TPUs within a range of distances. Stakes are in lamports, distances in µs.
+#[derive(Serialize)]
+pub struct HistogramBucket {
+    pub from_us: u32,
+    /// End of the range, excluded, `None` for the last bucket holding all the farther TPUs
+    pub to_us: Option<u32>,
+    pub tpus: u64,
+    pub stake: u64,
+}
+
+impl HistogramBucket {
+    /// Most buckets of a histogram, the last one holding the TPUs beyond
+    const MAX_BUCKETS: u32 = 20;
+    /// Width of the longest bar of the text histograms, in characters
+    const BAR_WIDTH: u64 = 40;
+
+    /// Return the successfully measured TPUs in buckets `width_us` wide, from 0 to the farthest TPU.
+    pub fn histogram(tpus: &[TpuReport], width_us: u32) -> Vec<HistogramBucket> {
+        let measured: Vec<(u32, u64)> = tpus.iter()
+            .filter(|t| t.error.is_none() && !t.alternate)
+            .filter_map(|t| Some((t.distance_us?, t.stake)))
+            .collect();
+        let width_us = width_us.max(1);
+        let Some(farthest) = measured.iter().map(|(distance, _)| *distance).max() else { return Vec::new() };
+        let count = (farthest / width_us + 1).min(Self::MAX_BUCKETS);
+        let mut buckets: Vec<HistogramBucket> = (0..count).map(|i| HistogramBucket {
+            from_us: i * width_us,
+            to_us: (i + 1 < count).then(|| (i + 1) * width_us),
+            tpus: 0,
+            stake: 0,
+        }).collect();
+        for (distance, stake) in measured {
+            let bucket = &mut buckets[(distance / width_us).min(count - 1) as usize];
+            bucket.tpus += 1;
+            bucket.stake += stake;
+        }
+        buckets
+    }
+
+    fn label(&self) -> String {
+        match self.to_us {
+            Some(to) => format!("{}–{} ms", self.from_us as f64 / 1000.0, to as f64 / 1000.0),
+            None => format!("≥{} ms", self.from_us as f64 / 1000.0),
+        }
+    }
+
+    /// Print a bar per bucket of `value`, the largest being `BAR_WIDTH` long, followed by `annotation`.
+    fn print_bars(buckets: &[HistogramBucket], value: impl Fn(&HistogramBucket) -> u64, annotation: impl Fn(&HistogramBucket) -> String) {
+        let largest = buckets.iter().map(&value).max().unwrap_or(0).max(1);
+        let label_width = buckets.iter().map(|b| b.label().chars().count()).max().unwrap_or(0);
+        for bucket in buckets {
+            let label = bucket.label();
+            let bar = "#".repeat((value(bucket) as u128 * Self::BAR_WIDTH as u128 / largest as u128) as usize);
+            println!("  {}{} |{:<bar_width$} {}", label, " ".repeat(label_width - label.chars().count()), bar, annotation(bucket),
+                     bar_width = Self::BAR_WIDTH as usize);
+        }
+    }
+
+    fn print_text(buckets: &[HistogramBucket], measured_stake: u64) {
+        println!("Distance histogram:");
+        Self::print_bars(buckets, |b| b.tpus, |b| format!("{} TPUs", b.tpus));
+        println!("Stake-weighted distance histogram:");
+        Self::print_bars(buckets, |b| b.stake, |b| match measured_stake {
+            0 => format!("{} SOL", b.stake / LAMPORTS_PER_SOL),
+            _ => format!("{} SOL ({:.1}%)", b.stake / LAMPORTS_PER_SOL, b.stake as f64 * 100.0 / measured_stake as f64),
+        });
+    }
+}
+
+/// Return the validator client implementation guessed from the version advertised in gossip.
+///
+/// Firedancer reports 0.x versions while Agave reports its own release numbers. Jito-Agave cannot be
+/// told apart from Agave since contact info only carries the version number, not the client id.
+pub fn client_from_version(version: Option<&str>) -> &'static str {
+    let major = version.and_then(|v| v.split('.').next()).and_then(|m| m.parse::<u32>().ok());
+    match major {
+        Some(0) => "Firedancer",
+        Some(_) => "Agave/Jito-Agave",
+        None => "Unknown",
+    }
+}
+
+/// Distance to the same identity over IPv4 and IPv6.
+#[derive(Serialize)]
+pub struct DualStackComparison {
+    pub pubkey: String,
+    pub ipv4: SocketAddr,
+    pub ipv4_distance_us: u32,
+    pub ipv6: SocketAddr,
+    pub ipv6_distance_us: u32,
+}
+
+/// Comparison of IPv4 and IPv6 distances of the identities measured in both address families.
+#[derive(Serialize)]
+pub struct DualStackReport {
+    pub validators: Vec<DualStackComparison>,
+    pub ipv6_faster: u64,
+    pub ipv6_slower: u64,
+    /// Average of IPv6 minus IPv4 distances, negative when IPv6 is faster
+    pub mean_difference_us: i64,
+    /// Average gain of the identities where IPv6 is faster
+    pub mean_ipv6_gain_us: Option<u64>,
+    /// Average loss of the identities where IPv6 is slower
+    pub mean_ipv6_loss_us: Option<u64>,
+}
+
+impl DualStackReport {
+    /// Compare the successful measures of identities found at both an IPv4 and an IPv6 TPU, if any.
+    pub fn compare(tpus: &[TpuReport]) -> Option<DualStackReport> {
+        let mut families: BTreeMap<&str, (Option<&TpuReport>, Option<&TpuReport>)> = BTreeMap::new();
+        for tpu in tpus {
+            // Failover backups are other sites, not the other address family of the advertised one
+            if let (Some(_), None, false) = (tpu.distance_us, &tpu.error, tpu.advertised == Some(false)) {
+                for pubkey in &tpu.pubkeys {
+                    let f = families.entry(pubkey).or_default();
+                    if tpu.address.is_ipv4() { f.0 = Some(tpu) } else { f.1 = Some(tpu) }
+                }
+            }
+        }
+        let validators: Vec<DualStackComparison> = families.into_iter().filter_map(|(pubkey, family)| match family {
+            (Some(v4), Some(v6)) => Some(DualStackComparison {
+                pubkey: pubkey.to_string(),
+                ipv4: v4.address,
+                ipv4_distance_us: v4.distance_us?,
+                ipv6: v6.address,
+                ipv6_distance_us: v6.distance_us?,
+            }),
+            _ => None,
+        }).collect();
+        if validators.is_empty() {
+            return None;
+        }
+        let diffs: Vec<i64> = validators.iter().map(|v| v.ipv6_distance_us as i64 - v.ipv4_distance_us as i64).collect();
+        let mean = |d: Vec<u64>| (!d.is_empty()).then(|| d.iter().sum::<u64>() / d.len() as u64);
+        let gains: Vec<u64> = diffs.iter().filter(|d| **d < 0).map(|d| d.unsigned_abs()).collect();
+        let losses: Vec<u64> = diffs.iter().filter(|d| **d > 0).map(|d| d.unsigned_abs()).collect();
+        Some(DualStackReport {
+            ipv6_faster: gains.len() as u64,
+            ipv6_slower: losses.len() as u64,
+            mean_difference_us: diffs.iter().sum::<i64>() / diffs.len() as i64,
+            mean_ipv6_gain_us: mean(gains),
+            mean_ipv6_loss_us: mean(losses),
+            validators,
+        })
+    }
+
+    fn print_text(&self, details: bool) {
+        println!("IPv4 vs IPv6, {} dual-stacked validators:", self.validators.len());
+        if details {
+            for v in &self.validators {
+                println!("  {} {} {} µs, {} {} µs", v.pubkey, v.ipv4, v.ipv4_distance_us, v.ipv6, v.ipv6_distance_us);
+            }
+        }
+        print!("  IPv6 faster: {}", self.ipv6_faster);
+        if let Some(gain) = self.mean_ipv6_gain_us {
+            print!(" (by {} µs on average)", gain);
+        }
+        print!(", IPv6 slower: {}", self.ipv6_slower);
+        if let Some(loss) = self.mean_ipv6_loss_us {
+            print!(" (by {} µs on average)", loss);
+        }
+        println!(", mean IPv6 - IPv4 difference: {} µs", self.mean_difference_us);
+    }
+}
+
+/// A TPU whose connection setup RTT differs from its steady-state RTT.
+#[derive(Serialize)]
+pub struct PathDivergence {
+    pub address: SocketAddr,
+    pub pubkeys: Vec<String>,
+    /// Median RTT of new connections
+    pub setup_rtt_us: u64,
+    pub steady_rtt_us: u64,
+}
+
+/// Comparison of the RTTs of new and persistent connections of the TPUs measured both ways.
+#[derive(Serialize)]
+pub struct SteadyStateReport {
+    pub compared: u64,
+    /// Average of setup minus steady-state RTTs
+    pub mean_difference_us: i64,
+    /// Average of the median handshake durations, which add the processing of the TPUs to the RTT
+    pub mean_connect_us: Option<u64>,
+    /// TPUs whose setup and steady-state RTTs differ by more than 1 ms and 20%, e.g. handshakes going through
+    /// DDoS scrubbing, by decreasing difference
+    pub divergent: Vec<PathDivergence>,
+}
+
+/// Minimum difference between setup and steady-state RTTs reported as a divergence, in µs and in % of the steady RTT
+const DIVERGENCE_MIN_US: u64 = 1000;
+const DIVERGENCE_MIN_PCT: u64 = 20;
+
+impl SteadyStateReport {
+    /// Compare the RTTs of new and persistent connections of the successfully measured TPUs, if any has both.
+    pub fn compare(tpus: &[TpuReport]) -> Option<SteadyStateReport> {
+        let pairs: Vec<(&TpuReport, u64, u64)> = tpus.iter()
+            .filter(|t| t.error.is_none())
+            .filter_map(|t| Some((t, t.rtt?.median_us, t.steady_rtt_us?)))
+            .collect();
+        if pairs.is_empty() {
+            return None;
+        }
+        let mean_difference_us = pairs.iter().map(|(_, setup, steady)| *setup as i64 - *steady as i64).sum::<i64>() / pairs.len() as i64;
+        let mut divergent: Vec<PathDivergence> = pairs.iter()
+            .filter(|(_, setup, steady)| {
+                let diff = setup.abs_diff(*steady);
+                diff > DIVERGENCE_MIN_US && diff * 100 > steady * DIVERGENCE_MIN_PCT
+            })
+            .map(|(t, setup, steady)| PathDivergence { address: t.address, pubkeys: t.pubkeys.clone(), setup_rtt_us: *setup, steady_rtt_us: *steady })
+            .collect();
+        divergent.sort_by_key(|d| std::cmp::Reverse(d.setup_rtt_us.abs_diff(d.steady_rtt_us)));
+        let connect: Vec<u64> = pairs.iter().filter_map(|(t, _, _)| t.connect_us).collect();
+        let mean_connect_us = (!connect.is_empty()).then(|| connect.iter().sum::<u64>() / connect.len() as u64);
+        Some(SteadyStateReport { compared: pairs.len() as u64, mean_difference_us, mean_connect_us, divergent })
+    }
+
+    fn print_text(&self) {
+        print!("Setup vs steady-state RTT, {} TPUs: mean difference {} µs", self.compared, self.mean_difference_us);
+        if let Some(connect) = self.mean_connect_us {
+            print!(", mean connect time {} µs", connect);
+        }
+        println!(", {} divergent", self.divergent.len());
+        for d in &self.divergent {
+            println!("  {} {:?} setup {} µs, steady {} µs", d.address, d.pubkeys, d.setup_rtt_us, d.steady_rtt_us);
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorReport {
+    pub error: String,
+    pub count: u64,
+    /// Stake of the affected nodes, or `None` when not meaningful for this error
+    pub stake: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct UdpReport {
+    pub tx_datagrams: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+    pub rx_datagrams: u64,
+    pub rx_bytes: u64,
+    /// Host-wide receive buffer overflows during the run, when available
+    pub os_rcvbuf_errors: Option<u64>,
+    /// Host-wide send buffer overflows during the run, when available
+    pub os_sndbuf_errors: Option<u64>,
+}
+
+/// Expected cost of a measure, as returned by [`DistanceMeasurer::estimate`](crate::DistanceMeasurer::estimate).
+#[derive(Serialize)]
+pub struct Estimate {
+    pub schema_version: u32,
+    /// Number of TPUs to measure, alternate addresses included
+    pub tpus: usize,
+    /// Number of connection attempts per TPU
+    pub attempts: usize,
+    /// Spacing of connection attempts
+    pub interval_ms: u64,
+    /// Number of successive batches of TPUs, with `--max-concurrent`
+    pub batches: u32,
+    /// Upper bound of the duration of the connection attempts, discovery excluded
+    pub max_duration_ms: u64,
+    pub connections: usize,
+    /// Approximate number of packets sent
+    pub tx_packets: u64,
+    /// Approximate number of bytes sent
+    pub tx_bytes: u64,
+}
+
+impl Estimate {
+    pub fn print_text(&self) {
+        println!("TPUs: {}", self.tpus);
+        println!("Attempts per TPU: {} every {} ms, in {} batch(es)", self.attempts, self.interval_ms, self.batches);
+        println!("Expected duration: at most {:.1} s after discovery", self.max_duration_ms as f64 / 1000.0);
+        println!("Connections: {}", self.connections);
+        println!("Packets sent: about {} ({} bytes)", self.tx_packets, self.tx_bytes);
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize estimate"));
+    }
+}
+
+/// Second pass over the TPUs without successful connection, resolved with a fresh snapshot of the cluster since
+/// they were too many for the first snapshot to be trusted, e.g. after validators moved. The TPUs and aggregates
+/// of the report are those of the first pass.
+#[derive(Serialize)]
+pub struct StaleRefresh {
+    /// TPUs without successful connection in the first pass
+    pub failed: u64,
+    /// TPUs of the second pass at an address other than the failed ones
+    pub moved: u64,
+    pub connection_successful: u64,
+    pub tpus: Vec<TpuReport>,
+}
+
+impl StaleRefresh {
+    fn print_text(&self, details: bool) {
+        println!("Stale snapshot suspected: {} TPUs without successful connection, re-measured with a fresh snapshot: {} moved, {} successful",
+                 self.failed, self.moved, self.connection_successful);
+        if details {
+            for tpu in &self.tpus {
+                match (&tpu.error, tpu.distance_us) {
+                    (None, Some(distance)) => println!("  {:21} {:?} {} µs", tpu.address, tpu.pubkeys, distance),
+                    (Some(error), _) => println!("  {:21} {:?} {}", tpu.address, tpu.pubkeys, error),
+                    (None, None) => println!("  {:21} {:?}", tpu.address, tpu.pubkeys),
+                }
+            }
+        }
+    }
+}
+
+/// Order of the TPUs in the reports, with `--sort`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TpuOrder {
+    /// Farthest first
+    Distance,
+    /// Highest first
+    Stake,
+    Address,
+}
+
+/// Result of a measure, as returned by [`DistanceMeasurer::measure`](crate::DistanceMeasurer::measure).
+#[derive(Serialize)]
+pub struct DistanceReport {
+    pub schema_version: u32,
+    /// Slice of the targets measured, with `--shard`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+    /// Start of the measure, in RFC 3339 format
+    pub timestamp: String,
+    /// Host, version and cluster state of the measure
+    pub metadata: RunMetadata,
+    /// Number of connection attempts per TPU
+    pub attempts: usize,
+    /// Spacing of connection attempts
+    pub interval_ms: u64,
+    /// True if the measure was cancelled, the TPUs having had fewer attempts than scheduled
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    pub tpus: Vec<TpuReport>,
+    pub simple: Option<Distance>,
+    pub stake_weighted: Option<Distance>,
+    /// Distances below which lie 50, 90 and 99% of the measured stake, with stake weighting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stake_percentiles: Option<StakePercentiles>,
+    /// Stake of the successfully measured nodes
+    pub measured_stake: u64,
+    /// Stake of all the requested nodes
+    pub total_stake: u64,
+    pub connection_successful: u64,
+    /// Average RTT distribution of the successfully measured TPUs
+    pub rtt: Option<RttStats>,
+    /// Aggregates per client implementation, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clients: Option<Vec<GroupReport>>,
+    /// Aggregates per country and per autonomous system, if a geolocation database was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countries: Option<Vec<GroupReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asns: Option<Vec<GroupReport>>,
+    /// Stake and median distance by hosting provider, with `--by-provider`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub providers: Option<Vec<ProviderReport>>,
+    /// Aggregates per network segment, e.g. Doublezero device, if a segment file was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segments: Option<Vec<GroupReport>>,
+    /// Aggregates of the addresses of each destination given by hostname
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostnames: Option<Vec<GroupReport>>,
+    /// Distance at which the closest TPUs reach shares of the stake, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stake_coverage: Option<Vec<StakeCoverage>>,
+    /// Number of TPUs and stake in buckets of distance, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Vec<HistogramBucket>>,
+    /// Number of TPUs and share of the stake in each latency band, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bands: Option<Vec<BandReport>>,
+    /// Networks where peering would most reduce the stake-weighted distance, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peering: Option<Vec<PeeringCandidate>>,
+    /// BGP context of the farthest TPUs, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing: Option<Vec<RouteContext>>,
+    /// Hops of the routes to the farthest or selected TPUs, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traceroutes: Option<Vec<TraceReport>>,
+    /// Comparison of IPv4 and IPv6 distances, if some identities were measured in both families
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dual_stack: Option<DualStackReport>,
+    /// Comparison of the RTTs of new and persistent connections, if persistent connections were measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub steady_state: Option<SteadyStateReport>,
+    /// Landing of a self-transfer after the measure, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_landing: Option<TxLanding>,
+    /// Second pass over the failed TPUs with a fresh snapshot of the cluster, if requested and they were many
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_refresh: Option<StaleRefresh>,
+    /// Average ranges of the one-way delay, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_way: Option<OneWayReport>,
+    /// Comparison with the latencies imported from other tools, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imported: Option<Vec<ImportReport>>,
+    pub errors: Vec<ErrorReport>,
+    pub udp: UdpReport,
+}
+
+impl DistanceReport {
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize report"));
+    }
+
+    /// Print the report as a single line, for a round of the watch mode.
+    pub fn print_json_line(&self) {
+        println!("{}", serde_json::to_string(self).expect("Cannot serialize report"));
+    }
+
+    /// Print the report as InfluxDB line protocol, one point per TPU and one of aggregates.
+    pub fn print_influx(&self) {
+        print!("{}", influx::to_lines(self));
+    }
+
+    /// Print one CSV row per TPU, preceded by the header if `header` is set, and followed by the aggregates
+    /// as comment lines, skipped by spreadsheets and `pandas.read_csv(comment="#")`.
+    pub fn print_csv(&self, header: bool) {
+        if header {
+            print!("{}", export::csv_header());
+        }
+        print!("{}", export::csv_rows(self));
+        let mut summary = format!("# timestamp={} connection_successful={} measured_stake={} total_stake={}",
+                                  self.timestamp, self.connection_successful, self.measured_stake, self.total_stake);
+        summary += &format!(" tool_version={}", self.metadata.tool_version);
+        if let Some(hostname) = &self.metadata.hostname {
+            summary += &format!(" hostname={}", hostname);
+        }
+        if let Some(ip) = self.metadata.public_ip {
+            summary += &format!(" public_ip={}", ip);
+        }
+        if let Some(url) = &self.metadata.rpc_url {
+            summary += &format!(" rpc_url={}", url);
+        }
+        if let (Some(epoch), Some(slot)) = (self.metadata.epoch, self.metadata.slot) {
+            summary += &format!(" epoch={} slot={}", epoch, slot);
+        }
+        for (name, distance) in [("simple", &self.simple), ("stake_weighted", &self.stake_weighted)] {
+            if let Some(distance) = distance {
+                summary += &format!(" {}_distance_us={}", name, distance.distance_us);
+                if let Some(stddev) = distance.stddev_us {
+                    summary += &format!(" {}_stddev_us={}", name, stddev);
+                }
+            }
+        }
+        if let Some(p) = &self.stake_percentiles {
+            summary += &format!(" stake_p50_us={} stake_p90_us={} stake_p99_us={}", p.p50_us, p.p90_us, p.p99_us);
+        }
+        println!("{}", summary);
+        for error in &self.errors {
+            match error.stake {
+                Some(stake) => println!("# error=\"{}\" count={} stake={}", error.error, error.count, stake),
+                None => println!("# error=\"{}\" count={}", error.error, error.count),
+            }
+        }
+    }
+
+    /// Print a single timestamped summary line, for a round of the watch mode.
+    pub fn print_round(&self) {
+        println!("{} {}", self.timestamp, self.round_summary());
+    }
+
+    /// Return the summary of a round of the watch mode, without timestamp.
+    pub fn round_summary(&self) -> String {
+        let mut line = String::new();
+        if let Some(simple) = &self.simple {
+            line += &format!("simple {} ", format_distance(simple));
+        }
+        if let Some(stake_weighted) = &self.stake_weighted {
+            line += &format!("stake-weighted {} ", format_distance(stake_weighted));
+        }
+        let errors: u64 = self.errors.iter().map(|e| e.count).sum();
+        line = format!("{}connections {} errors {}", line, self.connection_successful, errors);
+        if self.cancelled {
+            line += " cancelled";
+        }
+        line
+    }
+
+    /// Sort the TPUs in `order`, the TPUs without distance or with an error last when sorting by distance.
+    pub fn sort_tpus(&mut self, order: TpuOrder) {
+        match order {
+            TpuOrder::Distance => self.tpus.sort_by_key(|t| (t.error.is_some(), Reverse(t.distance_us), t.address)),
+            TpuOrder::Stake => self.tpus.sort_by_key(|t| (Reverse(t.stake), t.address)),
+            TpuOrder::Address => self.tpus.sort_by_key(|t| t.address),
+        }
+    }
+
+    /// Print the result of each TPU, or of the first `limit` ones.
+    pub fn print_details(&self, limit: Option<usize>) {
+        for tpu in self.tpus.iter().take(limit.unwrap_or(usize::MAX)) {
+            if self.total_stake > 0 {
+                print!("{:21} {:>9} SOL {:?} ", tpu.address, tpu.stake / LAMPORTS_PER_SOL, tpu.pubkeys);
+            } else {
+                print!("{:21} {:?} ", tpu.address, tpu.pubkeys);
+            }
+            if !tpu.names.is_empty() {
+                print!("({}) ", tpu.names.join(", "));
+            }
+            if let Some(hostname) = &tpu.hostname {
+                print!("{} ", hostname);
+            }
+            match (&tpu.error, tpu.distance_us, tpu.stddev_us) {
+                (Some(error), Some(distance), _) => print!("{} µs {}", distance, error),
+                (Some(error), None, _) => print!("{}", error),
+                (None, Some(distance), Some(stddev)) => print!("{} µs ± {} µs", distance, stddev),
+                (None, Some(distance), None) => print!("{} µs", distance),
+                (None, None, _) => (),
+            }
+            if let Some(band) = &tpu.band {
+                print!(" ({})", band);
+            }
+            if let Some(network) = tpu.network_distance_us {
+                print!(", network distance {} µs", network);
+            }
+            if let Some(rtt) = &tpu.rtt {
+                print!(", RTT {}", rtt);
+            }
+            if let Some(geo) = &tpu.geo {
+                let location: Vec<String> = [geo.city.clone(), geo.country.clone(), geo.as_name()].into_iter().flatten().collect();
+                print!(", {}", location.join(", "));
+            }
+            if let Some(one_way) = &tpu.one_way {
+                print!(", one-way between {} and {} µs", one_way.low_us, one_way.high_us);
+            }
+            if let Some(connect) = tpu.connect_us {
+                print!(", connect time {} µs", connect);
+            }
+            if let Some(stream) = tpu.stream_us {
+                print!(", stream open {} µs", stream);
+            }
+            if let Some(quic) = &tpu.quic {
+                print!(", {}/{} packets lost, MTU {}, min cwnd {} B", quic.lost_packets, quic.sent_packets, quic.mtu, quic.min_cwnd);
+                if quic.congestion_events > 0 {
+                    print!(", {} congestion events", quic.congestion_events);
+                }
+            }
+            if let Some(identity) = &tpu.server_identity {
+                print!(", server identity {}", identity);
+            }
+            if let Some(probe) = &tpu.mtu_probe {
+                print!(", path MTU {}", probe.mtu);
+                if probe.black_hole {
+                    print!(" (black hole above {} bytes)", MIN_MTU);
+                }
+            }
+            if let Some(resumption) = &tpu.resumption {
+                print!(", handshake {} µs, resumed {} µs{}", resumption.full_handshake_us, resumption.resumed_handshake_us,
+                       if resumption.zero_rtt { " with 0-RTT" } else { "" });
+            }
+            match tpu.steady_rtt_us {
+                Some(steady) => println!(", steady-state RTT {} µs", steady),
+                None => println!(),
+            }
+        }
+    }
+
+    /// Print the addresses of each failover target, the one advertised by gossip first.
+    fn print_failover(&self) {
+        let mut failover: BTreeMap<&str, Vec<&TpuReport>> = BTreeMap::new();
+        for tpu in self.tpus.iter().filter(|t| t.advertised.is_some()) {
+            for pubkey in &tpu.pubkeys {
+                failover.entry(pubkey).or_default().push(tpu);
+            }
+        }
+        for (pubkey, mut tpus) in failover {
+            tpus.sort_by_key(|t| (t.advertised != Some(true), t.address));
+            println!("Failover addresses of {}:", pubkey);
+            for tpu in tpus {
+                let role = if tpu.advertised == Some(true) { "advertised" } else { "backup" };
+                match (&tpu.error, tpu.distance_us) {
+                    (None, Some(distance)) => println!("  {:21} {:>10} {} µs", tpu.address, role, distance),
+                    (Some(error), _) => println!("  {:21} {:>10} {}", tpu.address, role, error),
+                    (None, None) => println!("  {:21} {:>10}", tpu.address, role),
+                }
+            }
+        }
+    }
+
+    /// Print the average distances and connection counts only, without the breakdowns.
+    pub fn print_aggregates(&self) {
+        if self.cancelled {
+            println!("Measure cancelled, results are partial");
+        }
+        if let (Some(epoch), Some(slot), Some(index), Some(slots)) =
+            (self.metadata.epoch, self.metadata.slot, self.metadata.slot_index, self.metadata.slots_in_epoch) {
+            println!("Measured at slot {}, {:.1}% into epoch {}", slot, index as f64 * 100.0 / slots.max(1) as f64, epoch);
+        }
+        if self.connection_successful > 0 {
+            if let Some(simple) = &self.simple {
+                print_distance("Simple distance", simple);
+            }
+            if let Some(stake_weighted) = &self.stake_weighted {
+                print_distance("Stake-weighted distance", stake_weighted);
+                if let Some(p) = &self.stake_percentiles {
+                    println!("Stake-weighted percentiles: p50 {} µs, p90 {} µs, p99 {} µs", p.p50_us, p.p90_us, p.p99_us);
+                }
+                println!("Total stake: {} SOL", self.measured_stake / LAMPORTS_PER_SOL);
+            }
+            println!("Connection successful: {}", self.connection_successful);
+            if let Some(rtt) = &self.rtt {
+                println!("Average RTT per TPU: {}", rtt);
+            }
+        }
+        let network: Vec<u64> = self.tpus.iter().filter(|t| !t.alternate).filter_map(|t| t.network_distance_us.map(u64::from)).collect();
+        if !network.is_empty() {
+            println!("Network distance of {} TPUs without successful connection: {} µs on average", network.len(), network.iter().sum::<u64>() / network.len() as u64);
+        }
+        let stream: Vec<u64> = self.tpus.iter().filter(|t| t.error.is_none() && !t.alternate).filter_map(|t| t.stream_us).collect();
+        if !stream.is_empty() {
+            println!("Stream open time of {} TPUs: {} µs on average", stream.len(), stream.iter().sum::<u64>() / stream.len() as u64);
+        }
+        let (sent, lost) = self.tpus.iter().filter(|t| t.error.is_none() && !t.alternate).filter_map(|t| t.quic.as_ref())
+            .fold((0, 0), |(sent, lost), q| (sent + q.sent_packets, lost + q.lost_packets));
+        if lost > 0 {
+            println!("QUIC packets lost during the handshakes: {} of {} ({:.2}%)", lost, sent, lost as f64 * 100.0 / sent as f64);
+        }
+        let mut probed: Vec<&MtuProbe> = self.tpus.iter().filter(|t| t.error.is_none() && !t.alternate).filter_map(|t| t.mtu_probe.as_ref()).collect();
+        if !probed.is_empty() {
+            probed.sort_unstable_by_key(|p| p.mtu);
+            println!("Path MTU of {} TPUs: median {} bytes, {} black-holing datagrams above {} bytes", probed.len(), probed[probed.len() / 2].mtu,
+                     probed.iter().filter(|p| p.black_hole).count(), MIN_MTU);
+        }
+        let resumed: Vec<&Resumption> = self.tpus.iter().filter(|t| t.error.is_none() && !t.alternate).filter_map(|t| t.resumption.as_ref()).collect();
+        if !resumed.is_empty() {
+            let saved: i64 = resumed.iter().map(|r| r.full_handshake_us as i64 - r.resumed_handshake_us as i64).sum();
+            println!("Session resumption of {} TPUs: {} µs saved on average, 0-RTT accepted by {}", resumed.len(), saved / resumed.len() as i64,
+                     resumed.iter().filter(|r| r.zero_rtt).count());
+        }
+    }
+
+    /// Print the summary, preceded by the result of each TPU, or of the first `limit` ones, if `details` is set.
+    pub fn print_text(&self, details: bool, limit: Option<usize>) {
+        if details {
+            self.print_details(limit);
+        }
+        self.print_aggregates();
+
+        if let Some(clients) = &self.clients {
+            println!("Distance by client implementation:");
+            for client in clients {
+                client.print_text();
+            }
+        }
+
+        if let Some(coverage) = &self.stake_coverage {
+            println!("Distance to stake coverage:");
+            for point in coverage {
+                point.print_text(self.total_stake);
+            }
+        }
+
+        if let Some(histogram) = &self.histogram {
+            HistogramBucket::print_text(histogram, self.measured_stake);
+        }
+
+        if let Some(bands) = &self.bands {
+            println!("Stake by latency band:");
+            for band in bands {
+                band.print_text(self.total_stake);
+            }
+        }
+
+        for (title, groups) in [("country", &self.countries), ("autonomous system", &self.asns), ("segment", &self.segments), ("hostname", &self.hostnames)] {
+            if let Some(groups) = groups {
+                println!("Distance by {}:", title);
+                for group in groups {
+                    group.print_text();
+                }
+            }
+        }
+
+        if let Some(providers) = &self.providers {
+            provider::print_text(providers, self.measured_stake);
+        }
+
+        if let Some(peering) = &self.peering {
+            peering::print_text(peering, self.measured_stake);
+        }
+
+        if let Some(routing) = &self.routing {
+            routing::print_text(routing);
+        }
+
+        if let Some(traceroutes) = &self.traceroutes {
+            traceroute::print_text(traceroutes);
+        }
+
+        if let Some(dual_stack) = &self.dual_stack {
+            dual_stack.print_text(details);
+        }
+
+        self.print_failover();
+
+        if let Some(steady_state) = &self.steady_state {
+            steady_state.print_text();
+        }
+
+        if let Some(tx_landing) = &self.tx_landing {
+            tx_landing.print_text();
+        }
+
+        if let Some(stale_refresh) = &self.stale_refresh {
+            stale_refresh.print_text(details);
+        }
+
+        if let Some(one_way) = &self.one_way {
+            one_way.print_text();
+        }
+
+        for imported in self.imported.iter().flatten() {
+            imported.print_text(details);
+        }
+
+        for error in &self.errors {
+            match error.stake {
+                Some(stk) => println!("{}: {} ({:.2}% of total stake)", error.error, error.count, 100.0 * stk as f64 / (self.total_stake as f64)),
+                None => println!("{}: {}", error.error, error.count),
+            }
+        }
+
+        let udp = &self.udp;
+        if details {
+            println!("UDP datagrams sent: {} ({} bytes), received: {} ({} bytes), send errors: {}",
+                     udp.tx_datagrams, udp.tx_bytes, udp.rx_datagrams, udp.rx_bytes, udp.tx_errors);
+        }
+        if let (Some(rcvbuf_errors), Some(sndbuf_errors)) = (udp.os_rcvbuf_errors, udp.os_sndbuf_errors) {
+            // These counters are host-wide: only report them when asked or when they may explain losses
+            if details || rcvbuf_errors > 0 || sndbuf_errors > 0 {
+                println!("Host UDP buffer overflows during the run: {} on receive, {} on send", rcvbuf_errors, sndbuf_errors);
+            }
+        }
+    }
+}
+
+fn format_distance(distance: &Distance) -> String {
+    match distance.stddev_us {
+        Some(stddev) => format!("{} ± {} µs", distance.distance_us, stddev),
+        None => format!("{} µs", distance.distance_us),
+    }
+}
+
+pub(crate) fn print_distance(label: &str, distance: &Distance) {
+    println!("{}: {}", label, format_distance(distance));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tpu(distance_us: Option<u32>, stake: u64) -> TpuReport {
+        TpuReport {
+            address: "192.0.2.1:8009".parse().unwrap(),
+            pubkeys: Vec::new(),
+            names: Vec::new(),
+            stake,
+            version: None,
+            client: "unknown",
+            min_rtt_us: None,
+            distance_us,
+            stddev_us: None,
+            rtt: None,
+            steady_rtt_us: None,
+            connect_us: None,
+            stream_us: None,
+            resumption: None,
+            mtu_probe: None,
+            quic: None,
+            server_identity: None,
+            geo: None,
+            doublezero: None,
+            band: None,
+            one_way: None,
+            network_distance_us: None,
+            error: None,
+            alternate: false,
+            advertised: None,
+            hostname: None,
+        }
+    }
+
+    fn percentiles(tpus: &[TpuReport]) -> Option<(u32, u32, u32)> {
+        StakePercentiles::compute(tpus).map(|p| (p.p50_us, p.p90_us, p.p99_us))
+    }
+
+    #[test]
+    fn weighs_the_distances_by_stake() {
+        let tpus = [tpu(Some(400), 5), tpu(Some(100), 50), tpu(Some(300), 15), tpu(Some(200), 30)];
+        assert_eq!(percentiles(&tpus), Some((100, 300, 400)));
+        let tpus = [tpu(Some(100), 1), tpu(Some(900), 99)];
+        assert_eq!(percentiles(&tpus), Some((900, 900, 900)));
+        assert_eq!(percentiles(&[tpu(Some(250), 7)]), Some((250, 250, 250)));
+    }
+
+    #[test]
+    fn leaves_out_the_unmeasured_tpus() {
+        let mut failed = tpu(Some(10), 1000);
+        failed.error = Some("No successful connection".to_string());
+        let mut alternate = tpu(Some(10), 1000);
+        alternate.alternate = true;
+        let tpus = [tpu(Some(100), 50), tpu(Some(200), 50), failed, alternate, tpu(None, 1000)];
+        assert_eq!(percentiles(&tpus), Some((100, 200, 200)));
+    }
+
+    #[test]
+    fn has_no_percentiles_without_measured_stake() {
+        assert_eq!(percentiles(&[]), None);
+        assert_eq!(percentiles(&[tpu(Some(100), 0), tpu(Some(200), 0)]), None);
+        assert_eq!(percentiles(&[tpu(None, 100)]), None);
+    }
+}
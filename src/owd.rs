@@ -0,0 +1,121 @@
+//! One-way delay of the TPUs as a range, instead of half of the round trip, which assumes symmetric paths.
+//!
+//! Without synchronized clocks a round trip cannot be split between its two directions, and TPUs don't answer
+//! with timestamps. Each direction takes at least the time light covers the great-circle distance in, when the
+//! positions of the probe and of the TPU are known, e.g. from a GeoIP City database: the one-way delay of either
+//! direction lies between this floor and the round trip minus the floor. Without positions, the range is the
+//! whole round trip.
+
+use crate::output::TpuReport;
+use crate::placement::km;
+use serde::Serialize;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Time light takes to cover a km in vacuum, in µs, faster than any path
+const US_PER_KM: f64 = 1e3 / 299_792.458;
+
+/// Position of the probe, given as `latitude,longitude`.
+#[derive(Clone, Copy, Debug)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (latitude, longitude) = s.split_once(',').ok_or_else(|| format!("Location {} is not of the form 'latitude,longitude'", s))?;
+        let latitude = latitude.trim().parse::<f64>().ok().filter(|l| (-90.0..=90.0).contains(l)).ok_or_else(|| format!("Invalid latitude in {}", s))?;
+        let longitude = longitude.trim().parse::<f64>().ok().filter(|l| (-180.0..=180.0).contains(l)).ok_or_else(|| format!("Invalid longitude in {}", s))?;
+        Ok(Location { latitude, longitude })
+    }
+}
+
+/// One-way delay of a TPU, or average over TPUs, in µs.
+#[derive(Clone, Copy, Serialize)]
+pub struct OneWay {
+    /// Half of the round trip, for symmetric paths
+    pub estimate_us: u32,
+    pub low_us: u32,
+    pub high_us: u32,
+}
+
+impl Display for OneWay {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} µs, between {} and {} µs", self.estimate_us, self.low_us, self.high_us)
+    }
+}
+
+impl OneWay {
+    /// Return the range of the one-way delay of a TPU at `distance_us`, whose directions both take at least
+    /// `floor_us`. A floor above the distance, from a wrong position, collapses the range to the estimate.
+    fn new(distance_us: u32, floor_us: u32) -> OneWay {
+        let floor_us = floor_us.min(distance_us);
+        OneWay { estimate_us: distance_us, low_us: floor_us, high_us: 2 * distance_us - floor_us }
+    }
+}
+
+/// Average one-way delays of the successfully measured TPUs.
+#[derive(Serialize)]
+pub struct OneWayReport {
+    /// TPUs with a known position, whose range is narrower than the round trip
+    pub located: u64,
+    pub simple: Option<OneWay>,
+    pub stake_weighted: Option<OneWay>,
+}
+
+/// Return the average of `ranges`, weighted by their second member, `None` without any weight.
+fn average(ranges: impl Iterator<Item = (OneWay, u64)>) -> Option<OneWay> {
+    let (mut estimate, mut low, mut high, mut weights) = (0u128, 0u128, 0u128, 0u128);
+    for (range, weight) in ranges {
+        estimate += range.estimate_us as u128 * weight as u128;
+        low += range.low_us as u128 * weight as u128;
+        high += range.high_us as u128 * weight as u128;
+        weights += weight as u128;
+    }
+    (weights > 0).then(|| OneWay {
+        estimate_us: (estimate / weights) as u32,
+        low_us: (low / weights) as u32,
+        high_us: (high / weights) as u32,
+    })
+}
+
+impl OneWayReport {
+    /// Set the one-way delay of each successfully measured TPU, bounded by the great-circle distance from
+    /// `location` if it and the position of the TPU are known, and return their averages. Alternate addresses
+    /// get a range but are left out of the averages.
+    pub fn estimate(tpus: &mut [TpuReport], location: Option<Location>) -> OneWayReport {
+        let mut located = 0;
+        for tpu in tpus.iter_mut().filter(|t| t.error.is_none()) {
+            let Some(distance) = tpu.distance_us else { continue };
+            let position = tpu.geo.as_ref().and_then(|g| g.latitude.zip(g.longitude));
+            let floor = match (location, position) {
+                (Some(location), Some((latitude, longitude))) => {
+                    located += u64::from(!tpu.alternate);
+                    (km(location.latitude, location.longitude, latitude, longitude) * US_PER_KM) as u32
+                }
+                _ => 0,
+            };
+            tpu.one_way = Some(OneWay::new(distance, floor));
+        }
+        let measured = || tpus.iter().filter(|t| !t.alternate).filter_map(|t| Some((t.one_way?, t.stake)));
+        OneWayReport {
+            located,
+            simple: average(measured().map(|(range, _)| (range, 1))),
+            stake_weighted: average(measured()),
+        }
+    }
+
+    pub(crate) fn print_text(&self) {
+        println!("One-way delay, {} TPUs located:", self.located);
+        if let Some(simple) = &self.simple {
+            println!("  Simple: {}", simple);
+        }
+        if let Some(stake_weighted) = &self.stake_weighted {
+            println!("  Stake-weighted: {}", stake_weighted);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Packet captures of the TPUs whose result deviates grossly from their baseline, for evidence of incidents in the
+//! watch mode.
+//!
+//! The baseline of a TPU is its distance in the previous rounds, as a moving average of its normal results. A TPU
+//! failing after a successful round, or farther than [`DEVIATION_FACTOR`] times its baseline plus
+//! [`DEVIATION_MARGIN_US`], is anomalous: tcpdump then captures the traffic to and from its address during the
+//! following round, in a pcap file of its own.
+
+use crate::output::TpuReport;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// A distance above this many times the baseline, plus the margin, is anomalous
+pub const DEVIATION_FACTOR: u32 = 2;
+pub const DEVIATION_MARGIN_US: u32 = 1000;
+/// Largest number of captures running at the same time
+const MAX_CAPTURES: usize = 8;
+/// Weight of a new distance in the moving average of the baseline, in %
+const BASELINE_WEIGHT_PCT: u64 = 20;
+
+/// Command capturing the traffic
+const TCPDUMP: &str = "tcpdump";
+
+/// Return true if tcpdump can be run.
+pub fn tcpdump_available() -> bool {
+    Command::new(TCPDUMP).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|s| s.success())
+}
+
+/// Baselines of the TPUs and captures in progress, writing to a directory.
+pub struct AnomalyCapture {
+    dir: PathBuf,
+    baselines: HashMap<SocketAddr, u32>,
+    captures: Vec<(SocketAddr, Child)>,
+}
+
+impl AnomalyCapture {
+    pub fn new(dir: &Path) -> AnomalyCapture {
+        AnomalyCapture { dir: dir.to_path_buf(), baselines: HashMap::new(), captures: Vec::new() }
+    }
+
+    /// Stop the captures of the previous round, compare the TPUs of a new round with their baseline, and start
+    /// capturing the anomalous ones for the next round.
+    pub fn check(&mut self, tpus: &[TpuReport]) {
+        self.stop();
+        for tpu in tpus {
+            let distance = tpu.distance_us.filter(|_| tpu.error.is_none());
+            let anomaly = match (self.baselines.get(&tpu.address), distance) {
+                (None, _) => None,
+                (Some(_), None) => Some(format!("failed: {}", tpu.error.as_deref().unwrap_or("no distance"))),
+                (Some(&baseline), Some(distance)) if distance > baseline.saturating_mul(DEVIATION_FACTOR).saturating_add(DEVIATION_MARGIN_US) => {
+                    Some(format!("{} µs, baseline {} µs", distance, baseline))
+                }
+                (Some(_), Some(_)) => None,
+            };
+            match (anomaly, distance) {
+                (Some(anomaly), _) => self.start(tpu.address, &anomaly),
+                (None, Some(distance)) => {
+                    let baseline = self.baselines.entry(tpu.address).or_insert(distance);
+                    *baseline = ((*baseline as u64 * (100 - BASELINE_WEIGHT_PCT) + distance as u64 * BASELINE_WEIGHT_PCT) / 100) as u32;
+                }
+                (None, None) => (),
+            }
+        }
+    }
+
+    /// Start capturing the traffic of `address`, unless too many captures run.
+    fn start(&mut self, address: SocketAddr, anomaly: &str) {
+        if self.captures.len() >= MAX_CAPTURES {
+            warn!("Anomaly of {}: {}, not captured, {} captures already running", address, anomaly, MAX_CAPTURES);
+            return;
+        }
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string().replace(':', "-");
+        let file = self.dir.join(format!("{}-{}-{}.pcap", timestamp, address.ip(), address.port()));
+        // Both the QUIC or gossip datagrams and the TCP connections of the probe types
+        let filter = format!("host {} and port {}", address.ip(), address.port());
+        let child = Command::new(TCPDUMP)
+            .args(["-i", "any", "-U", "-n", "-w"])
+            .arg(&file)
+            .arg(&filter)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match child {
+            Ok(child) => {
+                warn!("Anomaly of {}: {}, capturing its traffic of the next round to {}", address, anomaly, file.display());
+                self.captures.push((address, child));
+            }
+            Err(e) => warn!("Anomaly of {}: {}, failed to run {}: {}", address, anomaly, TCPDUMP, e),
+        }
+    }
+
+    /// Stop the captures in progress. Packets are written as they are captured, none is lost.
+    fn stop(&mut self) {
+        for (address, mut child) in self.captures.drain(..) {
+            // tcpdump may have exited already, e.g. without the permission to capture
+            if let Ok(Some(status)) = child.try_wait() {
+                warn!("Capture of {} exited early: {}", address, status);
+                continue;
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            info!("Capture of {} stopped", address);
+        }
+    }
+}
+
+impl Drop for AnomalyCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,109 @@
+//! Peering wishlist: the networks where peering, or a Doublezero link, would most reduce the stake-weighted distance.
+//!
+//! TPUs are grouped by network with an IP-to-network database, either in the ip2asn TSV format
+//! (`range_start range_end AS_number country_code AS_description`, e.g. from https://iptoasn.com) or as
+//! lines `prefix label`, e.g. the prefixes of the members of an IXP.
+
+use crate::address::Prefix;
+use crate::output::{TpuReport, LAMPORTS_PER_SOL};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Address ranges of networks, sorted by family and first address.
+pub struct NetworkDb {
+    ranges: Vec<(bool, u128, u128, String)>,
+}
+
+impl NetworkDb {
+    pub fn load(path: &Path) -> io::Result<NetworkDb> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid network range: {}", line));
+        let mut ranges = Vec::new();
+        for line in fs::read_to_string(path)?.lines().filter(|l| !l.trim().is_empty() && !l.starts_with('#')) {
+            let (first_field, rest) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+            if first_field.contains('/') {
+                let prefix = first_field.parse::<Prefix>().map_err(|_| invalid(line))?;
+                let (first, last) = prefix.range();
+                ranges.push((prefix.is_ipv4(), first, last, rest.trim().to_string()));
+            } else if let [start, end, asn, _country, description] = line.split('\t').collect::<Vec<_>>()[..] {
+                // Unrouted ranges are in AS 0
+                if asn.trim() == "0" {
+                    continue;
+                }
+                let start = start.trim().parse::<IpAddr>().map_err(|_| invalid(line))?;
+                let end = end.trim().parse::<IpAddr>().map_err(|_| invalid(line))?;
+                ranges.push((start.is_ipv4(), Prefix::bits(&start), Prefix::bits(&end), format!("AS{} {}", asn.trim(), description.trim())));
+            } else {
+                return Err(invalid(line));
+            }
+        }
+        ranges.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        Ok(NetworkDb { ranges })
+    }
+
+    /// Return the network of `ip`, the one with the closest first address when ranges overlap.
+    fn network(&self, ip: &IpAddr) -> Option<&str> {
+        let key = (ip.is_ipv4(), Prefix::bits(ip));
+        let i = self.ranges.partition_point(|r| (r.0, r.1) <= key);
+        let (ipv4, _, last, label) = self.ranges.get(i.checked_sub(1)?)?;
+        (*ipv4 == key.0 && *last >= key.1).then_some(label.as_str())
+    }
+}
+
+/// A network where peering would bring its TPUs closer.
+#[derive(Serialize)]
+pub struct PeeringCandidate {
+    pub network: String,
+    pub tpus: u64,
+    pub stake: u64,
+    pub stake_weighted_distance_us: u64,
+    /// Distance of the closest TPU of the network
+    pub best_distance_us: u32,
+    /// Sum of the stake in SOL times the distance in µs saved, if all TPUs of the network were as close as the best one
+    pub gain_sol_us: u128,
+    /// Reduction of the stake-weighted distance of the measure, in the same case
+    pub stake_weighted_gain_us: u64,
+}
+
+/// Rank the networks of the successfully measured TPUs by the reduction of the stake-weighted distance expected
+/// if all their TPUs were reached as fast as their best one, i.e. through a direct path to the network.
+pub fn wishlist(tpus: &[TpuReport], db: &NetworkDb, measured_stake: u64) -> Vec<PeeringCandidate> {
+    let mut networks: BTreeMap<&str, Vec<(u32, u64)>> = BTreeMap::new();
+    for tpu in tpus {
+        if let (Some(distance), None, false, Some(network)) = (tpu.distance_us, &tpu.error, tpu.alternate, db.network(&tpu.address.ip())) {
+            networks.entry(network).or_default().push((distance, tpu.stake));
+        }
+    }
+    let mut candidates: Vec<PeeringCandidate> = networks.into_iter().map(|(network, tpus)| {
+        let stake: u64 = tpus.iter().map(|(_, stake)| stake).sum();
+        let best = tpus.iter().map(|(distance, _)| *distance).min().unwrap_or_default();
+        let sum_w: u128 = tpus.iter().map(|(distance, stake)| *distance as u128 * *stake as u128).sum();
+        let gain_w: u128 = tpus.iter().map(|(distance, stake)| (distance - best) as u128 * *stake as u128).sum();
+        PeeringCandidate {
+            network: network.to_string(),
+            tpus: tpus.len() as u64,
+            stake,
+            stake_weighted_distance_us: if stake > 0 { (sum_w / stake as u128) as u64 } else { 0 },
+            best_distance_us: best,
+            gain_sol_us: gain_w / LAMPORTS_PER_SOL as u128,
+            stake_weighted_gain_us: if measured_stake > 0 { (gain_w / measured_stake as u128) as u64 } else { 0 },
+        }
+    }).filter(|c| c.gain_sol_us > 0).collect();
+    candidates.sort_by(|a, b| b.gain_sol_us.cmp(&a.gain_sol_us));
+    candidates
+}
+
+pub(crate) fn print_text(candidates: &[PeeringCandidate], measured_stake: u64) {
+    println!("Peering wishlist:");
+    if candidates.is_empty() {
+        println!("  No network has TPUs farther than its closest one");
+    }
+    for c in candidates {
+        println!("  {}: {} TPUs, {} SOL ({:.2}%), {} µs stake-weighted, best {} µs, gain {} SOL×µs, {} µs on the stake-weighted distance",
+                 c.network, c.tpus, c.stake / LAMPORTS_PER_SOL, 100.0 * c.stake as f64 / measured_stake.max(1) as f64,
+                 c.stake_weighted_distance_us, c.best_distance_us, c.gain_sol_us, c.stake_weighted_gain_us);
+    }
+}
@@ -0,0 +1,237 @@
+//! Estimate of the best single location of a relay or validator: the point on Earth minimizing the
+//! leader-weighted distance to the validators, from the measures of several agents at known positions.
+//!
+//! The reports of the agents, saved with `--output json` and `--geoip` with a City database, give the distance
+//! from each agent to each located validator. A model of the distance as a fixed cost plus a cost per km of
+//! great-circle distance is fitted on all of them, each validator keeping its mean residual over the agents,
+//! e.g. the cost of its last mile. The model is then minimized by simulated annealing, leader slots being
+//! weighted by stake like the leader schedule. It is a starting suggestion for placement studies: the
+//! positions of GeoIP databases are approximate and paths don't follow great circles.
+
+use crate::output::{LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Mean radius of the Earth
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Initial and final steps of the annealing, in degrees
+const MAX_STEP_DEG: f64 = 30.0;
+const MIN_STEP_DEG: f64 = 0.01;
+
+/// The report of an agent with its position, given as `latitude,longitude=report.json`.
+#[derive(Clone, Debug)]
+pub struct AgentReport {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub path: PathBuf,
+}
+
+impl FromStr for AgentReport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Agent report {} is not of the form 'latitude,longitude=report.json'", s);
+        let (position, path) = s.split_once('=').ok_or_else(invalid)?;
+        let (latitude, longitude) = position.split_once(',').ok_or_else(invalid)?;
+        let latitude = latitude.trim().parse::<f64>().ok().filter(|l| (-90.0..=90.0).contains(l)).ok_or_else(|| format!("Invalid latitude in {}", s))?;
+        let longitude = longitude.trim().parse::<f64>().ok().filter(|l| (-180.0..=180.0).contains(l)).ok_or_else(|| format!("Invalid longitude in {}", s))?;
+        Ok(AgentReport { latitude, longitude, path: PathBuf::from(path.trim()) })
+    }
+}
+
+/// The fields of a saved [`DistanceReport`](crate::output::DistanceReport) used for the estimate.
+#[derive(Deserialize)]
+struct SavedReport {
+    #[serde(default)]
+    schema_version: u32,
+    tpus: Vec<SavedTpu>,
+}
+
+#[derive(Deserialize)]
+struct SavedTpu {
+    pubkeys: Vec<String>,
+    stake: u64,
+    distance_us: Option<u32>,
+    error: Option<String>,
+    #[serde(default)]
+    alternate: bool,
+    geo: Option<SavedGeo>,
+}
+
+#[derive(Deserialize)]
+struct SavedGeo {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+fn invalid_data(error: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.into())
+}
+
+fn load(path: &Path) -> io::Result<SavedReport> {
+    let report: SavedReport = serde_json::from_slice(&fs::read(path)?).map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+    if report.schema_version > SCHEMA_VERSION {
+        return Err(invalid_data(format!("{} has schema version {}, this version reads up to {}", path.display(), report.schema_version, SCHEMA_VERSION)));
+    }
+    Ok(report)
+}
+
+/// Return the great-circle distance in km between two positions in degrees.
+pub(crate) fn km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let (dlat, dlon) = (lat2 - lat1, (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// A located validator, with the mean residual of the model over the agents which measured it.
+struct Validator {
+    latitude: f64,
+    longitude: f64,
+    stake: u64,
+    residual_us: f64,
+}
+
+/// Distance model, fitted on the measures of all the agents.
+#[derive(Clone, Copy, Serialize)]
+pub struct DistanceModel {
+    pub fixed_us: f64,
+    pub us_per_km: f64,
+}
+
+/// Stake-weighted distance to the located validators measured by an agent, and modelled at its position.
+#[derive(Serialize)]
+pub struct AgentFit {
+    pub report: PathBuf,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub measured_us: u64,
+    pub modelled_us: u64,
+}
+
+/// Estimated best location, with the model it minimizes.
+#[derive(Serialize)]
+pub struct Placement {
+    pub schema_version: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Modelled leader-weighted distance at the estimated location
+    pub distance_us: u64,
+    pub model: DistanceModel,
+    /// Validators located and measured by at least one agent
+    pub validators: usize,
+    pub stake: u64,
+    pub agents: Vec<AgentFit>,
+}
+
+/// Return the modelled stake-weighted distance from a position to the validators.
+fn weighted_distance(model: DistanceModel, validators: &[Validator], latitude: f64, longitude: f64) -> f64 {
+    let (sum, stake) = validators.iter().fold((0.0, 0.0), |(sum, stake), v| {
+        let distance = (model.fixed_us + model.us_per_km * km(latitude, longitude, v.latitude, v.longitude) + v.residual_us).max(0.0);
+        (sum + distance * v.stake as f64, stake + v.stake as f64)
+    });
+    sum / stake
+}
+
+impl Placement {
+    /// Estimate the best location from the reports of `agents`, with `iterations` steps of simulated annealing.
+    pub fn estimate(agents: &[AgentReport], iterations: usize) -> io::Result<Placement> {
+        // Located and measured validators by identity, with the (km, µs) samples of the agents
+        let mut samples: BTreeMap<String, (Validator, Vec<(f64, f64)>)> = BTreeMap::new();
+        let mut measured = Vec::new();
+        for agent in agents {
+            let report = load(&agent.path)?;
+            let (mut sum, mut stake) = (0u128, 0u128);
+            for tpu in report.tpus.iter().filter(|t| t.error.is_none() && !t.alternate && t.stake > 0) {
+                let (Some(distance), Some(SavedGeo { latitude: Some(latitude), longitude: Some(longitude) })) = (tpu.distance_us, &tpu.geo) else { continue };
+                let (latitude, longitude) = (*latitude, *longitude);
+                sum += distance as u128 * tpu.stake as u128;
+                stake += tpu.stake as u128;
+                let key = tpu.pubkeys.join(" ");
+                let entry = samples.entry(key).or_insert((Validator { latitude, longitude, stake: tpu.stake, residual_us: 0.0 }, Vec::new()));
+                entry.1.push((km(agent.latitude, agent.longitude, latitude, longitude), distance as f64));
+            }
+            measured.push(if stake > 0 { (sum / stake) as u64 } else { 0 });
+        }
+        let all: Vec<(f64, f64)> = samples.values().flat_map(|(_, s)| s.iter().copied()).collect();
+        if all.len() < 2 {
+            return Err(invalid_data("The reports have no validator located with --geoip and a City database"));
+        }
+
+        // Least squares fit of the distance on the km
+        let n = all.len() as f64;
+        let (mean_km, mean_us) = (all.iter().map(|s| s.0).sum::<f64>() / n, all.iter().map(|s| s.1).sum::<f64>() / n);
+        let covariance: f64 = all.iter().map(|(km, us)| (km - mean_km) * (us - mean_us)).sum();
+        let variance: f64 = all.iter().map(|(km, _)| (km - mean_km).powi(2)).sum();
+        if variance == 0.0 || covariance <= 0.0 {
+            return Err(invalid_data("The measured distances don't grow with the geographic distance, measure from agents farther apart"));
+        }
+        let us_per_km = covariance / variance;
+        let model = DistanceModel { fixed_us: mean_us - us_per_km * mean_km, us_per_km };
+        let validators: Vec<Validator> = samples.into_values().map(|(validator, samples)| {
+            let residual_us = samples.iter().map(|(km, us)| us - model.fixed_us - model.us_per_km * km).sum::<f64>() / samples.len() as f64;
+            Validator { residual_us, ..validator }
+        }).collect();
+
+        let agent_fits: Vec<AgentFit> = agents.iter().zip(measured).map(|(agent, measured_us)| AgentFit {
+            report: agent.path.clone(),
+            latitude: agent.latitude,
+            longitude: agent.longitude,
+            measured_us,
+            modelled_us: weighted_distance(model, &validators, agent.latitude, agent.longitude) as u64,
+        }).collect();
+
+        // Anneal from the best agent, with a fixed seed for reproducible estimates
+        let best_agent = agent_fits.iter().min_by_key(|a| a.modelled_us).expect("At least one agent");
+        let (mut latitude, mut longitude) = (best_agent.latitude, best_agent.longitude);
+        let mut cost = weighted_distance(model, &validators, latitude, longitude);
+        let (mut best, mut best_cost) = ((latitude, longitude), cost);
+        let initial_temperature = cost * 0.1;
+        let mut rng = StdRng::seed_from_u64(0);
+        for i in 0..iterations {
+            let t = 1.0 - i as f64 / iterations as f64;
+            let step = MIN_STEP_DEG + (MAX_STEP_DEG - MIN_STEP_DEG) * t;
+            let candidate_latitude = (latitude + rng.random_range(-step..=step)).clamp(-90.0, 90.0);
+            let candidate_longitude = (longitude + rng.random_range(-step..=step) + 540.0).rem_euclid(360.0) - 180.0;
+            let candidate_cost = weighted_distance(model, &validators, candidate_latitude, candidate_longitude);
+            let temperature = initial_temperature * t;
+            if candidate_cost < cost || (temperature > 0.0 && rng.random::<f64>() < ((cost - candidate_cost) / temperature).exp()) {
+                (latitude, longitude, cost) = (candidate_latitude, candidate_longitude, candidate_cost);
+                if cost < best_cost {
+                    (best, best_cost) = ((latitude, longitude), cost);
+                }
+            }
+        }
+
+        Ok(Placement {
+            schema_version: SCHEMA_VERSION,
+            latitude: best.0,
+            longitude: best.1,
+            distance_us: best_cost as u64,
+            model,
+            validators: validators.len(),
+            stake: validators.iter().map(|v| v.stake).sum(),
+            agents: agent_fits,
+        })
+    }
+
+    pub fn print_text(&self) {
+        println!("Best location: {:.2}, {:.2}, leader-weighted distance {} µs", self.latitude, self.longitude, self.distance_us);
+        println!("Model: {:.0} µs + {:.2} µs/km, fitted on {} validators ({} SOL)",
+                 self.model.fixed_us, self.model.us_per_km, self.validators, self.stake / LAMPORTS_PER_SOL);
+        for agent in &self.agents {
+            println!("  Agent {:.2}, {:.2} ({}): measured {} µs, modelled {} µs",
+                     agent.latitude, agent.longitude, agent.report.display(), agent.measured_us, agent.modelled_us);
+        }
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize placement"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Measurement plans: groups of targets, each measured with its own probe type and schedule by a single process.
+//!
+//! A plan is a JSON file such as:
+//! ```json
+//! {
+//!   "rpc": "https://api.mainnet-beta.solana.com",
+//!   "groups": [
+//!     {"name": "cluster", "probe": "quic", "schedule": "0 3 * * *"},
+//!     {"name": "partners", "probe": "quic", "destinations": ["puffinQSvKFriPbyE5atyx1ptfnyytovbzxybr1jsyy"], "every": "1m", "count": 3},
+//!     {"name": "entrypoints", "probe": "gossip", "destinations": ["entrypoint.mainnet-beta.solana.com:8001"], "every": "1m"},
+//!     {"name": "rpc", "probe": "rpc", "destinations": ["https://api.mainnet-beta.solana.com"], "every": "5m"}
+//!   ]
+//! }
+//! ```
+//!
+//! Each group starts its rounds either `every` interval, the first one immediately, or on a cron-like `schedule`.
+
+use crate::address::Prefix;
+use crate::cron::CronSchedule;
+use crate::gossip;
+use crate::output::RttStats;
+use crate::NodeError::NotAllowed;
+use crate::DEFAULT_RPC_URL;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::lookup_host;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+/// Spacing of the attempts of gossip and RPC probes
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+/// Time after which an attempt of a gossip or RPC probe is considered failed
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// QUIC handshakes with the TPU of validators, as outside of a plan
+    Quic,
+    /// Gossip pings to gossip host:port
+    Gossip,
+    /// `getHealth` requests to RPC URLs, over a kept-alive connection
+    Rpc,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub name: String,
+    pub probe: Probe,
+    /// Validator pubkeys or TPU ip:port for QUIC, the whole cluster if empty; gossip host:port; or RPC URLs
+    #[serde(default)]
+    pub destinations: Vec<String>,
+    /// Interval between the starts of two rounds, e.g. "30s" or "5m"
+    #[serde(default, deserialize_with = "duration")]
+    pub every: Option<Duration>,
+    /// Cron-like schedule of the starts of the rounds in UTC, e.g. "0 3 * * *", instead of an interval
+    #[serde(default, deserialize_with = "schedule")]
+    pub schedule: Option<CronSchedule>,
+    /// Number of attempts per target and round
+    #[serde(default = "default_count")]
+    pub count: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    /// URL of the RPC where cluster info for QUIC groups is fetched from
+    #[serde(default = "default_rpc")]
+    pub rpc: String,
+    pub groups: Vec<Group>,
+}
+
+fn default_count() -> usize {
+    5
+}
+
+fn default_rpc() -> String {
+    DEFAULT_RPC_URL.to_string()
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn schedule<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<CronSchedule>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+impl Plan {
+    /// Read and check the plan at `path`.
+    pub fn load(path: &Path) -> io::Result<Plan> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let plan: Plan = serde_json::from_slice(&fs::read(path)?).map_err(|e| invalid(e.to_string()))?;
+        if plan.groups.is_empty() {
+            return Err(invalid("No groups".to_string()));
+        }
+        for group in &plan.groups {
+            match (group.every, &group.schedule) {
+                (Some(every), None) if !every.is_zero() => (),
+                (None, Some(_)) => (),
+                _ => return Err(invalid(format!("Group {} needs either a nonzero interval or a schedule", group.name))),
+            }
+            if group.count == 0 {
+                return Err(invalid(format!("Group {} needs a nonzero count", group.name)));
+            }
+            if group.probe != Probe::Quic && group.destinations.is_empty() {
+                return Err(invalid(format!("Group {} has no destinations", group.name)));
+            }
+        }
+        Ok(plan)
+    }
+}
+
+/// Result of a gossip or RPC probe of a target. The distance is half of the minimum RTT.
+#[derive(Serialize)]
+pub struct ProbeResult {
+    pub target: String,
+    pub distance_us: Option<u32>,
+    pub rtt: Option<RttStats>,
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    fn new(target: String, rtts: Result<Vec<u32>, String>) -> ProbeResult {
+        match rtts {
+            Ok(rtts) if rtts.is_empty() => ProbeResult { target, distance_us: None, rtt: None, error: Some("No response".to_string()) },
+            Ok(rtts) => ProbeResult { target, distance_us: rtts.iter().min().map(|rtt| rtt / 2), rtt: RttStats::from_samples(&rtts), error: None },
+            Err(e) => ProbeResult { target, distance_us: None, rtt: None, error: Some(e) },
+        }
+    }
+
+    pub fn print_text(&self, prefix: &str) {
+        match (&self.error, self.distance_us, &self.rtt) {
+            (Some(error), _, _) => println!("{} {} {}", prefix, self.target, error),
+            (None, Some(distance), Some(rtt)) => println!("{} {} {} µs, RTT {}", prefix, self.target, distance, rtt),
+            _ => println!("{} {}", prefix, self.target),
+        }
+    }
+}
+
+/// Return the RTTs in µs of `count` `getHealth` requests to `url`, after a first one opening the connection.
+async fn rpc_rtts(client: &reqwest::Client, url: &str, count: usize) -> Result<Vec<u32>, reqwest::Error> {
+    let request = || client.post(url).header(CONTENT_TYPE, "application/json")
+        .body(r#"{"jsonrpc":"2.0","id":1,"method":"getHealth"}"#).timeout(PROBE_TIMEOUT).send();
+    request().await?.bytes().await?;
+    let mut rtts = Vec::new();
+    for _ in 0..count {
+        sleep(PROBE_INTERVAL).await;
+        let sent = Instant::now();
+        // Any answer, even an unhealthy node or an HTTP error, is a round trip
+        if let Ok(response) = request().await {
+            if response.bytes().await.is_ok() {
+                rtts.push(sent.elapsed().as_micros().try_into().expect("rtt overflow"));
+            }
+        }
+    }
+    Ok(rtts)
+}
+
+/// Return the host and port of the RPC `url`.
+fn rpc_host(url: &str) -> Result<(String, u16), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let host = url.host_str().ok_or_else(|| format!("No host in {}", url))?;
+    let port = url.port_or_known_default().ok_or_else(|| format!("No port in {}", url))?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']').to_string(), port))
+}
+
+/// Resolve `target`, a gossip host:port or an RPC URL, into the address to probe if it is within `allow_prefix`.
+async fn resolve_allowed(probe: Probe, target: &str, allow_prefix: &[Prefix]) -> Result<SocketAddr, String> {
+    let addr = match probe {
+        Probe::Rpc => lookup_host(rpc_host(target)?).await.map_err(|e| e.to_string())?.next(),
+        _ => lookup_host(target).await.map_err(|e| e.to_string())?.next(),
+    };
+    let addr = addr.ok_or_else(|| format!("Cannot resolve {}", target))?;
+    match allow_prefix.iter().any(|p| p.contains(&addr.ip())) {
+        true => Ok(addr),
+        false => Err(NotAllowed.to_string()),
+    }
+}
+
+/// Return a client sending the requests to the host of the RPC `url` to `addr`.
+fn pinned_client(url: &str, addr: SocketAddr) -> Result<reqwest::Client, String> {
+    let (host, _) = rpc_host(url)?;
+    reqwest::Client::builder().resolve(&host, addr).build().map_err(|e| e.to_string())
+}
+
+/// Probe all the destinations of a gossip or RPC group concurrently, return their results in the same order.
+/// If `allow_prefix` isn't empty, the destinations outside of it fail without being probed, and the others are
+/// probed at the address that was checked.
+pub async fn probe(group: &Group, allow_prefix: &[Prefix]) -> Vec<ProbeResult> {
+    let client = reqwest::Client::new();
+    let mut joins = JoinSet::new();
+    for (index, target) in group.destinations.iter().cloned().enumerate() {
+        let (probe, count, allow_prefix) = (group.probe, group.count, allow_prefix.to_vec());
+        let mut client = client.clone();
+        joins.spawn(async move {
+            let mut addr = target.clone();
+            if !allow_prefix.is_empty() {
+                let allowed = match resolve_allowed(probe, &target, &allow_prefix).await {
+                    Ok(allowed) => allowed,
+                    Err(e) => return (index, ProbeResult::new(target, Err(e))),
+                };
+                addr = allowed.to_string();
+                if probe == Probe::Rpc {
+                    client = match pinned_client(&target, allowed) {
+                        Ok(pinned) => pinned,
+                        Err(e) => return (index, ProbeResult::new(target, Err(e))),
+                    };
+                }
+            }
+            let rtts = match probe {
+                Probe::Gossip => {
+                    tokio::task::spawn_blocking(move || gossip::ping(&addr, count, PROBE_INTERVAL, PROBE_TIMEOUT))
+                        .await.expect("Gossip ping panicked").map_err(|e| e.to_string())
+                }
+                Probe::Rpc => rpc_rtts(&client, &target, count).await.map_err(|e| e.to_string()),
+                Probe::Quic => panic!("QUIC groups are measured with a DistanceMeasurer"),
+            };
+            (index, ProbeResult::new(target, rtts))
+        });
+    }
+    let mut results: Vec<(usize, ProbeResult)> = joins.join_all().await;
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
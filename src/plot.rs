@@ -0,0 +1,77 @@
+//! Stake vs distance scatter plot of the measured validators, as a CSV dataset or an SVG image.
+//!
+//! Stakes span several orders of magnitude, so the SVG plots them on a logarithmic axis. Only the TPUs with a
+//! measured distance and some stake are plotted, alternate addresses left out as in the aggregates.
+
+use crate::output::{DistanceReport, TpuReport, LAMPORTS_PER_SOL};
+use std::fmt::Write;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 500.0;
+/// Space left of and below the plot area for the axis labels, and above and right of it
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+const MARGIN: f64 = 20.0;
+const Y_TICKS: u32 = 5;
+
+fn points(report: &DistanceReport) -> Vec<(&TpuReport, f64, u32)> {
+    report.tpus.iter()
+        .filter(|t| t.error.is_none() && !t.alternate && t.stake > 0)
+        .filter_map(|t| Some((t, t.stake as f64 / LAMPORTS_PER_SOL as f64, t.distance_us?)))
+        .collect()
+}
+
+/// Return the plotted TPUs as CSV, one line per TPU with its stake in SOL and its distance in µs.
+pub fn to_csv(report: &DistanceReport) -> String {
+    let mut csv = "address,pubkeys,stake_sol,distance_us,client\n".to_string();
+    for (tpu, stake, distance) in points(report) {
+        writeln!(csv, "{},{},{:.0},{},{}", tpu.address, tpu.pubkeys.join(" "), stake, distance, tpu.client).unwrap();
+    }
+    csv
+}
+
+/// Return a step of about a fifth of `max`, rounded to 1, 2 or 5 times a power of 10.
+fn tick_step(max: f64) -> f64 {
+    let raw = (max / Y_TICKS as f64).max(1.0);
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter().map(|m| m * magnitude).find(|step| *step >= raw).unwrap_or(10.0 * magnitude)
+}
+
+/// Return the plotted TPUs as an SVG scatter plot, stake on a logarithmic x axis and distance on the y axis. Each
+/// point has the pubkeys of its TPU as tooltip.
+pub fn to_svg(report: &DistanceReport) -> String {
+    let points = points(report);
+    let (plot_width, plot_height) = (WIDTH - MARGIN_LEFT - MARGIN, HEIGHT - MARGIN_BOTTOM - MARGIN);
+    // Stakes below 1 SOL are plotted at 1 SOL
+    let decades = points.iter().map(|(_, stake, _)| stake.max(1.0).log10());
+    let min_decade = decades.clone().fold(f64::INFINITY, f64::min).floor();
+    let max_decade = decades.fold(f64::NEG_INFINITY, f64::max).ceil();
+    let (min_decade, max_decade) = if points.is_empty() { (0.0, 1.0) } else { (min_decade, max_decade.max(min_decade + 1.0)) };
+    let step = tick_step(points.iter().map(|(_, _, d)| *d as f64).fold(0.0, f64::max));
+    let max_distance = step * Y_TICKS as f64;
+    let x = |stake: f64| MARGIN_LEFT + (stake.max(1.0).log10() - min_decade) / (max_decade - min_decade) * plot_width;
+    let y = |distance: f64| MARGIN + plot_height - distance.min(max_distance) / max_distance * plot_height;
+
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" font-family="sans-serif" font-size="12">"#).unwrap();
+    writeln!(svg, r#"<rect width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#).unwrap();
+    for decade in min_decade as i32..=max_decade as i32 {
+        let tx = x(10f64.powi(decade));
+        writeln!(svg, r##"<line x1="{tx:.1}" y1="{MARGIN}" x2="{tx:.1}" y2="{:.1}" stroke="#ddd"/>"##, MARGIN + plot_height).unwrap();
+        writeln!(svg, r#"<text x="{tx:.1}" y="{:.1}" text-anchor="middle">{}</text>"#, MARGIN + plot_height + 18.0, 10u64.pow(decade as u32)).unwrap();
+    }
+    for i in 0..=Y_TICKS {
+        let distance = step * i as f64;
+        let ty = y(distance);
+        writeln!(svg, r##"<line x1="{MARGIN_LEFT}" y1="{ty:.1}" x2="{:.1}" y2="{ty:.1}" stroke="#ddd"/>"##, MARGIN_LEFT + plot_width).unwrap();
+        writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{distance}</text>"#, MARGIN_LEFT - 6.0, ty + 4.0).unwrap();
+    }
+    writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">Stake (SOL)</text>"#, MARGIN_LEFT + plot_width / 2.0, HEIGHT - 10.0).unwrap();
+    writeln!(svg, r#"<text transform="translate(16,{:.1}) rotate(-90)" text-anchor="middle">Distance (µs)</text>"#, MARGIN + plot_height / 2.0).unwrap();
+    for (tpu, stake, distance) in &points {
+        writeln!(svg, r##"<circle cx="{:.1}" cy="{:.1}" r="3" fill="#1f77b4" fill-opacity="0.6"><title>{} {} µs</title></circle>"##,
+                 x(*stake), y(*distance as f64), tpu.pubkeys.join(" "), distance).unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
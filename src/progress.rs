@@ -0,0 +1,86 @@
+//! Progress of a measure as its TPUs complete, and a progress line drawn on the terminal during long measures,
+//! e.g. full-cluster sweeps.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval at which the progress line is redrawn while no TPU completes, to update the ETA.
+const REDRAW: Duration = Duration::from_millis(500);
+/// Width of the bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Number of TPUs measured so far, reported at the start of a measure and each time a TPU completes.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
+    /// Completed TPUs with a distance, and without error
+    pub successful: usize,
+    pub failed: usize,
+    /// Time since the start of the attempts
+    pub elapsed: Duration,
+    /// Longest time the attempts can take, retries excluded
+    pub max_duration: Duration,
+}
+
+struct State {
+    last: Option<Progress>,
+    /// When `last` was received
+    received: Instant,
+}
+
+/// Progress line on standard error, redrawn as the TPUs complete and cleared at the end of each measure.
+#[derive(Clone)]
+pub struct ProgressLine(Arc<Mutex<State>>);
+
+impl ProgressLine {
+    /// Return a progress line, or `None` if standard output or error is not a terminal, e.g. redirected to a file.
+    pub fn new() -> Option<ProgressLine> {
+        if !io::stdout().is_terminal() || !io::stderr().is_terminal() {
+            return None;
+        }
+        let line = ProgressLine(Arc::new(Mutex::new(State { last: None, received: Instant::now() })));
+        let weak = Arc::downgrade(&line.0);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(REDRAW);
+            loop {
+                ticks.tick().await;
+                let Some(state) = weak.upgrade() else { return };
+                let state = state.lock().unwrap();
+                if let Some(progress) = &state.last {
+                    draw(progress, state.received.elapsed());
+                }
+            }
+        });
+        Some(line)
+    }
+
+    /// Record and draw `progress`, clearing the line once all the TPUs completed.
+    pub fn update(&self, progress: &Progress) {
+        let mut state = self.0.lock().unwrap();
+        if progress.completed >= progress.total {
+            state.last = None;
+            eprint!("\r\x1b[K");
+        } else {
+            state.last = Some(*progress);
+            state.received = Instant::now();
+            draw(progress, Duration::ZERO);
+        }
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Draw `progress`, received `since` ago.
+fn draw(progress: &Progress, since: Duration) {
+    let filled = BAR_WIDTH * progress.completed / progress.total.max(1);
+    let mut line = format!("\r[{}{}] {}/{} TPUs, {} successful, {} failed", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled),
+                           progress.completed, progress.total, progress.successful, progress.failed);
+    let remaining = progress.max_duration.saturating_sub(progress.elapsed + since);
+    // Past the schedule, only retries and late timeouts remain
+    if !remaining.is_zero() {
+        line += &format!(", ETA {}s", remaining.as_secs() + 1);
+    }
+    eprint!("{}\x1b[K", line);
+    let _ = io::stderr().flush();
+}
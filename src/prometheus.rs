@@ -0,0 +1,133 @@
+//! Prometheus metrics of the last measure, in the text exposition format.
+
+use crate::output::DistanceReport;
+use crate::NodeError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Gauges of the last measure, and error counters accumulated over all measures.
+pub struct Metrics {
+    last: Option<DistanceReport>,
+    rounds: u64,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        // Expose all the error counters from the start, as recommended for Prometheus counters
+        let errors = NodeError::ALL.iter().map(|e| (e.to_string(), 0)).collect();
+        Metrics { last: None, rounds: 0, errors }
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn update(&mut self, report: DistanceReport) {
+        for error in &report.errors {
+            *self.errors.entry(error.error.clone()).or_default() += error.count;
+        }
+        self.rounds += 1;
+        self.last = Some(report);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "# HELP solana_distance_rounds_total Number of completed measures").unwrap();
+        writeln!(out, "# TYPE solana_distance_rounds_total counter").unwrap();
+        writeln!(out, "solana_distance_rounds_total {}", self.rounds).unwrap();
+        writeln!(out, "# HELP solana_distance_errors_total Number of nodes affected by each error, over all measures").unwrap();
+        writeln!(out, "# TYPE solana_distance_errors_total counter").unwrap();
+        for (error, count) in &self.errors {
+            writeln!(out, "solana_distance_errors_total{{error=\"{}\"}} {}", escape(error), count).unwrap();
+        }
+        let Some(report) = &self.last else { return out };
+
+        writeln!(out, "# HELP solana_distance_us Distance to each TPU in the last measure, in µs").unwrap();
+        writeln!(out, "# TYPE solana_distance_us gauge").unwrap();
+        for tpu in &report.tpus {
+            if let (Some(distance), None) = (tpu.distance_us, &tpu.error) {
+                for pubkey in &tpu.pubkeys {
+                    writeln!(out, "solana_distance_us{{tpu=\"{}\",pubkey=\"{}\"}} {}", tpu.address, escape(pubkey), distance).unwrap();
+                }
+            }
+        }
+        if let Some(simple) = &report.simple {
+            writeln!(out, "# HELP solana_distance_simple_us Average distance to the measured TPUs, in µs").unwrap();
+            writeln!(out, "# TYPE solana_distance_simple_us gauge").unwrap();
+            writeln!(out, "solana_distance_simple_us {}", simple.distance_us).unwrap();
+        }
+        if let Some(stake_weighted) = &report.stake_weighted {
+            writeln!(out, "# HELP solana_distance_stake_weighted_us Stake-weighted average distance to the measured TPUs, in µs").unwrap();
+            writeln!(out, "# TYPE solana_distance_stake_weighted_us gauge").unwrap();
+            writeln!(out, "solana_distance_stake_weighted_us {}", stake_weighted.distance_us).unwrap();
+        }
+        if let Some(p) = &report.stake_percentiles {
+            writeln!(out, "# HELP solana_distance_stake_percentile_us Distance below which lies this percentile of the measured stake, in µs").unwrap();
+            writeln!(out, "# TYPE solana_distance_stake_percentile_us gauge").unwrap();
+            for (percentile, distance) in [(50, p.p50_us), (90, p.p90_us), (99, p.p99_us)] {
+                writeln!(out, "solana_distance_stake_percentile_us{{percentile=\"{}\"}} {}", percentile, distance).unwrap();
+            }
+        }
+        writeln!(out, "# HELP solana_distance_connection_successful Number of TPUs measured in the last measure").unwrap();
+        writeln!(out, "# TYPE solana_distance_connection_successful gauge").unwrap();
+        writeln!(out, "solana_distance_connection_successful {}", report.connection_successful).unwrap();
+        writeln!(out, "# HELP solana_distance_measured_stake_lamports Stake of the TPUs measured in the last measure").unwrap();
+        writeln!(out, "# TYPE solana_distance_measured_stake_lamports gauge").unwrap();
+        writeln!(out, "solana_distance_measured_stake_lamports {}", report.measured_stake).unwrap();
+        writeln!(out, "# HELP solana_distance_total_stake_lamports Stake of all the requested nodes").unwrap();
+        writeln!(out, "# TYPE solana_distance_total_stake_lamports gauge").unwrap();
+        writeln!(out, "solana_distance_total_stake_lamports {}", report.total_stake).unwrap();
+        out
+    }
+}
+
+/// Write the metrics to `path` for the textfile collector of node_exporter, through a temporary file in the
+/// same directory renamed over it, so that the collector never reads a partial file.
+pub fn write_textfile(path: &Path, metrics: &Metrics) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", process::id()));
+    std::fs::write(&tmp, metrics.render())?;
+    std::fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+/// Serve `/metrics` on `listener` until the process exits.
+pub async fn serve(listener: TcpListener, metrics: Arc<Mutex<Metrics>>) {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else { continue };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // Only the request line matters, which fits in the first read
+            let mut buf = [0; 1024];
+            let Ok(n) = stream.read(&mut buf).await else { return };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or("");
+            let response = if path == "/metrics" || path.starts_with("/metrics?") {
+                let body = metrics.lock().unwrap().render();
+                format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Bind `addr` and serve the metrics in the background.
+pub async fn spawn_server(addr: SocketAddr) -> io::Result<Arc<Mutex<Metrics>>> {
+    let listener = TcpListener::bind(addr).await?;
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    tokio::spawn(serve(listener, metrics.clone()));
+    Ok(metrics)
+}
@@ -0,0 +1,146 @@
+//! Hosting provider of the TPUs, e.g. OVH, Latitude.sh or TeraSwitch, and the stake and distance of each, since
+//! validator operators choose datacenters rather than individual peers.
+//!
+//! The provider of an address is found, in turn, in a file of lines `prefix-or-ASN provider`, in a bundled table
+//! of the autonomous systems of known hosting providers with the ASN of `--geoip`, or is the name of its AS.
+
+use crate::address::Prefix;
+use crate::output::{TpuReport, LAMPORTS_PER_SOL};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Label of the TPUs of unknown provider
+pub const UNKNOWN_PROVIDER: &str = "Unknown";
+
+/// Autonomous systems of hosting providers common among validators.
+const KNOWN_PROVIDERS: [(u32, &str); 34] = [
+    (16276, "OVH"),
+    (24940, "Hetzner"),
+    (396356, "Latitude.sh"),
+    (262287, "Latitude.sh"),
+    (397423, "TeraSwitch"),
+    (54825, "Equinix Metal"),
+    (59642, "Cherry Servers"),
+    (16509, "Amazon"),
+    (14618, "Amazon"),
+    (15169, "Google Cloud"),
+    (396982, "Google Cloud"),
+    (8075, "Microsoft Azure"),
+    (31898, "Oracle Cloud"),
+    (14061, "DigitalOcean"),
+    (20473, "Vultr"),
+    (63949, "Akamai Linode"),
+    (19318, "InterServer"),
+    (29802, "Hivelocity"),
+    (60781, "Leaseweb"),
+    (16265, "Leaseweb"),
+    (28753, "Leaseweb"),
+    (30633, "Leaseweb"),
+    (59253, "Leaseweb"),
+    (40676, "Psychz"),
+    (60068, "DataPacket"),
+    (49544, "i3D.net"),
+    (51167, "Contabo"),
+    (21859, "Zenlayer"),
+    (8560, "IONOS"),
+    (12876, "Scaleway"),
+    (7979, "Servers.com"),
+    (46475, "Limestone Networks"),
+    (36352, "ColoCrossing"),
+    (23470, "ReliableSite"),
+];
+
+/// Providers of prefixes and autonomous systems, read from a file.
+#[derive(Default)]
+pub struct ProviderMap {
+    prefixes: Vec<(Prefix, String)>,
+    asns: HashMap<u32, String>,
+}
+
+impl ProviderMap {
+    pub fn load(path: &Path) -> io::Result<ProviderMap> {
+        let mut map = ProviderMap::default();
+        for line in fs::read_to_string(path)?.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid provider line: {}", line));
+            let (key, provider) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let provider = provider.trim().to_string();
+            match key.trim_start_matches("AS").parse::<u32>() {
+                Ok(asn) => {
+                    map.asns.insert(asn, provider);
+                }
+                Err(_) => map.prefixes.push((key.parse().map_err(|_| invalid())?, provider)),
+            }
+        }
+        // The most specific prefix first
+        map.prefixes.sort_by_key(|(prefix, _)| {
+            let (first, last) = prefix.range();
+            last - first
+        });
+        Ok(map)
+    }
+
+    /// Return the provider of `tpu`, `UNKNOWN_PROVIDER` if neither the map nor its GeoIP information tell it.
+    pub fn provider(map: Option<&ProviderMap>, tpu: &TpuReport) -> String {
+        let ip = tpu.address.ip();
+        let asn = tpu.geo.as_ref().and_then(|g| g.asn);
+        map.and_then(|map| {
+            map.prefixes.iter().find(|(prefix, _)| prefix.contains(&ip)).map(|(_, provider)| provider.clone())
+                .or_else(|| map.asns.get(&asn?).cloned())
+        })
+            .or_else(|| KNOWN_PROVIDERS.iter().find(|(known, _)| Some(*known) == asn).map(|(_, provider)| provider.to_string()))
+            .or_else(|| tpu.geo.as_ref().and_then(|g| g.as_name()))
+            .unwrap_or_else(|| UNKNOWN_PROVIDER.to_string())
+    }
+}
+
+/// Measured TPUs of a provider. Stakes are in lamports, distances in µs.
+#[derive(Serialize)]
+pub struct ProviderReport {
+    pub provider: String,
+    pub connection_successful: u64,
+    pub measured_stake: u64,
+    pub median_distance_us: u32,
+    pub stake_weighted_distance_us: Option<u64>,
+}
+
+impl ProviderReport {
+    /// Group the successfully measured TPUs by provider, the providers with the most stake first.
+    pub fn group(tpus: &[TpuReport], map: Option<&ProviderMap>) -> Vec<ProviderReport> {
+        let mut groups: BTreeMap<String, Vec<&TpuReport>> = BTreeMap::new();
+        for tpu in tpus.iter().filter(|t| t.error.is_none() && !t.alternate && t.distance_us.is_some()) {
+            groups.entry(ProviderMap::provider(map, tpu)).or_default().push(tpu);
+        }
+        let mut reports: Vec<ProviderReport> = groups.into_iter().map(|(provider, tpus)| {
+            let mut distances: Vec<u32> = tpus.iter().filter_map(|t| t.distance_us).collect();
+            distances.sort_unstable();
+            let stake: u64 = tpus.iter().map(|t| t.stake).sum();
+            let weighted: u128 = tpus.iter().map(|t| t.distance_us.unwrap_or_default() as u128 * t.stake as u128).sum();
+            ProviderReport {
+                provider,
+                connection_successful: tpus.len() as u64,
+                measured_stake: stake,
+                median_distance_us: distances[distances.len() / 2],
+                stake_weighted_distance_us: (stake > 0).then(|| (weighted / stake as u128) as u64),
+            }
+        }).collect();
+        reports.sort_by(|a, b| b.measured_stake.cmp(&a.measured_stake).then_with(|| a.provider.cmp(&b.provider)));
+        reports
+    }
+}
+
+pub(crate) fn print_text(providers: &[ProviderReport], measured_stake: u64) {
+    println!("Distance by provider:");
+    for p in providers {
+        print!("  {}: median {} µs", p.provider, p.median_distance_us);
+        if let Some(distance) = p.stake_weighted_distance_us {
+            print!(", stake-weighted {} µs", distance);
+        }
+        if measured_stake > 0 {
+            print!(", {} SOL ({:.1}% of measured stake)", p.measured_stake / LAMPORTS_PER_SOL, p.measured_stake as f64 * 100.0 / measured_stake as f64);
+        }
+        println!(", {} TPUs", p.connection_successful);
+    }
+}
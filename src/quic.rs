@@ -1,37 +1,85 @@
 use std::fmt::{Debug, Formatter};
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use quinn::{ClientConfig, Endpoint, TransportConfig};
+use quinn::{ClientConfig, Connection, Endpoint, EndpointConfig, MtuDiscoveryConfig, Runtime, TokioRuntime, TransportConfig};
 use quinn::crypto::rustls::QuicClientConfig;
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::crypto::CryptoProvider;
+use rustls::crypto::{verify_tls13_signature, CryptoProvider, WebPkiSupportedAlgorithms};
 use solana_keypair::{Keypair, Signer};
+use solana_pubkey::Pubkey;
 use rustls::crypto::aws_lc_rs as provider;
 use rustls::{DigitallySignedStruct, SignatureScheme};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use crate::udp::{CountingSocket, PacketTrace, UdpCounters};
+use socket2::{Domain, Protocol, Socket, Type};
 
 const ALPN_TPU_PROTOCOL_ID: &[u8] = b"solana-tpu"; // Application protocol transported by TLS
 pub const QUIC_KEEP_ALIVE: Duration = Duration::from_millis(1000);
 pub const QUIC_MAX_TIMEOUT: Duration = Duration::from_millis(20000);
+/// Keep-alive of persistent connections, frequent enough for the smoothed RTT to forget the handshake
+pub const STEADY_KEEP_ALIVE: Duration = Duration::from_millis(100);
+/// Smallest MTU of QUIC over IPv6, assumed without discovery
+pub const MIN_MTU: u16 = 1280;
+/// Largest MTU probed by path MTU discovery, of an Ethernet path of 1500 bytes with IPv6 and UDP headers
+pub const MAX_PROBED_MTU: u16 = 1452;
 
 pub fn socket_addr_to_quic_server_name(peer: SocketAddr) -> String {
-    format!("{}.{}.sol", peer.ip(), peer.port())
+    match peer.ip() {
+        IpAddr::V4(ip) => format!("{}.{}.sol", ip, peer.port()),
+        // Colons are not valid in a server name, and SNI being disabled any valid name does
+        IpAddr::V6(ip) => format!("{}.{}.sol", ip.segments().map(|s| format!("{:x}", s)).join("-"), peer.port()),
+    }
+}
+
+/// Return a UDP socket bound to `bind`, or to a dual-stack address if IPv6 is available, and to `interface`
+/// if specified.
+fn bind_socket(bind: Option<SocketAddr>, interface: Option<&str>) -> io::Result<std::net::UdpSocket> {
+    let bind_one = |addr: SocketAddr| -> io::Result<std::net::UdpSocket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if addr.is_ipv6() && addr.ip().is_unspecified() {
+            socket.set_only_v6(false)?;
+        }
+        if let Some(interface) = interface {
+            bind_device(&socket, interface)?;
+        }
+        socket.bind(&addr.into())?;
+        Ok(socket.into())
+    };
+    match bind {
+        Some(addr) => bind_one(addr),
+        None => bind_one(SocketAddr::from(([0u16; 8], 0))).or_else(|_| bind_one(SocketAddr::from(([0, 0, 0, 0], 0)))),
+    }
 }
 
-pub async fn new_quic_endpoint(keypair: &Keypair, client_port: u16) -> Endpoint {
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &Socket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Binding to an interface is only supported on Linux"))
+}
+
+/// Return true if sockets can be bound to an interface on this platform.
+pub(crate) const BIND_DEVICE: bool = cfg!(any(target_os = "android", target_os = "fuchsia", target_os = "linux"));
+
+/// Return the client configuration of connections to TPUs, keeping them alive with a ping every `keep_alive`, and
+/// checking the handshake signature of the servers with `verify_signatures`.
+pub(crate) fn client_config(keypair: &Keypair, keep_alive: Duration, verify_signatures: bool) -> ClientConfig {
     let root_store = rustls::RootCertStore::empty();
 
-    let (cert, private_key) = new_x509_certificate(&keypair);
+    let (cert, private_key) = new_x509_certificate(keypair);
 
-    let mut tls_config = rustls::ClientConfig::builder_with_provider(
-        CryptoProvider {
-            cipher_suites: vec![provider::cipher_suite::TLS13_AES_128_GCM_SHA256],
-            kx_groups: vec![provider::kx_group::X25519],
-            ..provider::default_provider()
-        }
-            .into(),
-    )
+    let crypto = CryptoProvider {
+        cipher_suites: vec![provider::cipher_suite::TLS13_AES_128_GCM_SHA256],
+        kx_groups: vec![provider::kx_group::X25519],
+        ..provider::default_provider()
+    };
+    let algorithms = verify_signatures.then_some(crypto.signature_verification_algorithms);
+    let mut tls_config = rustls::ClientConfig::builder_with_provider(crypto.into())
         .with_protocol_versions(&[&rustls::version::TLS13])
         .unwrap()
         .with_root_certificates(root_store.clone())
@@ -42,30 +90,72 @@ pub async fn new_quic_endpoint(keypair: &Keypair, client_port: u16) -> Endpoint
     tls_config.alpn_protocols = vec![ALPN_TPU_PROTOCOL_ID.to_vec()];
     tls_config.enable_sni = false;
 
-    let verifier = SkipServerVerification::new();
+    let verifier = ServerVerification::new(algorithms);
     tls_config.dangerous().set_certificate_verifier(verifier);
 
     // QUIC config
     let mut config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls_config).unwrap()));
+    config.transport_config(Arc::new(transport_config(keep_alive)));
+    config
+}
+
+fn transport_config(keep_alive: Duration) -> TransportConfig {
     let mut transport_config = TransportConfig::default();
     transport_config.max_idle_timeout(Some(
         QUIC_MAX_TIMEOUT.try_into().expect("Cannot convert timeout"),
     ));
-    transport_config.keep_alive_interval(Some(
-        QUIC_KEEP_ALIVE
-            .try_into()
-            .expect("Cannot convert keep alive"),
-    ));
+    transport_config.keep_alive_interval(Some(keep_alive));
     transport_config.mtu_discovery_config(None);
-    transport_config.min_mtu(1280);
+    transport_config.min_mtu(MIN_MTU);
     transport_config.send_fairness(false);
+    transport_config
+}
+
+/// Return the client configuration of connections to TPUs, with path MTU discovery up to `MAX_PROBED_MTU`.
+pub(crate) fn mtu_discovery_client_config(keypair: &Keypair) -> ClientConfig {
+    let mut config = client_config(keypair, QUIC_KEEP_ALIVE, false);
+    let mut transport_config = transport_config(QUIC_KEEP_ALIVE);
+    let mut discovery = MtuDiscoveryConfig::default();
+    discovery.upper_bound(MAX_PROBED_MTU);
+    transport_config.mtu_discovery_config(Some(discovery));
     config.transport_config(Arc::new(transport_config));
+    config
+}
+
+/// Client configurations with fresh keypairs, each used for `every` connections.
+pub(crate) struct IdentityRotator {
+    every: usize,
+    verify_signatures: bool,
+    current: Mutex<(usize, ClientConfig)>,
+}
+
+impl IdentityRotator {
+    pub(crate) fn new(every: usize, verify_signatures: bool) -> IdentityRotator {
+        let config = client_config(&Keypair::new(), QUIC_KEEP_ALIVE, verify_signatures);
+        IdentityRotator { every, verify_signatures, current: Mutex::new((0, config)) }
+    }
+
+    /// Return the configuration of the next connection, with a new identity every `every` connections.
+    pub(crate) fn next(&self) -> ClientConfig {
+        let mut current = self.current.lock().unwrap();
+        if current.0 == self.every {
+            *current = (0, client_config(&Keypair::new(), QUIC_KEEP_ALIVE, self.verify_signatures));
+        }
+        current.0 += 1;
+        current.1.clone()
+    }
+}
 
-    // Local address
-    let client_addr = SocketAddr::from(([0, 0, 0, 0], client_port));
-    let mut endpoint = Endpoint::client(client_addr).expect("Cannot create endpoint");
-    endpoint.set_default_client_config(config);
-    endpoint
+pub async fn new_quic_endpoint(keypair: &Keypair, bind: Option<SocketAddr>, interface: Option<&str>, trace: Option<Arc<PacketTrace>>,
+                               verify_signatures: bool) -> io::Result<(Endpoint, Arc<UdpCounters>)> {
+    // Local address, dual-stack when IPv6 is available so that both address families can be measured
+    let socket = bind_socket(bind, interface)?;
+    let runtime = Arc::new(TokioRuntime);
+    let counters = Arc::new(UdpCounters::default());
+    let socket = CountingSocket::new(runtime.wrap_udp_socket(socket)?, counters.clone(), trace);
+    let mut endpoint = Endpoint::new_with_abstract_socket(EndpointConfig::default(), None, socket, runtime)?;
+    endpoint.set_default_client_config(client_config(keypair, QUIC_KEEP_ALIVE, verify_signatures));
+    Ok((endpoint, counters))
 }
 
 fn new_x509_certificate(keypair: &Keypair) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
@@ -105,21 +195,37 @@ fn new_x509_certificate(keypair: &Keypair) -> (CertificateDer<'static>, PrivateK
     )
 }
 
-pub struct SkipServerVerification;
+/// Return the identity in the self-signed certificate of the server of `connection`, its Ed25519 public key, or
+/// `None` if the certificate has another kind of key.
+pub(crate) fn server_identity(connection: &Connection) -> Option<Pubkey> {
+    // Algorithm identifier of Ed25519, followed by the header of the bit string of the key
+    const SPKI_PREFIX: [u8; 8] = [0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+    let certificates = connection.peer_identity()?.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+    let der = certificates.first()?.as_ref();
+    let start = der.windows(SPKI_PREFIX.len()).position(|w| w == SPKI_PREFIX)? + SPKI_PREFIX.len();
+    Some(Pubkey::new_from_array(der.get(start..start + 32)?.try_into().ok()?))
+}
+
+/// Verifier of the certificates of the servers, self-signed and accepted as they are. With signature algorithms,
+/// the TLS 1.3 handshake signature is checked against the key of the certificate, so that a server can't present
+/// the public certificate of another node without its private key.
+pub struct ServerVerification {
+    algorithms: Option<WebPkiSupportedAlgorithms>,
+}
 
-impl SkipServerVerification {
-    pub fn new() -> Arc<Self> {
-        Arc::new(Self)
+impl ServerVerification {
+    pub fn new(algorithms: Option<WebPkiSupportedAlgorithms>) -> Arc<Self> {
+        Arc::new(Self { algorithms })
     }
 }
 
-impl Debug for SkipServerVerification {
+impl Debug for ServerVerification {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "SkipServerVerification")
+        write!(f, "ServerVerification {{ signatures: {} }}", self.algorithms.is_some())
     }
 }
 
-impl ServerCertVerifier for SkipServerVerification {
+impl ServerCertVerifier for ServerVerification {
     fn verify_server_cert(
         &self,
         _: &CertificateDer<'_>,
@@ -142,14 +248,79 @@ impl ServerCertVerifier for SkipServerVerification {
 
     fn verify_tls13_signature(
         &self,
-        _: &[u8],
-        _: &CertificateDer<'_>,
-        _: &DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
+        match &self.algorithms {
+            Some(algorithms) => verify_tls13_signature(message, cert, dss, algorithms),
+            None => Ok(HandshakeSignatureValid::assertion()),
+        }
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
         vec![SignatureScheme::ED25519]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quinn::crypto::rustls::QuicServerConfig;
+    use quinn::{ConnectionError, ServerConfig};
+    use rustls::server::{ClientHello, ResolvesServerCert};
+    use rustls::sign::CertifiedKey;
+
+    /// Server presenting a fixed certificate, signing the handshakes with a key which may not be its own.
+    #[derive(Debug)]
+    struct FixedCert(Arc<CertifiedKey>);
+
+    impl ResolvesServerCert for FixedCert {
+        fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+            Some(self.0.clone())
+        }
+    }
+
+    /// Connect to a local server presenting the certificate of `cert` and signing with the key of `key`, return
+    /// the identity found in its certificate.
+    async fn handshake(cert: &Keypair, key: &Keypair, verify_signatures: bool) -> Result<Option<Pubkey>, ConnectionError> {
+        let crypto = provider::default_provider();
+        let (cert, _) = new_x509_certificate(cert);
+        let (_, key) = new_x509_certificate(key);
+        let key = crypto.key_provider.load_private_key(key).unwrap();
+        let resolver = FixedCert(Arc::new(CertifiedKey::new(vec![cert], key)));
+        let mut tls_config = rustls::ServerConfig::builder_with_provider(crypto.into())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        tls_config.alpn_protocols = vec![ALPN_TPU_PROTOCOL_ID.to_vec()];
+        let server_config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls_config).unwrap()));
+        let server = Endpoint::server(server_config, SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            if let Some(incoming) = server.accept().await {
+                let _ = incoming.await;
+            }
+        });
+
+        let bind = Some(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let (client, _) = new_quic_endpoint(&Keypair::new(), bind, None, None, verify_signatures).await.unwrap();
+        let connection = client.connect(addr, &socket_addr_to_quic_server_name(addr)).unwrap().await?;
+        Ok(server_identity(&connection))
+    }
+
+    #[tokio::test]
+    async fn accepts_the_signature_of_the_identity() {
+        let identity = Keypair::new();
+        assert_eq!(handshake(&identity, &identity, true).await.unwrap(), Some(identity.pubkey()));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_replayed_certificate() {
+        let (identity, hijacker) = (Keypair::new(), Keypair::new());
+        assert!(handshake(&identity, &hijacker, true).await.is_err());
+        // Without checking the signature, the certificate alone passes for the identity
+        assert_eq!(handshake(&identity, &hijacker, false).await.unwrap(), Some(identity.pubkey()));
+    }
+}
@@ -0,0 +1,123 @@
+//! Spacing of the connection attempts to co-located TPUs, limited per destination IP address and per subnet, a
+//! /24 in IPv4 and a /48 in IPv6, since bursts of connections to hosts sharing infrastructure can trigger the QUIC
+//! connection throttling of the validators and inflate their RTTs.
+//!
+//! Each IP address and subnet is a token bucket holding a single token, refilled at its rate: an attempt takes
+//! the tokens of its address and subnet, waiting for the later of the two.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::Error;
+
+/// Key of the subnet of an address.
+fn subnet(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & 0xffff_ff00)),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !((1u128 << 80) - 1))),
+    }
+}
+
+/// Return the interval between the attempts at `rate` per second, an error if `rate` is not positive or too low for
+/// the interval to be represented.
+fn spacing(rate: f64) -> Result<Duration, Error> {
+    match Duration::try_from_secs_f64(1.0 / rate) {
+        Ok(spacing) if rate > 0.0 => Ok(spacing),
+        _ => Err(Error::Rate(rate)),
+    }
+}
+
+/// Instants at which the next attempt to each address and subnet may start.
+pub(crate) struct RateLimiter {
+    ip_spacing: Option<Duration>,
+    subnet_spacing: Option<Duration>,
+    next: Mutex<(HashMap<IpAddr, Instant>, HashMap<IpAddr, Instant>)>,
+}
+
+impl RateLimiter {
+    /// Return a limiter of the attempts per second to each IP address and subnet, `None` if neither is limited.
+    pub(crate) fn new(per_ip: Option<f64>, per_subnet: Option<f64>) -> Result<Option<RateLimiter>, Error> {
+        let (ip_spacing, subnet_spacing) = (per_ip.map(spacing).transpose()?, per_subnet.map(spacing).transpose()?);
+        Ok((ip_spacing.is_some() || subnet_spacing.is_some()).then(|| RateLimiter {
+            ip_spacing,
+            subnet_spacing,
+            next: Mutex::new((HashMap::new(), HashMap::new())),
+        }))
+    }
+
+    /// Reserve the first instant from `earliest` at which an attempt to `ip` may start, and return it.
+    pub(crate) fn reserve(&self, ip: IpAddr, earliest: Instant) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let (ips, subnets) = &mut *next;
+        let subnet = subnet(ip);
+        let start = [ips.get(&ip).filter(|_| self.ip_spacing.is_some()), subnets.get(&subnet).filter(|_| self.subnet_spacing.is_some())]
+            .into_iter()
+            .flatten()
+            .fold(earliest, |start, next| start.max(*next));
+        if let Some(spacing) = self.ip_spacing {
+            ips.insert(ip, start + spacing);
+        }
+        if let Some(spacing) = self.subnet_spacing {
+            subnets.insert(subnet, start + spacing);
+        }
+        start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        for rate in [0.0, -0.0, -1.0, f64::NAN, f64::NEG_INFINITY, 1e-300] {
+            assert!(matches!(RateLimiter::new(Some(rate), None), Err(Error::Rate(_))), "{}", rate);
+            assert!(matches!(RateLimiter::new(None, Some(rate)), Err(Error::Rate(_))), "{}", rate);
+        }
+        assert!(RateLimiter::new(None, None).unwrap().is_none());
+        assert!(RateLimiter::new(Some(0.5), Some(f64::INFINITY)).unwrap().is_some());
+    }
+
+    #[test]
+    fn keys_subnets_by_24_and_48() {
+        assert_eq!(subnet(ip("192.0.2.77")), ip("192.0.2.0"));
+        assert_eq!(subnet(ip("2001:db8:1:2:3::4")), ip("2001:db8:1::"));
+        assert_eq!(subnet(ip("2001:db8:1:ffff::")), subnet(ip("2001:db8:1::1")));
+        assert_ne!(subnet(ip("2001:db8:1::")), subnet(ip("2001:db8:2::")));
+    }
+
+    #[test]
+    fn spaces_attempts_to_an_ip() {
+        let limiter = RateLimiter::new(Some(4.0), None).unwrap().unwrap();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(ip("192.0.2.1"), now), now);
+        assert_eq!(limiter.reserve(ip("192.0.2.1"), now), now + Duration::from_millis(250));
+        assert_eq!(limiter.reserve(ip("192.0.2.1"), now), now + Duration::from_millis(500));
+        // Only limited per address, the neighbours start at once
+        assert_eq!(limiter.reserve(ip("192.0.2.2"), now), now);
+        // An attempt later than the reserved instant is not delayed
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.reserve(ip("192.0.2.1"), later), later);
+    }
+
+    #[test]
+    fn spaces_attempts_to_a_subnet() {
+        let limiter = RateLimiter::new(Some(1.0), Some(10.0)).unwrap().unwrap();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(ip("192.0.2.1"), now), now);
+        assert_eq!(limiter.reserve(ip("192.0.2.200"), now), now + Duration::from_millis(100));
+        assert_eq!(limiter.reserve(ip("192.0.2.3"), now), now + Duration::from_millis(200));
+        // The later of the address and subnet instants
+        assert_eq!(limiter.reserve(ip("192.0.2.1"), now), now + Duration::from_secs(1));
+        assert_eq!(limiter.reserve(ip("192.0.3.1"), now), now);
+        assert_eq!(limiter.reserve(ip("2001:db8:1:2::1"), now), now);
+        assert_eq!(limiter.reserve(ip("2001:db8:1:3::1"), now), now + Duration::from_millis(100));
+        assert_eq!(limiter.reserve(ip("2001:db8:2::1"), now), now);
+    }
+}
@@ -0,0 +1,127 @@
+//! Measures run from several vantage points at once, executing the tool on remote hosts over SSH with
+//! `--output json`, and a matrix of their distances, e.g. to pick the datacenter of a new validator.
+//!
+//! Hosts are given as SSH destinations, `[user@]host` or aliases of the SSH configuration, and must have the tool
+//! installed. SSH runs in batch mode: authentication must not prompt, e.g. with keys loaded in an agent.
+
+use crate::output::SCHEMA_VERSION;
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Command run on the hosts, and the arguments of their measure.
+pub struct RemoteOptions {
+    /// Path or name of the tool on the hosts
+    pub binary: String,
+    /// Arguments of the measure, e.g. destinations and options, `--output json` being added
+    pub args: Vec<String>,
+}
+
+/// The fields of a [`DistanceReport`](crate::output::DistanceReport) read from a host.
+#[derive(Deserialize)]
+struct HostReport {
+    #[serde(default)]
+    schema_version: u32,
+    simple: Option<HostDistance>,
+    stake_weighted: Option<HostDistance>,
+    connection_successful: u64,
+    #[serde(default)]
+    cancelled: bool,
+}
+
+#[derive(Deserialize)]
+struct HostDistance {
+    distance_us: u64,
+}
+
+/// Distances measured from a host, in µs, or the reason it has none.
+#[derive(Serialize)]
+pub struct Vantage {
+    pub host: String,
+    pub stake_weighted_us: Option<u64>,
+    pub simple_us: Option<u64>,
+    pub connection_successful: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Distances from all the hosts, the closest to the stake first and failed hosts last.
+#[derive(Serialize)]
+pub struct RemoteReport {
+    pub schema_version: u32,
+    pub vantages: Vec<Vantage>,
+}
+
+/// Quote `arg` for the remote shell, which SSH gives the command line to.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Run the measure on `host`, and return its distances.
+fn measure(host: &str, options: &RemoteOptions) -> Vantage {
+    let mut vantage = Vantage { host: host.to_string(), stake_weighted_us: None, simple_us: None, connection_successful: 0, cancelled: false, error: None };
+    let command = [options.binary.as_str(), "--output", "json"].into_iter().chain(options.args.iter().map(String::as_str))
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "--", host, &command])
+        .stdin(Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            vantage.error = Some(format!("Failed to run ssh: {}", e));
+            return vantage;
+        }
+    };
+    // A measure exceeding the thresholds of a health check exits with an error but reports
+    match serde_json::from_slice::<HostReport>(&output.stdout) {
+        Ok(report) if report.schema_version > SCHEMA_VERSION => {
+            vantage.error = Some(format!("Report of schema version {}, this version reads up to {}", report.schema_version, SCHEMA_VERSION));
+        }
+        Ok(report) => {
+            vantage.stake_weighted_us = report.stake_weighted.map(|d| d.distance_us);
+            vantage.simple_us = report.simple.map(|d| d.distance_us);
+            vantage.connection_successful = report.connection_successful;
+            vantage.cancelled = report.cancelled;
+        }
+        Err(_) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("no report").trim().to_string();
+            vantage.error = Some(format!("{}: {}", output.status, reason));
+        }
+    }
+    vantage
+}
+
+impl RemoteReport {
+    /// Measure from all `hosts` at the same time, and wait for all of them.
+    pub fn run(hosts: &[String], options: &RemoteOptions) -> RemoteReport {
+        let mut vantages: Vec<Vantage> = thread::scope(|scope| {
+            let measures: Vec<_> = hosts.iter().map(|host| scope.spawn(move || measure(host, options))).collect();
+            measures.into_iter().map(|m| m.join().expect("Remote measure panicked")).collect()
+        });
+        vantages.sort_by_key(|v| (v.stake_weighted_us.or(v.simple_us).is_none(), v.stake_weighted_us.or(v.simple_us)));
+        RemoteReport { schema_version: SCHEMA_VERSION, vantages }
+    }
+
+    pub fn print_text(&self) {
+        let width = self.vantages.iter().map(|v| v.host.len()).max().unwrap_or(0).max("Vantage".len());
+        println!("{:<width$} {:>16} {:>10} {:>8}", "Vantage", "Stake-weighted", "Simple", "Reached");
+        let us = |d: Option<u64>| d.map_or("-".to_string(), |d| format!("{} µs", d));
+        for v in &self.vantages {
+            match &v.error {
+                Some(error) => println!("{:<width$} failed: {}", v.host, error),
+                None => println!("{:<width$} {:>16} {:>10} {:>8}{}", v.host, us(v.stake_weighted_us), us(v.simple_us), v.connection_successful,
+                                 if v.cancelled { " (cancelled)" } else { "" }),
+            }
+        }
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize remote report"));
+    }
+}
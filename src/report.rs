@@ -0,0 +1,287 @@
+//! Self-contained HTML report of a measure, for `--output html`: a world map of the validators located by a City
+//! database of `--geoip`, colored by distance and sized by stake, a histogram of the distances, and the aggregates.
+//!
+//! The page has no external resource, so that it can be archived or sent as is: the map is an SVG drawn from a
+//! coarse outline of the continents, and the tooltips, zoom and pan are done by a small embedded script.
+
+use crate::output::{Distance, DistanceReport, TpuReport, LAMPORTS_PER_SOL};
+use std::fmt::Write;
+
+const MAP_WIDTH: f64 = 960.0;
+const MAP_HEIGHT: f64 = 480.0;
+const HISTOGRAM_WIDTH: f64 = 960.0;
+const HISTOGRAM_HEIGHT: f64 = 240.0;
+/// Space left of and below the histogram for the axis labels, and above and right of it
+const MARGIN_LEFT: f64 = 50.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+const MARGIN: f64 = 10.0;
+const HISTOGRAM_BINS: u32 = 30;
+/// Radii of the least and most staked validators on the map
+const MIN_RADIUS: f64 = 2.0;
+const MAX_RADIUS: f64 = 14.0;
+
+/// Coarse outlines of the land masses, as longitude and latitude in degrees, enough to recognize the regions.
+const CONTINENTS: [&[(f64, f64)]; 8] = [
+    // North America
+    &[(-168.0, 66.0), (-162.0, 70.0), (-140.0, 70.0), (-120.0, 74.0), (-95.0, 72.0), (-80.0, 73.0), (-62.0, 60.0), (-56.0, 52.0),
+      (-66.0, 45.0), (-76.0, 35.0), (-81.0, 25.0), (-90.0, 29.0), (-97.0, 26.0), (-97.0, 20.0), (-87.0, 21.0), (-83.0, 10.0),
+      (-78.0, 8.0), (-92.0, 15.0), (-105.0, 20.0), (-112.0, 30.0), (-118.0, 34.0), (-124.0, 40.0), (-124.0, 48.0), (-135.0, 58.0),
+      (-150.0, 60.0), (-165.0, 60.0)],
+    // Greenland
+    &[(-73.0, 78.0), (-60.0, 82.0), (-30.0, 83.0), (-20.0, 75.0), (-22.0, 70.0), (-43.0, 60.0), (-52.0, 64.0), (-58.0, 75.0)],
+    // South America
+    &[(-78.0, 8.0), (-72.0, 12.0), (-62.0, 11.0), (-50.0, 0.0), (-35.0, -6.0), (-39.0, -14.0), (-48.0, -26.0), (-58.0, -36.0),
+      (-65.0, -42.0), (-68.0, -54.0), (-74.0, -50.0), (-72.0, -30.0), (-70.0, -18.0), (-76.0, -14.0), (-81.0, -5.0), (-80.0, 1.0)],
+    // Eurasia
+    &[(-10.0, 36.0), (-9.0, 43.0), (-2.0, 44.0), (-5.0, 48.0), (2.0, 51.0), (8.0, 54.0), (10.0, 58.0), (5.0, 62.0), (15.0, 69.0),
+      (28.0, 71.0), (40.0, 67.0), (60.0, 69.0), (80.0, 73.0), (105.0, 78.0), (140.0, 72.0), (180.0, 69.0), (180.0, 65.0),
+      (160.0, 60.0), (163.0, 55.0), (156.0, 51.0), (140.0, 54.0), (135.0, 43.0), (128.0, 35.0), (122.0, 40.0), (121.0, 31.0),
+      (117.0, 24.0), (108.0, 21.0), (106.0, 10.0), (100.0, 13.0), (103.0, 1.0), (98.0, 8.0), (98.0, 16.0), (92.0, 22.0),
+      (80.0, 15.0), (77.0, 8.0), (72.0, 21.0), (66.0, 25.0), (57.0, 25.0), (56.0, 27.0), (50.0, 30.0), (48.0, 28.0), (56.0, 24.0),
+      (59.0, 22.0), (52.0, 16.0), (43.0, 13.0), (39.0, 21.0), (35.0, 28.0), (34.0, 31.0), (36.0, 36.0), (28.0, 36.0), (26.0, 40.0),
+      (23.0, 38.0), (20.0, 40.0), (16.0, 38.0), (12.0, 44.0), (9.0, 44.0), (3.0, 43.0), (-1.0, 37.0), (-6.0, 36.0)],
+    // Great Britain
+    &[(-5.0, 50.0), (1.0, 51.0), (2.0, 53.0), (-2.0, 56.0), (-2.0, 58.0), (-6.0, 58.0), (-5.0, 55.0), (-3.0, 54.0), (-5.0, 52.0)],
+    // Japan
+    &[(130.0, 31.0), (132.0, 34.0), (140.0, 36.0), (142.0, 40.0), (141.0, 45.0), (145.0, 44.0), (140.0, 38.0), (136.0, 34.0)],
+    // Africa
+    &[(-17.0, 21.0), (-16.0, 28.0), (-10.0, 30.0), (-6.0, 36.0), (10.0, 37.0), (11.0, 33.0), (20.0, 31.0), (32.0, 31.0), (34.0, 28.0),
+      (43.0, 12.0), (51.0, 12.0), (40.0, -2.0), (40.0, -15.0), (35.0, -25.0), (28.0, -34.0), (20.0, -35.0), (17.0, -29.0),
+      (12.0, -17.0), (13.0, -5.0), (9.0, 4.0), (-4.0, 5.0), (-13.0, 8.0), (-17.0, 14.0)],
+    // Australia
+    &[(114.0, -22.0), (122.0, -18.0), (130.0, -12.0), (137.0, -12.0), (142.0, -11.0), (146.0, -19.0), (153.0, -26.0), (151.0, -34.0),
+      (146.0, -39.0), (140.0, -38.0), (135.0, -35.0), (129.0, -32.0), (115.0, -34.0)],
+];
+
+/// Tooltips of the points of the map and the bars of the histogram, and zoom and pan of the map.
+const SCRIPT: &str = r#"
+const tooltip = document.getElementById("tooltip");
+document.querySelectorAll("[data-tip]").forEach(e => {
+  e.addEventListener("mousemove", ev => {
+    tooltip.textContent = e.dataset.tip;
+    tooltip.style.display = "block";
+    tooltip.style.left = (ev.pageX + 12) + "px";
+    tooltip.style.top = (ev.pageY + 12) + "px";
+  });
+  e.addEventListener("mouseleave", () => tooltip.style.display = "none");
+});
+const map = document.getElementById("map");
+// Copied, the base value of the view box follows its changes
+const full = { x: 0, y: 0, width: map.viewBox.baseVal.width, height: map.viewBox.baseVal.height };
+let view = { x: full.x, y: full.y, width: full.width, height: full.height };
+let drag = null;
+function show() {
+  map.setAttribute("viewBox", `${view.x} ${view.y} ${view.width} ${view.height}`);
+  // Keep the points the same size on screen
+  const scale = view.width / full.width;
+  map.querySelectorAll("circle").forEach(c => c.setAttribute("r", c.dataset.r * scale));
+}
+map.addEventListener("wheel", ev => {
+  ev.preventDefault();
+  const box = map.getBoundingClientRect();
+  const px = view.x + (ev.clientX - box.left) / box.width * view.width;
+  const py = view.y + (ev.clientY - box.top) / box.height * view.height;
+  // Not farther out than the whole map
+  const factor = Math.min(ev.deltaY > 0 ? 1.25 : 0.8, full.width / view.width);
+  view.width *= factor;
+  view.height *= factor;
+  view.x = px - (px - view.x) * factor;
+  view.y = py - (py - view.y) * factor;
+  show();
+}, { passive: false });
+map.addEventListener("mousedown", ev => drag = { x: ev.clientX, y: ev.clientY });
+window.addEventListener("mouseup", () => drag = null);
+window.addEventListener("mousemove", ev => {
+  if (!drag) return;
+  const box = map.getBoundingClientRect();
+  view.x -= (ev.clientX - drag.x) / box.width * view.width;
+  view.y -= (ev.clientY - drag.y) / box.height * view.height;
+  drag = { x: ev.clientX, y: ev.clientY };
+  show();
+});
+map.addEventListener("dblclick", () => {
+  view = { x: full.x, y: full.y, width: full.width, height: full.height };
+  show();
+});
+"#;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; }
+td { padding: 2px 12px 2px 0; }
+svg { display: block; margin: 1em 0; border: 1px solid #ccc; }
+#map { background: #eaf2f8; cursor: grab; }
+#map .land { fill: #ddd; stroke: #bbb; vector-effect: non-scaling-stroke; }
+#map circle { stroke: #333; stroke-width: 0.5; vector-effect: non-scaling-stroke; fill-opacity: 0.75; }
+#tooltip { position: absolute; display: none; background: #fff; border: 1px solid #888; padding: 4px 8px; font-size: 12px; white-space: pre; pointer-events: none; }
+.legend { font-size: 12px; color: #555; }
+"#;
+
+/// Return `s` with the characters special in HTML escaped.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Return the position of a longitude and latitude on the equirectangular map.
+fn project(longitude: f64, latitude: f64) -> (f64, f64) {
+    ((longitude + 180.0) / 360.0 * MAP_WIDTH, (90.0 - latitude) / 180.0 * MAP_HEIGHT)
+}
+
+/// Return the color of `distance`, from green for the closest validators to red for `max` and beyond.
+fn color(distance: u32, max: u32) -> String {
+    let ratio = if max == 0 { 0.0 } else { (distance as f64 / max as f64).min(1.0) };
+    format!("hsl({:.0}, 80%, 45%)", 120.0 * (1.0 - ratio))
+}
+
+/// Return the successfully measured TPUs, alternate addresses left out as in the aggregates.
+fn measured(report: &DistanceReport) -> Vec<(&TpuReport, u32)> {
+    report.tpus.iter()
+        .filter(|t| t.error.is_none() && !t.alternate)
+        .filter_map(|t| Some((t, t.distance_us?)))
+        .collect()
+}
+
+/// Return the distance of the 95th percentile, the top of the color scale and of the histogram, so that a few
+/// far validators don't squeeze all the others into one color or bin.
+fn scale_max(measured: &[(&TpuReport, u32)]) -> u32 {
+    let mut distances: Vec<u32> = measured.iter().map(|(_, d)| *d).collect();
+    distances.sort_unstable();
+    distances.get(distances.len() * 95 / 100).or(distances.last()).copied().unwrap_or(0).max(1)
+}
+
+fn tip(tpu: &TpuReport, distance: u32) -> String {
+    let mut tip = format!("{}\n{}\n{} µs, {} SOL", tpu.pubkeys.join("\n"), tpu.address, distance, tpu.stake / LAMPORTS_PER_SOL);
+    if let Some(geo) = &tpu.geo {
+        let place: Vec<&str> = [geo.city.as_deref(), geo.country.as_deref()].into_iter().flatten().collect();
+        if !place.is_empty() {
+            write!(tip, "\n{}", place.join(", ")).unwrap();
+        }
+        if let Some(name) = geo.as_name() {
+            write!(tip, "\n{}", name).unwrap();
+        }
+    }
+    escape(&tip)
+}
+
+fn map_svg(measured: &[(&TpuReport, u32)], max: u32) -> String {
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg id="map" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {MAP_WIDTH} {MAP_HEIGHT}" width="{MAP_WIDTH}" height="{MAP_HEIGHT}">"#).unwrap();
+    for outline in CONTINENTS {
+        let points: Vec<String> = outline.iter().map(|(lon, lat)| {
+            let (x, y) = project(*lon, *lat);
+            format!("{:.1},{:.1}", x, y)
+        }).collect();
+        writeln!(svg, r#"<polygon class="land" points="{}"/>"#, points.join(" ")).unwrap();
+    }
+    let max_stake = measured.iter().map(|(t, _)| t.stake).max().unwrap_or(0).max(1) as f64;
+    // The most staked validators are drawn first, so that the smaller ones stay visible above them
+    let mut located: Vec<_> = measured.iter()
+        .filter_map(|(tpu, distance)| {
+            let geo = tpu.geo.as_ref()?;
+            Some((*tpu, *distance, geo.longitude?, geo.latitude?))
+        })
+        .collect();
+    located.sort_by_key(|(tpu, ..)| std::cmp::Reverse(tpu.stake));
+    for (tpu, distance, longitude, latitude) in located {
+        let (x, y) = project(longitude, latitude);
+        // The area is proportional to the stake
+        let radius = MIN_RADIUS + (tpu.stake as f64 / max_stake).sqrt() * (MAX_RADIUS - MIN_RADIUS);
+        writeln!(svg, r#"<circle cx="{x:.1}" cy="{y:.1}" r="{radius:.1}" data-r="{radius:.1}" fill="{}" data-tip="{}"/>"#,
+                 color(distance, max), tip(tpu, distance)).unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn histogram_svg(measured: &[(&TpuReport, u32)], max: u32) -> String {
+    let (plot_width, plot_height) = (HISTOGRAM_WIDTH - MARGIN_LEFT - MARGIN, HISTOGRAM_HEIGHT - MARGIN_BOTTOM - MARGIN);
+    let bin_width = max.div_ceil(HISTOGRAM_BINS).max(1);
+    // The last bin holds the validators beyond the scale
+    let mut bins = vec![(0u64, 0u64); HISTOGRAM_BINS as usize];
+    for (tpu, distance) in measured {
+        let bin = &mut bins[((distance / bin_width) as usize).min(HISTOGRAM_BINS as usize - 1)];
+        bin.0 += 1;
+        bin.1 += tpu.stake;
+    }
+    let highest = bins.iter().map(|(count, _)| *count).max().unwrap_or(0).max(1);
+    let bar_width = plot_width / HISTOGRAM_BINS as f64;
+
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{HISTOGRAM_WIDTH}" height="{HISTOGRAM_HEIGHT}" font-size="12">"#).unwrap();
+    for (i, (count, stake)) in bins.iter().enumerate() {
+        let height = *count as f64 / highest as f64 * plot_height;
+        let (low, high) = (i as u32 * bin_width, (i as u32 + 1) * bin_width);
+        let range = match i + 1 == bins.len() {
+            true => format!("{} µs and more", low),
+            false => format!("{} to {} µs", low, high),
+        };
+        writeln!(svg, r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" data-tip="{}: {} TPUs, {} SOL"/>"#,
+                 MARGIN_LEFT + i as f64 * bar_width + 1.0, MARGIN + plot_height - height, bar_width - 2.0, height,
+                 color(low + bin_width / 2, max), range, count, stake / LAMPORTS_PER_SOL).unwrap();
+    }
+    for i in (0..=HISTOGRAM_BINS).step_by(5) {
+        writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+                 MARGIN_LEFT + i as f64 * bar_width, MARGIN + plot_height + 16.0, i * bin_width).unwrap();
+    }
+    writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{highest}</text>"#, MARGIN_LEFT - 6.0, MARGIN + 10.0).unwrap();
+    writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="end">0</text>"#, MARGIN_LEFT - 6.0, MARGIN + plot_height).unwrap();
+    writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">Distance (µs)</text>"#, MARGIN_LEFT + plot_width / 2.0, HISTOGRAM_HEIGHT - 6.0).unwrap();
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn distance_row(html: &mut String, label: &str, distance: &Distance) {
+    write!(html, "<tr><td>{}</td><td>{} µs", label, distance.distance_us).unwrap();
+    if let Some(stddev) = distance.stddev_us {
+        write!(html, " ± {} µs", stddev).unwrap();
+    }
+    writeln!(html, "</td></tr>").unwrap();
+}
+
+/// Return the report as a standalone HTML page. Validators without a position, when no City database located
+/// them, are left out of the map but counted in the histogram and the aggregates.
+pub fn to_html(report: &DistanceReport) -> String {
+    let measured = measured(report);
+    let max = scale_max(&measured);
+    let unlocated = measured.iter().filter(|(t, _)| t.geo.as_ref().and_then(|g| g.latitude).is_none()).count();
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Solana distance {}</title>", escape(&report.timestamp)).unwrap();
+    writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE).unwrap();
+    writeln!(html, "<h1>Solana distance</h1>\n<p>Measured at {}</p>", escape(&report.timestamp)).unwrap();
+    if report.cancelled {
+        writeln!(html, "<p><strong>Measure cancelled, results are partial</strong></p>").unwrap();
+    }
+
+    writeln!(html, "<table>").unwrap();
+    if let Some(stake_weighted) = &report.stake_weighted {
+        distance_row(&mut html, "Stake-weighted distance", stake_weighted);
+    }
+    if let Some(simple) = &report.simple {
+        distance_row(&mut html, "Simple distance", simple);
+    }
+    writeln!(html, "<tr><td>Connection successful</td><td>{} of {} TPUs</td></tr>", report.connection_successful, report.tpus.iter().filter(|t| !t.alternate).count()).unwrap();
+    writeln!(html, "<tr><td>Measured stake</td><td>{} SOL of {} SOL</td></tr>", report.measured_stake / LAMPORTS_PER_SOL, report.total_stake / LAMPORTS_PER_SOL).unwrap();
+    if let Some(rtt) = &report.rtt {
+        writeln!(html, "<tr><td>Average RTT per TPU</td><td>{}</td></tr>", escape(&rtt.to_string())).unwrap();
+    }
+    for error in &report.errors {
+        writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", escape(&error.error), error.count).unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    writeln!(html, "<h2>Validators</h2>").unwrap();
+    html.push_str(&map_svg(&measured, max));
+    write!(html, r#"<p class="legend">Colored from green, closest, to red, {} µs and farther; sized by stake. Scroll to zoom, drag to pan, double-click to reset."#, max).unwrap();
+    if unlocated > 0 {
+        write!(html, " {} measured TPUs without a known position are not shown.", unlocated).unwrap();
+    }
+    writeln!(html, "</p>").unwrap();
+
+    writeln!(html, "<h2>Distances</h2>").unwrap();
+    html.push_str(&histogram_svg(&measured, max));
+    writeln!(html, r#"<p class="legend">Number of TPUs by distance, the last bar including all the farther ones.</p>"#).unwrap();
+
+    writeln!(html, "<div id=\"tooltip\"></div>\n<script>{}</script>\n</body>\n</html>", SCRIPT).unwrap();
+    html
+}
@@ -0,0 +1,135 @@
+//! Routing context of the farthest TPUs: their BGP prefix and the AS paths seen towards them by a looking glass,
+//! RIPEstat by default.
+
+use crate::output::TpuReport;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Looking glass queried by default, `{ip}` being replaced by the address of the TPU
+pub const RIPESTAT_LOOKING_GLASS: &str = "https://stat.ripe.net/data/looking-glass/data.json?resource={ip}";
+/// Number of distinct AS paths reported per TPU, the most frequent ones
+const MAX_PATHS: usize = 3;
+const LOOKING_GLASS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Response of the RIPEstat looking-glass API, with the fields used.
+#[derive(Deserialize)]
+struct LookingGlass {
+    data: LookingGlassData,
+}
+
+#[derive(Deserialize)]
+struct LookingGlassData {
+    #[serde(default)]
+    rrcs: Vec<Collector>,
+}
+
+#[derive(Deserialize)]
+struct Collector {
+    #[serde(default)]
+    peers: Vec<Peer>,
+}
+
+#[derive(Deserialize)]
+struct Peer {
+    prefix: String,
+    as_path: String,
+}
+
+/// BGP context of a TPU.
+#[derive(Serialize)]
+pub struct RouteContext {
+    pub address: SocketAddr,
+    pub pubkeys: Vec<String>,
+    pub distance_us: u32,
+    /// Most specific prefix announced for the address
+    pub prefix: Option<String>,
+    pub origin_asn: Option<String>,
+    /// Most frequent AS paths seen by the looking glass peers, with the number of peers seeing them
+    pub as_paths: Vec<AsPath>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AsPath {
+    pub path: String,
+    pub peers: u64,
+}
+
+/// Return the prefix and the most frequent AS paths of `ip` seen by the looking glass at `url_template`.
+async fn query(client: &reqwest::Client, url_template: &str, ip: &str) -> Result<(Option<String>, Vec<AsPath>), String> {
+    let url = url_template.replace("{ip}", ip);
+    let response = client.get(&url).send().await.and_then(|r| r.error_for_status()).map_err(|e| e.to_string())?;
+    let body = response.bytes().await.map_err(|e| e.to_string())?;
+    let lg: LookingGlass = serde_json::from_slice(&body).map_err(|e| format!("Invalid response: {}", e))?;
+    let mut prefixes: HashMap<String, u64> = HashMap::new();
+    let mut paths: HashMap<String, u64> = HashMap::new();
+    for peer in lg.data.rrcs.into_iter().flat_map(|c| c.peers) {
+        *prefixes.entry(peer.prefix).or_default() += 1;
+        *paths.entry(peer.as_path).or_default() += 1;
+    }
+    // Peers may still see a covering prefix, keep the most specific one
+    let prefix = prefixes.into_keys().max_by_key(|p| p.rsplit_once('/').and_then(|(_, len)| len.parse::<u8>().ok()));
+    let mut paths: Vec<AsPath> = paths.into_iter().map(|(path, peers)| AsPath { path, peers }).collect();
+    paths.sort_by(|a, b| b.peers.cmp(&a.peers).then_with(|| a.path.cmp(&b.path)));
+    paths.truncate(MAX_PATHS);
+    Ok((prefix, paths))
+}
+
+/// Query the looking glass at `url_template` for the `worst` farthest successfully measured TPUs, farthest first.
+/// A failed query is reported in the `error` of its TPU.
+pub async fn worst_routes(url_template: &str, tpus: &[TpuReport], worst: usize) -> Vec<RouteContext> {
+    let mut measured: Vec<&TpuReport> = tpus.iter().filter(|t| t.error.is_none() && t.distance_us.is_some()).collect();
+    measured.sort_by_key(|t| std::cmp::Reverse(t.distance_us));
+    measured.truncate(worst);
+
+    let client = reqwest::Client::builder().timeout(LOOKING_GLASS_TIMEOUT).build().expect("Cannot build HTTP client");
+    let mut queries = JoinSet::new();
+    for (i, tpu) in measured.iter().enumerate() {
+        let (client, url_template, ip) = (client.clone(), url_template.to_string(), tpu.address.ip().to_string());
+        queries.spawn(async move { (i, query(&client, &url_template, &ip).await) });
+    }
+    let mut results = HashMap::new();
+    while let Some(Ok((i, result))) = queries.join_next().await {
+        results.insert(i, result);
+    }
+
+    measured.into_iter().enumerate().map(|(i, tpu)| {
+        let (prefix, as_paths, error) = match results.remove(&i) {
+            Some(Ok((prefix, as_paths))) => (prefix, as_paths, None),
+            Some(Err(e)) => (None, Vec::new(), Some(e)),
+            None => (None, Vec::new(), Some("Query aborted".to_string())),
+        };
+        RouteContext {
+            address: tpu.address,
+            pubkeys: tpu.pubkeys.clone(),
+            distance_us: tpu.distance_us.unwrap_or_default(),
+            origin_asn: as_paths.first().and_then(|p| p.path.split_whitespace().last()).map(|asn| format!("AS{}", asn)),
+            prefix,
+            as_paths,
+            error,
+        }
+    }).collect()
+}
+
+pub(crate) fn print_text(routes: &[RouteContext]) {
+    println!("Routing context of the farthest TPUs:");
+    for r in routes {
+        print!("  {} {:?} {} µs", r.address, r.pubkeys, r.distance_us);
+        if let Some(prefix) = &r.prefix {
+            print!(", prefix {}", prefix);
+        }
+        if let Some(origin) = &r.origin_asn {
+            print!(" from {}", origin);
+        }
+        match &r.error {
+            Some(error) => println!(", looking glass failed: {}", error),
+            None => println!(),
+        }
+        for path in &r.as_paths {
+            println!("    {} ({} peers)", path.path, path.peers);
+        }
+    }
+}
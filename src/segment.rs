@@ -0,0 +1,34 @@
+//! Segments of the network the validators are reached through, e.g. the Doublezero device or city each validator
+//! is connected to, read from a file of lines `pubkey segment`.
+
+use crate::output::TpuReport;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Label of the TPUs whose pubkeys are in no segment
+pub const NO_SEGMENT: &str = "Other";
+
+/// Segment of validator identities.
+pub struct SegmentMap {
+    segments: HashMap<String, String>,
+}
+
+impl SegmentMap {
+    pub fn load(path: &Path) -> io::Result<SegmentMap> {
+        let mut segments = HashMap::new();
+        for line in fs::read_to_string(path)?.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let Some((pubkey, segment)) = line.split_once(char::is_whitespace) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid segment line: {}", line)));
+            };
+            segments.insert(pubkey.to_string(), segment.trim().to_string());
+        }
+        Ok(SegmentMap { segments })
+    }
+
+    /// Return the segment of the first pubkey of `tpu` which has one.
+    pub fn segment(&self, tpu: &TpuReport) -> String {
+        tpu.pubkeys.iter().find_map(|pk| self.segments.get(pk)).cloned().unwrap_or_else(|| NO_SEGMENT.to_string())
+    }
+}
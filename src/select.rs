@@ -0,0 +1,85 @@
+//! Selection of the validators by patterns over the attributes of their contact info, e.g. `version=2.2.*` or
+//! `pubkey=Cert*`, for ad-hoc cohorts without a list of pubkeys.
+
+use crate::output::client_from_version;
+use std::str::FromStr;
+
+/// Attribute of a node matched by a [`Selector`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectField {
+    /// Identity pubkey
+    Pubkey,
+    /// Version advertised in gossip
+    Version,
+    /// Client implementation, e.g. Firedancer, told by the version
+    Client,
+}
+
+/// Pattern `field=glob` over an attribute of the nodes, `*` matching any characters and `?` any single one, e.g.
+/// `version=2.2.*`. Matching is case-sensitive.
+#[derive(Clone, Debug)]
+pub struct Selector {
+    field: SelectField,
+    pattern: Vec<char>,
+}
+
+impl Selector {
+    /// Return true if the node of identity `pubkey` advertising `version` matches. Nodes without version only match
+    /// version patterns of `*`.
+    pub fn matches(&self, pubkey: &str, version: Option<&str>) -> bool {
+        let value = match self.field {
+            SelectField::Pubkey => pubkey,
+            SelectField::Version => version.unwrap_or_default(),
+            SelectField::Client => client_from_version(version),
+        };
+        glob_match(&self.pattern, &value.chars().collect::<Vec<char>>())
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((field, pattern)) = s.split_once('=') else { return Err(format!("Selector {} is not of the form 'field=pattern'", s)) };
+        let field = match field.trim() {
+            "pubkey" => SelectField::Pubkey,
+            "version" => SelectField::Version,
+            "client" => SelectField::Client,
+            other => return Err(format!("Unknown field {} in selector {}, expected pubkey, version or client", other, s)),
+        };
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(format!("Empty pattern in selector {}", s));
+        }
+        Ok(Selector { field, pattern: pattern.chars().collect() })
+    }
+}
+
+/// Return true if `value` matches the glob `pattern`, backtracking to the last `*` on a mismatch.
+fn glob_match(pattern: &[char], value: &[char]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    // Position of the last star in the pattern, and of the value it was tried at
+    let mut star = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                // Let the star match one more character
+                Some((star_p, star_v)) => {
+                    p = star_p + 1;
+                    v = star_v + 1;
+                    star = Some((star_p, star_v + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
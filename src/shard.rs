@@ -0,0 +1,274 @@
+//! Sharding of a measure across several probes, each measuring a deterministic slice of the targets, and merge
+//! of the reports of all the shards.
+//!
+//! Validators are assigned to shards by a hash of their identity, or of the destination as given, so that
+//! probes with the same options measure disjoint slices covering all the targets. Identities sharing a TPU
+//! may fall in different shards, the TPU then being measured by each of them and merged back by address.
+
+use crate::output::{print_distance, Distance, ErrorReport, LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Slice `index` of `count` of the targets, given as `index/count` from `1/count` to `count/count`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Return true if the target identified by `key`, a pubkey or a destination, is in this shard.
+    pub fn contains(&self, key: &str) -> bool {
+        let hash = Sha256::digest(key.as_bytes());
+        let value = u64::from_le_bytes(hash[..8].try_into().expect("Hash is 32 bytes"));
+        value % self.count as u64 == (self.index - 1) as u64
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((index, count)) = s.split_once('/') else { return Err(format!("Shard {} is not of the form 'index/count'", s)) };
+        let index = index.trim().parse::<u32>().map_err(|_| format!("Invalid shard index in {}", s))?;
+        let count = count.trim().parse::<u32>().map_err(|_| format!("Invalid shard count in {}", s))?;
+        if index == 0 || index > count {
+            return Err(format!("Shard index of {} must be from 1 to {}", s, count));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl Serialize for Shard {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Shard {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The fields of a saved [`DistanceReport`](crate::output::DistanceReport) used by the merge, the TPUs being kept
+/// as they are.
+#[derive(Deserialize)]
+struct ShardReport {
+    #[serde(default)]
+    schema_version: u32,
+    shard: Option<Shard>,
+    attempts: usize,
+    tpus: Vec<Value>,
+    total_stake: u64,
+    errors: Vec<SavedError>,
+}
+
+#[derive(Deserialize)]
+struct SavedError {
+    error: String,
+    count: u64,
+    stake: Option<u64>,
+}
+
+fn invalid_data(error: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn load(path: &Path) -> io::Result<ShardReport> {
+    let report: ShardReport = serde_json::from_slice(&fs::read(path)?).map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+    if report.schema_version > SCHEMA_VERSION {
+        return Err(invalid_data(format!("{} has schema version {}, this version reads up to {}", path.display(), report.schema_version, SCHEMA_VERSION)));
+    }
+    Ok(report)
+}
+
+/// Report of all the shards of a measure, with the fields of a [`DistanceReport`](crate::output::DistanceReport)
+/// which can be recomputed from the TPUs, so that it can be read like a report, e.g. by `snapshot diff`.
+#[derive(Serialize)]
+pub struct MergedReport {
+    pub schema_version: u32,
+    /// Number of merged shards
+    pub shards: u32,
+    pub attempts: usize,
+    pub tpus: Vec<Value>,
+    pub simple: Option<Distance>,
+    pub stake_weighted: Option<Distance>,
+    pub measured_stake: u64,
+    pub total_stake: u64,
+    pub connection_successful: u64,
+    pub errors: Vec<ErrorReport>,
+}
+
+impl MergedReport {
+    /// Merge the reports at `paths`, which must be all the shards of a measure, each once.
+    pub fn merge(paths: &[PathBuf]) -> io::Result<MergedReport> {
+        let mut shards = BTreeMap::new();
+        for path in paths {
+            let report = load(path)?;
+            let Some(shard) = report.shard else { return Err(invalid_data(format!("{} is not the report of a shard", path.display()))) };
+            if shards.insert(shard.index, (shard, report)).is_some() {
+                return Err(invalid_data(format!("Shard {} is given twice", shard)));
+            }
+        }
+        let count = shards.values().next().map(|(shard, _)| shard.count).ok_or_else(|| invalid_data("No report to merge".to_string()))?;
+        if let Some((shard, _)) = shards.values().find(|(shard, _)| shard.count != count) {
+            return Err(invalid_data(format!("Shard {} is not one of {} shards", shard, count)));
+        }
+        if let Some(missing) = (1..=count).find(|i| !shards.contains_key(i)) {
+            return Err(invalid_data(format!("Shard {}/{} is missing", missing, count)));
+        }
+
+        let mut merged = MergedReport {
+            schema_version: SCHEMA_VERSION,
+            shards: count,
+            attempts: 0,
+            tpus: Vec::new(),
+            simple: None,
+            stake_weighted: None,
+            measured_stake: 0,
+            total_stake: 0,
+            connection_successful: 0,
+            errors: Vec::new(),
+        };
+        // TPUs by address and alternate flag, the identities of a TPU split across shards being put back together
+        let mut tpus: BTreeMap<(String, bool), Value> = BTreeMap::new();
+        let mut errors: BTreeMap<String, ErrorReport> = BTreeMap::new();
+        for (_, report) in shards.into_values() {
+            merged.attempts = merged.attempts.max(report.attempts);
+            merged.total_stake += report.total_stake;
+            for tpu in report.tpus {
+                let key = (tpu["address"].as_str().unwrap_or_default().to_string(), tpu["alternate"].as_bool().unwrap_or(false));
+                match tpus.get_mut(&key) {
+                    Some(existing) => {
+                        let stake = existing["stake"].as_u64().unwrap_or(0) + tpu["stake"].as_u64().unwrap_or(0);
+                        existing["stake"] = stake.into();
+                        if let (Some(pubkeys), Some(more)) = (existing["pubkeys"].as_array_mut(), tpu["pubkeys"].as_array()) {
+                            pubkeys.extend(more.iter().cloned());
+                        }
+                    }
+                    None => {
+                        tpus.insert(key, tpu);
+                    }
+                }
+            }
+            for error in report.errors {
+                let merged_error = errors.entry(error.error.clone()).or_insert(ErrorReport { error: error.error, count: 0, stake: error.stake.map(|_| 0) });
+                merged_error.count += error.count;
+                merged_error.stake = merged_error.stake.zip(error.stake).map(|(a, b)| a + b);
+            }
+        }
+        merged.errors = errors.into_values().collect();
+
+        let (mut lat_sum, mut lat_cnt, mut var_sum, mut lat_sum_w, mut lat_stk, mut var_sum_w) = (0u64, 0u64, 0u128, 0u128, 0u64, 0u128);
+        for tpu in tpus.values().filter(|t| t["error"].is_null() && !t["alternate"].as_bool().unwrap_or(false)) {
+            let Some(distance) = tpu["distance_us"].as_u64() else { continue };
+            let stake = tpu["stake"].as_u64().unwrap_or(0);
+            let variance = tpu["stddev_us"].as_u64().map(|s| s as u128 * s as u128).unwrap_or(0);
+            lat_sum += distance;
+            lat_cnt += 1;
+            var_sum += variance;
+            lat_sum_w += distance as u128 * stake as u128;
+            lat_stk += stake;
+            var_sum_w += variance * stake as u128;
+        }
+        merged.tpus = tpus.into_values().collect();
+        merged.connection_successful = lat_cnt;
+        merged.measured_stake = lat_stk;
+        if lat_cnt > 0 {
+            let with_stddev = merged.attempts > 1;
+            merged.simple = Some(Distance {
+                distance_us: lat_sum / lat_cnt,
+                stddev_us: with_stddev.then(|| (var_sum / lat_cnt as u128).isqrt() as u64),
+            });
+            merged.stake_weighted = (merged.total_stake > 0 && lat_stk > 0).then(|| Distance {
+                distance_us: (lat_sum_w / lat_stk as u128) as u64,
+                stddev_us: with_stddev.then(|| (var_sum_w / lat_stk as u128).isqrt() as u64),
+            });
+        }
+        Ok(merged)
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize report"));
+    }
+
+    pub fn print_text(&self) {
+        println!("Merged shards: {}", self.shards);
+        if let Some(simple) = &self.simple {
+            print_distance("Simple distance", simple);
+        }
+        if let Some(stake_weighted) = &self.stake_weighted {
+            print_distance("Stake-weighted distance", stake_weighted);
+            println!("Total stake: {} SOL", self.measured_stake / LAMPORTS_PER_SOL);
+        }
+        println!("Connection successful: {}", self.connection_successful);
+        for error in &self.errors {
+            match error.stake {
+                Some(stk) => println!("{}: {} ({:.2}% of total stake)", error.error, error.count, 100.0 * stk as f64 / (self.total_stake as f64)),
+                None => println!("{}: {}", error.error, error.count),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_pubkey::Pubkey;
+    use std::collections::BTreeSet;
+
+    fn keys() -> Vec<String> {
+        (0..1000u32).map(|i| Pubkey::new_from_array(Sha256::digest(i.to_le_bytes()).into()).to_string()).chain(["entrypoint.example.com:8001".to_string()]).collect()
+    }
+
+    /// Return the keys in each of `count` shards.
+    fn split(keys: &[String], count: u32) -> Vec<BTreeSet<&String>> {
+        (1..=count).map(|index| keys.iter().filter(|key| Shard { index, count }.contains(key)).collect()).collect()
+    }
+
+    #[test]
+    fn shards_partition_the_targets() {
+        let keys = keys();
+        for count in [1, 2, 3, 7, 16] {
+            let shards = split(&keys, count);
+            let union: BTreeSet<&String> = shards.iter().flatten().copied().collect();
+            assert_eq!(union, keys.iter().collect(), "{} shards don't cover the targets", count);
+            assert_eq!(shards.iter().map(BTreeSet::len).sum::<usize>(), keys.len(), "{} shards overlap", count);
+            assert!(shards.iter().all(|shard| !shard.is_empty()), "{} shards include an empty one", count);
+        }
+    }
+
+    #[test]
+    fn sharding_is_deterministic() {
+        let keys = keys();
+        assert_eq!(split(&keys, 5), split(&keys, 5));
+        // Probes running other builds must agree on the slices
+        let key = "puffinQSvKFriPbyE5atyx1ptfnyytovbzxybr1jsyy";
+        let index = (1..=4).find(|&index| Shard { index, count: 4 }.contains(key)).unwrap();
+        assert_eq!(index, 4);
+    }
+
+    #[test]
+    fn parses_shards() {
+        assert_eq!("2/3".parse::<Shard>(), Ok(Shard { index: 2, count: 3 }));
+        assert_eq!(" 1 / 1 ".parse::<Shard>(), Ok(Shard { index: 1, count: 1 }));
+        for shard in ["0/3", "4/3", "3", "a/3", "1/b", "1/0"] {
+            assert!(shard.parse::<Shard>().is_err(), "{} parsed", shard);
+        }
+        assert_eq!(Shard { index: 2, count: 3 }.to_string(), "2/3");
+    }
+}
@@ -0,0 +1,126 @@
+//! Topology diff between two snapshots of the cluster, i.e. reports saved with `--output json`.
+
+use crate::output::{LAMPORTS_PER_SOL, SCHEMA_VERSION};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// The fields of a saved [`DistanceReport`](crate::output::DistanceReport) describing the topology, in any
+/// version of the schema up to the current one. Fields added since the first version default.
+#[derive(Deserialize)]
+struct Snapshot {
+    #[serde(default)]
+    schema_version: u32,
+    tpus: Vec<SnapshotTpu>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotTpu {
+    address: SocketAddr,
+    pubkeys: Vec<String>,
+    stake: u64,
+    #[serde(default)]
+    alternate: bool,
+}
+
+/// TPU address and stake of a validator identity. Identities sharing a TPU are given its whole stake.
+#[derive(Clone, Copy, Serialize)]
+pub struct Node {
+    pub address: SocketAddr,
+    pub stake: u64,
+}
+
+#[derive(Serialize)]
+pub struct NodeChange {
+    pub pubkey: String,
+    pub old: Node,
+    pub new: Node,
+}
+
+#[derive(Serialize)]
+pub struct NodePresence {
+    pub pubkey: String,
+    #[serde(flatten)]
+    pub node: Node,
+}
+
+/// Changes of the measured validators between two snapshots. Stakes are in lamports.
+#[derive(Serialize)]
+pub struct TopologyDiff {
+    pub schema_version: u32,
+    pub appeared: Vec<NodePresence>,
+    pub disappeared: Vec<NodePresence>,
+    pub address_changed: Vec<NodeChange>,
+    /// Validators whose stake changed by more than the threshold, relative to the old stake
+    pub stake_changed: Vec<NodeChange>,
+}
+
+/// Read the snapshot at `path`, return the nodes by identity, leaving out alternate addresses.
+fn load(path: &Path) -> io::Result<BTreeMap<String, Node>> {
+    let snapshot: Snapshot = serde_json::from_slice(&fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if snapshot.schema_version > SCHEMA_VERSION {
+        let error = format!("Snapshot of schema version {}, this version reads up to {}", snapshot.schema_version, SCHEMA_VERSION);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+    }
+    let mut nodes = BTreeMap::new();
+    for tpu in snapshot.tpus.into_iter().filter(|t| !t.alternate) {
+        for pubkey in tpu.pubkeys {
+            nodes.insert(pubkey, Node { address: tpu.address, stake: tpu.stake });
+        }
+    }
+    Ok(nodes)
+}
+
+impl TopologyDiff {
+    /// Compare the snapshots at `old` and `new`, reporting stake changes above `stake_change_pct` %.
+    pub fn compare(old: &Path, new: &Path, stake_change_pct: f64) -> io::Result<TopologyDiff> {
+        let old = load(old)?;
+        let new = load(new)?;
+        let mut diff = TopologyDiff { schema_version: SCHEMA_VERSION, appeared: Vec::new(), disappeared: Vec::new(), address_changed: Vec::new(), stake_changed: Vec::new() };
+        for (pubkey, &node) in &old {
+            match new.get(pubkey) {
+                None => diff.disappeared.push(NodePresence { pubkey: pubkey.clone(), node }),
+                Some(&new_node) => {
+                    let change = || NodeChange { pubkey: pubkey.clone(), old: node, new: new_node };
+                    if new_node.address != node.address {
+                        diff.address_changed.push(change());
+                    }
+                    if new_node.stake.abs_diff(node.stake) as f64 > node.stake as f64 * stake_change_pct / 100.0 {
+                        diff.stake_changed.push(change());
+                    }
+                }
+            }
+        }
+        for (pubkey, &node) in &new {
+            if !old.contains_key(pubkey) {
+                diff.appeared.push(NodePresence { pubkey: pubkey.clone(), node });
+            }
+        }
+        Ok(diff)
+    }
+
+    pub fn print_text(&self) {
+        for n in &self.appeared {
+            println!("Appeared: {} at {}, {} SOL", n.pubkey, n.node.address, n.node.stake / LAMPORTS_PER_SOL);
+        }
+        for n in &self.disappeared {
+            println!("Disappeared: {} at {}, {} SOL", n.pubkey, n.node.address, n.node.stake / LAMPORTS_PER_SOL);
+        }
+        for c in &self.address_changed {
+            println!("Address changed: {} {} -> {}", c.pubkey, c.old.address, c.new.address);
+        }
+        for c in &self.stake_changed {
+            let pct = if c.old.stake > 0 { format!("{:+.1}%", (c.new.stake as f64 / c.old.stake as f64 - 1.0) * 100.0) } else { "new".to_string() };
+            println!("Stake changed: {} {} SOL -> {} SOL ({})", c.pubkey, c.old.stake / LAMPORTS_PER_SOL, c.new.stake / LAMPORTS_PER_SOL, pct);
+        }
+        println!("{} appeared, {} disappeared, {} changed address, {} changed stake",
+                 self.appeared.len(), self.disappeared.len(), self.address_changed.len(), self.stake_changed.len());
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize diff"));
+    }
+}
@@ -0,0 +1,55 @@
+//! Defaults from the configuration of the Solana CLI, `~/.config/solana/cli/config.yml`, so that the RPC and
+//! identity match the existing tooling of validator operators.
+//!
+//! Only the top-level scalars of the file are read, which is all the Solana CLI writes besides address labels.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Settings of the Solana CLI used as defaults.
+#[derive(Debug, Default)]
+pub struct SolanaConfig {
+    pub json_rpc_url: Option<String>,
+    pub keypair_path: Option<PathBuf>,
+}
+
+/// Return the path of the configuration of the Solana CLI, in the home directory like the CLI does.
+pub fn default_path() -> Option<PathBuf> {
+    Some(PathBuf::from(env::var_os("HOME")?).join(".config/solana/cli/config.yml"))
+}
+
+/// Return a YAML scalar without its quotes.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && (value.starts_with('"') && value.ends_with('"') || value.starts_with('\'') && value.ends_with('\'')) {
+        value[1..value.len() - 1].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl SolanaConfig {
+    /// Read the configuration at `path`, empty settings being left unset.
+    pub fn load(path: &Path) -> io::Result<SolanaConfig> {
+        let mut config = SolanaConfig::default();
+        for line in fs::read_to_string(path)?.lines() {
+            // Nested values, e.g. address labels, are indented
+            if line.starts_with([' ', '\t', '#']) {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else { continue };
+            let value = unquote(value);
+            if value.is_empty() {
+                continue;
+            }
+            match key.trim() {
+                "json_rpc_url" => config.json_rpc_url = Some(value),
+                "keypair_path" => config.keypair_path = Some(PathBuf::from(value)),
+                _ => (),
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,230 @@
+//! History of the measures in a SQLite database, with `--store`, and trends of the distance over time.
+//!
+//! Each measure is a row of `runs`, with its aggregates, and has a row of `tpu_results` per TPU. Pubkeys
+//! sharing a TPU are stored comma-separated. Reading and writing the database requires the `sqlite` feature.
+
+use serde::Serialize;
+
+#[cfg(feature = "sqlite")]
+pub use db::Store;
+
+/// Length of the periods of a trend.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Period {
+    Hour,
+    Day,
+    Week,
+}
+
+/// Aggregates of the measures of a period. Distances are averaged over the runs, TPUs over their measures.
+#[derive(Serialize)]
+pub struct TrendPoint {
+    pub period: String,
+    pub runs: u64,
+    pub simple_distance_us: Option<u64>,
+    pub stake_weighted_distance_us: Option<u64>,
+    pub min_stake_weighted_distance_us: Option<u64>,
+    pub max_stake_weighted_distance_us: Option<u64>,
+    pub connection_successful: Option<u64>,
+}
+
+#[cfg(feature = "sqlite")]
+mod db {
+    use super::{Period, TrendPoint};
+    use crate::output::DistanceReport;
+    use rusqlite::{params, Connection};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        timestamp TEXT NOT NULL,
+        tool_version TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        interval_ms INTEGER NOT NULL,
+        simple_distance_us INTEGER,
+        simple_stddev_us INTEGER,
+        stake_weighted_distance_us INTEGER,
+        stake_weighted_stddev_us INTEGER,
+        measured_stake INTEGER NOT NULL,
+        total_stake INTEGER NOT NULL,
+        connection_successful INTEGER NOT NULL,
+        errors INTEGER NOT NULL,
+        hostname TEXT,
+        public_ip TEXT,
+        rpc_url TEXT,
+        epoch INTEGER,
+        slot INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS runs_timestamp ON runs (timestamp);
+    CREATE TABLE IF NOT EXISTS tpu_results (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        address TEXT NOT NULL,
+        pubkeys TEXT NOT NULL,
+        stake INTEGER NOT NULL,
+        client TEXT NOT NULL,
+        version TEXT,
+        distance_us INTEGER,
+        stddev_us INTEGER,
+        min_rtt_us INTEGER,
+        error TEXT,
+        alternate INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tpu_results_run ON tpu_results (run_id);
+    ";
+
+    /// Columns of `runs` added after its first version, added to older databases when opened
+    const ADDED_RUN_COLUMNS: [(&str, &str); 5] = [("hostname", "TEXT"), ("public_ip", "TEXT"), ("rpc_url", "TEXT"), ("epoch", "INTEGER"), ("slot", "INTEGER")];
+
+    impl Period {
+        /// `strftime` format of the start of the period of a timestamp
+        fn format(self) -> &'static str {
+            match self {
+                Period::Hour => "%Y-%m-%d %H:00",
+                Period::Day => "%Y-%m-%d",
+                Period::Week => "%Y-W%W",
+            }
+        }
+    }
+
+    /// A database of measures. The connection is locked for each operation, for the store to be shared by
+    /// concurrent measures.
+    pub struct Store {
+        connection: Mutex<Connection>,
+    }
+
+    impl Store {
+        /// Open the database at `path`, creating it and its schema if needed.
+        pub fn open(path: &Path) -> rusqlite::Result<Store> {
+            let connection = Connection::open(path)?;
+            connection.execute_batch(SCHEMA)?;
+            let columns = connection.prepare("SELECT name FROM pragma_table_info('runs')")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            for (column, kind) in ADDED_RUN_COLUMNS.iter().filter(|(column, _)| !columns.iter().any(|c| c == column)) {
+                connection.execute_batch(&format!("ALTER TABLE runs ADD COLUMN {} {};", column, kind))?;
+            }
+            Ok(Store { connection: Mutex::new(connection) })
+        }
+
+        /// Append the results of a measure, return the id of its run.
+        pub fn append(&self, report: &DistanceReport) -> rusqlite::Result<i64> {
+            let mut connection = self.connection.lock().unwrap();
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "INSERT INTO runs (timestamp, tool_version, attempts, interval_ms, simple_distance_us, simple_stddev_us,
+                                   stake_weighted_distance_us, stake_weighted_stddev_us, measured_stake, total_stake,
+                                   connection_successful, errors, hostname, public_ip, rpc_url, epoch, slot)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+                params![
+                    report.timestamp,
+                    report.metadata.tool_version,
+                    report.attempts as i64,
+                    report.interval_ms as i64,
+                    report.simple.as_ref().map(|d| d.distance_us as i64),
+                    report.simple.as_ref().and_then(|d| d.stddev_us).map(|s| s as i64),
+                    report.stake_weighted.as_ref().map(|d| d.distance_us as i64),
+                    report.stake_weighted.as_ref().and_then(|d| d.stddev_us).map(|s| s as i64),
+                    report.measured_stake as i64,
+                    report.total_stake as i64,
+                    report.connection_successful as i64,
+                    report.errors.iter().map(|e| e.count).sum::<u64>() as i64,
+                    report.metadata.hostname,
+                    report.metadata.public_ip.map(|ip| ip.to_string()),
+                    report.metadata.rpc_url,
+                    report.metadata.epoch.map(|e| e as i64),
+                    report.metadata.slot.map(|s| s as i64),
+                ],
+            )?;
+            let run_id = transaction.last_insert_rowid();
+            {
+                let mut insert = transaction.prepare(
+                    "INSERT INTO tpu_results (run_id, address, pubkeys, stake, client, version, distance_us, stddev_us,
+                                              min_rtt_us, error, alternate)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                )?;
+                for tpu in &report.tpus {
+                    insert.execute(params![
+                        run_id,
+                        tpu.address.to_string(),
+                        tpu.pubkeys.join(","),
+                        tpu.stake as i64,
+                        tpu.client,
+                        tpu.version,
+                        tpu.distance_us,
+                        tpu.stddev_us.map(|s| s as i64),
+                        tpu.min_rtt_us.map(|m| m as i64),
+                        tpu.error,
+                        tpu.alternate,
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(run_id)
+        }
+
+        /// Return the aggregates of the runs of the last `days` days, per `period`, oldest first. If `pubkey` is
+        /// set, the distances are those of its TPUs rather than the aggregates of the runs.
+        pub fn trend(&self, days: u32, period: Period, pubkey: Option<&str>) -> rusqlite::Result<Vec<TrendPoint>> {
+            let connection = self.connection.lock().unwrap();
+            let since = format!("-{} days", days);
+            let row = |row: &rusqlite::Row| -> rusqlite::Result<TrendPoint> {
+                let get = |i| row.get::<_, Option<f64>>(i).map(|v| v.map(|v| v.round() as u64));
+                Ok(TrendPoint {
+                    period: row.get(0)?,
+                    runs: row.get::<_, i64>(1)? as u64,
+                    simple_distance_us: get(2)?,
+                    stake_weighted_distance_us: get(3)?,
+                    min_stake_weighted_distance_us: get(4)?,
+                    max_stake_weighted_distance_us: get(5)?,
+                    connection_successful: get(6)?,
+                })
+            };
+            // Alternate addresses have their own distance, which isn't the one reported for the validator
+            let mut query = connection.prepare(match pubkey {
+                None => "SELECT strftime(?1, timestamp) AS period, COUNT(*), AVG(simple_distance_us), AVG(stake_weighted_distance_us),
+                                MIN(stake_weighted_distance_us), MAX(stake_weighted_distance_us), AVG(connection_successful)
+                         FROM runs WHERE julianday(timestamp) >= julianday('now', ?2)
+                         GROUP BY period ORDER BY period",
+                Some(_) => "SELECT period, COUNT(DISTINCT id), AVG(distance_us), AVG(distance_us), MIN(distance_us), MAX(distance_us), COUNT(distance_us)
+                            FROM (SELECT strftime(?1, r.timestamp) AS period, r.id, CASE WHEN t.error IS NULL THEN t.distance_us END AS distance_us
+                                  FROM runs r JOIN tpu_results t ON t.run_id = r.id
+                                  WHERE julianday(r.timestamp) >= julianday('now', ?2) AND NOT t.alternate
+                                    AND ',' || t.pubkeys || ',' LIKE '%,' || ?3 || ',%')
+                            GROUP BY period ORDER BY period",
+            })?;
+            let points = match pubkey {
+                None => query.query_map(params![period.format(), since], row)?,
+                Some(pubkey) => query.query_map(params![period.format(), since, pubkey], row)?,
+            };
+            points.collect()
+        }
+    }
+}
+
+pub fn print_text(points: &[TrendPoint], pubkey: Option<&str>) {
+    if points.is_empty() {
+        println!("No measure in this period");
+        return;
+    }
+    match pubkey {
+        Some(pubkey) => println!("Distance to {}:", pubkey),
+        None => println!("{:<16} {:>5} {:>12} {:>16} {:>12} {:>12} {:>12}", "Period", "Runs", "Simple (µs)", "Stake-weighted", "Min", "Max", "Connections"),
+    }
+    for p in points {
+        let field = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        match pubkey {
+            Some(_) => println!("{:<16} {:>5} runs: {} µs (min {} µs, max {} µs), {} successful",
+                                p.period, p.runs, field(p.simple_distance_us), field(p.min_stake_weighted_distance_us),
+                                field(p.max_stake_weighted_distance_us), field(p.connection_successful)),
+            None => println!("{:<16} {:>5} {:>12} {:>16} {:>12} {:>12} {:>12}", p.period, p.runs, field(p.simple_distance_us),
+                             field(p.stake_weighted_distance_us), field(p.min_stake_weighted_distance_us),
+                             field(p.max_stake_weighted_distance_us), field(p.connection_successful)),
+        }
+    }
+}
+
+pub fn print_json(points: &[TrendPoint]) {
+    println!("{}", serde_json::to_string_pretty(points).expect("Cannot serialize trend"));
+}
@@ -0,0 +1,165 @@
+//! Stress test of the QUIC ingress of a single TPU: a burst of concurrent connections, reporting how many the TPU
+//! accepts and keeps, and how long their handshakes take under load.
+//!
+//! It is meant for operators testing their own validator from a remote vantage point. The burst is bounded to
+//! [`MAX_CONNECTIONS`] connections, opened at most [`MAX_RATE`] per second, and all of them are closed at its end.
+
+use crate::cancel::Cancellation;
+use crate::output::{RttStats, SCHEMA_VERSION};
+use crate::quic::{new_quic_endpoint, socket_addr_to_quic_server_name, IdentityRotator};
+use crate::Error;
+use quinn::{Connection, VarInt};
+use serde::Serialize;
+use solana_keypair::Keypair;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{sleep_until, timeout, Instant};
+
+/// Largest burst of connections.
+pub const MAX_CONNECTIONS: usize = 1000;
+/// Highest rate of new connections, per second.
+pub const MAX_RATE: u32 = 500;
+
+/// Size and pace of a burst.
+pub struct StressOptions {
+    pub connections: usize,
+    /// New connections per second
+    pub rate: u32,
+    pub connection_timeout: Duration,
+    /// Identity of the connections, a new keypair if `None`
+    pub identity: Option<Keypair>,
+    /// Connect with a new identity for each connection, so that per-identity limits of the TPU don't apply
+    pub fresh_identities: bool,
+    pub bind: Option<SocketAddr>,
+    pub interface: Option<String>,
+}
+
+enum Outcome {
+    Accepted(u32, Connection),
+    Refused,
+    TimedOut,
+}
+
+/// Result of a burst. Durations are in µs.
+#[derive(Serialize)]
+pub struct StressReport {
+    pub schema_version: u32,
+    pub target: SocketAddr,
+    /// Connections started, fewer than requested if cancelled
+    pub attempted: usize,
+    /// Completed handshakes
+    pub accepted: usize,
+    /// Accepted connections closed by the TPU before the end of the burst, e.g. over its connection limits
+    pub closed: usize,
+    pub refused: usize,
+    pub timed_out: usize,
+    /// Share of the attempted connections accepted and still open at the end of the burst, in %
+    pub acceptance_pct: f64,
+    /// Distribution of the handshake durations of the accepted connections
+    pub handshake: Option<RttStats>,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+}
+
+impl StressReport {
+    pub fn print_text(&self) {
+        if self.cancelled {
+            println!("Stress test cancelled, results are partial");
+        }
+        println!("Stress test of {}: {} connections in {} ms", self.target, self.attempted, self.duration_ms);
+        println!("Accepted: {} ({:.1}% still open at the end of the burst)", self.accepted, self.acceptance_pct);
+        println!("Closed by the TPU: {}, refused: {}, timed out: {}", self.closed, self.refused, self.timed_out);
+        if let Some(handshake) = &self.handshake {
+            println!("Handshake duration: {}", handshake);
+        }
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string_pretty(self).expect("Cannot serialize stress report"));
+    }
+}
+
+/// Open `options.connections` connections to `target`, evenly spaced at `options.rate` per second, keep the
+/// accepted ones open until the last handshake completes, and close them all. Stop starting connections when
+/// `cancellation` is cancelled.
+pub async fn run(target: SocketAddr, options: &StressOptions, cancellation: &Cancellation) -> Result<StressReport, Error> {
+    if !(1..=MAX_CONNECTIONS).contains(&options.connections) {
+        return Err(Error::Stress(format!("the burst must have 1 to {} connections", MAX_CONNECTIONS)));
+    }
+    if !(1..=MAX_RATE).contains(&options.rate) {
+        return Err(Error::Stress(format!("the rate must be 1 to {} connections per second", MAX_RATE)));
+    }
+    let new_keypair;
+    let keypair = match &options.identity {
+        Some(identity) => identity,
+        None => {
+            new_keypair = Keypair::new();
+            &new_keypair
+        }
+    };
+    let (endpoint, _) = new_quic_endpoint(keypair, options.bind, options.interface.as_deref(), None, false).await.map_err(Error::Endpoint)?;
+    let rotator = options.fresh_identities.then(|| IdentityRotator::new(1, false));
+    let server_name = socket_addr_to_quic_server_name(target);
+    let spacing = Duration::from_secs(1) / options.rate;
+
+    let start = Instant::now();
+    let mut handshakes = JoinSet::new();
+    let mut cancelled = false;
+    for i in 0..options.connections {
+        tokio::select! {
+            _ = sleep_until(start + spacing * i as u32) => (),
+            _ = cancellation.cancelled() => {
+                cancelled = true;
+                break;
+            }
+        }
+        let connecting = match &rotator {
+            Some(rotator) => endpoint.connect_with(rotator.next(), target, &server_name),
+            None => endpoint.connect(target, &server_name),
+        };
+        let connecting = connecting.expect("Connection configuration error");
+        let connection_timeout = options.connection_timeout;
+        handshakes.spawn(async move {
+            let started = Instant::now();
+            match timeout(connection_timeout, connecting).await {
+                Ok(Ok(connection)) => Outcome::Accepted(started.elapsed().as_micros().try_into().expect("connect time overflow"), connection),
+                Ok(Err(_)) => Outcome::Refused,
+                Err(_) => Outcome::TimedOut,
+            }
+        });
+    }
+    let attempted = handshakes.len();
+
+    // Hold the accepted connections until the end of the burst, for the TPU to have them all at once
+    let (mut connections, mut samples, mut refused, mut timed_out) = (Vec::new(), Vec::new(), 0, 0);
+    while let Some(joined) = handshakes.join_next().await {
+        match joined {
+            Ok(Outcome::Accepted(connect_us, connection)) => {
+                samples.push(connect_us);
+                connections.push(connection);
+            }
+            Ok(Outcome::Refused) | Err(_) => refused += 1,
+            Ok(Outcome::TimedOut) => timed_out += 1,
+        }
+    }
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let closed = connections.iter().filter(|c| c.close_reason().is_some()).count();
+    for connection in &connections {
+        connection.close(VarInt::default(), &[]);
+    }
+    Ok(StressReport {
+        schema_version: SCHEMA_VERSION,
+        target,
+        attempted,
+        accepted: connections.len(),
+        closed,
+        refused,
+        timed_out,
+        acceptance_pct: if attempted > 0 { (connections.len() - closed) as f64 * 100.0 / attempted as f64 } else { 0.0 },
+        handshake: RttStats::from_samples(&samples),
+        duration_ms,
+        cancelled,
+    })
+}
@@ -0,0 +1,94 @@
+//! Resolved set of TPUs saved with `--save-targets`, and measured again with `--targets` without fetching the
+//! cluster, e.g. for the identical set of validators to be measured from several vantage points.
+
+use crate::discovery::{Targets, Tpu};
+use crate::output::SCHEMA_VERSION;
+use crate::{Errors, NodeError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// TPUs to measure, with the total stake of the cluster and the nodes left out when they were resolved. Stakes
+/// are in lamports.
+#[derive(Serialize, Deserialize)]
+struct TargetFile {
+    #[serde(default)]
+    schema_version: u32,
+    /// When the targets were resolved
+    timestamp: String,
+    total_stake: u64,
+    tpus: Vec<TargetTpu>,
+    #[serde(default)]
+    errors: Vec<TargetError>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TargetTpu {
+    address: SocketAddr,
+    pubkeys: Vec<String>,
+    stake: u64,
+    version: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    alternate: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    advertised: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+}
+
+/// Nodes left out for a reason, as in the error breakdown of the report.
+#[derive(Serialize, Deserialize)]
+struct TargetError {
+    error: String,
+    count: u64,
+    stake: u64,
+}
+
+fn invalid_data(error: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.into())
+}
+
+/// Write `targets` to `path`, sorted by address.
+pub(crate) fn save(path: &Path, targets: &Targets) -> io::Result<()> {
+    let mut tpus: Vec<TargetTpu> = targets.tpus.iter().map(|(address, tpu)| TargetTpu {
+        address: *address,
+        pubkeys: tpu.ids.clone(),
+        stake: tpu.stake,
+        version: tpu.version.clone(),
+        alternate: tpu.alternate,
+        advertised: tpu.advertised,
+        hostname: tpu.hostname.clone(),
+    }).collect();
+    tpus.sort_unstable_by_key(|t| t.address);
+    let file = TargetFile {
+        schema_version: SCHEMA_VERSION,
+        timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        total_stake: targets.total_stake,
+        tpus,
+        errors: targets.errors.0.iter().map(|(error, (count, stake))| TargetError { error: error.to_string(), count: *count, stake: *stake }).collect(),
+    };
+    fs::write(path, serde_json::to_vec_pretty(&file).expect("Cannot serialize targets"))
+}
+
+/// Read the targets saved at `path`. The leader schedule is not saved, and left to be fetched if requested.
+pub(crate) fn load(path: &Path) -> io::Result<Targets> {
+    let file: TargetFile = serde_json::from_slice(&fs::read(path)?).map_err(|e| invalid_data(e.to_string()))?;
+    if file.schema_version > SCHEMA_VERSION {
+        return Err(invalid_data(format!("Targets of schema version {}, this version reads up to {}", file.schema_version, SCHEMA_VERSION)));
+    }
+    let tpus = file.tpus.into_iter().map(|t| {
+        let tpu = Tpu { stake: t.stake, ids: t.pubkeys, version: t.version, alternate: t.alternate, advertised: t.advertised, hostname: t.hostname };
+        (t.address, tpu)
+    }).collect();
+    let mut errors = Errors(HashMap::new());
+    for saved in file.errors {
+        let error = NodeError::ALL.into_iter().find(|e| e.to_string() == saved.error)
+            .ok_or_else(|| invalid_data(format!("Unknown error {}", saved.error)))?;
+        errors.0.insert(error, (saved.count, saved.stake));
+    }
+    Ok(Targets { tpus, total_stake: file.total_stake, errors, leader_schedule: None })
+}
@@ -0,0 +1,72 @@
+//! Named target sets, stored as one validator pubkey or TPU ip:port per line under
+//! `$XDG_CONFIG_HOME/solana-distance/targets` (`~/.config/solana-distance/targets` by default).
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Return the configuration directory of the tool.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("solana-distance"))
+}
+
+/// Return the cache directory of the tool, `$XDG_CACHE_HOME/solana-distance` or `~/.cache/solana-distance`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("solana-distance"))
+}
+
+fn set_path(name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid target set name {}", name)));
+    }
+    let dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Cannot find configuration directory"))?;
+    Ok(dir.join("targets").join(name))
+}
+
+pub fn save(name: &str, targets: &[String]) -> io::Result<PathBuf> {
+    let path = set_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut content = targets.join("\n");
+    content.push('\n');
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+pub fn load(name: &str) -> io::Result<Vec<String>> {
+    let content = fs::read_to_string(set_path(name)?)?;
+    Ok(content.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect())
+}
+
+pub fn delete(name: &str) -> io::Result<()> {
+    fs::remove_file(set_path(name)?)
+}
+
+/// Return the names of the saved target sets, sorted.
+pub fn list() -> io::Result<Vec<String>> {
+    let Some(dir) = config_dir() else { return Ok(vec![]) };
+    let entries = match fs::read_dir(dir.join("targets")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
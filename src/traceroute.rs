@@ -0,0 +1,129 @@
+//! Traceroutes to the farthest or selected TPUs, run in parallel with the traceroute command, for the hops where
+//! latency is added on the path to high-stake validators.
+
+use crate::output::TpuReport;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
+use tokio::task::JoinSet;
+
+/// Command tracing the routes
+const TRACEROUTE: &str = "traceroute";
+/// Longest route traced, in hops
+const MAX_HOPS: u8 = 30;
+/// Time waited for the answer of each hop, in seconds
+const WAIT_SECS: u8 = 2;
+
+/// Probes of the traceroutes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TracerouteMethod {
+    /// UDP datagrams to high ports, unprivileged
+    #[default]
+    Udp,
+    /// ICMP echoes, often let through where UDP isn't, requiring root or CAP_NET_RAW
+    Icmp,
+}
+
+/// Router answering a probe of limited TTL, `None` if none answered in time.
+#[derive(Serialize)]
+pub struct Hop {
+    pub ttl: u8,
+    pub address: Option<IpAddr>,
+    pub rtt_us: Option<u64>,
+}
+
+/// Route to a TPU.
+#[derive(Serialize)]
+pub struct TraceReport {
+    pub address: SocketAddr,
+    pub pubkeys: Vec<String>,
+    pub distance_us: Option<u32>,
+    pub hops: Vec<Hop>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Return true if the traceroute command can be run.
+pub fn traceroute_available() -> bool {
+    Command::new(TRACEROUTE).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok()
+}
+
+/// Parse a hop line of traceroute with one probe per hop, e.g. ` 3  192.0.2.1  12.345 ms` or ` 4  *`.
+fn parse_hop(line: &str) -> Option<Hop> {
+    let mut fields = line.split_whitespace();
+    let ttl = fields.next()?.parse().ok()?;
+    let address = fields.next()?.parse().ok();
+    let rtt_us = address.and(fields.next()).and_then(|ms| ms.parse::<f64>().ok()).map(|ms| (ms * 1000.0) as u64);
+    Some(Hop { ttl, address, rtt_us })
+}
+
+/// Trace the route to `ip`, and return its hops.
+fn trace(ip: IpAddr, method: TracerouteMethod) -> Result<Vec<Hop>, String> {
+    let mut command = Command::new(TRACEROUTE);
+    command.args(["-n", "-q", "1", "-w", &WAIT_SECS.to_string(), "-m", &MAX_HOPS.to_string()]);
+    if method == TracerouteMethod::Icmp {
+        command.arg("-I");
+    }
+    let output = command.arg(ip.to_string()).stdin(Stdio::null()).output().map_err(|e| format!("Failed to run {}: {}", TRACEROUTE, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("traceroute failed").trim().to_string());
+    }
+    // The first line is the header
+    Ok(String::from_utf8_lossy(&output.stdout).lines().skip(1).filter_map(parse_hop).collect())
+}
+
+/// Trace the routes to the `worst` farthest successfully measured TPUs, farthest first, and to the TPUs whose
+/// address or identity is in `selected`, all at once. A failed traceroute is reported in the `error` of its TPU.
+pub async fn trace_routes(tpus: &[TpuReport], worst: usize, selected: &[String], method: TracerouteMethod) -> Vec<TraceReport> {
+    let mut traced: Vec<&TpuReport> = tpus.iter().filter(|t| t.error.is_none() && t.distance_us.is_some()).collect();
+    traced.sort_by_key(|t| std::cmp::Reverse(t.distance_us));
+    traced.truncate(worst);
+    for tpu in tpus {
+        let is_selected = selected.iter().any(|s| *s == tpu.address.to_string() || tpu.pubkeys.contains(s));
+        if is_selected && !traced.iter().any(|t| t.address == tpu.address) {
+            traced.push(tpu);
+        }
+    }
+
+    let mut traces = JoinSet::new();
+    for (i, tpu) in traced.iter().enumerate() {
+        let ip = tpu.address.ip();
+        traces.spawn_blocking(move || (i, trace(ip, method)));
+    }
+    let mut results = HashMap::new();
+    while let Some(Ok((i, result))) = traces.join_next().await {
+        results.insert(i, result);
+    }
+
+    traced.into_iter().enumerate().map(|(i, tpu)| {
+        let (hops, error) = match results.remove(&i) {
+            Some(Ok(hops)) => (hops, None),
+            Some(Err(e)) => (Vec::new(), Some(e)),
+            None => (Vec::new(), Some("Traceroute aborted".to_string())),
+        };
+        TraceReport { address: tpu.address, pubkeys: tpu.pubkeys.clone(), distance_us: tpu.distance_us, hops, error }
+    }).collect()
+}
+
+pub(crate) fn print_text(traces: &[TraceReport]) {
+    println!("Traceroutes:");
+    for t in traces {
+        print!("  {} {:?}", t.address, t.pubkeys);
+        if let Some(distance) = t.distance_us {
+            print!(" {} µs", distance);
+        }
+        match &t.error {
+            Some(error) => println!(", traceroute failed: {}", error),
+            None => println!(),
+        }
+        for hop in &t.hops {
+            match (hop.address, hop.rtt_us) {
+                (Some(address), Some(rtt)) => println!("    {:>2} {} {} µs", hop.ttl, address, rtt),
+                (Some(address), None) => println!("    {:>2} {}", hop.ttl, address),
+                (None, _) => println!("    {:>2} *", hop.ttl),
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Live dashboard of the watch mode in the terminal: the validators of the last round sorted by distance, the
+//! stake-weighted distance of the past rounds as a sparkline, and error counters.
+//!
+//! Drawing the dashboard requires the `tui` feature.
+
+use crate::output::{DistanceReport, LAMPORTS_PER_SOL};
+use crate::NodeError;
+use std::collections::{BTreeMap, VecDeque};
+
+#[cfg(feature = "tui")]
+pub use screen::Tui;
+
+/// Stand-in for the dashboard without the `tui` feature, which cannot be started.
+#[cfg(not(feature = "tui"))]
+pub enum Tui {}
+
+#[cfg(not(feature = "tui"))]
+impl Tui {
+    pub fn update(&self, _report: DistanceReport) {
+        match *self {}
+    }
+
+    pub fn fail(&self, _error: String) {
+        match *self {}
+    }
+}
+
+/// Number of past rounds kept for the sparkline, more than a terminal is wide.
+const HISTORY: usize = 512;
+
+/// State shown by the dashboard, updated at each round.
+pub struct Dashboard {
+    last: Option<DistanceReport>,
+    rounds: u64,
+    /// Stake-weighted distance of the past rounds, oldest first, 0 for rounds without any
+    history: VecDeque<u64>,
+    /// Nodes affected by each error, over all rounds
+    errors: BTreeMap<String, u64>,
+    /// Error of the last round, if it failed
+    failure: Option<String>,
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        // List all the errors from the start, so that the counters don't move around
+        let errors = NodeError::ALL.iter().map(|e| (e.to_string(), 0)).collect();
+        Dashboard { last: None, rounds: 0, history: VecDeque::new(), errors, failure: None }
+    }
+}
+
+impl Dashboard {
+    pub fn update(&mut self, report: DistanceReport) {
+        for error in &report.errors {
+            *self.errors.entry(error.error.clone()).or_default() += error.count;
+        }
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(report.stake_weighted.as_ref().map_or(0, |d| d.distance_us));
+        self.rounds += 1;
+        self.failure = None;
+        self.last = Some(report);
+    }
+
+    /// Record a round which failed as a whole, e.g. on an RPC error, keeping the results of the previous one.
+    pub fn fail(&mut self, error: String) {
+        self.failure = Some(error);
+    }
+
+    /// Return the summary line of the last round.
+    pub fn summary(&self) -> String {
+        let Some(report) = &self.last else { return "Measuring...".to_string() };
+        format!("{}  round {}  {}  stake {} / {} SOL", report.timestamp, self.rounds, report.round_summary(),
+                report.measured_stake / LAMPORTS_PER_SOL, report.total_stake / LAMPORTS_PER_SOL)
+    }
+
+    /// Return the rows of the validator table, sorted by distance, the TPUs without distance last.
+    pub fn rows(&self) -> Vec<[String; 5]> {
+        let Some(report) = &self.last else { return Vec::new() };
+        let mut tpus: Vec<_> = report.tpus.iter().collect();
+        tpus.sort_by_key(|t| (t.error.is_some() || t.distance_us.is_none(), t.distance_us, t.address));
+        tpus.into_iter().map(|t| {
+            let distance = match (t.distance_us, t.stddev_us) {
+                (Some(distance), Some(stddev)) => format!("{} ± {}", distance, stddev),
+                (Some(distance), None) => distance.to_string(),
+                (None, _) => "-".to_string(),
+            };
+            let status = t.error.clone().or_else(|| t.band.clone()).unwrap_or_default();
+            [distance, (t.stake / LAMPORTS_PER_SOL).to_string(), t.address.to_string(), t.pubkeys.join(" "), status]
+        }).collect()
+    }
+}
+
+#[cfg(feature = "tui")]
+mod screen {
+    use super::Dashboard;
+    use crate::cancel::Cancellation;
+    use crate::output::DistanceReport;
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Modifier, Style};
+    use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table};
+    use ratatui::{DefaultTerminal, Frame};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Interval at which the input thread checks whether the measures were cancelled elsewhere.
+    const INPUT_POLL: Duration = Duration::from_millis(200);
+
+    struct Screen {
+        terminal: DefaultTerminal,
+        dashboard: Dashboard,
+    }
+
+    impl Screen {
+        fn draw(&mut self) {
+            // A failed draw is retried at the next round or resize
+            let _ = self.terminal.draw(|frame| draw(frame, &self.dashboard));
+        }
+    }
+
+    fn draw(frame: &mut Frame, dashboard: &Dashboard) {
+        let [summary, sparkline, body, help] = Layout::vertical([Constraint::Length(3), Constraint::Length(6), Constraint::Min(0), Constraint::Length(1)])
+            .areas(frame.area());
+        let [validators, errors] = Layout::horizontal([Constraint::Min(0), Constraint::Length(44)]).areas(body);
+
+        let text = match &dashboard.failure {
+            Some(error) => format!("{}  last round failed: {}", dashboard.summary(), error),
+            None => dashboard.summary(),
+        };
+        frame.render_widget(Paragraph::new(text).block(Block::bordered().title("solana-distance")), summary);
+
+        // Show the latest rounds which fit in the block
+        let width = sparkline.width.saturating_sub(2) as usize;
+        let skip = dashboard.history.len().saturating_sub(width);
+        let history = Sparkline::default()
+            .block(Block::bordered().title("Stake-weighted distance (µs)"))
+            .data(dashboard.history.iter().skip(skip).copied());
+        frame.render_widget(history, sparkline);
+
+        let header = Row::new(["Distance (µs)", "Stake (SOL)", "TPU", "Pubkeys", "Status"]).style(Style::new().add_modifier(Modifier::BOLD));
+        let widths = [Constraint::Length(16), Constraint::Length(11), Constraint::Length(22), Constraint::Min(44), Constraint::Length(24)];
+        let table = Table::new(dashboard.rows().into_iter().map(Row::new), widths)
+            .header(header)
+            .block(Block::bordered().title("Validators"));
+        frame.render_widget(table, validators);
+
+        let last: Vec<(&str, u64)> = dashboard.last.iter().flat_map(|r| r.errors.iter().map(|e| (e.error.as_str(), e.count))).collect();
+        let rows = dashboard.errors.iter().map(|(error, total)| {
+            let round = last.iter().find(|(e, _)| *e == error.as_str()).map_or(0, |(_, count)| *count);
+            Row::new([error.clone(), round.to_string(), total.to_string()])
+        });
+        let header = Row::new(["Error", "Round", "Total"]).style(Style::new().add_modifier(Modifier::BOLD));
+        let table = Table::new(rows, [Constraint::Min(24), Constraint::Length(6), Constraint::Length(7)])
+            .header(header)
+            .block(Block::bordered().title("Errors"));
+        frame.render_widget(table, errors);
+
+        frame.render_widget(Paragraph::new("q: quit"), help);
+    }
+
+    /// Dashboard drawn in the alternate screen of the terminal, restored when dropped.
+    pub struct Tui {
+        screen: Arc<Mutex<Screen>>,
+    }
+
+    impl Tui {
+        /// Take over the terminal, and cancel the measures on q, Esc or Ctrl-C, which raw mode doesn't turn into
+        /// a signal.
+        pub fn start(cancellation: Cancellation) -> io::Result<Tui> {
+            let mut screen = Screen { terminal: ratatui::try_init()?, dashboard: Dashboard::default() };
+            screen.draw();
+            let screen = Arc::new(Mutex::new(screen));
+            let input = screen.clone();
+            thread::spawn(move || {
+                while !cancellation.is_cancelled() {
+                    if !event::poll(INPUT_POLL).unwrap_or(false) {
+                        continue;
+                    }
+                    match event::read() {
+                        Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                            let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                            if ctrl_c || key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
+                                cancellation.cancel();
+                            }
+                        }
+                        Ok(Event::Resize(_, _)) => input.lock().unwrap().draw(),
+                        Ok(_) => (),
+                        Err(_) => return,
+                    }
+                }
+            });
+            Ok(Tui { screen })
+        }
+
+        pub fn update(&self, report: DistanceReport) {
+            let mut screen = self.screen.lock().unwrap();
+            screen.dashboard.update(report);
+            screen.draw();
+        }
+
+        pub fn fail(&self, error: String) {
+            let mut screen = self.screen.lock().unwrap();
+            screen.dashboard.fail(error);
+            screen.draw();
+        }
+    }
+
+    impl Drop for Tui {
+        fn drop(&mut self) {
+            ratatui::restore();
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Transaction landing latency: the time from the submission of a tiny transaction to its confirmation, to
+//! correlate the distance to the leaders with the actual landing performance.
+//!
+//! The transaction is a transfer of a few lamports from a funded keypair to itself, only costing the base fee of
+//! 5000 lamports. It is sent through RPC, which forwards it to the upcoming leaders, and its status polled until
+//! confirmed.
+
+use crate::discovery::with_retries;
+use crate::output::TpuReport;
+use serde::Serialize;
+use solana_commitment_config::CommitmentConfig;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_keypair::{Keypair, Signer};
+use solana_pubkey::Pubkey;
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_transaction::Transaction;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Program id of the system program, `11111111111111111111111111111111`
+const SYSTEM_PROGRAM_ID: Pubkey = Pubkey::new_from_array([0; 32]);
+/// Index of the transfer instruction of the system program
+const SYSTEM_TRANSFER: u32 = 2;
+/// Spacing of the signature status requests
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Time after which a transaction is not expected to land anymore, its blockhash having expired
+const LANDING_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Landing of a self-transfer, in [`DistanceReport::tx_landing`](crate::DistanceReport::tx_landing).
+#[derive(Serialize)]
+pub struct TxLanding {
+    /// Signature of the transaction, if it could be built
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Slot observed just before the submission
+    pub submitted_slot: Option<u64>,
+    /// Slot the transaction landed in, if confirmed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub landed_slot: Option<u64>,
+    /// Time from the submission to the observation of the confirmation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub landing_ms: Option<u64>,
+    /// Leader of the landing slot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leader: Option<String>,
+    /// Measured distance to the TPU of the leader, if it was measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leader_distance_us: Option<u32>,
+    /// Failure to submit or confirm the transaction, or its execution error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TxLanding {
+    pub(crate) fn print_text(&self) {
+        match (self.landing_ms, self.landed_slot) {
+            (Some(ms), Some(slot)) => {
+                print!("Transaction landed in {} ms, in slot {}", ms, slot);
+                if let Some(submitted) = self.submitted_slot {
+                    print!(" ({} slots after submission)", slot.saturating_sub(submitted));
+                }
+                if let Some(leader) = &self.leader {
+                    print!(", leader {}", leader);
+                    if let Some(distance) = self.leader_distance_us {
+                        print!(" at {} µs", distance);
+                    }
+                }
+                println!();
+            }
+            _ => println!("Transaction {}did not land: {}", self.signature.as_ref().map(|s| format!("{} ", s)).unwrap_or_default(),
+                          self.error.as_deref().unwrap_or("unknown error")),
+        }
+        if let (Some(error), Some(_)) = (&self.error, self.landed_slot) {
+            println!("Transaction failed: {}", error);
+        }
+    }
+}
+
+/// Submit a self-transfer of `payer` through the first of `rpc_clients` and wait for its confirmation, the
+/// distance to the leader of the landing slot being taken from `tpus`.
+pub(crate) fn land(rpc_clients: &[RpcClient], payer: &Keypair, tpus: &[TpuReport]) -> TxLanding {
+    let rpc_client = &rpc_clients[0];
+    let mut landing = TxLanding {
+        signature: None,
+        submitted_slot: None,
+        landed_slot: None,
+        landing_ms: None,
+        leader: None,
+        leader_distance_us: None,
+        error: None,
+    };
+    let blockhash = match with_retries("latest blockhash", rpc_clients, RpcClient::get_latest_blockhash) {
+        Ok(blockhash) => blockhash,
+        Err(e) => {
+            landing.error = Some(e.to_string());
+            return landing;
+        }
+    };
+    // A varying amount makes the transactions of successive measures distinct, even with the same blockhash
+    let lamports = 1 + SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_micros() as u64;
+    let data = [SYSTEM_TRANSFER.to_le_bytes().as_slice(), lamports.to_le_bytes().as_slice()].concat();
+    let accounts = vec![AccountMeta::new(payer.pubkey(), true), AccountMeta::new(payer.pubkey(), false)];
+    let transfer = Instruction::new_with_bytes(SYSTEM_PROGRAM_ID, &data, accounts);
+    let tx = Transaction::new_signed_with_payer(&[transfer], Some(&payer.pubkey()), &[payer], blockhash);
+    landing.signature = Some(tx.signatures[0].to_string());
+    landing.submitted_slot = rpc_client.get_slot_with_commitment(CommitmentConfig::processed()).ok();
+
+    let start = Instant::now();
+    if let Err(e) = rpc_client.send_transaction(&tx) {
+        landing.error = Some(format!("Failed to send transaction: {}", e));
+        return landing;
+    }
+    let status = loop {
+        if start.elapsed() > LANDING_TIMEOUT {
+            landing.error = Some(format!("Not confirmed after {} s", LANDING_TIMEOUT.as_secs()));
+            return landing;
+        }
+        sleep(POLL_INTERVAL);
+        let status = rpc_client.get_signature_statuses(&[tx.signatures[0]]).ok().and_then(|r| r.value.into_iter().next().flatten());
+        if let Some(status) = status.filter(|s| s.satisfies_commitment(CommitmentConfig::confirmed())) {
+            break status;
+        }
+    };
+    landing.landing_ms = Some(start.elapsed().as_millis() as u64);
+    landing.landed_slot = Some(status.slot);
+    landing.error = status.err.map(|e| e.to_string());
+    landing.leader = rpc_client.get_slot_leaders(status.slot, 1).ok().and_then(|l| l.first().map(Pubkey::to_string));
+    landing.leader_distance_us = landing.leader.as_ref()
+        .and_then(|leader| tpus.iter().find(|t| !t.alternate && t.error.is_none() && t.pubkeys.contains(leader)))
+        .and_then(|t| t.distance_us);
+    landing
+}
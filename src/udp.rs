@@ -0,0 +1,215 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::IoSliceMut;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use quinn::{AsyncUdpSocket, UdpPoller};
+use quinn::udp::{RecvMeta, Transmit};
+use crate::OnTrace;
+
+/// Datagram and byte counters shared between the endpoint socket and the reporting code.
+#[derive(Default)]
+pub struct UdpCounters {
+    tx_datagrams: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_errors: AtomicU64,
+    rx_datagrams: AtomicU64,
+    rx_bytes: AtomicU64,
+}
+
+impl UdpCounters {
+    pub fn totals(&self) -> UdpTotals {
+        UdpTotals {
+            tx_datagrams: self.tx_datagrams.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_errors: self.tx_errors.load(Ordering::Relaxed),
+            rx_datagrams: self.rx_datagrams.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of [`UdpCounters`].
+#[derive(Clone, Copy)]
+pub struct UdpTotals {
+    pub tx_datagrams: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+    pub rx_datagrams: u64,
+    pub rx_bytes: u64,
+}
+
+impl UdpTotals {
+    pub fn since(&self, earlier: &UdpTotals) -> UdpTotals {
+        UdpTotals {
+            tx_datagrams: self.tx_datagrams - earlier.tx_datagrams,
+            tx_bytes: self.tx_bytes - earlier.tx_bytes,
+            tx_errors: self.tx_errors - earlier.tx_errors,
+            rx_datagrams: self.rx_datagrams - earlier.rx_datagrams,
+            rx_bytes: self.rx_bytes - earlier.rx_bytes,
+        }
+    }
+}
+
+/// Timeline of the events related to a single traced destination, written to `on_trace` or, without it, to
+/// stderr so as not to mix with the report.
+pub struct PacketTrace {
+    addr: SocketAddr,
+    start: Instant,
+    on_trace: Option<OnTrace>,
+}
+
+impl PacketTrace {
+    pub fn new(addr: SocketAddr, on_trace: Option<OnTrace>) -> Arc<Self> {
+        Arc::new(Self { addr, start: Instant::now(), on_trace })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn line(&self, line: &str) {
+        match &self.on_trace {
+            Some(on_trace) => on_trace(line),
+            None => eprintln!("{}", line),
+        }
+    }
+
+    pub fn event(&self, event: std::fmt::Arguments) {
+        self.line(&format!("[{:>10.3} ms] {} {}", self.start.elapsed().as_secs_f64() * 1000.0, self.addr, event));
+    }
+}
+
+/// Return the type of the first QUIC packet of a datagram, from its header.
+fn quic_packet_type(datagram: &[u8]) -> &'static str {
+    match datagram.first() {
+        None => "empty",
+        Some(b) if b & 0x80 == 0 => "1-RTT",
+        Some(b) => match (b & 0x30) >> 4 {
+            0 => "Initial",
+            1 => "0-RTT",
+            2 => "Handshake",
+            _ => "Retry",
+        },
+    }
+}
+
+/// Wrapper around the endpoint UDP socket counting every datagram going through it.
+pub struct CountingSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    counters: Arc<UdpCounters>,
+    trace: Option<Arc<PacketTrace>>,
+}
+
+impl CountingSocket {
+    pub fn new(inner: Arc<dyn AsyncUdpSocket>, counters: Arc<UdpCounters>, trace: Option<Arc<PacketTrace>>) -> Arc<Self> {
+        Arc::new(Self { inner, counters, trace })
+    }
+}
+
+impl Debug for CountingSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CountingSocket({:?})", self.inner)
+    }
+}
+
+impl AsyncUdpSocket for CountingSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let res = self.inner.try_send(transmit);
+        match &res {
+            Ok(()) => {
+                // With GSO, a single transmit carries several datagrams of `segment_size` bytes
+                let datagrams = match transmit.segment_size {
+                    Some(size) if size > 0 => transmit.contents.len().div_ceil(size),
+                    _ => 1,
+                };
+                self.counters.tx_datagrams.fetch_add(datagrams as u64, Ordering::Relaxed);
+                self.counters.tx_bytes.fetch_add(transmit.contents.len() as u64, Ordering::Relaxed);
+                if let Some(trace) = self.trace.as_ref().filter(|t| t.addr == transmit.destination) {
+                    trace.event(format_args!("sent {} datagram(s), {} bytes, {} packet",
+                                             datagrams, transmit.contents.len(), quic_packet_type(transmit.contents)));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => {
+                self.counters.tx_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        res
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        let res = self.inner.poll_recv(cx, bufs, meta);
+        if let Poll::Ready(Ok(n)) = res {
+            for (m, buf) in meta[..n].iter().zip(bufs.iter()) {
+                // With GRO, a single buffer carries several datagrams of `stride` bytes
+                let datagrams = if m.stride > 0 { m.len.div_ceil(m.stride) } else { 1 };
+                self.counters.rx_datagrams.fetch_add(datagrams as u64, Ordering::Relaxed);
+                self.counters.rx_bytes.fetch_add(m.len as u64, Ordering::Relaxed);
+                if let Some(trace) = self.trace.as_ref().filter(|t| t.addr == m.addr) {
+                    trace.event(format_args!("received {} datagram(s), {} bytes, {} packet",
+                                             datagrams, m.len, quic_packet_type(&buf[..m.len])));
+                }
+            }
+        }
+        res
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.inner.max_transmit_segments()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Host-wide UDP buffer overflow counters, as reported by the kernel.
+///
+/// Only available on Linux, through `/proc/net/snmp`. Since these counters are not specific to our
+/// socket, only the difference between two snapshots taken around the measurement is meaningful.
+#[derive(Clone, Copy)]
+pub struct OsUdpDrops {
+    pub rcvbuf_errors: u64,
+    pub sndbuf_errors: u64,
+}
+
+impl OsUdpDrops {
+    pub fn snapshot() -> Option<Self> {
+        let snmp = std::fs::read_to_string("/proc/net/snmp").ok()?;
+        let mut lines = snmp.lines().filter(|l| l.starts_with("Udp:"));
+        let header: Vec<&str> = lines.next()?.split_whitespace().collect();
+        let values: Vec<&str> = lines.next()?.split_whitespace().collect();
+        let get = |name: &str| -> Option<u64> {
+            let i = header.iter().position(|h| *h == name)?;
+            values.get(i)?.parse().ok()
+        };
+        Some(OsUdpDrops {
+            rcvbuf_errors: get("RcvbufErrors")?,
+            sndbuf_errors: get("SndbufErrors")?,
+        })
+    }
+
+    pub fn since(&self, earlier: &OsUdpDrops) -> OsUdpDrops {
+        OsUdpDrops {
+            rcvbuf_errors: self.rcvbuf_errors.saturating_sub(earlier.rcvbuf_errors),
+            sndbuf_errors: self.sndbuf_errors.saturating_sub(earlier.sndbuf_errors),
+        }
+    }
+}